@@ -0,0 +1,582 @@
+use clap::{Args, Parser, Subcommand};
+use poing_core::batch::{self, BatchEvent, BatchOptions, JobOutcome};
+use poing_core::catalog;
+use poing_core::config;
+use poing_core::export::{ExportFormat, ExportOptions};
+use poing_core::install::{self, FileOutcome, InstallEvent, InstallOptions, ModelManifest};
+use poing_core::model::{self, OnnxModel};
+use poing_core::musicgen::{GenerationHooks, GenerationParams, MusicGenPipeline, SAMPLE_RATE};
+use poing_core::worker::{self, Job, JobKind, MAX_VARIATIONS};
+use poing_core::{resample, server, wav};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::Instant;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+/// Generate audio with MusicGen ONNX models from the command line.
+#[derive(Parser)]
+#[command(name = "poing", version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Generate audio from a text prompt.
+    Generate {
+        /// Text description of the music to generate.
+        prompt: String,
+        #[command(flatten)]
+        options: GenerateOptions,
+    },
+    /// Continue an existing WAV file guided by a text prompt.
+    Continue {
+        /// WAV or FLAC file to continue. Mixed to mono and resampled to 32 kHz.
+        input: PathBuf,
+        /// Text description of the continuation.
+        prompt: String,
+        #[command(flatten)]
+        options: GenerateOptions,
+    },
+    /// Render every job in a JSONL or TOML manifest with one loaded model.
+    Batch {
+        /// Manifest file (`.jsonl`, or `.toml` with `[[job]]` tables).
+        manifest: PathBuf,
+        /// Directory outputs are written to, along with a summary report.
+        #[arg(short, long, default_value = ".")]
+        output_dir: PathBuf,
+        /// Model directory. Defaults to the first model in the Poing config.
+        #[arg(short, long)]
+        model: Option<PathBuf>,
+        /// Default seconds of audio for jobs that don't set a duration.
+        #[arg(short, long, default_value_t = 10.0)]
+        duration: f32,
+        /// Re-render jobs whose output already exists instead of skipping them.
+        #[arg(long)]
+        overwrite: bool,
+    },
+    /// Serve generation over a local HTTP/JSON API using the configured models.
+    Serve {
+        /// Address to listen on.
+        #[arg(short, long, default_value = "127.0.0.1:7878")]
+        addr: String,
+        /// Additional model directories to offer besides the configured ones.
+        #[arg(short, long)]
+        model: Vec<PathBuf>,
+    },
+    /// List the configured models and those found under the model roots,
+    /// with their size and precision.
+    Models,
+    /// Install a model from a mirror directory or .tar/.tar.gz archive,
+    /// verifying each file against the SHA-256 hashes of its manifest.
+    /// Rerun to resume an interrupted install.
+    Install {
+        /// Mirror directory or archive holding the model files.
+        source: PathBuf,
+        /// Manifest to install by. Defaults to poing-model.json in the source.
+        #[arg(long)]
+        manifest: Option<PathBuf>,
+        /// Folder to create the model directory in. Defaults to Poing's models folder.
+        #[arg(short, long)]
+        dir: Option<PathBuf>,
+        /// Don't add the model to the Poing config.
+        #[arg(long)]
+        no_register: bool,
+    },
+    /// Write a manifest with SHA-256 hashes of every file in a model directory,
+    /// for installing it elsewhere with `install`.
+    Manifest {
+        /// Model directory to describe.
+        model: PathBuf,
+        /// Model name. Defaults to the directory name.
+        #[arg(short, long)]
+        name: Option<String>,
+        /// Description shown with the model.
+        #[arg(short, long)]
+        description: Option<String>,
+        /// Output path. Defaults to poing-model.json in the model directory.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Print the input and output signatures of every ONNX file in a model directory.
+    Inspect {
+        /// Model directory. Defaults to the first model in the Poing config.
+        model: Option<PathBuf>,
+    },
+    /// Check that a model directory contains everything Poing needs and loads.
+    Validate {
+        /// Model directory. Defaults to the first model in the Poing config.
+        model: Option<PathBuf>,
+    },
+    /// Measure model load time and generation speed.
+    Bench {
+        /// Model directory. Defaults to the first model in the Poing config.
+        #[arg(short, long)]
+        model: Option<PathBuf>,
+        /// Seconds of audio per run.
+        #[arg(short, long, default_value_t = 5.0)]
+        duration: f32,
+        /// Number of timed runs.
+        #[arg(short, long, default_value_t = 3)]
+        runs: usize,
+    },
+}
+
+#[derive(Args)]
+struct GenerateOptions {
+    /// Model directory. Defaults to the first model in the Poing config.
+    #[arg(short, long)]
+    model: Option<PathBuf>,
+    /// Seconds of new audio to generate.
+    #[arg(short, long, default_value_t = 10.0)]
+    duration: f32,
+    /// Classifier-free guidance scale.
+    #[arg(short, long, default_value_t = 3.0)]
+    guidance: f32,
+    /// Top-K sampling, at least 1.
+    #[arg(
+        short = 'k',
+        long,
+        default_value_t = 50,
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    top_k: usize,
+    /// Sampling seed. Variations use consecutive seeds.
+    #[arg(short, long)]
+    seed: Option<u64>,
+    /// Output WAV or FLAC path. Variations are numbered, e.g. output_1.wav.
+    #[arg(short, long, default_value = "output.wav")]
+    output: PathBuf,
+    /// Output format: wav16, wav24, float, flac16 or flac24. Defaults to
+    /// 32-bit float WAV, or 24-bit FLAC for a .flac output.
+    #[arg(short, long)]
+    format: Option<ExportFormat>,
+    /// Resample the output to this rate in Hz.
+    #[arg(long)]
+    sample_rate: Option<u32>,
+    /// Number of takes to generate, up to 16.
+    #[arg(short = 'n', long, default_value_t = 1, value_parser = parse_variations)]
+    variations: usize,
+}
+
+/// Parse `--variations`, from 1 to [`MAX_VARIATIONS`].
+fn parse_variations(value: &str) -> std::result::Result<usize, String> {
+    let count: usize = value.parse().map_err(|e| format!("{}", e))?;
+    if (1..=MAX_VARIATIONS).contains(&count) {
+        Ok(count)
+    } else {
+        Err(format!("must be between 1 and {}", MAX_VARIATIONS))
+    }
+}
+
+impl GenerateOptions {
+    fn params(&self) -> GenerationParams {
+        GenerationParams {
+            duration_seconds: self.duration,
+            guidance_scale: self.guidance,
+            top_k: self.top_k,
+            seed: self.seed,
+        }
+    }
+
+    fn export_options(&self) -> ExportOptions {
+        let format = self.format.unwrap_or_else(|| {
+            ExportFormat::for_path(&self.output, ExportFormat::WavFloat)
+                .unwrap_or(ExportFormat::WavFloat)
+        });
+        ExportOptions {
+            format,
+            sample_rate: self.sample_rate,
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let result = match cli.command {
+        Command::Generate { prompt, options } => generate(&prompt, None, &options),
+        Command::Continue {
+            input,
+            prompt,
+            options,
+        } => generate(&prompt, Some(&input), &options),
+        Command::Batch {
+            manifest,
+            output_dir,
+            model,
+            duration,
+            overwrite,
+        } => resolve_model(model)
+            .and_then(|dir| batch(&manifest, &output_dir, dir, duration, !overwrite)),
+        Command::Serve { addr, model } => serve(&addr, model),
+        Command::Models => models(),
+        Command::Install {
+            source,
+            manifest,
+            dir,
+            no_register,
+        } => install_model(InstallOptions {
+            source,
+            manifest,
+            models_dir: dir,
+            register: !no_register,
+        }),
+        Command::Manifest {
+            model,
+            name,
+            description,
+            output,
+        } => write_manifest(&model, name, description, output),
+        Command::Inspect { model } => resolve_model(model).and_then(|dir| inspect(&dir)),
+        Command::Validate { model } => resolve_model(model).and_then(|dir| validate(&dir)),
+        Command::Bench {
+            model,
+            duration,
+            runs,
+        } => resolve_model(model).and_then(|dir| bench(&dir, duration, runs)),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("poing: error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Use the given model directory, or fall back to the first configured one.
+fn resolve_model(model: Option<PathBuf>) -> Result<PathBuf> {
+    if let Some(dir) = model {
+        return Ok(dir);
+    }
+    catalog::model_dirs(&config::try_load_config()?)
+        .into_iter()
+        .next()
+        .ok_or_else(|| "no --model given and no models configured in Poing".into())
+}
+
+fn generate(prompt: &str, input: Option<&Path>, options: &GenerateOptions) -> Result<()> {
+    let model_dir = resolve_model(options.model.clone())?;
+    let count = options.variations;
+    let kind = match input {
+        Some(path) => {
+            let (samples, rate) = wav::read_audio_file(path)?;
+            JobKind::Continue {
+                input_audio: Arc::new(resample::resample(&samples, rate, SAMPLE_RATE)),
+                count,
+            }
+        }
+        None if count > 1 => JobKind::Variations { count },
+        None => JobKind::Generate,
+    };
+    let job = Job {
+        prompt: prompt.to_string(),
+        model_dir,
+        params: options.params(),
+        kind,
+        tempo: None,
+        post_process: None,
+        settings: None,
+    };
+
+    eprintln!("Prompt: {}", prompt);
+    eprintln!("Model: {}", job.model_dir.display());
+    let mut pipeline = MusicGenPipeline::load(&job.model_dir)?;
+
+    let label = if count > 1 {
+        format!("Generating {} takes", count)
+    } else {
+        "Generating".to_string()
+    };
+    let progress = |p: f32| {
+        eprint!("\r{}... {:>3.0}%", label, p * 100.0);
+        let _ = std::io::stderr().flush();
+    };
+    let export_options = options.export_options();
+    worker::render_job(
+        0,
+        &job,
+        &mut pipeline,
+        &GenerationHooks::progress(&progress),
+        |index, take| {
+            let output = numbered_output(&options.output, index, count);
+            take.export(&output, &export_options)?;
+            eprintln!();
+            println!(
+                "Wrote {} ({:.1}s, seed {})",
+                output.display(),
+                take.audio.len() as f64 / SAMPLE_RATE as f64,
+                take.params.seed.unwrap_or_default()
+            );
+            Ok(())
+        },
+    )?;
+    Ok(())
+}
+
+/// `out.wav` for a single take, `out_1.wav`, `out_2.wav`, ... for variations.
+fn numbered_output(output: &Path, index: usize, count: usize) -> PathBuf {
+    if count <= 1 {
+        return output.to_path_buf();
+    }
+    let stem = output
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "output".into());
+    let ext = output
+        .extension()
+        .map(|e| e.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "wav".into());
+    output.with_file_name(format!("{}_{}.{}", stem, index + 1, ext))
+}
+
+fn batch(
+    manifest: &Path,
+    output_dir: &Path,
+    model_dir: PathBuf,
+    duration: f32,
+    resume: bool,
+) -> Result<()> {
+    let jobs = batch::load_manifest(manifest)?;
+    let options = BatchOptions {
+        model_dir,
+        output_dir: output_dir.to_path_buf(),
+        defaults: GenerationParams {
+            duration_seconds: duration,
+            ..GenerationParams::default()
+        },
+        resume,
+    };
+
+    let report = batch::run_batch(&jobs, &options, |event| match event {
+        BatchEvent::Started { index, total, job } => {
+            eprintln!("[{}/{}] {}", index + 1, total, job.prompt);
+        }
+        BatchEvent::Progress { progress, .. } => {
+            eprint!("\rGenerating... {:>3.0}%", progress * 100.0);
+            let _ = std::io::stderr().flush();
+        }
+        BatchEvent::Finished { entry, .. } => match &entry.outcome {
+            JobOutcome::Rendered { .. } => eprintln!("\rWrote {}", entry.output.display()),
+            JobOutcome::Skipped { .. } => eprintln!("Skipped {} (exists)", entry.output.display()),
+            JobOutcome::Failed { error } => {
+                eprintln!("\rFailed {}: {}", entry.output.display(), error)
+            }
+        },
+    })?;
+
+    println!(
+        "{} rendered, {} skipped, {} failed; report in {}",
+        report.rendered,
+        report.skipped,
+        report.failed,
+        output_dir.join(batch::REPORT_FILE_NAME).display()
+    );
+    if report.failed > 0 {
+        return Err(format!("{} job(s) failed", report.failed).into());
+    }
+    Ok(())
+}
+
+fn serve(addr: &str, extra_models: Vec<PathBuf>) -> Result<()> {
+    let mut model_paths = catalog::model_dirs(&config::try_load_config()?);
+    for path in extra_models {
+        if !model_paths.contains(&path) {
+            model_paths.push(path);
+        }
+    }
+    if model_paths.is_empty() {
+        return Err("no models configured; pass --model".into());
+    }
+    let server = server::start(addr, model_paths).map_err(|e| e.to_string())?;
+    println!("Listening on http://{}", server.local_addr());
+    server.join();
+    Ok(())
+}
+
+fn models() -> Result<()> {
+    let entries = catalog::entries(&catalog::refresh_details()?);
+    if entries.is_empty() {
+        return Err("no models configured in Poing".into());
+    }
+    for entry in entries {
+        println!("{}", entry.label());
+        println!("  {}", entry.path.display());
+        if let Some(description) = &entry.details.description {
+            println!("  {}", description);
+        }
+    }
+    Ok(())
+}
+
+fn install_model(options: InstallOptions) -> Result<()> {
+    let report = install::install(&options, |event| match event {
+        InstallEvent::Started { index, total, file } => {
+            eprintln!("[{}/{}] {}", index + 1, total, file.path);
+        }
+        InstallEvent::Progress { written, .. } => {
+            eprint!("\r{:>8}", catalog::format_size(written));
+            let _ = std::io::stderr().flush();
+        }
+        InstallEvent::Finished { file, outcome, .. } => match outcome {
+            FileOutcome::Installed => eprintln!("\rVerified {}", file.path),
+            FileOutcome::Resumed => eprintln!("\rResumed and verified {}", file.path),
+            FileOutcome::Present => eprintln!("Skipped {} (already installed)", file.path),
+        },
+    })?;
+    println!(
+        "Installed {} to {}",
+        report.manifest.name,
+        report.model_dir.display()
+    );
+    if options.register {
+        println!("Added to the Poing config");
+    }
+    Ok(())
+}
+
+fn write_manifest(
+    model_dir: &Path,
+    name: Option<String>,
+    description: Option<String>,
+    output: Option<PathBuf>,
+) -> Result<()> {
+    let name = name.unwrap_or_else(|| {
+        model_dir
+            .canonicalize()
+            .unwrap_or_else(|_| model_dir.to_path_buf())
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "model".into())
+    });
+    let mut manifest = ModelManifest::for_dir(model_dir, &name)?;
+    manifest.description = description;
+    let output = output.unwrap_or_else(|| model_dir.join(install::MANIFEST_FILE));
+    manifest.save(&output)?;
+    println!(
+        "Wrote {} ({} files)",
+        output.display(),
+        manifest.files.len()
+    );
+    Ok(())
+}
+
+fn inspect(model_dir: &Path) -> Result<()> {
+    let files = model::onnx_files(model_dir)?;
+    if files.is_empty() {
+        return Err(format!("no .onnx files in {}", model_dir.display()).into());
+    }
+    for path in files {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        println!("=== {} ===", name);
+        match OnnxModel::load(&path) {
+            Ok(m) => {
+                println!("Inputs:");
+                for input in m.inputs() {
+                    println!("  {} : {}", input.name, input.dtype);
+                }
+                println!("Outputs:");
+                for output in m.outputs() {
+                    println!("  {} : {}", output.name, output.dtype);
+                }
+            }
+            Err(e) => println!("  Failed to load: {}", e),
+        }
+        println!();
+    }
+    Ok(())
+}
+
+fn validate(model_dir: &Path) -> Result<()> {
+    let missing = config::missing_model_files(model_dir);
+    if !missing.is_empty() {
+        return Err(format!(
+            "{} is missing required files: {}",
+            model_dir.display(),
+            missing.join(", ")
+        )
+        .into());
+    }
+    let pipeline = MusicGenPipeline::load(model_dir)?;
+    println!("{}: OK", model_dir.display());
+    println!(
+        "Audio continuation: {}",
+        if pipeline.supports_continuation() {
+            "supported"
+        } else {
+            "unsupported (no encodec_encode.onnx)"
+        }
+    );
+    Ok(())
+}
+
+fn bench(model_dir: &Path, duration: f32, runs: usize) -> Result<()> {
+    let start = Instant::now();
+    let mut pipeline = MusicGenPipeline::load(model_dir)?;
+    println!("Load: {:.2}s", start.elapsed().as_secs_f64());
+
+    let params = GenerationParams {
+        duration_seconds: duration,
+        seed: Some(0),
+        ..GenerationParams::default()
+    };
+    let mut total = 0.0;
+    for run in 1..=runs.max(1) {
+        let start = Instant::now();
+        let samples = pipeline.generate("upbeat electronic dance music", &params, |_| {})?;
+        let elapsed = start.elapsed().as_secs_f64();
+        let audio_secs = samples.len() as f64 / SAMPLE_RATE as f64;
+        println!(
+            "Run {}: {:.2}s for {:.1}s of audio ({:.2}x real time)",
+            run,
+            elapsed,
+            audio_secs,
+            audio_secs / elapsed
+        );
+        total += elapsed;
+    }
+    println!("Mean: {:.2}s", total / runs.max(1) as f64);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_numbered_output() {
+        let output = Path::new("renders/kick.flac");
+        assert_eq!(numbered_output(output, 0, 1), output);
+        assert_eq!(
+            numbered_output(output, 1, 4),
+            Path::new("renders/kick_2.flac")
+        );
+        assert_eq!(
+            numbered_output(Path::new("take"), 0, 2),
+            Path::new("take_1.wav")
+        );
+    }
+
+    #[test]
+    fn test_parse_variations() {
+        assert_eq!(parse_variations("4"), Ok(4));
+        assert!(parse_variations("0").is_err());
+        assert!(parse_variations("17").is_err());
+        assert!(parse_variations("many").is_err());
+    }
+
+    #[test]
+    fn test_top_k_must_be_positive() {
+        assert!(Cli::try_parse_from(["poing", "generate", "kick", "-k", "0"]).is_err());
+        let cli = Cli::try_parse_from(["poing", "generate", "kick", "-k", "1"]).unwrap();
+        let Command::Generate { options, .. } = cli.command else {
+            panic!("expected generate");
+        };
+        assert_eq!(options.top_k, 1);
+    }
+}
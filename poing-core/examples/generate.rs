@@ -1,3 +1,4 @@
+use poing_core::musicgen::GenerationParams;
 use std::path::Path;
 
 fn main() {
@@ -16,9 +17,10 @@ fn main() {
     println!("Output: {}", output_path.display());
     println!();
 
-    let samples = poing_core::musicgen::generate_from_text(&prompt, model_dir, |progress| {
+    let params = GenerationParams::default();
+    let samples = poing_core::musicgen::generate_from_text(&prompt, model_dir, &params, |progress| {
         let pct = (progress * 100.0) as u32;
-        if pct % 5 == 0 {
+        if pct.is_multiple_of(5) {
             eprint!("\rGenerating... {}%", pct);
         }
     })
@@ -0,0 +1,436 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use crate::install::is_relative_inside;
+use crate::musicgen::{GenerationParams, MusicGenPipeline, SAMPLE_RATE};
+use crate::wav;
+
+/// File name of the summary written into the output directory after every job.
+pub const REPORT_FILE_NAME: &str = "poing_batch_report.json";
+
+/// One clip to render. Unset parameters fall back to the batch defaults.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BatchJob {
+    pub prompt: String,
+    /// Output file, relative to the output directory and without `..`.
+    /// `.wav` is added if missing.
+    pub output: String,
+    #[serde(default, alias = "duration")]
+    pub duration_seconds: Option<f32>,
+    #[serde(default, alias = "guidance")]
+    pub guidance_scale: Option<f32>,
+    #[serde(default)]
+    pub top_k: Option<usize>,
+    #[serde(default)]
+    pub seed: Option<u64>,
+}
+
+impl BatchJob {
+    /// Reject parameters the model can't render with.
+    fn validate(&self) -> Result<(), String> {
+        if self.top_k == Some(0) {
+            return Err("top_k must be at least 1".into());
+        }
+        Ok(())
+    }
+
+    fn params(&self, defaults: &GenerationParams) -> GenerationParams {
+        GenerationParams {
+            duration_seconds: self.duration_seconds.unwrap_or(defaults.duration_seconds),
+            guidance_scale: self.guidance_scale.unwrap_or(defaults.guidance_scale),
+            top_k: self.top_k.unwrap_or(defaults.top_k),
+            seed: self.seed.or(defaults.seed),
+        }
+    }
+
+    /// Where the output goes, or an error when it would leave `output_dir`.
+    fn output_path(&self, output_dir: &Path) -> Result<PathBuf, String> {
+        if !is_relative_inside(&self.output) {
+            return Err(format!(
+                "output \"{}\" must be a relative path inside the output directory",
+                self.output
+            ));
+        }
+        let path = output_dir.join(&self.output);
+        if path.extension().is_some() {
+            Ok(path)
+        } else {
+            Ok(path.with_extension("wav"))
+        }
+    }
+}
+
+/// TOML manifests list jobs as `[[job]]` tables.
+#[derive(Deserialize)]
+struct TomlManifest {
+    #[serde(default)]
+    job: Vec<BatchJob>,
+}
+
+/// Load jobs from a `.toml` manifest (`[[job]]` tables) or a JSONL manifest
+/// (one job object per line; blank lines and `#` comments are skipped).
+pub fn load_manifest(path: &Path) -> Result<Vec<BatchJob>, Box<dyn std::error::Error>> {
+    let contents = std::fs::read_to_string(path)?;
+    if path.extension().is_some_and(|ext| ext == "toml") {
+        let manifest: TomlManifest = toml::from_str(&contents)?;
+        for (i, job) in manifest.job.iter().enumerate() {
+            job.validate()
+                .map_err(|e| format!("{}: job {}: {}", path.display(), i + 1, e))?;
+        }
+        return Ok(manifest.job);
+    }
+
+    contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| {
+            let line = line.trim();
+            !line.is_empty() && !line.starts_with('#')
+        })
+        .map(|(i, line)| {
+            serde_json::from_str(line)
+                .map_err(|e| e.to_string())
+                .and_then(|job: BatchJob| job.validate().map(|_| job))
+                .map_err(|e| format!("{}:{}: {}", path.display(), i + 1, e).into())
+        })
+        .collect()
+}
+
+/// Options for [`run_batch`].
+#[derive(Debug, Clone)]
+pub struct BatchOptions {
+    pub model_dir: PathBuf,
+    pub output_dir: PathBuf,
+    /// Parameters for fields a job leaves unset.
+    pub defaults: GenerationParams,
+    /// Skip jobs whose output file already exists.
+    pub resume: bool,
+}
+
+/// Outcome of a single job.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum JobOutcome {
+    Rendered { seed: u64, audio_seconds: f32, render_seconds: f32 },
+    Skipped {
+        /// How the existing output was rendered, from the previous report.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        previous: Option<Box<JobOutcome>>,
+    },
+    Failed { error: String },
+}
+
+/// Report entry for one job.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BatchEntry {
+    pub prompt: String,
+    pub output: PathBuf,
+    #[serde(flatten)]
+    pub outcome: JobOutcome,
+}
+
+/// Summary of a batch run, written to [`REPORT_FILE_NAME`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BatchReport {
+    pub model_dir: PathBuf,
+    pub rendered: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    pub entries: Vec<BatchEntry>,
+}
+
+impl BatchReport {
+    fn add(&mut self, entry: BatchEntry) {
+        match entry.outcome {
+            JobOutcome::Rendered { .. } => self.rendered += 1,
+            JobOutcome::Skipped { .. } => self.skipped += 1,
+            JobOutcome::Failed { .. } => self.failed += 1,
+        }
+        self.entries.push(entry);
+    }
+
+    fn write(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// The render that produced each output listed in the report at `path`.
+    /// A missing or unreadable report yields nothing.
+    fn load_renders(path: &Path) -> HashMap<PathBuf, JobOutcome> {
+        let Some(report) = std::fs::read_to_string(path)
+            .ok()
+            .and_then(|json| serde_json::from_str::<BatchReport>(&json).ok())
+        else {
+            return HashMap::new();
+        };
+        report
+            .entries
+            .into_iter()
+            .filter_map(|entry| {
+                let render = match entry.outcome {
+                    JobOutcome::Skipped { previous } => *previous?,
+                    outcome => outcome,
+                };
+                matches!(render, JobOutcome::Rendered { .. }).then_some((entry.output, render))
+            })
+            .collect()
+    }
+}
+
+/// Progress notifications from [`run_batch`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum BatchEvent<'a> {
+    /// Job `index` of `total` started rendering.
+    Started { index: usize, total: usize, job: &'a BatchJob },
+    /// Generation progress (0.0..=1.0) of the current job.
+    Progress { index: usize, progress: f32 },
+    /// Job `index` finished, was skipped, or failed.
+    Finished { index: usize, entry: &'a BatchEntry },
+}
+
+/// Render every job sequentially with a single loaded pipeline.
+///
+/// A failing job is recorded and the run continues. Each output is written to
+/// a `.part` file first and renamed when complete, so an interrupted run can be
+/// resumed without mistaking a half-written file for a finished one. The
+/// report is rewritten after every job, and a resumed run carries over the
+/// previous report's render details for the outputs it skips.
+pub fn run_batch(
+    jobs: &[BatchJob],
+    options: &BatchOptions,
+    on_event: impl Fn(BatchEvent),
+) -> Result<BatchReport, Box<dyn std::error::Error>> {
+    std::fs::create_dir_all(&options.output_dir)?;
+    let report_path = options.output_dir.join(REPORT_FILE_NAME);
+    let mut previous = if options.resume {
+        BatchReport::load_renders(&report_path)
+    } else {
+        HashMap::new()
+    };
+    let mut report = BatchReport {
+        model_dir: options.model_dir.clone(),
+        rendered: 0,
+        skipped: 0,
+        failed: 0,
+        entries: Vec::with_capacity(jobs.len()),
+    };
+    report.write(&report_path)?;
+    // Loaded for the first job rendered; a model that fails to load fails
+    // every job, and the report still gets written
+    let mut pipeline: Option<Result<MusicGenPipeline, String>> = None;
+
+    for (index, job) in jobs.iter().enumerate() {
+        let (output, outcome) = match job.output_path(&options.output_dir) {
+            Err(error) => (
+                options.output_dir.join(&job.output),
+                JobOutcome::Failed { error },
+            ),
+            Ok(output) if options.resume && output.exists() => {
+                let previous = previous.remove(&output).map(Box::new);
+                (output, JobOutcome::Skipped { previous })
+            }
+            Ok(output) => {
+                on_event(BatchEvent::Started {
+                    index,
+                    total: jobs.len(),
+                    job,
+                });
+                let loaded = pipeline.get_or_insert_with(|| {
+                    MusicGenPipeline::load(&options.model_dir)
+                        .map_err(|e| format!("model failed to load: {}", e))
+                });
+                let result = match loaded {
+                    Ok(pipeline) => {
+                        render_job(pipeline, job, &output, &options.defaults, |progress| {
+                            on_event(BatchEvent::Progress { index, progress })
+                        })
+                        .map_err(|e| e.to_string())
+                    }
+                    Err(e) => Err(e.clone()),
+                };
+                let outcome = result.unwrap_or_else(|error| JobOutcome::Failed { error });
+                (output, outcome)
+            }
+        };
+
+        report.add(BatchEntry {
+            prompt: job.prompt.clone(),
+            output,
+            outcome,
+        });
+        // Written as we go, so an interrupted run still leaves a report
+        report.write(&report_path)?;
+        on_event(BatchEvent::Finished {
+            index,
+            entry: report.entries.last().unwrap(),
+        });
+    }
+    Ok(report)
+}
+
+fn render_job(
+    pipeline: &mut MusicGenPipeline,
+    job: &BatchJob,
+    output: &Path,
+    defaults: &GenerationParams,
+    progress: impl Fn(f32),
+) -> Result<JobOutcome, Box<dyn std::error::Error>> {
+    let mut params = job.params(defaults);
+    let seed = params.seed.unwrap_or_else(rand::random);
+    params.seed = Some(seed);
+
+    let start = Instant::now();
+    let samples = pipeline.generate(&job.prompt, &params, progress)?;
+    let render_seconds = start.elapsed().as_secs_f32();
+
+    if let Some(parent) = output.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut partial = output.as_os_str().to_owned();
+    partial.push(".part");
+    let partial = PathBuf::from(partial);
+    wav::write_wav(&samples, SAMPLE_RATE, &partial)?;
+    std::fs::rename(&partial, output)?;
+
+    Ok(JobOutcome::Rendered {
+        seed,
+        audio_seconds: samples.len() as f32 / SAMPLE_RATE as f32,
+        render_seconds,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_dir;
+
+    #[test]
+    fn test_load_jsonl_manifest() {
+        let dir = temp_dir("batch_jsonl");
+        let path = dir.join("jobs.jsonl");
+        std::fs::write(
+            &path,
+            "# kicks\n{\"prompt\": \"gabber kick\", \"output\": \"kick\", \"seed\": 7}\n\n\
+             {\"prompt\": \"hoover\", \"output\": \"leads/hoover.wav\", \"duration\": 4.0}\n",
+        )
+        .unwrap();
+
+        let jobs = load_manifest(&path).unwrap();
+        assert_eq!(jobs.len(), 2);
+        assert_eq!(jobs[0].seed, Some(7));
+        assert_eq!(jobs[1].duration_seconds, Some(4.0));
+        assert_eq!(jobs[0].output_path(&dir), Ok(dir.join("kick.wav")));
+        assert_eq!(jobs[1].output_path(&dir), Ok(dir.join("leads/hoover.wav")));
+
+        std::fs::write(
+            &path,
+            "{\"prompt\": \"kick\", \"output\": \"kick\"}\n\
+             {\"prompt\": \"kick\", \"output\": \"kick\", \"top_k\": 0}\n",
+        )
+        .unwrap();
+        let error = load_manifest(&path).unwrap_err().to_string();
+        assert!(error.contains("jobs.jsonl:2: top_k"), "{}", error);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_load_toml_manifest() {
+        let dir = temp_dir("batch_toml");
+        let path = dir.join("jobs.toml");
+        std::fs::write(
+            &path,
+            "[[job]]\nprompt = \"gabber kick\"\noutput = \"kick\"\ntop_k = 100\n",
+        )
+        .unwrap();
+
+        let jobs = load_manifest(&path).unwrap();
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].top_k, Some(100));
+        assert_eq!(jobs[0].params(&GenerationParams::default()).top_k, 100);
+
+        std::fs::write(
+            &path,
+            "[[job]]\nprompt = \"kick\"\noutput = \"kick\"\ntop_k = 0\n",
+        )
+        .unwrap();
+        let error = load_manifest(&path).unwrap_err().to_string();
+        assert!(error.contains("job 1: top_k"), "{}", error);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_resume_skips_finished_outputs() {
+        let dir = temp_dir("batch_resume");
+        std::fs::write(dir.join("done.wav"), b"").unwrap();
+        let job = |output: &str| BatchJob {
+            prompt: "gabber kick".into(),
+            output: output.into(),
+            duration_seconds: None,
+            guidance_scale: None,
+            top_k: None,
+            seed: None,
+        };
+        let jobs = vec![
+            job("done"),
+            job("../escaped"),
+            job("/tmp/absolute"),
+            job("new"),
+        ];
+        let options = BatchOptions {
+            model_dir: dir.join("no-model"),
+            output_dir: dir.clone(),
+            defaults: GenerationParams::default(),
+            resume: true,
+        };
+
+        // An earlier run rendered the existing output
+        let rendered = JobOutcome::Rendered {
+            seed: 7,
+            audio_seconds: 10.0,
+            render_seconds: 20.0,
+        };
+        let mut earlier = BatchReport {
+            model_dir: options.model_dir.clone(),
+            rendered: 0,
+            skipped: 0,
+            failed: 0,
+            entries: Vec::new(),
+        };
+        earlier.add(BatchEntry {
+            prompt: "gabber kick".into(),
+            output: dir.join("done.wav"),
+            outcome: rendered.clone(),
+        });
+        let report_path = dir.join(REPORT_FILE_NAME);
+        earlier.write(&report_path).unwrap();
+
+        // Outputs outside the folder and the missing model fail their jobs,
+        // and the report is written after each of them
+        let report = run_batch(&jobs, &options, |event| {
+            if let BatchEvent::Finished { index, .. } = event {
+                let json = std::fs::read_to_string(&report_path).unwrap();
+                let written: BatchReport = serde_json::from_str(&json).unwrap();
+                assert_eq!(written.entries.len(), index + 1);
+            }
+        })
+        .unwrap();
+        assert_eq!(report.skipped, 1);
+        assert_eq!(report.rendered, 0);
+        assert_eq!(report.failed, 3);
+        assert!(matches!(
+            &report.entries[3].outcome,
+            JobOutcome::Failed { error } if error.contains("model failed to load")
+        ));
+
+        // The seed of the earlier render survives any number of resumes
+        let skipped = JobOutcome::Skipped {
+            previous: Some(Box::new(rendered)),
+        };
+        assert_eq!(report.entries[0].outcome, skipped);
+        let report = run_batch(&jobs, &options, |_| {}).unwrap();
+        assert_eq!(report.entries[0].outcome, skipped);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
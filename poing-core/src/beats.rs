@@ -0,0 +1,173 @@
+use crate::tempo::{self, OnsetEnvelope};
+use serde::{Deserialize, Serialize};
+
+/// Resolution of the beat phase search, in onset envelope frames.
+const PHASE_STEP: f32 = 0.25;
+
+/// Beat positions of a clip with a steady tempo.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct BeatGrid {
+    pub bpm: f32,
+    pub beats_per_bar: u32,
+    /// Time in seconds of the first downbeat. Negative when bar 1 starts just
+    /// before the clip does.
+    pub first_downbeat: f32,
+}
+
+impl BeatGrid {
+    pub fn beat_seconds(&self) -> f32 {
+        60.0 / self.bpm
+    }
+
+    /// The bar line closest to `time` seconds.
+    pub fn nearest_downbeat(&self, time: f32) -> f32 {
+        let bar = self.beat_seconds() * self.beats_per_bar.max(1) as f32;
+        if bar <= 0.0 || !bar.is_finite() {
+            return time;
+        }
+        self.first_downbeat + ((time - self.first_downbeat) / bar).round() * bar
+    }
+
+    /// Every beat within `0..duration` seconds as `(time, is_downbeat)`.
+    pub fn beat_times(&self, duration: f32) -> Vec<(f32, bool)> {
+        let beat = self.beat_seconds();
+        if beat <= 0.0 || !beat.is_finite() {
+            return Vec::new();
+        }
+        let beats_per_bar = self.beats_per_bar.max(1) as i64;
+        // Index of the first beat at or after time 0, relative to the first downbeat
+        let first = (-self.first_downbeat / beat).ceil() as i64;
+        (first..)
+            .map(|index| (index, self.first_downbeat + index as f32 * beat))
+            .take_while(|(_, time)| *time < duration)
+            .map(|(index, time)| (time, index.rem_euclid(beats_per_bar) == 0))
+            .collect()
+    }
+}
+
+/// Find the beat grid of mono audio: its tempo, beat phase and which beat of
+/// the bar comes first.
+///
+/// The phase is the offset at which a comb of beat-period spaced teeth
+/// collects the most onset strength. Of the `beats_per_bar` beats in a bar,
+/// the one with the strongest low-frequency onsets (kick drums and bass notes
+/// usually land on the one) is taken as the downbeat.
+pub fn detect_beats(
+    samples: &[f32],
+    sample_rate: u32,
+    expected_bpm: Option<f32>,
+    beats_per_bar: u32,
+) -> Option<BeatGrid> {
+    let envelope = tempo::onset_envelope(samples, sample_rate);
+    let bpm = tempo::tempo_from_envelope(&envelope, expected_bpm)?;
+    let period = 60.0 * envelope.frame_rate / bpm;
+    let frames = envelope.values.len() as f32;
+
+    let beat_frames = |phase: f32| {
+        (0..)
+            .map(move |k| phase + k as f32 * period)
+            .take_while(move |frame| *frame < frames)
+    };
+    let comb = |env: &OnsetEnvelope, phase: f32| -> f32 {
+        beat_frames(phase).map(|frame| env.value_at(frame)).sum()
+    };
+
+    let steps = (period / PHASE_STEP).ceil() as usize;
+    let phase = (0..steps)
+        .map(|step| step as f32 * PHASE_STEP)
+        .max_by(|a, b| comb(&envelope, *a).total_cmp(&comb(&envelope, *b)))?;
+
+    let beats_per_bar = beats_per_bar.max(1);
+    let bass = tempo::bass_onset_envelope(samples, sample_rate);
+    let bar_strength = |offset: u32| -> f32 {
+        beat_frames(phase)
+            .skip(offset as usize)
+            .step_by(beats_per_bar as usize)
+            .map(|frame| bass.value_at(frame) + 0.5 * envelope.value_at(frame))
+            .sum()
+    };
+    let downbeat =
+        (0..beats_per_bar).max_by(|a, b| bar_strength(*a).total_cmp(&bar_strength(*b)))?;
+
+    Some(BeatGrid {
+        bpm,
+        beats_per_bar,
+        first_downbeat: envelope.frame_time(phase + downbeat as f32 * period),
+    })
+}
+
+/// Shift a clip so its first downbeat lands on sample 0: audio before the
+/// downbeat is trimmed, and silence is prepended when the downbeat lies just
+/// before the clip.
+pub fn align_to_downbeat(samples: &[f32], sample_rate: u32, grid: &BeatGrid) -> Vec<f32> {
+    let offset = (grid.first_downbeat * sample_rate as f32).round() as isize;
+    if offset >= 0 {
+        samples[(offset as usize).min(samples.len())..].to_vec()
+    } else {
+        let mut aligned = vec![0.0; offset.unsigned_abs()];
+        aligned.extend_from_slice(samples);
+        aligned
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Hi-hat ticks on every beat plus a kick on each downbeat, starting at `start` seconds.
+    fn drum_loop(bpm: f32, start: f32, seconds: f32, sample_rate: u32) -> Vec<f32> {
+        let sr = sample_rate as f32;
+        let beat = 60.0 / bpm * sr;
+        let mut seed = 7u32;
+        (0..(seconds * sr) as usize)
+            .map(|i| {
+                seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+                let noise = (seed >> 8) as f32 / (1u32 << 24) as f32 * 2.0 - 1.0;
+                let t = i as f32 - start * sr;
+                if t < 0.0 {
+                    return 0.0;
+                }
+                let index = (t / beat) as usize;
+                let since = t - index as f32 * beat;
+                let mut sample = 0.4 * noise * (-since / (0.005 * sr)).exp();
+                if index.is_multiple_of(4) {
+                    let phase = 2.0 * std::f32::consts::PI * 55.0 * since / sr;
+                    sample += phase.sin() * (-since / (0.15 * sr)).exp();
+                }
+                sample
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_finds_late_first_downbeat() {
+        let audio = drum_loop(130.0, 0.3, 10.0, 32000);
+        let grid = detect_beats(&audio, 32000, Some(130.0), 4).unwrap();
+        assert!((grid.bpm - 130.0).abs() < 1.0, "{}", grid.bpm);
+        assert!(
+            (grid.first_downbeat - 0.3).abs() < 0.015,
+            "{}",
+            grid.first_downbeat
+        );
+
+        let aligned = align_to_downbeat(&audio, 32000, &grid);
+        let kick_start = aligned.iter().position(|s| s.abs() > 0.01).unwrap();
+        assert!(kick_start < 480, "{}", kick_start);
+    }
+
+    #[test]
+    fn test_beat_times_marks_downbeats() {
+        let grid = BeatGrid {
+            bpm: 120.0,
+            beats_per_bar: 4,
+            first_downbeat: -0.25,
+        };
+        let beats = grid.beat_times(2.5);
+        assert_eq!(beats.len(), 5);
+        assert!((beats[0].0 - 0.25).abs() < 1e-6);
+        assert_eq!(
+            beats.iter().map(|b| b.1).collect::<Vec<_>>(),
+            [false, false, false, true, false]
+        );
+    }
+}
@@ -0,0 +1,304 @@
+use crate::config::{self, PoingConfig};
+use crate::metadata::model_name;
+use crate::model::{self, Quantization};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Folder levels searched below a model root.
+pub const MAX_SCAN_DEPTH: usize = 6;
+
+/// Graph the precision of a model is read from.
+const DECODER_FILE: &str = "decoder_model_merged.onnx";
+
+/// What the config keeps about one model directory.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ModelDetails {
+    /// Shown instead of the folder name.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Total size of the files in bytes, when last scanned.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// Precision of the decoder, detected again when `size` changes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quantization: Option<Quantization>,
+    /// Left out of the list although found below a model root.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub hidden: bool,
+}
+
+/// A model directory as offered for generation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelEntry {
+    pub path: PathBuf,
+    pub details: ModelDetails,
+}
+
+impl ModelEntry {
+    /// The user-given name, or the folder name.
+    pub fn name(&self) -> String {
+        self.details
+            .name
+            .clone()
+            .filter(|name| !name.trim().is_empty())
+            .unwrap_or_else(|| model_name(&self.path))
+    }
+
+    /// Precision and size, e.g. "int8, 412 MB", or empty before a scan.
+    pub fn summary(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(quantization) = self.details.quantization {
+            parts.push(quantization.name().to_string());
+        }
+        if let Some(size) = self.details.size {
+            parts.push(format_size(size));
+        }
+        parts.join(", ")
+    }
+
+    /// Name followed by the summary in parentheses.
+    pub fn label(&self) -> String {
+        let summary = self.summary();
+        if summary.is_empty() {
+            self.name()
+        } else {
+            format!("{} ({})", self.name(), summary)
+        }
+    }
+}
+
+/// The configured model directories followed by those found under the
+/// model roots and not hidden, each once.
+pub fn model_dirs(config: &PoingConfig) -> Vec<PathBuf> {
+    let mut dirs = config.model_paths.clone();
+    for root in &config.model_roots {
+        for dir in discover(root) {
+            let hidden = config
+                .models
+                .get(&dir)
+                .is_some_and(|details| details.hidden);
+            if !hidden && !dirs.contains(&dir) {
+                dirs.push(dir);
+            }
+        }
+    }
+    dirs
+}
+
+/// [`model_dirs`] with the details stored for each.
+pub fn entries(config: &PoingConfig) -> Vec<ModelEntry> {
+    with_details(config, model_dirs(config))
+}
+
+/// `dirs` with the details stored for each, without searching the roots.
+pub fn with_details(config: &PoingConfig, dirs: Vec<PathBuf>) -> Vec<ModelEntry> {
+    dirs.into_iter()
+        .map(|path| ModelEntry {
+            details: config.models.get(&path).cloned().unwrap_or_default(),
+            path,
+        })
+        .collect()
+}
+
+/// Offer the model in `dir`, showing it again if it was hidden.
+pub fn add(dir: &Path) -> Result<PoingConfig, Box<dyn std::error::Error>> {
+    config::update_config(|config| {
+        if !config.model_paths.iter().any(|path| path == dir) {
+            config.model_paths.push(dir.to_path_buf());
+        }
+        if let Some(details) = config.models.get_mut(dir) {
+            details.hidden = false;
+        }
+    })
+}
+
+/// Stop offering the model in `dir`: forget it when it was added by path
+/// and hide it when it lies below a model root.
+pub fn remove(dir: &Path) -> Result<PoingConfig, Box<dyn std::error::Error>> {
+    config::update_config(|config| {
+        config.model_paths.retain(|path| path != dir);
+        if config.model_roots.iter().any(|root| dir.starts_with(root)) {
+            config.models.entry(dir.to_path_buf()).or_default().hidden = true;
+        }
+    })
+}
+
+/// Every complete model directory at or below `root`, sorted. Hidden
+/// folders and those inside a model directory are not searched.
+pub fn discover(root: &Path) -> Vec<PathBuf> {
+    let mut found = Vec::new();
+    discover_into(root, 0, &mut found);
+    found.sort();
+    found
+}
+
+fn discover_into(dir: &Path, depth: usize, found: &mut Vec<PathBuf>) {
+    if config::validate_model_dir(dir) {
+        found.push(dir.to_path_buf());
+        return;
+    }
+    if depth >= MAX_SCAN_DEPTH {
+        return;
+    }
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let hidden = entry.file_name().to_string_lossy().starts_with('.');
+        if !hidden && entry.file_type().is_ok_and(|kind| kind.is_dir()) {
+            discover_into(&entry.path(), depth + 1, found);
+        }
+    }
+}
+
+/// Measure `dir` and, when its size changed since `previous`, detect the
+/// precision of its decoder again.
+pub fn scan(dir: &Path, previous: &ModelDetails) -> ModelDetails {
+    let size = dir_size(dir);
+    let quantization = if previous.size == Some(size) && previous.quantization.is_some() {
+        previous.quantization
+    } else {
+        model::detect_quantization(&dir.join(DECODER_FILE)).ok()
+    };
+    ModelDetails {
+        size: Some(size),
+        quantization,
+        ..previous.clone()
+    }
+}
+
+/// Scan every model directory and store sizes and precisions that changed,
+/// keeping names and descriptions edited in the meantime.
+pub fn refresh_details() -> Result<PoingConfig, Box<dyn std::error::Error>> {
+    let config = config::try_load_config()?;
+    let scanned: Vec<ModelEntry> = entries(&config)
+        .into_iter()
+        .map(|entry| ModelEntry {
+            details: scan(&entry.path, &entry.details),
+            path: entry.path,
+        })
+        .collect();
+    let changed = scanned
+        .iter()
+        .any(|entry| config.models.get(&entry.path) != Some(&entry.details));
+    if !changed {
+        return Ok(config);
+    }
+    config::update_config(|config| {
+        for entry in scanned {
+            let details = config.models.entry(entry.path).or_default();
+            details.size = entry.details.size;
+            details.quantization = entry.details.quantization;
+        }
+    })
+}
+
+/// Change the name and description of the model in `dir`; empty text
+/// clears them.
+pub fn describe(
+    dir: &Path,
+    name: &str,
+    description: &str,
+) -> Result<PoingConfig, Box<dyn std::error::Error>> {
+    let text = |text: &str| Some(text.trim().to_string()).filter(|text| !text.is_empty());
+    config::update_config(|config| {
+        let details = config.models.entry(dir.to_path_buf()).or_default();
+        details.name = text(name);
+        details.description = text(description);
+    })
+}
+
+/// Total size of the files below `dir`.
+pub fn dir_size(dir: &Path) -> u64 {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return 0;
+    };
+    entries
+        .flatten()
+        .map(|entry| match entry.file_type() {
+            Ok(kind) if kind.is_dir() => dir_size(&entry.path()),
+            Ok(_) => entry.metadata().map_or(0, |metadata| metadata.len()),
+            Err(_) => 0,
+        })
+        .sum()
+}
+
+/// Size in the largest unit below 1000 of it, e.g. "412 MB" or "1.6 GB".
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1000.0 && unit < UNITS.len() - 1 {
+        size /= 1000.0;
+        unit += 1;
+    }
+    if unit > 0 && size < 10.0 {
+        format!("{:.1} {}", size, UNITS[unit])
+    } else {
+        format!("{:.0} {}", size, UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_discovers_models_below_roots() {
+        let root = std::env::temp_dir().join(format!("poing_catalog_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let small = root.join("musicgen/small");
+        let medium = root.join("musicgen/medium-fp16");
+        let incomplete = root.join("incomplete");
+        let hidden = root.join(".trash/old");
+        for dir in [&small, &medium, &incomplete, &hidden] {
+            fs::create_dir_all(dir).unwrap();
+            fs::write(dir.join("tokenizer.json"), "{}").unwrap();
+        }
+        for dir in [&small, &medium, &hidden] {
+            for file in ["text_encoder.onnx", DECODER_FILE, "encodec_decode.onnx"] {
+                fs::write(dir.join(file), [0u8; 100]).unwrap();
+            }
+        }
+
+        assert_eq!(discover(&root), [medium.clone(), small.clone()]);
+
+        let mut config = PoingConfig {
+            model_paths: vec![small.clone()],
+            model_roots: vec![root.clone()],
+            ..PoingConfig::default()
+        };
+        config.models.insert(
+            medium.clone(),
+            ModelDetails {
+                name: Some("MusicGen medium".into()),
+                size: Some(1_600_000_000),
+                quantization: Some(Quantization::Fp16),
+                ..ModelDetails::default()
+            },
+        );
+        let entries = entries(&config);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].label(), "small");
+        assert_eq!(entries[1].label(), "MusicGen medium (fp16, 1.6 GB)");
+
+        let details = scan(&small, &ModelDetails::default());
+        assert_eq!(details.size, Some(302));
+
+        // A hidden model stays listed only when added by path
+        config.models.entry(small.clone()).or_default().hidden = true;
+        config.models.get_mut(&medium).unwrap().hidden = true;
+        assert_eq!(model_dirs(&config), [small]);
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(412_300_000), "412 MB");
+        assert_eq!(format_size(1_634_000_000), "1.6 GB");
+    }
+}
@@ -1,12 +1,85 @@
+use crate::catalog::ModelDetails;
+use crate::prompts::{self, PromptTemplate};
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+/// Schema version written by this build. Raising it needs a step in [`MIGRATIONS`].
+pub const CONFIG_VERSION: u32 = 2;
+
+/// How often [`watch_config`] looks at the file.
+pub const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Steps bringing a config up one version; entry `n` turns version `n + 1`
+/// into `n + 2`. Files without a `version` field are version 1.
+const MIGRATIONS: &[fn(&mut Map<String, Value>)] = &[migrate_unversioned];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
 pub struct PoingConfig {
+    /// Schema version, [`CONFIG_VERSION`] once read or written.
+    #[serde(default)]
+    pub version: u32,
+    #[serde(default)]
     pub model_paths: Vec<PathBuf>,
+    /// Folders searched for model directories besides `model_paths`.
+    #[serde(default)]
+    pub model_roots: Vec<PathBuf>,
+    /// Names, descriptions and scanned details of model directories.
+    #[serde(default)]
+    pub models: BTreeMap<PathBuf, ModelDetails>,
+    /// UDP port of the plugin's OSC remote control listener; disabled when unset.
+    #[serde(default)]
+    pub osc_port: Option<u16>,
+    /// Folder every finished take is saved to; [`default_library_dir`] when unset.
+    #[serde(default)]
+    pub library_dir: Option<PathBuf>,
+    /// Prompt layouts offered in the editor; [`prompts::default_templates`] when empty.
+    #[serde(default)]
+    pub prompt_templates: Vec<PromptTemplate>,
+    /// Genre and instrument chips; the built-in lists when empty.
+    #[serde(default)]
+    pub genre_tags: Vec<String>,
+    #[serde(default)]
+    pub instrument_tags: Vec<String>,
+    /// Prompts generated from, most recent first.
+    #[serde(default)]
+    pub prompt_history: Vec<String>,
+}
+
+impl PoingConfig {
+    pub fn templates(&self) -> Vec<PromptTemplate> {
+        if self.prompt_templates.is_empty() {
+            prompts::default_templates()
+        } else {
+            self.prompt_templates.clone()
+        }
+    }
+
+    pub fn genres(&self) -> Vec<String> {
+        or_defaults(&self.genre_tags, prompts::DEFAULT_GENRES)
+    }
+
+    pub fn instruments(&self) -> Vec<String> {
+        or_defaults(&self.instrument_tags, prompts::DEFAULT_INSTRUMENTS)
+    }
+}
+
+fn or_defaults(tags: &[String], defaults: &[&str]) -> Vec<String> {
+    if tags.is_empty() {
+        defaults.iter().map(|tag| tag.to_string()).collect()
+    } else {
+        tags.to_vec()
+    }
 }
 
-const REQUIRED_MODEL_FILES: &[&str] = &[
+/// Files every model directory must contain.
+pub const REQUIRED_MODEL_FILES: &[&str] = &[
     "text_encoder.onnx",
     "decoder_model_merged.onnx",
     "encodec_decode.onnx",
@@ -20,26 +93,295 @@ pub fn config_path() -> PathBuf {
     path
 }
 
+/// Where the generation library lives unless `library_dir` is configured.
+pub fn default_library_dir() -> PathBuf {
+    let mut path = dirs::data_dir().unwrap_or_else(|| PathBuf::from("."));
+    path.push("poing");
+    path.push("library");
+    path
+}
+
+/// Where installed models go unless another folder is given.
+pub fn default_models_dir() -> PathBuf {
+    let mut path = dirs::data_dir().unwrap_or_else(|| PathBuf::from("."));
+    path.push("poing");
+    path.push("models");
+    path
+}
+
+/// Read the config, reporting a file that cannot be parsed or was written
+/// by a newer Poing. A missing file gives the defaults.
+pub fn try_load_config() -> Result<PoingConfig, Box<dyn std::error::Error>> {
+    read_config(&config_path())
+}
+
+/// Read the config, falling back to the defaults when it cannot be read.
 pub fn load_config() -> PoingConfig {
-    let path = config_path();
-    match std::fs::read_to_string(&path) {
-        Ok(contents) => serde_json::from_str(&contents).unwrap_or_default(),
-        Err(_) => PoingConfig::default(),
+    try_load_config().unwrap_or_else(|e| {
+        eprintln!("[poing] Failed to read the config, using defaults: {}", e);
+        PoingConfig::default()
+    })
+}
+
+/// Change the config on disk, and return the result.
+///
+/// The file is re-read under a lock so changes other instances saved in the
+/// meantime are kept, then replaced in one rename. A file that cannot be read
+/// is left alone rather than overwritten with defaults.
+pub fn update_config(
+    change: impl FnOnce(&mut PoingConfig),
+) -> Result<PoingConfig, Box<dyn std::error::Error>> {
+    update_config_at(&config_path(), change)
+}
+
+/// Put `prompt` at the front of the saved prompt history.
+pub fn record_prompt(prompt: &str) -> Result<(), Box<dyn std::error::Error>> {
+    update_config(|config| prompts::record_history(&mut config.prompt_history, prompt))?;
+    Ok(())
+}
+
+/// Background thread polling the config file. Stops when dropped.
+pub struct ConfigWatcher {
+    stop: Arc<AtomicBool>,
+}
+
+impl Drop for ConfigWatcher {
+    fn drop(&mut self) {
+        // Not joined: the thread sees the flag within one interval
+        self.stop.store(true, Ordering::Relaxed);
     }
 }
 
-pub fn save_config(config: &PoingConfig) {
-    let path = config_path();
+/// Call `on_change` from a background thread with the config re-read
+/// whenever the file changes, until it returns `false` or the returned
+/// watcher is dropped.
+pub fn watch_config<F>(on_change: F) -> ConfigWatcher
+where
+    F: FnMut(Result<PoingConfig, String>) -> bool + Send + 'static,
+{
+    watch_file(config_path(), WATCH_INTERVAL, on_change)
+}
+
+fn read_config(path: &Path) -> Result<PoingConfig, Box<dyn std::error::Error>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(PoingConfig::default()),
+        Err(e) => return Err(format!("{}: {}", path.display(), e).into()),
+    };
+    parse_config(&contents).map_err(|e| format!("{}: {}", path.display(), e).into())
+}
+
+/// Parse a config of any known version, migrating it to [`CONFIG_VERSION`].
+fn parse_config(contents: &str) -> Result<PoingConfig, Box<dyn std::error::Error>> {
+    let mut value: Value = serde_json::from_str(contents)?;
+    let Some(fields) = value.as_object_mut() else {
+        return Err("expected a JSON object".into());
+    };
+    let version = match fields.get("version") {
+        None => 1,
+        Some(version) => version
+            .as_u64()
+            .filter(|&version| version >= 1)
+            .ok_or("invalid version")? as u32,
+    };
+    if version > CONFIG_VERSION {
+        return Err(format!(
+            "written by a newer Poing (version {}, this one reads up to {})",
+            version, CONFIG_VERSION
+        )
+        .into());
+    }
+    for migrate in &MIGRATIONS[version as usize - 1..] {
+        migrate(fields);
+    }
+    fields.insert("version".into(), CONFIG_VERSION.into());
+    Ok(serde_json::from_value(value)?)
+}
+
+/// Version 1 to 2: fields set to `null` by hand take their defaults, and
+/// model folders listed twice are kept once.
+fn migrate_unversioned(fields: &mut Map<String, Value>) {
+    fields.retain(|_, value| !value.is_null());
+    if let Some(Value::Array(paths)) = fields.get_mut("model_paths") {
+        let mut seen = Vec::new();
+        paths.retain(|path| {
+            let first = !seen.contains(path);
+            seen.push(path.clone());
+            first
+        });
+    }
+}
+
+fn update_config_at(
+    path: &Path,
+    change: impl FnOnce(&mut PoingConfig),
+) -> Result<PoingConfig, Box<dyn std::error::Error>> {
     if let Some(parent) = path.parent() {
-        let _ = std::fs::create_dir_all(parent);
+        fs::create_dir_all(parent)?;
     }
-    if let Ok(json) = serde_json::to_string_pretty(config) {
-        let _ = std::fs::write(&path, json);
+    let _lock = lock(path)?;
+    let mut config = read_config(path)?;
+    change(&mut config);
+    config.version = CONFIG_VERSION;
+    write_atomically(path, serde_json::to_string_pretty(&config)?.as_bytes())?;
+    Ok(config)
+}
+
+/// Hold an exclusive lock on the file next to `path` until the result is
+/// dropped.
+fn lock(path: &Path) -> io::Result<File> {
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path.with_extension("lock"))?;
+    file.lock()?;
+    Ok(file)
+}
+
+/// Write `contents` to a temp file beside `path` and rename it over `path`,
+/// so readers see either the old or the new file, never a partial one.
+fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let temp = path.with_extension(format!("tmp{}", std::process::id()));
+    let result = File::create(&temp).and_then(|mut file| {
+        file.write_all(contents)?;
+        file.sync_all()
+    });
+    match result.and_then(|()| fs::rename(&temp, path)) {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            let _ = fs::remove_file(&temp);
+            Err(e)
+        }
     }
 }
 
+fn watch_file<F>(path: PathBuf, interval: Duration, mut on_change: F) -> ConfigWatcher
+where
+    F: FnMut(Result<PoingConfig, String>) -> bool + Send + 'static,
+{
+    // Modification time and length; a rename also changes the length in
+    // nearly every case where the time resolution is too coarse.
+    let stamp = move |path: &Path| -> Option<(SystemTime, u64)> {
+        let metadata = fs::metadata(path).ok()?;
+        Some((metadata.modified().ok()?, metadata.len()))
+    };
+    // Taken before spawning so writes made while the thread starts are seen
+    let mut last = stamp(&path);
+    let stop = Arc::new(AtomicBool::new(false));
+    let thread_stop = stop.clone();
+    std::thread::spawn(move || loop {
+        std::thread::sleep(interval);
+        if thread_stop.load(Ordering::Relaxed) {
+            break;
+        }
+        let current = stamp(&path);
+        if current == last {
+            continue;
+        }
+        last = current;
+        if !on_change(read_config(&path).map_err(|e| e.to_string())) {
+            break;
+        }
+    });
+    ConfigWatcher { stop }
+}
+
 pub fn validate_model_dir(path: &Path) -> bool {
+    missing_model_files(path).is_empty()
+}
+
+/// Required model files that are absent from `path`.
+pub fn missing_model_files(path: &Path) -> Vec<&'static str> {
     REQUIRED_MODEL_FILES
         .iter()
-        .all(|file| path.join(file).exists())
+        .copied()
+        .filter(|file| !path.join(file).exists())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_migrates_and_rejects_newer_versions() {
+        let config = parse_config(
+            r#"{"model_paths": ["/models/a", "/models/b", "/models/a"], "osc_port": null, "genre_tags": null}"#,
+        )
+        .unwrap();
+        assert_eq!(config.version, CONFIG_VERSION);
+        assert_eq!(
+            config.model_paths,
+            [PathBuf::from("/models/a"), PathBuf::from("/models/b")]
+        );
+        assert_eq!(config.osc_port, None);
+        assert!(config.genre_tags.is_empty());
+        let cleared = parse_config(r#"{"model_paths": null, "library_dir": null}"#).unwrap();
+        assert!(cleared.model_paths.is_empty());
+        assert_eq!(cleared.library_dir, None);
+
+        let current = format!(r#"{{"version": {}, "model_paths": []}}"#, CONFIG_VERSION);
+        assert_eq!(
+            parse_config(&current).unwrap(),
+            PoingConfig {
+                version: CONFIG_VERSION,
+                ..PoingConfig::default()
+            }
+        );
+
+        let newer = format!(
+            r#"{{"version": {}, "model_paths": []}}"#,
+            CONFIG_VERSION + 1
+        );
+        assert!(parse_config(&newer)
+            .unwrap_err()
+            .to_string()
+            .contains("newer"));
+        assert!(parse_config("{\"model_paths\": [").is_err());
+    }
+
+    #[test]
+    fn test_updates_merge_and_are_watched() {
+        let dir = std::env::temp_dir().join(format!("poing_config_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let path = dir.join("config.json");
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        let watcher = watch_file(path.clone(), Duration::from_millis(10), move |config| {
+            tx.send(config).is_ok()
+        });
+
+        // Two instances each change a different field
+        update_config_at(&path, |config| config.model_paths.push("/models/a".into())).unwrap();
+        update_config_at(&path, |config| config.prompt_history.push("kick".into())).unwrap();
+        let config = read_config(&path).unwrap();
+        assert_eq!(config.model_paths, [PathBuf::from("/models/a")]);
+        assert_eq!(config.prompt_history, ["kick"]);
+        let watched = rx.recv_timeout(Duration::from_secs(5)).unwrap().unwrap();
+        assert_eq!(watched.model_paths, [PathBuf::from("/models/a")]);
+
+        // A broken file is reported and left in place
+        fs::write(&path, "{ not json").unwrap();
+        assert!(update_config_at(&path, |config| config.osc_port = Some(9000)).is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{ not json");
+        let error = loop {
+            match rx.recv_timeout(Duration::from_secs(5)).unwrap() {
+                Ok(_) => continue,
+                Err(e) => break e,
+            }
+        };
+        assert!(error.contains("config.json"));
+
+        // Dropping the watcher ends its thread, which drops the sender
+        drop(watcher);
+        let ended = loop {
+            match rx.recv_timeout(Duration::from_secs(5)) {
+                Ok(_) => continue,
+                Err(e) => break e,
+            }
+        };
+        assert_eq!(ended, std::sync::mpsc::RecvTimeoutError::Disconnected);
+        let _ = fs::remove_dir_all(&dir);
+    }
 }
@@ -0,0 +1,247 @@
+use crate::{flac, resample, wav};
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+/// File format of exported audio.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExportFormat {
+    Wav16,
+    #[default]
+    Wav24,
+    WavFloat,
+    Flac16,
+    Flac24,
+}
+
+impl ExportFormat {
+    pub const ALL: [ExportFormat; 5] = [
+        ExportFormat::Wav24,
+        ExportFormat::Wav16,
+        ExportFormat::WavFloat,
+        ExportFormat::Flac24,
+        ExportFormat::Flac16,
+    ];
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Wav16 | ExportFormat::Wav24 | ExportFormat::WavFloat => "wav",
+            ExportFormat::Flac16 | ExportFormat::Flac24 => "flac",
+        }
+    }
+
+    /// Bit depth of integer formats, `None` for float.
+    pub fn bits(self) -> Option<u32> {
+        match self {
+            ExportFormat::Wav16 | ExportFormat::Flac16 => Some(16),
+            ExportFormat::Wav24 | ExportFormat::Flac24 => Some(24),
+            ExportFormat::WavFloat => None,
+        }
+    }
+
+    /// The format to write `path` in: `preferred` if its extension matches,
+    /// otherwise the 24-bit format for the extension. `None` for unknown
+    /// extensions.
+    pub fn for_path(path: &Path, preferred: ExportFormat) -> Option<ExportFormat> {
+        let extension = path.extension()?.to_string_lossy().to_ascii_lowercase();
+        if extension == preferred.extension() {
+            return Some(preferred);
+        }
+        match extension.as_str() {
+            "wav" => Some(ExportFormat::Wav24),
+            "flac" => Some(ExportFormat::Flac24),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ExportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ExportFormat::Wav16 => "WAV 16-bit",
+            ExportFormat::Wav24 => "WAV 24-bit",
+            ExportFormat::WavFloat => "WAV 32-bit float",
+            ExportFormat::Flac16 => "FLAC 16-bit",
+            ExportFormat::Flac24 => "FLAC 24-bit",
+        })
+    }
+}
+
+/// Parses the short names "wav16", "wav24", "float", "flac16" and "flac24"
+/// ("wav" and "flac" are 24-bit).
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "wav16" => Ok(ExportFormat::Wav16),
+            "wav" | "wav24" => Ok(ExportFormat::Wav24),
+            "float" | "wav32" => Ok(ExportFormat::WavFloat),
+            "flac16" => Ok(ExportFormat::Flac16),
+            "flac" | "flac24" => Ok(ExportFormat::Flac24),
+            _ => Err(format!(
+                "unknown format {:?} (expected wav16, wav24, float, flac16 or flac24)",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ExportOptions {
+    pub format: ExportFormat,
+    /// Resample to this rate before writing. Keeps the source rate when unset.
+    pub sample_rate: Option<u32>,
+}
+
+impl ExportOptions {
+    pub fn output_rate(&self, source_rate: u32) -> u32 {
+        self.sample_rate.unwrap_or(source_rate)
+    }
+}
+
+/// Write mono f32 samples to `path` in the given format.
+pub fn export(
+    samples: &[f32],
+    sample_rate: u32,
+    path: &Path,
+    options: &ExportOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let rate = options.output_rate(sample_rate);
+    let resampled;
+    let samples = if rate != sample_rate {
+        resampled = resample::resample(samples, sample_rate, rate);
+        &resampled[..]
+    } else {
+        samples
+    };
+
+    let Some(bits) = options.format.bits() else {
+        return wav::write_wav(samples, rate, path);
+    };
+    let quantized = quantize(samples, bits);
+    match options.format {
+        ExportFormat::Flac16 | ExportFormat::Flac24 => {
+            std::fs::write(path, flac::encode(&quantized, rate, bits))?;
+        }
+        _ => {
+            let spec = hound::WavSpec {
+                channels: 1,
+                sample_rate: rate,
+                bits_per_sample: bits as u16,
+                sample_format: hound::SampleFormat::Int,
+            };
+            let mut writer = hound::WavWriter::create(path, spec)?;
+            for sample in quantized {
+                writer.write_sample(sample)?;
+            }
+            writer.finalize()?;
+        }
+    }
+    Ok(())
+}
+
+/// Convert to `bits`-bit integers with TPDF dither: the sum of two uniform
+/// random values of ±½ LSB is added before rounding, which turns
+/// quantization distortion into a constant, signal-independent noise floor.
+pub fn quantize(samples: &[f32], bits: u32) -> Vec<i32> {
+    let scale = (1i64 << (bits - 1)) as f64;
+    let (min, max) = (-scale, scale - 1.0);
+    // Fixed seed, so exporting the same take twice gives identical files
+    let mut state = 0x9E37_79B9_7F4A_7C15u64;
+    let mut uniform = move || {
+        // xorshift64*
+        state ^= state >> 12;
+        state ^= state << 25;
+        state ^= state >> 27;
+        (state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 11) as f64 / (1u64 << 53) as f64 - 0.5
+    };
+    samples
+        .iter()
+        .map(|&s| {
+            let dither = uniform() + uniform();
+            (s as f64 * scale + dither).round().clamp(min, max) as i32
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dither_decorrelates_quantization_error() {
+        // A sine far below 1 LSB vanishes without dither but survives on average with it
+        let quiet: Vec<f32> = (0..32000)
+            .map(|i| 0.3 / 32768.0 * (i as f32 * 0.01).sin())
+            .collect();
+        let quantized = quantize(&quiet, 16);
+        assert!(quantized.iter().all(|s| s.abs() <= 2));
+        let correlation: f64 = quantized
+            .iter()
+            .zip(&quiet)
+            .map(|(q, s)| *q as f64 * *s as f64)
+            .sum();
+        assert!(correlation > 0.0);
+
+        let loud = quantize(&[1.0, -1.0, 0.5], 24);
+        assert_eq!(loud[0], (1 << 23) - 1);
+        assert_eq!(loud[1], -(1 << 23));
+        assert!((loud[2] - (1 << 22)).abs() <= 1);
+    }
+
+    #[test]
+    fn test_export_formats() {
+        let dir = std::env::temp_dir().join(format!("poing_export_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let samples: Vec<f32> = (0..32000).map(|i| (i as f32 * 0.05).sin() * 0.5).collect();
+
+        for format in ExportFormat::ALL {
+            let path = dir.join(format!("take.{}", format.extension()));
+            let options = ExportOptions {
+                format,
+                sample_rate: Some(48000),
+            };
+            export(&samples, 32000, &path, &options).unwrap();
+            let (rate, len, bits) = if format.extension() == "flac" {
+                let reader = claxon::FlacReader::open(&path).unwrap();
+                let info = reader.streaminfo();
+                (
+                    info.sample_rate,
+                    info.samples.unwrap(),
+                    info.bits_per_sample,
+                )
+            } else {
+                let reader = hound::WavReader::open(&path).unwrap();
+                let spec = reader.spec();
+                (
+                    spec.sample_rate,
+                    reader.len() as u64,
+                    spec.bits_per_sample as u32,
+                )
+            };
+            assert_eq!(rate, 48000, "{}", format);
+            assert_eq!(len, 48000, "{}", format);
+            assert_eq!(bits, format.bits().unwrap_or(32), "{}", format);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_format_for_path() {
+        let flac = Path::new("take.FLAC");
+        assert_eq!(
+            ExportFormat::for_path(flac, ExportFormat::Wav16),
+            Some(ExportFormat::Flac24)
+        );
+        assert_eq!(
+            ExportFormat::for_path(flac, ExportFormat::Flac16),
+            Some(ExportFormat::Flac16)
+        );
+        assert_eq!(
+            ExportFormat::for_path(Path::new("take.mp3"), ExportFormat::Wav16),
+            None
+        );
+        assert_eq!("float".parse(), Ok(ExportFormat::WavFloat));
+    }
+}
@@ -0,0 +1,321 @@
+use md5::{Digest, Md5};
+use std::path::Path;
+
+/// Samples per frame, the block size the reference encoder uses by default.
+const BLOCK_SIZE: usize = 4096;
+/// Highest fixed predictor order defined by the format.
+const MAX_FIXED_ORDER: usize = 4;
+/// Rice parameters above this need the escape code, which we never emit.
+const MAX_RICE_PARAM: u32 = 14;
+
+/// Encode mono integer samples of `bits_per_sample` bits (16 or 24) as a FLAC
+/// stream.
+///
+/// Every frame picks whichever of the fixed polynomial predictors codes it in
+/// the fewest bits, falling back to verbatim samples for noise. This trades a
+/// little compression against LPC for a much simpler encoder.
+pub fn encode(samples: &[i32], sample_rate: u32, bits_per_sample: u32) -> Vec<u8> {
+    let mut frames = Vec::new();
+    let mut min_frame = u32::MAX;
+    let mut max_frame = 0;
+    for (number, block) in samples.chunks(BLOCK_SIZE).enumerate() {
+        let frame = encode_frame(block, number as u64, bits_per_sample);
+        min_frame = min_frame.min(frame.len() as u32);
+        max_frame = max_frame.max(frame.len() as u32);
+        frames.extend_from_slice(&frame);
+    }
+    if samples.is_empty() {
+        min_frame = 0;
+    }
+
+    let mut md5 = Md5::new();
+    let bytes_per_sample = bits_per_sample as usize / 8;
+    for &sample in samples {
+        md5.update(&sample.to_le_bytes()[..bytes_per_sample]);
+    }
+
+    let mut info = BitWriter::default();
+    // A single final block may be shorter, which the minimum block size
+    // doesn't need to account for
+    info.write(BLOCK_SIZE as u64, 16);
+    info.write(BLOCK_SIZE as u64, 16);
+    info.write(min_frame as u64, 24);
+    info.write(max_frame as u64, 24);
+    info.write(sample_rate as u64, 20);
+    info.write(0, 3); // channels - 1
+    info.write(bits_per_sample as u64 - 1, 5);
+    info.write(samples.len() as u64, 36);
+    let mut info = info.into_bytes();
+    info.extend_from_slice(&md5.finalize());
+
+    let mut stream = b"fLaC".to_vec();
+    // Last-metadata-block flag and STREAMINFO type, then the 24-bit length
+    stream.push(0x80);
+    stream.extend_from_slice(&(info.len() as u32).to_be_bytes()[1..]);
+    stream.extend_from_slice(&info);
+    stream.extend_from_slice(&frames);
+    stream
+}
+
+/// Decode a FLAC file and mix it down to mono f32 samples, returning them with the file's sample rate.
+pub fn read_flac(path: &Path) -> Result<(Vec<f32>, u32), Box<dyn std::error::Error>> {
+    let mut reader = claxon::FlacReader::open(path)?;
+    let info = reader.streaminfo();
+    let scale = 1.0 / (1u64 << (info.bits_per_sample - 1)) as f32;
+    let channels = info.channels.max(1) as usize;
+    let interleaved: Vec<i32> = reader.samples().collect::<Result<_, _>>()?;
+    let mono = interleaved
+        .chunks(channels)
+        .map(|frame| frame.iter().map(|&s| s as f32 * scale).sum::<f32>() / channels as f32)
+        .collect();
+    Ok((mono, info.sample_rate))
+}
+
+fn encode_frame(block: &[i32], number: u64, bits_per_sample: u32) -> Vec<u8> {
+    let mut frame = BitWriter::default();
+    frame.write(0xFFF8, 16); // sync code, fixed block size
+    let block_size_code = if block.len() == BLOCK_SIZE {
+        0b1100
+    } else {
+        0b0111
+    };
+    frame.write(block_size_code, 4);
+    frame.write(0, 4); // sample rate from STREAMINFO
+    frame.write(0, 4); // mono
+    let sample_size_code = match bits_per_sample {
+        16 => 0b100,
+        24 => 0b110,
+        _ => 0b000,
+    };
+    frame.write(sample_size_code, 3);
+    frame.write(0, 1);
+    frame.write_utf8(number);
+    if block_size_code == 0b0111 {
+        frame.write(block.len() as u64 - 1, 16);
+    }
+    let crc = crc8(&frame.bytes);
+    frame.write(crc as u64, 8);
+
+    write_subframe(&mut frame, block, bits_per_sample);
+    let mut bytes = frame.into_bytes();
+    let crc = crc16(&bytes);
+    bytes.extend_from_slice(&crc.to_be_bytes());
+    bytes
+}
+
+fn write_subframe(out: &mut BitWriter, block: &[i32], bits_per_sample: u32) {
+    let bps = bits_per_sample as usize;
+    if block.iter().all(|&s| s == block[0]) {
+        out.write(0, 8); // padding bit, CONSTANT, no wasted bits
+        out.write_signed(block[0] as i64, bps);
+        return;
+    }
+
+    let verbatim_bits = block.len() * bps;
+    let best = (0..=MAX_FIXED_ORDER.min(block.len() - 1))
+        .map(|order| {
+            let residual = fixed_residual(block, order);
+            let param = rice_parameter(&residual);
+            let bits = order * bps + 10 + rice_bits(&residual, param);
+            (order, residual, param, bits)
+        })
+        .min_by_key(|(.., bits)| *bits);
+
+    match best {
+        Some((order, residual, param, bits)) if bits < verbatim_bits => {
+            out.write(0b0_001000 | order as u64, 7);
+            out.write(0, 1);
+            for &warmup in &block[..order] {
+                out.write_signed(warmup as i64, bps);
+            }
+            out.write(0b00, 2); // 4-bit Rice parameters
+            out.write(0, 4); // partition order 0: one partition
+            out.write(param as u64, 4);
+            for &r in &residual {
+                let folded = fold(r);
+                let quotient = folded >> param;
+                out.write_unary(quotient);
+                out.write(folded & ((1 << param) - 1), param as usize);
+            }
+        }
+        _ => {
+            out.write(0b0_000001, 7);
+            out.write(0, 1);
+            for &sample in block {
+                out.write_signed(sample as i64, bps);
+            }
+        }
+    }
+}
+
+/// Residual of the fixed polynomial predictor of `order` after its warm-up samples.
+fn fixed_residual(block: &[i32], order: usize) -> Vec<i64> {
+    let s = |i: usize| block[i] as i64;
+    (order..block.len())
+        .map(|i| match order {
+            0 => s(i),
+            1 => s(i) - s(i - 1),
+            2 => s(i) - 2 * s(i - 1) + s(i - 2),
+            3 => s(i) - 3 * s(i - 1) + 3 * s(i - 2) - s(i - 3),
+            _ => s(i) - 4 * s(i - 1) + 6 * s(i - 2) - 4 * s(i - 3) + s(i - 4),
+        })
+        .collect()
+}
+
+/// Map signed residuals to unsigned: 0, -1, 1, -2, ... -> 0, 1, 2, 3, ...
+fn fold(residual: i64) -> u64 {
+    ((residual << 1) ^ (residual >> 63)) as u64
+}
+
+/// Rice parameter estimated from the mean folded residual.
+fn rice_parameter(residual: &[i64]) -> u32 {
+    if residual.is_empty() {
+        return 0;
+    }
+    let mean = residual.iter().map(|&r| fold(r)).sum::<u64>() / residual.len() as u64;
+    (64 - mean.leading_zeros()).min(MAX_RICE_PARAM)
+}
+
+fn rice_bits(residual: &[i64], param: u32) -> usize {
+    residual
+        .iter()
+        .map(|&r| (fold(r) >> param) as usize + 1 + param as usize)
+        .sum()
+}
+
+/// MSB-first bit packer.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    accumulator: u64,
+    bits: usize,
+}
+
+impl BitWriter {
+    /// Write the low `count` bits of `value`, `count` at most 57.
+    fn write(&mut self, value: u64, count: usize) {
+        if count == 0 {
+            return;
+        }
+        self.accumulator = (self.accumulator << count) | (value & ((1 << count) - 1));
+        self.bits += count;
+        while self.bits >= 8 {
+            self.bits -= 8;
+            self.bytes.push((self.accumulator >> self.bits) as u8);
+        }
+    }
+
+    fn write_signed(&mut self, value: i64, count: usize) {
+        self.write(value as u64, count);
+    }
+
+    /// `value` zeros followed by a one.
+    fn write_unary(&mut self, mut value: u64) {
+        while value >= 32 {
+            self.write(0, 32);
+            value -= 32;
+        }
+        self.write(1, value as usize + 1);
+    }
+
+    /// The frame number in the UTF-8-like variable length coding FLAC uses.
+    fn write_utf8(&mut self, value: u64) {
+        if value < 0x80 {
+            self.write(value, 8);
+            return;
+        }
+        let continuation_bytes = match value {
+            0x80..0x800 => 1,
+            0x800..0x1_0000 => 2,
+            0x1_0000..0x20_0000 => 3,
+            0x20_0000..0x400_0000 => 4,
+            _ => 5,
+        };
+        let lead_marker = (!0u64 << (7 - continuation_bytes)) & 0xFF;
+        self.write(lead_marker | (value >> (6 * continuation_bytes)), 8);
+        for i in (0..continuation_bytes).rev() {
+            self.write(0x80 | ((value >> (6 * i)) & 0x3F), 8);
+        }
+    }
+
+    /// Pad to a byte boundary with zeros.
+    fn into_bytes(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.write(0, 8 - self.bits);
+        }
+        self.bytes
+    }
+}
+
+fn crc8(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |mut crc, &byte| {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+fn crc16(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0u16, |mut crc, &byte| {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x8005
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(bytes: &[u8]) -> (Vec<i32>, claxon::metadata::StreamInfo) {
+        let mut reader = claxon::FlacReader::new(std::io::Cursor::new(bytes)).unwrap();
+        let info = reader.streaminfo();
+        let samples = reader.samples().collect::<Result<_, _>>().unwrap();
+        (samples, info)
+    }
+
+    #[test]
+    fn test_round_trip_is_lossless() {
+        // Tone, silence and noise, over several blocks ending in a short one
+        let mut seed = 1u32;
+        let samples: Vec<i32> = (0..10_000)
+            .map(|i| match i {
+                0..4000 => ((i as f32 * 0.05).sin() * 20_000.0) as i32,
+                4000..6000 => 0,
+                _ => {
+                    seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+                    (seed >> 16) as i32 - 32768
+                }
+            })
+            .collect();
+        let encoded = encode(&samples, 32000, 16);
+        let (decoded, info) = decode(&encoded);
+        assert_eq!(decoded, samples);
+        assert_eq!(info.sample_rate, 32000);
+        assert_eq!(info.bits_per_sample, 16);
+        assert_eq!(info.samples, Some(10_000));
+        assert!(encoded.len() < samples.len() * 2);
+
+        let loud: Vec<i32> = samples.iter().map(|s| s * 200).collect();
+        assert_eq!(decode(&encode(&loud, 48000, 24)).0, loud);
+
+        let path = std::env::temp_dir().join(format!("poing_flac_{}.flac", std::process::id()));
+        std::fs::write(&path, &encoded).unwrap();
+        let (mono, rate) = read_flac(&path).unwrap();
+        assert_eq!((mono.len(), rate), (10_000, 32000));
+        assert!((mono[100] - samples[100] as f32 / 32768.0).abs() < 1e-6);
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,589 @@
+use crate::config;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Component, Path, PathBuf};
+
+/// Manifest looked for in a mirror directory or archive.
+pub const MANIFEST_FILE: &str = "poing-model.json";
+
+/// Bytes read at a time, and at least copied between progress events.
+const CHUNK_SIZE: usize = 1 << 20;
+
+/// The files making up a model, with their SHA-256 hashes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModelManifest {
+    /// Folder name of the installed model, and its name in the dropdown.
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub files: Vec<ManifestFile>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ManifestFile {
+    /// Path inside the installed model directory.
+    pub path: String,
+    /// Lowercase hex SHA-256 of the contents.
+    pub sha256: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// Path in the mirror or archive when it differs from `path`, e.g.
+    /// `onnx/text_encoder_int8.onnx`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+}
+
+impl ManifestFile {
+    fn source(&self) -> &str {
+        self.source.as_deref().unwrap_or(&self.path)
+    }
+}
+
+impl ModelManifest {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let contents = fs::read_to_string(path)?;
+        Self::parse(&contents).map_err(|e| format!("{}: {}", path.display(), e).into())
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Parse and check a manifest: paths must stay inside the model
+    /// directory, hashes must be SHA-256 and the required files listed.
+    pub fn parse(contents: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut manifest: Self = serde_json::from_str(contents)?;
+        if !is_relative_inside(&manifest.name) || manifest.name.contains(['/', '\\']) {
+            return Err(format!("invalid model name \"{}\"", manifest.name).into());
+        }
+        for file in &mut manifest.files {
+            if !is_relative_inside(&file.path) || !is_relative_inside(file.source()) {
+                return Err(format!("invalid file path \"{}\"", file.path).into());
+            }
+            file.sha256 = file.sha256.trim().to_lowercase();
+            if file.sha256.len() != 64 || !file.sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(format!("invalid SHA-256 for {}", file.path).into());
+            }
+        }
+        let missing: Vec<&str> = config::REQUIRED_MODEL_FILES
+            .iter()
+            .copied()
+            .filter(|required| !manifest.files.iter().any(|file| file.path == *required))
+            .collect();
+        if !missing.is_empty() {
+            return Err(format!("manifest lacks required files: {}", missing.join(", ")).into());
+        }
+        Ok(manifest)
+    }
+
+    /// Describe the model in `dir`: every file except manifests and
+    /// partial downloads, sorted.
+    pub fn for_dir(dir: &Path, name: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut paths = Vec::new();
+        list_files(dir, Path::new(""), &mut paths)?;
+        paths.sort();
+        let mut files = Vec::new();
+        for path in paths {
+            let full = dir.join(&path);
+            files.push(ManifestFile {
+                path: path.to_string_lossy().replace('\\', "/"),
+                sha256: sha256_file(&full)?,
+                size: Some(fs::metadata(&full)?.len()),
+                source: None,
+            });
+        }
+        let manifest = Self {
+            name: name.to_string(),
+            description: None,
+            files,
+        };
+        // Check it the way an install would
+        Self::parse(&serde_json::to_string(&manifest)?)
+    }
+}
+
+fn list_files(dir: &Path, relative: &Path, paths: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir.join(relative))? {
+        let entry = entry?;
+        let path = relative.join(entry.file_name());
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if entry.file_type()?.is_dir() {
+            list_files(dir, &path, paths)?;
+        } else if name != MANIFEST_FILE && !name.ends_with(".part") {
+            paths.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// A relative path without `..`, so it cannot leave the folder it is
+/// joined to.
+pub(crate) fn is_relative_inside(path: &str) -> bool {
+    !path.is_empty()
+        && Path::new(path)
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+}
+
+/// Where to install from and to.
+#[derive(Debug, Clone)]
+pub struct InstallOptions {
+    /// Mirror directory, or `.tar`, `.tar.gz` or `.tgz` archive.
+    pub source: PathBuf,
+    /// Manifest to install by; [`MANIFEST_FILE`] in the source when unset.
+    pub manifest: Option<PathBuf>,
+    /// Folder the model's directory is created in; the default models
+    /// folder when unset.
+    pub models_dir: Option<PathBuf>,
+    /// Add the installed model to `model_paths` in the config.
+    pub register: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileOutcome {
+    Installed,
+    /// Completed from a partial file left by an interrupted install.
+    Resumed,
+    /// Already present with the right contents.
+    Present,
+}
+
+/// Progress of [`install`].
+pub enum InstallEvent<'a> {
+    /// File `index` of `total` started copying.
+    Started {
+        index: usize,
+        total: usize,
+        file: &'a ManifestFile,
+    },
+    /// Bytes of the current file written so far.
+    Progress { index: usize, written: u64 },
+    Finished {
+        index: usize,
+        file: &'a ManifestFile,
+        outcome: FileOutcome,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct InstallReport {
+    pub manifest: ModelManifest,
+    pub model_dir: PathBuf,
+    pub outcomes: Vec<FileOutcome>,
+}
+
+/// Install the model described by the manifest from a mirror directory or
+/// archive, verifying every file against its SHA-256.
+///
+/// Files are written to `.part` files and renamed once verified. Running
+/// again after an interruption keeps verified files and continues partial
+/// ones; a partial file that turns out corrupt is deleted so the next run
+/// starts it over. Files in the source that the manifest doesn't list are
+/// left out.
+pub fn install(
+    options: &InstallOptions,
+    on_event: impl Fn(InstallEvent),
+) -> Result<InstallReport, Box<dyn std::error::Error>> {
+    let source = Source::open(&options.source)?;
+    let manifest = match &options.manifest {
+        Some(path) => ModelManifest::load(path)?,
+        None => ModelManifest::parse(&source.read_manifest()?)?,
+    };
+    let model_dir = options
+        .models_dir
+        .clone()
+        .unwrap_or_else(config::default_models_dir)
+        .join(&manifest.name);
+
+    let mut outcomes: Vec<Option<FileOutcome>> = vec![None; manifest.files.len()];
+    for (index, file) in manifest.files.iter().enumerate() {
+        if is_installed(&model_dir.join(&file.path), file)? {
+            outcomes[index] = Some(FileOutcome::Present);
+            on_event(InstallEvent::Finished {
+                index,
+                file,
+                outcome: FileOutcome::Present,
+            });
+        }
+    }
+
+    match &source {
+        Source::Dir(dir) => {
+            for (index, file) in manifest.files.iter().enumerate() {
+                if outcomes[index].is_none() {
+                    let path = dir.join(file.source());
+                    let mut reader =
+                        File::open(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+                    outcomes[index] = Some(copy_file(
+                        &manifest,
+                        index,
+                        &model_dir,
+                        &mut reader,
+                        &on_event,
+                    )?);
+                }
+            }
+        }
+        Source::Archive(_) => {
+            let mut archive = source.archive()?;
+            for entry in archive.entries()? {
+                let mut entry = entry?;
+                let path = entry.path()?.into_owned();
+                let wanted = manifest.files.iter().enumerate().position(|(index, file)| {
+                    outcomes[index].is_none() && path.ends_with(file.source())
+                });
+                if let Some(index) = wanted {
+                    outcomes[index] = Some(copy_file(
+                        &manifest, index, &model_dir, &mut entry, &on_event,
+                    )?);
+                }
+            }
+        }
+    }
+
+    let missing: Vec<&str> = manifest
+        .files
+        .iter()
+        .zip(&outcomes)
+        .filter(|(_, outcome)| outcome.is_none())
+        .map(|(file, _)| file.source())
+        .collect();
+    if !missing.is_empty() {
+        return Err(format!(
+            "{} lacks files listed in the manifest: {}",
+            options.source.display(),
+            missing.join(", ")
+        )
+        .into());
+    }
+
+    if options.register {
+        register(&model_dir, &manifest)?;
+    }
+    Ok(InstallReport {
+        manifest,
+        model_dir,
+        outcomes: outcomes.into_iter().flatten().collect(),
+    })
+}
+
+/// Install file `index` of the manifest from `reader`, reporting progress.
+fn copy_file(
+    manifest: &ModelManifest,
+    index: usize,
+    model_dir: &Path,
+    reader: &mut dyn Read,
+    on_event: &dyn Fn(InstallEvent),
+) -> Result<FileOutcome, Box<dyn std::error::Error>> {
+    let file = &manifest.files[index];
+    let total = manifest.files.len();
+    on_event(InstallEvent::Started { index, total, file });
+    let target = model_dir.join(&file.path);
+    let outcome = install_file(&target, file, reader, &mut |written| {
+        on_event(InstallEvent::Progress { index, written })
+    })?;
+    on_event(InstallEvent::Finished {
+        index,
+        file,
+        outcome,
+    });
+    Ok(outcome)
+}
+
+/// Offer the installed model, named after its manifest unless the user
+/// already named it.
+fn register(model_dir: &Path, manifest: &ModelManifest) -> Result<(), Box<dyn std::error::Error>> {
+    config::update_config(|config| {
+        if !config.model_paths.iter().any(|path| path == model_dir) {
+            config.model_paths.push(model_dir.to_path_buf());
+        }
+        let details = config.models.entry(model_dir.to_path_buf()).or_default();
+        details.name.get_or_insert_with(|| manifest.name.clone());
+        if details.description.is_none() {
+            details.description = manifest.description.clone();
+        }
+    })?;
+    Ok(())
+}
+
+enum Source {
+    Dir(PathBuf),
+    Archive(PathBuf),
+}
+
+impl Source {
+    fn open(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        if path.is_dir() {
+            return Ok(Source::Dir(path.to_path_buf()));
+        }
+        if !path.is_file() {
+            return Err(format!("{} does not exist", path.display()).into());
+        }
+        let name = path.to_string_lossy().to_lowercase();
+        if [".tar", ".tar.gz", ".tgz"]
+            .iter()
+            .any(|ext| name.ends_with(ext))
+        {
+            Ok(Source::Archive(path.to_path_buf()))
+        } else {
+            Err(format!(
+                "{}: expected a directory or a .tar, .tar.gz or .tgz archive",
+                path.display()
+            )
+            .into())
+        }
+    }
+
+    /// Read through the archive from the start.
+    fn archive(&self) -> io::Result<tar::Archive<Box<dyn Read>>> {
+        let Source::Archive(path) = self else {
+            return Err(io::ErrorKind::InvalidInput.into());
+        };
+        let file = io::BufReader::new(File::open(path)?);
+        let name = path.to_string_lossy().to_lowercase();
+        let reader: Box<dyn Read> = if name.ends_with(".tar") {
+            Box::new(file)
+        } else {
+            Box::new(flate2::read::GzDecoder::new(file))
+        };
+        Ok(tar::Archive::new(reader))
+    }
+
+    /// The manifest in the source. In an archive, the one nearest the root.
+    fn read_manifest(&self) -> Result<String, Box<dyn std::error::Error>> {
+        match self {
+            Source::Dir(dir) => {
+                let path = dir.join(MANIFEST_FILE);
+                fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e).into())
+            }
+            Source::Archive(path) => {
+                let mut found: Option<(usize, String)> = None;
+                for entry in self.archive()?.entries()? {
+                    let mut entry = entry?;
+                    let entry_path = entry.path()?.into_owned();
+                    let depth = entry_path.components().count();
+                    let nearer = found.as_ref().is_none_or(|(best, _)| depth < *best);
+                    if entry_path.file_name() == Some(MANIFEST_FILE.as_ref()) && nearer {
+                        let mut contents = String::new();
+                        entry.read_to_string(&mut contents)?;
+                        found = Some((depth, contents));
+                        if depth == 1 {
+                            break;
+                        }
+                    }
+                }
+                found.map(|(_, contents)| contents).ok_or_else(|| {
+                    format!(
+                        "no {} in {}; pass a manifest",
+                        MANIFEST_FILE,
+                        path.display()
+                    )
+                    .into()
+                })
+            }
+        }
+    }
+}
+
+/// Whether `target` exists with the size and hash the manifest gives.
+fn is_installed(target: &Path, file: &ManifestFile) -> io::Result<bool> {
+    let Ok(metadata) = fs::metadata(target) else {
+        return Ok(false);
+    };
+    if file.size.is_some_and(|size| size != metadata.len()) {
+        return Ok(false);
+    }
+    Ok(sha256_file(target)? == file.sha256)
+}
+
+/// Copy `source` to `target` through a `.part` file, continuing an earlier
+/// partial copy, and rename it into place once its hash matches.
+fn install_file(
+    target: &Path,
+    file: &ManifestFile,
+    source: &mut dyn Read,
+    on_progress: &mut dyn FnMut(u64),
+) -> Result<FileOutcome, Box<dyn std::error::Error>> {
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let partial = part_path(target);
+    let mut hasher = Sha256::new();
+    let mut written = match fs::metadata(&partial) {
+        Ok(metadata) if file.size.is_none_or(|size| metadata.len() <= size) => {
+            io::copy(&mut File::open(&partial)?, &mut hasher)?
+        }
+        _ => 0,
+    };
+    let resumed = written > 0;
+    let mut output = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(!resumed)
+        .append(resumed)
+        .open(&partial)?;
+
+    // The source can only be read forward; skip what the partial file holds
+    if io::copy(&mut source.take(written), &mut io::sink())? < written {
+        fs::remove_file(&partial)?;
+        return Err(format!("{} is shorter than its partial copy", file.source()).into());
+    }
+    let mut buffer = vec![0; CHUNK_SIZE];
+    let mut reported = written;
+    loop {
+        let count = source.read(&mut buffer)?;
+        if count == 0 {
+            break;
+        }
+        output.write_all(&buffer[..count])?;
+        hasher.update(&buffer[..count]);
+        written += count as u64;
+        if written - reported >= CHUNK_SIZE as u64 {
+            reported = written;
+            on_progress(written);
+        }
+    }
+    output.sync_all()?;
+    drop(output);
+
+    let hash = hex(&hasher.finalize());
+    if hash != file.sha256 {
+        fs::remove_file(&partial)?;
+        return Err(format!(
+            "checksum mismatch for {}: expected {}, got {}",
+            file.path, file.sha256, hash
+        )
+        .into());
+    }
+    fs::rename(&partial, target)?;
+    Ok(if resumed {
+        FileOutcome::Resumed
+    } else {
+        FileOutcome::Installed
+    })
+}
+
+fn part_path(target: &Path) -> PathBuf {
+    let mut name = target.file_name().unwrap_or_default().to_os_string();
+    name.push(".part");
+    target.with_file_name(name)
+}
+
+/// Lowercase hex SHA-256 of the file at `path`.
+pub fn sha256_file(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hex(&hasher.finalize()))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_dir;
+
+    /// A mirror holding a fake model and its manifest.
+    fn mirror(dir: &Path) -> ModelManifest {
+        for (index, file) in config::REQUIRED_MODEL_FILES.iter().enumerate() {
+            fs::write(dir.join(file), vec![index as u8; 3000 + index]).unwrap();
+        }
+        let mut manifest = ModelManifest::for_dir(dir, "tiny").unwrap();
+        manifest.description = Some("Test model".into());
+        manifest.save(&dir.join(MANIFEST_FILE)).unwrap();
+        manifest
+    }
+
+    fn options(source: &Path, models_dir: &Path) -> InstallOptions {
+        InstallOptions {
+            source: source.to_path_buf(),
+            manifest: None,
+            models_dir: Some(models_dir.to_path_buf()),
+            register: false,
+        }
+    }
+
+    #[test]
+    fn test_install_resumes_and_verifies() {
+        let root = temp_dir("install_mirror");
+        let source = root.join("mirror");
+        let models = root.join("models");
+        fs::create_dir_all(&source).unwrap();
+        let manifest = mirror(&source);
+        fs::write(source.join("unused.onnx"), "not listed").unwrap();
+
+        // An interrupted install left half of one file and all of another
+        let model_dir = models.join("tiny");
+        fs::create_dir_all(&model_dir).unwrap();
+        let decoder = fs::read(source.join("decoder_model_merged.onnx")).unwrap();
+        fs::write(
+            model_dir.join("decoder_model_merged.onnx.part"),
+            &decoder[..1000],
+        )
+        .unwrap();
+        fs::copy(
+            source.join("tokenizer.json"),
+            model_dir.join("tokenizer.json"),
+        )
+        .unwrap();
+
+        let report = install(&options(&source, &models), |_| {}).unwrap();
+        assert_eq!(report.manifest, manifest);
+        assert_eq!(report.model_dir, model_dir);
+        let outcome = |path: &str| {
+            let index = manifest.files.iter().position(|f| f.path == path).unwrap();
+            report.outcomes[index]
+        };
+        assert_eq!(outcome("decoder_model_merged.onnx"), FileOutcome::Resumed);
+        assert_eq!(outcome("tokenizer.json"), FileOutcome::Present);
+        assert_eq!(outcome("text_encoder.onnx"), FileOutcome::Installed);
+        assert_eq!(
+            fs::read(model_dir.join("decoder_model_merged.onnx")).unwrap(),
+            decoder
+        );
+        assert!(config::validate_model_dir(&model_dir));
+        assert!(!model_dir.join("unused.onnx").exists());
+
+        // A corrupt mirror file is rejected and not left behind
+        fs::remove_file(model_dir.join("encodec_decode.onnx")).unwrap();
+        fs::write(source.join("encodec_decode.onnx"), "tampered").unwrap();
+        let error = install(&options(&source, &models), |_| {}).unwrap_err();
+        assert!(error.to_string().contains("checksum mismatch"));
+        assert!(!model_dir.join("encodec_decode.onnx").exists());
+        assert!(!model_dir.join("encodec_decode.onnx.part").exists());
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_install_from_archive() {
+        let root = temp_dir("install_archive");
+        let source = root.join("tiny-model");
+        fs::create_dir_all(&source).unwrap();
+        mirror(&source);
+
+        let archive_path = root.join("tiny.tar.gz");
+        let encoder = flate2::write::GzEncoder::new(
+            File::create(&archive_path).unwrap(),
+            flate2::Compression::fast(),
+        );
+        let mut builder = tar::Builder::new(encoder);
+        builder.append_dir_all("tiny-model", &source).unwrap();
+        builder.into_inner().unwrap().finish().unwrap();
+
+        let models = root.join("models");
+        let report = install(&options(&archive_path, &models), |_| {}).unwrap();
+        assert!(report.outcomes.iter().all(|&o| o == FileOutcome::Installed));
+        assert!(config::validate_model_dir(&models.join("tiny")));
+
+        assert!(ModelManifest::parse(
+            r#"{"name": "x", "files": [{"path": "../escape", "sha256": "00"}]}"#
+        )
+        .is_err());
+        let _ = fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,249 @@
+use rustfft::num_complex::Complex;
+use rustfft::FftPlanner;
+use std::fmt;
+use std::str::FromStr;
+
+/// STFT frame length for chroma analysis. Long frames resolve semitones in the bass.
+const FRAME_SIZE: usize = 8192;
+const HOP_SIZE: usize = FRAME_SIZE / 2;
+/// Frequency range folded into the chromagram.
+const MIN_FREQ: f32 = 60.0;
+const MAX_FREQ: f32 = 2000.0;
+
+/// Krumhansl-Kessler key profiles, starting from the tonic.
+const MAJOR_PROFILE: [f32; 12] = [
+    6.35, 2.23, 3.48, 2.33, 4.38, 4.09, 2.52, 5.19, 2.39, 3.66, 2.29, 2.88,
+];
+const MINOR_PROFILE: [f32; 12] = [
+    6.33, 2.68, 3.52, 5.38, 2.60, 3.53, 2.54, 4.75, 3.98, 2.69, 3.34, 3.17,
+];
+
+const SHARP_NAMES: [&str; 12] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
+/// Conventional spelling for each tonic: flats for F, Bb, Eb, Ab major and
+/// their relative minors, sharps elsewhere.
+const MAJOR_NAMES: [&str; 12] = [
+    "C", "Db", "D", "Eb", "E", "F", "F#", "G", "Ab", "A", "Bb", "B",
+];
+const MINOR_NAMES: [&str; 12] = [
+    "C", "C#", "D", "Eb", "E", "F", "F#", "G", "G#", "A", "Bb", "B",
+];
+
+/// A musical key such as D minor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Key {
+    /// Pitch class of the tonic, 0 = C.
+    pub tonic: u8,
+    pub minor: bool,
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names = if self.minor {
+            &MINOR_NAMES
+        } else {
+            &MAJOR_NAMES
+        };
+        let mode = if self.minor { "minor" } else { "major" };
+        write!(f, "{} {}", names[self.tonic as usize % 12], mode)
+    }
+}
+
+/// Parses "D minor", "Dm", "F# major", "Bb" (major), "eb min" and similar.
+impl FromStr for Key {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let invalid = || format!("invalid key {:?}", s);
+        let mut chars = s.chars();
+        let letter = chars.next().ok_or_else(invalid)?.to_ascii_uppercase();
+        let natural = SHARP_NAMES
+            .iter()
+            .position(|name| name.len() == 1 && name.starts_with(letter))
+            .ok_or_else(invalid)? as i32;
+
+        let rest = chars.as_str();
+        let (accidental, rest) = match rest.chars().next() {
+            Some(c @ ('#' | '♯')) => (1, &rest[c.len_utf8()..]),
+            Some(c @ ('b' | '♭')) => (-1, &rest[c.len_utf8()..]),
+            _ => (0, rest),
+        };
+        let minor = match rest.trim().to_ascii_lowercase().as_str() {
+            "" | "maj" | "major" => false,
+            "m" | "min" | "minor" => true,
+            _ => return Err(invalid()),
+        };
+        Ok(Key {
+            tonic: (natural + accidental).rem_euclid(12) as u8,
+            minor,
+        })
+    }
+}
+
+/// Pitch-class energy of mono audio, normalized to sum to 1. All zeros for silence.
+pub fn chromagram(samples: &[f32], sample_rate: u32) -> [f32; 12] {
+    let mut chroma = [0.0f32; 12];
+    if samples.is_empty() {
+        return chroma;
+    }
+
+    let fft = FftPlanner::<f32>::new().plan_fft_forward(FRAME_SIZE);
+    let window: Vec<f32> = (0..FRAME_SIZE)
+        .map(|i| 0.5 - 0.5 * (2.0 * std::f32::consts::PI * i as f32 / FRAME_SIZE as f32).cos())
+        .collect();
+    // Pitch class of every bin in range
+    let bin_hz = sample_rate as f32 / FRAME_SIZE as f32;
+    let bins: Vec<(usize, usize)> = (1..FRAME_SIZE / 2)
+        .filter_map(|bin| {
+            let freq = bin as f32 * bin_hz;
+            if !(MIN_FREQ..=MAX_FREQ).contains(&freq) {
+                return None;
+            }
+            let midi = 69.0 + 12.0 * (freq / 440.0).log2();
+            Some((bin, (midi.round() as i32).rem_euclid(12) as usize))
+        })
+        .collect();
+
+    let mut buffer = vec![Complex::new(0.0, 0.0); FRAME_SIZE];
+    let mut start = 0;
+    loop {
+        for (i, slot) in buffer.iter_mut().enumerate() {
+            let sample = samples.get(start + i).copied().unwrap_or(0.0);
+            *slot = Complex::new(sample * window[i], 0.0);
+        }
+        fft.process(&mut buffer);
+        for &(bin, pitch_class) in &bins {
+            chroma[pitch_class] += buffer[bin].norm();
+        }
+        start += HOP_SIZE;
+        if start + FRAME_SIZE > samples.len() {
+            break;
+        }
+    }
+
+    let total: f32 = chroma.iter().sum();
+    if total > f32::EPSILON {
+        chroma.iter_mut().for_each(|c| *c /= total);
+    }
+    chroma
+}
+
+/// Estimate the key of mono audio by correlating its chromagram with the
+/// Krumhansl-Kessler profile of every major and minor key.
+pub fn estimate_key(samples: &[f32], sample_rate: u32) -> Option<Key> {
+    let chroma = chromagram(samples, sample_rate);
+    if chroma.iter().all(|c| *c == 0.0) {
+        return None;
+    }
+
+    (0..12u8)
+        .flat_map(|tonic| [false, true].map(|minor| Key { tonic, minor }))
+        .map(|key| {
+            let profile = if key.minor {
+                &MINOR_PROFILE
+            } else {
+                &MAJOR_PROFILE
+            };
+            let rotated: Vec<f32> = (0..12)
+                .map(|pc| profile[(pc + 12 - key.tonic as usize) % 12])
+                .collect();
+            (key, correlation(&chroma, &rotated))
+        })
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(key, _)| key)
+}
+
+/// Pearson correlation of two equally long series.
+fn correlation(a: &[f32], b: &[f32]) -> f32 {
+    let mean = |x: &[f32]| x.iter().sum::<f32>() / x.len() as f32;
+    let (mean_a, mean_b) = (mean(a), mean(b));
+    let mut covariance = 0.0;
+    let mut var_a = 0.0;
+    let mut var_b = 0.0;
+    for (x, y) in a.iter().zip(b) {
+        covariance += (x - mean_a) * (y - mean_b);
+        var_a += (x - mean_a).powi(2);
+        var_b += (y - mean_b).powi(2);
+    }
+    if var_a <= 0.0 || var_b <= 0.0 {
+        0.0
+    } else {
+        covariance / (var_a * var_b).sqrt()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One second per chord, each note with a few harmonics. Notes are MIDI numbers.
+    fn progression(chords: &[[u8; 3]], sample_rate: u32) -> Vec<f32> {
+        let sr = sample_rate as f32;
+        chords
+            .iter()
+            .flat_map(|chord| {
+                (0..sample_rate).map(move |i| {
+                    let t = i as f32 / sr;
+                    chord
+                        .iter()
+                        .map(|&note| {
+                            let freq = 440.0 * 2f32.powf((note as f32 - 69.0) / 12.0);
+                            (1..=3)
+                                .map(|h| {
+                                    (2.0 * std::f32::consts::PI * freq * h as f32 * t).sin()
+                                        / h as f32
+                                })
+                                .sum::<f32>()
+                        })
+                        .sum::<f32>()
+                        * 0.1
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_estimates_key_of_progression() {
+        // i - iv - V - i in D minor: Dm, Gm, A, Dm
+        let d_minor = progression(
+            &[[50, 53, 57], [55, 58, 62], [57, 61, 64], [50, 53, 57]],
+            44100,
+        );
+        let key = estimate_key(&d_minor, 44100).unwrap();
+        assert_eq!(key.to_string(), "D minor");
+
+        // I - IV - V - I in Eb major
+        let e_flat = progression(
+            &[[51, 55, 58], [56, 60, 63], [58, 62, 65], [51, 55, 58]],
+            48000,
+        );
+        assert_eq!(
+            estimate_key(&e_flat, 48000).unwrap().to_string(),
+            "Eb major"
+        );
+
+        assert_eq!(estimate_key(&vec![0.0; 44100], 44100), None);
+    }
+
+    #[test]
+    fn test_parse_key() {
+        let d_minor = Key {
+            tonic: 2,
+            minor: true,
+        };
+        assert_eq!("D minor".parse(), Ok(d_minor));
+        assert_eq!("dm".parse(), Ok(d_minor));
+        assert_eq!(
+            "Bb".parse(),
+            Ok(Key {
+                tonic: 10,
+                minor: false
+            })
+        );
+        assert_eq!("F# min".parse::<Key>().unwrap().to_string(), "F# minor");
+        assert!("H major".parse::<Key>().is_err());
+        assert!("C dorian".parse::<Key>().is_err());
+    }
+}
@@ -1,12 +1,46 @@
 pub mod audio_buffer;
+pub mod batch;
+pub mod beats;
+pub mod catalog;
 pub mod config;
+pub mod export;
+pub mod flac;
+pub mod install;
+pub mod key;
+pub mod library;
+pub mod looping;
+pub mod metadata;
 pub mod model;
 pub mod musicgen;
+pub mod postprocess;
+pub mod prompts;
+pub mod recording;
+pub mod resample;
+#[cfg(feature = "server")]
+pub mod server;
+pub mod spectrogram;
+pub mod stretch;
+pub mod tempfiles;
+pub mod tempo;
+#[cfg(test)]
+mod test_util;
 pub mod wav;
+pub mod waveform;
+pub mod worker;
 
-use std::path::PathBuf;
-use std::sync::atomic::AtomicBool;
+use key::Key;
+use library::Library;
+use metadata::TakeMetadata;
+use musicgen::{GenerationParams, MAX_DURATION_SECONDS};
+use postprocess::PostProcessSettings;
+use recording::{InputChannel, RecordSource, Recording, TransportPosition};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU8, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
 use std::sync::{Arc, Mutex};
+use tempfiles::TempFiles;
+use tempo::TempoTarget;
+use worker::{InferenceWorker, Job, JobId, JobKind, Take, WorkerEvent};
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum GenerationState {
@@ -16,6 +50,95 @@ pub enum GenerationState {
     Error(String),
 }
 
+/// Generation settings edited in the GUI or set remotely.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GenerationSettings {
+    /// User prompt, without the hints `template` adds at submission.
+    pub prompt: String,
+    /// Layout of the prompt sent to the model, see [`prompts::PromptTemplate`].
+    pub template: String,
+    /// Genre filled into the template's `{genre}`.
+    pub genre: Option<String>,
+    /// Instruments filled into the template's `{instruments}`.
+    pub instruments: Vec<String>,
+    pub bpm: f32,
+    pub bars: u32,
+    /// Key added to the prompt as e.g. "in D minor".
+    pub key: Option<Key>,
+    /// Make generated clips loop seamlessly.
+    pub loop_mode: bool,
+    pub guidance_scale: f32,
+    pub top_k: usize,
+    /// Fixed sampling seed, e.g. restored from an exported take. Random when unset.
+    pub seed: Option<u64>,
+}
+
+impl Default for GenerationSettings {
+    fn default() -> Self {
+        let params = GenerationParams::default();
+        Self {
+            prompt: String::new(),
+            template: prompts::DEFAULT_TEMPLATE.into(),
+            genre: None,
+            instruments: Vec::new(),
+            bpm: 120.0,
+            bars: 4,
+            key: None,
+            loop_mode: false,
+            guidance_scale: params.guidance_scale,
+            top_k: params.top_k,
+            seed: None,
+        }
+    }
+}
+
+/// Longest imported file kept as conditioning input, matching the plugin's
+/// recording buffer.
+pub const MAX_IMPORT_SECONDS: f32 = 30.0;
+
+/// Extra audio generated beyond the target length, so a clip that comes out
+/// slower than requested or starts its first bar late still contains every
+/// bar once it is conformed.
+const TEMPO_HEADROOM: f32 = 1.2;
+
+impl GenerationSettings {
+    /// The prompt sent to the model: `template` filled with the prompt and
+    /// the tempo, key, genre and instrument hints.
+    pub fn full_prompt(&self) -> String {
+        let bpm = format!("{:.0}", self.bpm);
+        let key = self.key.map(|key| key.to_string()).unwrap_or_default();
+        let genre = self.genre.clone().unwrap_or_default();
+        let instruments = self.instruments.join(", ");
+        prompts::render(
+            &self.template,
+            &[
+                ("prompt", &self.prompt),
+                ("bpm", &bpm),
+                ("key", &key),
+                ("genre", &genre),
+                ("instruments", &instruments),
+            ],
+        )
+    }
+
+    /// Grid generated clips are stretched to, for the given time signature numerator.
+    pub fn tempo_target(&self, beats_per_bar: f32) -> TempoTarget {
+        TempoTarget {
+            bpm: self.bpm,
+            bars: self.bars,
+            beats_per_bar: beats_per_bar.round().max(1.0) as u32,
+            looped: self.loop_mode,
+        }
+    }
+}
+
+/// Tempo and key detected in recorded input.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RecordingAnalysis {
+    pub bpm: Option<f32>,
+    pub key: Option<Key>,
+}
+
 /// Shared state for cross-thread communication between the audio thread,
 /// GUI, and inference thread.
 #[derive(Clone)]
@@ -25,8 +148,20 @@ pub struct SharedState {
     pub generation_state: Arc<Mutex<GenerationState>>,
     pub progress: Arc<Mutex<f32>>,
     pub generated_audio: Arc<Mutex<Option<Vec<f32>>>>,
-    pub recorded_audio: Arc<Mutex<Vec<f32>>>,
+    pub recorded_audio: Arc<Mutex<Recording>>,
     pub is_recording: Arc<AtomicBool>,
+    /// Recording waits for the next bar line of the playing host transport.
+    pub record_armed: Arc<AtomicBool>,
+    /// Starting a recording arms it instead, see [`SharedState::request_recording`].
+    pub record_synced: Arc<AtomicBool>,
+    /// Bars to record when armed, or 0 to record until the loop end or stop.
+    pub record_bars: Arc<AtomicU32>,
+    /// [`InputChannel::index`] of the channels recorded.
+    pub input_channel: Arc<AtomicU8>,
+    /// [`RecordSource::index`] of the plugin input recorded.
+    pub record_source: Arc<AtomicU8>,
+    /// The host's active audio layout gives the plugin a sidechain input.
+    pub has_sidechain: Arc<AtomicBool>,
     pub sample_rate: Arc<Mutex<f32>>,
     pub model_paths: Arc<Mutex<Vec<PathBuf>>>,
     pub pending_browse: Arc<AtomicBool>,
@@ -35,28 +170,374 @@ pub struct SharedState {
     pub host_tempo: Arc<Mutex<Option<f64>>>,
     /// Host time signature (numerator, denominator), updated from the audio process thread.
     pub host_time_sig: Arc<Mutex<Option<(i32, i32)>>>,
+    /// Host transport at the start of the last process block, for the playhead.
+    pub host_transport: Arc<Mutex<TransportPosition>>,
+    /// Every take generated in this session, oldest first.
+    pub takes: Arc<Mutex<Vec<Take>>>,
+    /// Index into `takes` of the take shown in `generated_audio`.
+    pub selected_take: Arc<Mutex<Option<usize>>>,
+    /// Background inference worker shared by the editor and remote controls.
+    pub worker: Arc<InferenceWorker>,
+    /// Settings used by [`SharedState::submit_generation`].
+    pub settings: Arc<Mutex<GenerationSettings>>,
+    /// Post-processing applied to takes from [`SharedState::submit_generation`].
+    /// Persisted with the plugin state.
+    pub post_process: Arc<Mutex<PostProcessSettings>>,
+    /// Temp files handed to the host, deleted when the plugin is unloaded.
+    pub temp_files: Arc<TempFiles>,
+    /// Every finished take is saved here.
+    pub library: Arc<Library>,
+    /// Listeners notified when settings, recording or take selection change
+    /// outside the editor.
+    change_listeners: Arc<Mutex<Vec<Sender<()>>>>,
 }
 
 impl SharedState {
     pub fn new() -> Self {
         let cfg = config::load_config();
-        let first_path = cfg.model_paths.first().cloned();
-        Self {
+        // The model roots are searched in the background
+        let model_paths = cfg.model_paths.clone();
+        let first_path = model_paths.first().cloned();
+        let generation_state = Arc::new(Mutex::new(GenerationState::Idle));
+        let progress = Arc::new(Mutex::new(0.0));
+        let generated_audio = Arc::new(Mutex::new(None));
+        let takes = Arc::new(Mutex::new(Vec::new()));
+        let selected_take = Arc::new(Mutex::new(None));
+        let library = Arc::new(Library::new(
+            cfg.library_dir.clone().unwrap_or_else(config::default_library_dir),
+        ));
+
+        // Mirror worker events into the shared fields so the state stays current
+        // even while no editor is open.
+        let worker = {
+            let generation_state = generation_state.clone();
+            let progress = progress.clone();
+            let generated_audio = generated_audio.clone();
+            let takes = takes.clone();
+            let selected_take = selected_take.clone();
+            let library = library.clone();
+            let running: Mutex<Option<JobId>> = Mutex::new(None);
+            InferenceWorker::new(move |event| match event {
+                WorkerEvent::Queued { .. } | WorkerEvent::Partial { .. } => {}
+                WorkerEvent::Started { id } => {
+                    *running.lock().unwrap() = Some(*id);
+                    *progress.lock().unwrap() = 0.0;
+                    *generation_state.lock().unwrap() = GenerationState::Generating;
+                }
+                WorkerEvent::Progress { progress: p, .. } => {
+                    *progress.lock().unwrap() = *p;
+                }
+                WorkerEvent::Done { takes: new_takes, .. } => {
+                    for take in new_takes {
+                        if let Err(e) = library.add(take) {
+                            eprintln!("[poing] Failed to save take to the library: {}", e);
+                        }
+                    }
+                    let mut takes = takes.lock().unwrap();
+                    takes.extend(new_takes.iter().cloned());
+                    if let Some(last) = takes.last() {
+                        *generated_audio.lock().unwrap() = Some(last.audio.to_vec());
+                        *selected_take.lock().unwrap() = Some(takes.len() - 1);
+                    }
+                    *generation_state.lock().unwrap() = GenerationState::Complete;
+                }
+                WorkerEvent::Error { message, .. } => {
+                    *generation_state.lock().unwrap() = GenerationState::Error(message.clone());
+                }
+                WorkerEvent::Cancelled { id } => {
+                    if *running.lock().unwrap() == Some(*id) {
+                        *generation_state.lock().unwrap() = GenerationState::Idle;
+                    }
+                }
+            })
+        };
+
+        let state = Self {
             prompt: Arc::new(Mutex::new(String::new())),
             model_path: Arc::new(Mutex::new(first_path)),
-            generation_state: Arc::new(Mutex::new(GenerationState::Idle)),
-            progress: Arc::new(Mutex::new(0.0)),
-            generated_audio: Arc::new(Mutex::new(None)),
-            recorded_audio: Arc::new(Mutex::new(Vec::new())),
+            generation_state,
+            progress,
+            generated_audio,
+            recorded_audio: Arc::new(Mutex::new(Recording::default())),
             is_recording: Arc::new(AtomicBool::new(false)),
+            record_armed: Arc::new(AtomicBool::new(false)),
+            record_synced: Arc::new(AtomicBool::new(false)),
+            record_bars: Arc::new(AtomicU32::new(0)),
+            input_channel: Arc::new(AtomicU8::new(InputChannel::default().index())),
+            record_source: Arc::new(AtomicU8::new(RecordSource::default().index())),
+            has_sidechain: Arc::new(AtomicBool::new(false)),
             sample_rate: Arc::new(Mutex::new(44100.0)),
-            model_paths: Arc::new(Mutex::new(cfg.model_paths)),
+            model_paths: Arc::new(Mutex::new(model_paths)),
             pending_browse: Arc::new(AtomicBool::new(false)),
             browse_result: Arc::new(Mutex::new(None)),
             host_tempo: Arc::new(Mutex::new(None)),
             host_time_sig: Arc::new(Mutex::new(None)),
+            host_transport: Arc::new(Mutex::new(TransportPosition::default())),
+            takes,
+            selected_take,
+            worker: Arc::new(worker),
+            settings: Arc::new(Mutex::new(GenerationSettings::default())),
+            post_process: Arc::new(Mutex::new(PostProcessSettings::default())),
+            temp_files: Arc::new(TempFiles::new()),
+            library,
+            change_listeners: Arc::new(Mutex::new(Vec::new())),
+        };
+        state.discover_models(cfg);
+        state
+    }
+
+    /// Add the models found below the model roots of `cfg` to `model_paths`
+    /// and select the first model if none is selected yet. Roots can be large
+    /// or on the network, so the search runs on its own thread.
+    fn discover_models(&self, cfg: config::PoingConfig) {
+        if cfg.model_roots.is_empty() {
+            return;
+        }
+        let state = self.clone();
+        std::thread::spawn(move || {
+            let found = catalog::model_dirs(&cfg);
+            let first = {
+                let mut model_paths = state.model_paths.lock().unwrap();
+                for dir in found {
+                    if !model_paths.contains(&dir) {
+                        model_paths.push(dir);
+                    }
+                }
+                model_paths.first().cloned()
+            };
+            {
+                let mut model_path = state.model_path.lock().unwrap();
+                if model_path.is_none() {
+                    *model_path = first;
+                }
+            }
+            state.notify_changed();
+        });
+    }
+
+    /// Receive a notification whenever [`SharedState::notify_changed`] is called.
+    pub fn watch_changes(&self) -> Receiver<()> {
+        let (tx, rx) = mpsc::channel();
+        self.change_listeners.lock().unwrap().push(tx);
+        rx
+    }
+
+    /// Tell watchers that settings, recording or take selection changed.
+    pub fn notify_changed(&self) {
+        self.change_listeners
+            .lock()
+            .unwrap()
+            .retain(|tx| tx.send(()).is_ok());
+    }
+
+    /// Like [`SharedState::notify_changed`], but skipped rather than blocking
+    /// when a watcher is being added. For the audio thread.
+    pub fn try_notify_changed(&self) {
+        if let Ok(mut listeners) = self.change_listeners.try_lock() {
+            listeners.retain(|tx| tx.send(()).is_ok());
+        }
+    }
+
+    pub fn input_channel(&self) -> InputChannel {
+        InputChannel::from_index(self.input_channel.load(Ordering::Relaxed))
+    }
+
+    pub fn record_source(&self) -> RecordSource {
+        RecordSource::from_index(self.record_source.load(Ordering::Relaxed))
+    }
+
+    /// The input actually recorded: the main input stands in for the
+    /// sidechain while the host provides none.
+    pub fn recorded_source(&self) -> RecordSource {
+        match self.record_source() {
+            RecordSource::Sidechain if !self.has_sidechain.load(Ordering::Relaxed) => {
+                RecordSource::Main
+            }
+            source => source,
         }
     }
+
+    /// Arm or disarm transport-synced recording. Disarming also stops a
+    /// recording in progress.
+    pub fn set_armed(&self, armed: bool) {
+        self.record_armed.store(armed, Ordering::Relaxed);
+        if !armed {
+            self.is_recording.store(false, Ordering::Relaxed);
+        }
+    }
+
+    /// Start or stop recording as the Record button does. Starting arms
+    /// transport-synced recording when `record_synced` is set and records at
+    /// once otherwise; stopping disarms and ends any recording. Starting while
+    /// armed or recording changes nothing.
+    pub fn request_recording(&self, start: bool) {
+        let armed = self.record_armed.load(Ordering::Relaxed);
+        if !start {
+            if armed {
+                self.set_armed(false);
+            } else {
+                self.set_recording(false);
+            }
+        } else if !armed && !self.is_recording.load(Ordering::Relaxed) {
+            if self.record_synced.load(Ordering::Relaxed) {
+                self.set_armed(true);
+            } else {
+                self.set_recording(true);
+            }
+        }
+    }
+
+    /// Beats per bar from the host time signature, defaulting to 4.
+    pub fn beats_per_bar(&self) -> f32 {
+        self.host_time_sig
+            .try_lock()
+            .ok()
+            .and_then(|ts| ts.map(|(num, _)| num as f32))
+            .unwrap_or(4.0)
+    }
+
+    /// Queue a generation from the current settings and selected model.
+    pub fn submit_generation(&self) -> Result<JobId, String> {
+        let settings = self.settings.lock().unwrap().clone();
+        let result = self.build_job(&settings).map(|job| {
+            *self.prompt.lock().unwrap() = job.prompt.clone();
+            self.worker.submit(job)
+        });
+        if result.is_ok() {
+            if let Err(e) = config::record_prompt(&settings.prompt) {
+                eprintln!("[poing] Failed to save the prompt history: {}", e);
+            }
+        }
+        if let Err(e) = &result {
+            *self.generation_state.lock().unwrap() = GenerationState::Error(e.clone());
+        }
+        result
+    }
+
+    fn build_job(&self, settings: &GenerationSettings) -> Result<Job, String> {
+        if settings.prompt.trim().is_empty() {
+            return Err("Please enter a prompt".into());
+        }
+        let model_dir = self
+            .model_path
+            .lock()
+            .unwrap()
+            .clone()
+            .ok_or("No model path configured")?;
+
+        let beats_per_bar = self.beats_per_bar();
+        let target = settings.tempo_target(beats_per_bar);
+        // Loops also need the beat after the last bar for the crossfade
+        let loop_tail = if target.looped { 60.0 / target.bpm } else { 0.0 };
+        let needed = target.duration_seconds() + loop_tail;
+        if needed > MAX_DURATION_SECONDS {
+            return Err(format!(
+                "{} bars at {} BPM last {:.1} s, but the model generates at most {} s",
+                target.bars, target.bpm, needed, MAX_DURATION_SECONDS
+            ));
+        }
+        let duration = needed * TEMPO_HEADROOM;
+        Ok(Job {
+            prompt: settings.full_prompt(),
+            model_dir,
+            params: GenerationParams {
+                duration_seconds: duration.min(MAX_DURATION_SECONDS),
+                guidance_scale: settings.guidance_scale,
+                top_k: settings.top_k,
+                seed: settings.seed,
+            },
+            kind: JobKind::Generate,
+            // MusicGen only loosely follows the BPM hint
+            tempo: Some(target),
+            post_process: Some(self.post_process.lock().unwrap().clone()),
+            settings: Some(settings.clone()),
+        })
+    }
+
+    /// Load a WAV or FLAC file into `recorded_audio` as conditioning input,
+    /// resampled to the host rate and truncated to
+    /// [`MAX_IMPORT_SECONDS`]. Stops any recording in progress. Returns the
+    /// number of samples loaded.
+    pub fn import_recording(&self, path: &Path) -> Result<usize, Box<dyn std::error::Error>> {
+        let (samples, file_rate) = wav::read_audio_file(path)?;
+        if samples.is_empty() {
+            return Err("the file contains no audio".into());
+        }
+        Ok(self.set_conditioning(&samples, file_rate))
+    }
+
+    /// Replace `recorded_audio` with `samples`, e.g. a region of a take,
+    /// resampled to the host rate and truncated to [`MAX_IMPORT_SECONDS`].
+    /// Stops any recording in progress. Returns the number of samples stored.
+    pub fn set_conditioning(&self, samples: &[f32], sample_rate: u32) -> usize {
+        let host_rate = *self.sample_rate.lock().unwrap() as u32;
+        let mut audio = resample::resample(samples, sample_rate, host_rate);
+        audio.truncate((MAX_IMPORT_SECONDS * host_rate as f32) as usize);
+
+        self.set_recording(false);
+        let len = audio.len();
+        *self.recorded_audio.lock().unwrap() = Recording::mono(audio);
+        len
+    }
+
+    /// Restore the settings an exported take was generated with, and select
+    /// its model if that model is configured.
+    pub fn restore_settings(&self, metadata: &TakeMetadata) {
+        *self.settings.lock().unwrap() = metadata.settings.clone();
+        let configured = self
+            .model_paths
+            .lock()
+            .unwrap()
+            .contains(&metadata.model_dir);
+        if configured {
+            *self.model_path.lock().unwrap() = Some(metadata.model_dir.clone());
+        }
+        self.notify_changed();
+    }
+
+    /// Start or stop capturing input audio. Starting discards the previous recording.
+    pub fn set_recording(&self, recording: bool) {
+        let was_recording = self.is_recording.swap(recording, Ordering::Relaxed);
+        if recording && !was_recording {
+            self.recorded_audio.lock().unwrap().clear();
+        }
+    }
+
+    /// The selected take, if any.
+    pub fn current_take(&self) -> Option<Take> {
+        let selected = (*self.selected_take.lock().unwrap())?;
+        self.takes.lock().unwrap().get(selected).cloned()
+    }
+
+    /// Estimate the tempo and key of `recorded_audio`. This takes a while for
+    /// long recordings, so call it off the GUI thread.
+    pub fn analyze_recording(&self) -> RecordingAnalysis {
+        let samples = self.recorded_audio.lock().unwrap().mix_down();
+        let sample_rate = *self.sample_rate.lock().unwrap() as u32;
+        RecordingAnalysis {
+            bpm: tempo::estimate_tempo(&samples, sample_rate, None),
+            key: key::estimate_key(&samples, sample_rate),
+        }
+    }
+
+    /// Append `take`, e.g. one opened from the library, and select it.
+    pub fn add_take(&self, take: Take) {
+        let mut takes = self.takes.lock().unwrap();
+        *self.generated_audio.lock().unwrap() = Some(take.audio.to_vec());
+        takes.push(take);
+        *self.selected_take.lock().unwrap() = Some(takes.len() - 1);
+    }
+
+    /// Make the take at `index` the current one. Returns false if it doesn't exist.
+    pub fn select_take(&self, index: usize) -> bool {
+        let takes = self.takes.lock().unwrap();
+        let Some(take) = takes.get(index) else {
+            return false;
+        };
+        *self.generated_audio.lock().unwrap() = Some(take.audio.to_vec());
+        *self.selected_take.lock().unwrap() = Some(index);
+        true
+    }
 }
 
 impl Default for SharedState {
@@ -0,0 +1,510 @@
+use crate::beats::BeatGrid;
+use crate::key::Key;
+use crate::musicgen::{GenerationParams, SAMPLE_RATE};
+use crate::tempfiles::{slug, timestamp};
+use crate::worker::Take;
+use crate::{metadata, prompts, resample, wav, GenerationSettings};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Size of the waveform thumbnail saved with every entry.
+pub const THUMBNAIL_WIDTH: u32 = 128;
+pub const THUMBNAIL_HEIGHT: u32 = 32;
+/// Thumbnail colour, the output colour of the editor's waveform view.
+const THUMBNAIL_COLOR: [u8; 3] = [61, 122, 209];
+
+/// Everything known about a saved take, stored next to its audio as JSON.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EntryInfo {
+    /// Prompt as typed, without the tempo and key hints.
+    pub prompt: String,
+    /// Prompt as sent to the model.
+    pub full_prompt: String,
+    /// Name of the model folder, for display and filtering.
+    pub model: String,
+    pub model_dir: PathBuf,
+    /// Parameters of the generation; `params.seed` is the seed it used.
+    pub params: GenerationParams,
+    /// Tempo of the beat grid, or the requested tempo without one.
+    #[serde(default)]
+    pub bpm: Option<f32>,
+    #[serde(default)]
+    pub bars: Option<u32>,
+    /// Beats per bar of the time signature it was generated in.
+    #[serde(default)]
+    pub beats_per_bar: Option<u32>,
+    #[serde(default)]
+    pub key: Option<String>,
+    /// Template the full prompt was built from.
+    #[serde(default)]
+    pub template: Option<String>,
+    #[serde(default)]
+    pub genre: Option<String>,
+    #[serde(default)]
+    pub instruments: Vec<String>,
+    #[serde(default)]
+    pub loop_mode: bool,
+    #[serde(default)]
+    pub grid: Option<BeatGrid>,
+    /// The audio loops seamlessly.
+    #[serde(default)]
+    pub looped: bool,
+    /// The take was generated from editor settings, which are restored with it.
+    #[serde(default)]
+    pub tempo_synced: bool,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub starred: bool,
+    /// Seconds since the Unix epoch.
+    pub created: u64,
+}
+
+impl EntryInfo {
+    pub fn from_take(take: &Take) -> Self {
+        let settings = take.settings.as_ref();
+        Self {
+            prompt: settings.map_or_else(|| take.prompt.clone(), |s| s.prompt.clone()),
+            full_prompt: take.prompt.clone(),
+            model: metadata::model_name(&take.model_dir),
+            model_dir: take.model_dir.clone(),
+            params: take.params.clone(),
+            bpm: take.grid.map(|grid| grid.bpm).or(settings.map(|s| s.bpm)),
+            bars: settings.map(|s| s.bars),
+            beats_per_bar: Some(take.beats_per_bar),
+            key: settings.and_then(|s| s.key).map(|key| key.to_string()),
+            template: settings.map(|s| s.template.clone()),
+            genre: settings.and_then(|s| s.genre.clone()),
+            instruments: settings.map_or_else(Vec::new, |s| s.instruments.clone()),
+            loop_mode: settings.is_some_and(|s| s.loop_mode),
+            grid: take.grid,
+            looped: take.looped,
+            tempo_synced: settings.is_some(),
+            tags: Vec::new(),
+            starred: false,
+            created: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+        }
+    }
+
+    /// The settings of the job that produced the take, if it came from the editor.
+    fn settings(&self) -> Option<GenerationSettings> {
+        self.tempo_synced.then(|| GenerationSettings {
+            prompt: self.prompt.clone(),
+            template: self
+                .template
+                .clone()
+                .unwrap_or_else(|| prompts::DEFAULT_TEMPLATE.into()),
+            genre: self.genre.clone(),
+            instruments: self.instruments.clone(),
+            bpm: self.bpm.unwrap_or(GenerationSettings::default().bpm),
+            bars: self.bars.unwrap_or(GenerationSettings::default().bars),
+            key: self.key.as_deref().and_then(|key| key.parse::<Key>().ok()),
+            loop_mode: self.loop_mode,
+            guidance_scale: self.params.guidance_scale,
+            top_k: self.params.top_k,
+            seed: self.params.seed,
+        })
+    }
+}
+
+/// A take in the library: `<id>.wav`, its `<id>.json` sidecar and a
+/// `<id>.png` thumbnail.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LibraryEntry {
+    pub id: String,
+    dir: PathBuf,
+    pub info: EntryInfo,
+    /// Read from the PNG, missing if it was deleted or is unreadable.
+    pub thumbnail: Option<Thumbnail>,
+}
+
+impl LibraryEntry {
+    pub fn audio_path(&self) -> PathBuf {
+        self.dir.join(format!("{}.wav", self.id))
+    }
+
+    pub fn sidecar_path(&self) -> PathBuf {
+        self.dir.join(format!("{}.json", self.id))
+    }
+
+    pub fn thumbnail_path(&self) -> PathBuf {
+        self.dir.join(format!("{}.png", self.id))
+    }
+
+    /// Read the take back, with the grid, loop flag and settings it was
+    /// generated with. Its job id is 0.
+    pub fn load_take(&self) -> Result<Take, Box<dyn std::error::Error>> {
+        let (samples, rate) = wav::read_wav(&self.audio_path())?;
+        let audio = resample::resample(&samples, rate, SAMPLE_RATE);
+        Ok(Take {
+            job_id: 0,
+            prompt: self.info.full_prompt.clone(),
+            model_dir: self.info.model_dir.clone(),
+            params: self.info.params.clone(),
+            audio: Arc::new(audio),
+            grid: self.info.grid,
+            looped: self.info.looped,
+            beats_per_bar: self
+                .info
+                .beats_per_bar
+                .or(self.info.grid.map(|grid| grid.beats_per_bar))
+                .unwrap_or(4),
+            settings: self.info.settings(),
+        })
+    }
+
+    /// Write the sidecar, e.g. after changing tags or the star.
+    pub fn save_info(&self) -> Result<(), Box<dyn std::error::Error>> {
+        fs::write(
+            self.sidecar_path(),
+            serde_json::to_string_pretty(&self.info)?,
+        )?;
+        Ok(())
+    }
+}
+
+/// Folder of automatically saved takes.
+#[derive(Debug, Clone)]
+pub struct Library {
+    dir: PathBuf,
+}
+
+impl Library {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Save `take` under a new id made of the current time and its prompt.
+    pub fn add(&self, take: &Take) -> Result<LibraryEntry, Box<dyn std::error::Error>> {
+        fs::create_dir_all(&self.dir)?;
+        let info = EntryInfo::from_take(take);
+        let stem = format!("{}_{}", timestamp(SystemTime::now()), slug(&info.prompt));
+        // Claiming the sidecar name keeps concurrent saves apart
+        let mut entry = LibraryEntry {
+            id: stem.clone(),
+            dir: self.dir.clone(),
+            info,
+            thumbnail: Some(Thumbnail::from_audio(&take.audio, THUMBNAIL_WIDTH as usize)),
+        };
+        for n in 2.. {
+            match OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(entry.sidecar_path())
+            {
+                Ok(_) => break,
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    entry.id = format!("{}-{}", stem, n);
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        take.write_wav(&entry.audio_path())?;
+        if let Some(thumbnail) = &entry.thumbnail {
+            thumbnail.write_png(&entry.thumbnail_path())?;
+        }
+        entry.save_info()?;
+        Ok(entry)
+    }
+
+    /// Every entry with a readable sidecar, newest first.
+    pub fn entries(&self) -> Vec<LibraryEntry> {
+        let Ok(dir) = fs::read_dir(&self.dir) else {
+            return Vec::new();
+        };
+        let mut entries: Vec<LibraryEntry> = dir
+            .flatten()
+            .filter_map(|file| {
+                let path = file.path();
+                if path.extension()? != "json" {
+                    return None;
+                }
+                let info = serde_json::from_str(&fs::read_to_string(&path).ok()?).ok()?;
+                let mut entry = LibraryEntry {
+                    id: path.file_stem()?.to_string_lossy().into_owned(),
+                    dir: self.dir.clone(),
+                    info,
+                    thumbnail: None,
+                };
+                entry.thumbnail = Thumbnail::read_png(&entry.thumbnail_path()).ok();
+                Some(entry)
+            })
+            .collect();
+        entries.sort_by(|a, b| (b.info.created, &b.id).cmp(&(a.info.created, &a.id)));
+        entries
+    }
+
+    /// Delete the entry's audio, sidecar and thumbnail.
+    pub fn remove(&self, entry: &LibraryEntry) -> io::Result<()> {
+        fs::remove_file(entry.sidecar_path())?;
+        let _ = fs::remove_file(entry.audio_path());
+        let _ = fs::remove_file(entry.thumbnail_path());
+        Ok(())
+    }
+}
+
+/// Which entries the library browser lists.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LibraryFilter {
+    /// Words that must all appear in the prompt or a tag, ignoring case.
+    /// Words starting with `#` must be tags.
+    pub text: String,
+    pub starred_only: bool,
+    /// Inclusive tempo range; entries without a tempo never match.
+    pub bpm: Option<(f32, f32)>,
+    /// Model name as in [`EntryInfo::model`].
+    pub model: Option<String>,
+}
+
+impl LibraryFilter {
+    pub fn matches(&self, info: &EntryInfo) -> bool {
+        if self.starred_only && !info.starred {
+            return false;
+        }
+        if let Some((low, high)) = self.bpm {
+            if !info
+                .bpm
+                .is_some_and(|bpm| (low..=high).contains(&bpm.round()))
+            {
+                return false;
+            }
+        }
+        if self
+            .model
+            .as_ref()
+            .is_some_and(|model| *model != info.model)
+        {
+            return false;
+        }
+        let prompt = info.prompt.to_lowercase();
+        let tags: Vec<String> = info.tags.iter().map(|tag| tag.to_lowercase()).collect();
+        self.text.split_whitespace().all(|word| {
+            let word = word.to_lowercase();
+            match word.strip_prefix('#') {
+                Some(tag) => tags.iter().any(|t| t == tag),
+                None => prompt.contains(&word) || tags.iter().any(|t| t.contains(&word)),
+            }
+        })
+    }
+
+    /// Parse a tempo filter such as `120` or `90-110`; `None` for anything else.
+    pub fn parse_bpm(text: &str) -> Option<(f32, f32)> {
+        let (low, high) = match text.split_once('-') {
+            Some((low, high)) => (low.trim().parse().ok()?, high.trim().parse().ok()?),
+            None => {
+                let bpm: f32 = text.trim().parse().ok()?;
+                (bpm, bpm)
+            }
+        };
+        (low <= high).then_some((low, high))
+    }
+}
+
+/// Tags typed as a comma separated list, trimmed and without empties.
+pub fn parse_tags(text: &str) -> Vec<String> {
+    text.split(',')
+        .map(str::trim)
+        .filter(|tag| !tag.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// Peak level of evenly spaced columns of a take, drawn as a small waveform.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Thumbnail {
+    /// Peak absolute sample value of each column, 0 to 1.
+    pub columns: Vec<f32>,
+}
+
+impl Thumbnail {
+    pub fn from_audio(samples: &[f32], width: usize) -> Self {
+        let columns = (0..width)
+            .map(|column| {
+                let start = column * samples.len() / width;
+                let end = ((column + 1) * samples.len() / width).max(start);
+                samples[start..end]
+                    .iter()
+                    .fold(0.0f32, |peak, s| peak.max(s.abs()))
+                    .min(1.0)
+            })
+            .collect();
+        Self { columns }
+    }
+
+    /// Draw the columns centered on a transparent background.
+    pub fn write_png(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let (width, height) = (self.columns.len(), THUMBNAIL_HEIGHT as usize);
+        let mut pixels = vec![0u8; width * height * 4];
+        for (x, peak) in self.columns.iter().enumerate() {
+            // At least the center line, kept symmetric
+            let half = ((peak * height as f32 / 2.0).round() as usize).clamp(1, height / 2);
+            for y in height / 2 - half..height / 2 + half {
+                let pixel = (y * width + x) * 4;
+                pixels[pixel..pixel + 3].copy_from_slice(&THUMBNAIL_COLOR);
+                pixels[pixel + 3] = 255;
+            }
+        }
+        let mut encoder = png::Encoder::new(
+            BufWriter::new(File::create(path)?),
+            width as u32,
+            height as u32,
+        );
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header()?.write_image_data(&pixels)?;
+        Ok(())
+    }
+
+    /// Recover the columns from a PNG written by [`Thumbnail::write_png`].
+    pub fn read_png(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut reader = png::Decoder::new(File::open(path)?).read_info()?;
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        let frame = reader.next_frame(&mut pixels)?;
+        if frame.color_type != png::ColorType::Rgba || frame.bit_depth != png::BitDepth::Eight {
+            return Err("not a Poing thumbnail".into());
+        }
+        let (width, height) = (frame.width as usize, frame.height as usize);
+        let columns = (0..width)
+            .map(|x| {
+                let opaque = (0..height)
+                    .filter(|y| pixels[(y * width + x) * 4 + 3] > 0)
+                    .count();
+                opaque as f32 / height as f32
+            })
+            .collect();
+        Ok(Self { columns })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_take() -> Take {
+        let audio: Vec<f32> = (0..SAMPLE_RATE as usize * 2)
+            .map(|i| if i < SAMPLE_RATE as usize { 0.5 } else { -0.25 })
+            .collect();
+        Take {
+            job_id: 7,
+            prompt: "96 bpm. Dusty breakbeat".into(),
+            model_dir: PathBuf::from("/models/musicgen-small"),
+            params: GenerationParams {
+                duration_seconds: 2.0,
+                seed: Some(42),
+                ..GenerationParams::default()
+            },
+            audio: Arc::new(audio),
+            grid: Some(BeatGrid {
+                bpm: 96.0,
+                beats_per_bar: 4,
+                first_downbeat: 0.1,
+            }),
+            looped: true,
+            beats_per_bar: 4,
+            settings: Some(GenerationSettings {
+                prompt: "Dusty breakbeat".into(),
+                template: "{bpm} bpm. {prompt}".into(),
+                bpm: 96.0,
+                bars: 1,
+                seed: Some(42),
+                ..GenerationSettings::default()
+            }),
+        }
+    }
+
+    #[test]
+    fn test_saved_take_loads_back() {
+        let dir = std::env::temp_dir().join(format!("poing_library_test_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let library = Library::new(dir.clone());
+        let take = test_take();
+        let first = library.add(&take).unwrap();
+        let second = library.add(&take).unwrap();
+        assert_ne!(first.id, second.id);
+        assert!(first.id.ends_with("_dusty-breakbeat"), "{}", first.id);
+
+        let mut entries = library.entries();
+        assert_eq!(entries.len(), 2);
+        let entry = entries.iter_mut().find(|e| e.id == first.id).unwrap();
+        assert_eq!(entry.info.model, "musicgen-small");
+        assert_eq!(entry.info.bpm, Some(96.0));
+        assert_eq!(entry.info.template.as_deref(), Some("{bpm} bpm. {prompt}"));
+        let thumbnail = entry.thumbnail.as_ref().unwrap();
+        assert_eq!(thumbnail.columns.len(), THUMBNAIL_WIDTH as usize);
+        assert_eq!(thumbnail.columns[0], 0.5);
+        assert_eq!(thumbnail.columns[THUMBNAIL_WIDTH as usize - 1], 0.25);
+
+        let loaded = entry.load_take().unwrap();
+        assert_eq!(loaded.audio, take.audio);
+        assert_eq!(
+            Take {
+                job_id: take.job_id,
+                ..loaded
+            },
+            take
+        );
+
+        entry.info.starred = true;
+        entry.info.tags = parse_tags("drums, ,vinyl ");
+        entry.save_info().unwrap();
+        let reloaded = library.entries();
+        let entry = reloaded.iter().find(|e| e.id == first.id).unwrap();
+        assert!(entry.info.starred);
+        assert_eq!(entry.info.tags, ["drums", "vinyl"]);
+
+        library.remove(entry).unwrap();
+        assert_eq!(library.entries().len(), 1);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_filter_by_text_tag_star_bpm_and_model() {
+        let mut info = EntryInfo::from_take(&test_take());
+        info.tags = vec!["Drums".into()];
+        let filter = |text: &str| LibraryFilter {
+            text: text.into(),
+            ..LibraryFilter::default()
+        };
+        assert!(filter("").matches(&info));
+        assert!(filter("BREAK dusty").matches(&info));
+        assert!(filter("drum").matches(&info));
+        assert!(filter("#drums").matches(&info));
+        assert!(!filter("#drum").matches(&info));
+        assert!(!filter("piano").matches(&info));
+
+        let starred = LibraryFilter {
+            starred_only: true,
+            ..LibraryFilter::default()
+        };
+        assert!(!starred.matches(&info));
+        info.starred = true;
+        assert!(starred.matches(&info));
+
+        assert_eq!(LibraryFilter::parse_bpm("90 - 110"), Some((90.0, 110.0)));
+        assert_eq!(LibraryFilter::parse_bpm("fast"), None);
+        let bpm = |text: &str| LibraryFilter {
+            bpm: LibraryFilter::parse_bpm(text),
+            ..LibraryFilter::default()
+        };
+        assert!(bpm("96").matches(&info));
+        assert!(bpm("90-100").matches(&info));
+        assert!(!bpm("120").matches(&info));
+
+        let model = |name: &str| LibraryFilter {
+            model: Some(name.into()),
+            ..LibraryFilter::default()
+        };
+        assert!(model("musicgen-small").matches(&info));
+        assert!(!model("musicgen-medium").matches(&info));
+    }
+}
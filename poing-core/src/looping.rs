@@ -0,0 +1,88 @@
+use std::f32::consts::FRAC_PI_2;
+
+/// Length of the fades used when there is no audio past the loop end.
+const FALLBACK_FADE_SECONDS: f32 = 0.005;
+
+/// Make the first `loop_len` samples loop seamlessly.
+///
+/// Audio past the loop end is the natural continuation of the last bar, so up
+/// to `crossfade_len` samples of it are crossfaded into the head with an
+/// equal-power curve. Playback wrapping from the last sample to the first then
+/// continues exactly where the clip would have gone, with the head fading in
+/// underneath. Without any such tail, short fades at both ends keep the seam
+/// click-free instead.
+///
+/// No zero crossing is searched for: the seam itself joins two neighbouring
+/// samples of the original clip, so it is continuous wherever it falls, and the
+/// crossfade spans many periods of anything audible. Moving the seam would also
+/// break the exact bar length.
+pub fn make_seamless(
+    samples: &[f32],
+    loop_len: usize,
+    crossfade_len: usize,
+    sample_rate: u32,
+) -> Vec<f32> {
+    let mut output = samples[..loop_len.min(samples.len())].to_vec();
+    output.resize(loop_len, 0.0);
+    let tail = samples.get(loop_len..).unwrap_or_default();
+
+    let fade = crossfade_len.min(tail.len()).min(loop_len / 2);
+    if fade == 0 {
+        let fade = ((FALLBACK_FADE_SECONDS * sample_rate as f32) as usize).min(loop_len / 2);
+        for i in 0..fade {
+            let gain = (i as f32 + 0.5) / fade as f32;
+            output[i] *= gain;
+            output[loop_len - 1 - i] *= gain;
+        }
+        return output;
+    }
+
+    for (i, (head, tail)) in output.iter_mut().zip(tail).take(fade).enumerate() {
+        let t = (i as f32 + 0.5) / fade as f32 * FRAC_PI_2;
+        *head = *head * t.sin() + tail * t.cos();
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seam_continues_into_tail() {
+        // A ramp has a large jump between its end and start
+        let samples: Vec<f32> = (0..1200).map(|i| i as f32 / 1200.0).collect();
+        let looped = make_seamless(&samples, 1000, 100, 32000);
+        assert_eq!(looped.len(), 1000);
+        // Wrapping around continues the ramp instead of jumping back to zero
+        let step = 1.0 / 1200.0;
+        assert!((looped[0] - looped[999] - step).abs() < 0.01);
+        assert_eq!(looped[500], samples[500]);
+    }
+
+    #[test]
+    fn test_seam_is_smooth_off_zero_crossing() {
+        // Loop end falls mid-cycle, far from any zero crossing
+        let samples: Vec<f32> = (0..1400)
+            .map(|i| (i as f32 * 2.0 * std::f32::consts::PI * 440.0 / 32000.0 + 0.7).sin())
+            .collect();
+        let max_step = samples
+            .windows(2)
+            .map(|w| (w[1] - w[0]).abs())
+            .fold(0.0, f32::max);
+        let looped = make_seamless(&samples, 1000, 300, 32000);
+        assert!(looped[999].abs() > 0.3);
+        let wrapped: Vec<f32> = looped.iter().chain(&looped[..1]).copied().collect();
+        for w in wrapped.windows(2) {
+            assert!((w[1] - w[0]).abs() <= max_step * 1.5);
+        }
+    }
+
+    #[test]
+    fn test_fades_without_tail() {
+        let samples = vec![1.0; 1000];
+        let looped = make_seamless(&samples, 1000, 100, 32000);
+        assert!(looped[0] < 0.05 && looped[999] < 0.05);
+        assert_eq!(looped[500], 1.0);
+    }
+}
@@ -0,0 +1,288 @@
+use crate::key::Key;
+use crate::worker::Take;
+use crate::{wav, GenerationSettings};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Software name written to the INFO chunk, and recognized on import.
+const SOFTWARE: &str = "Poing";
+/// Root element holding our fields inside the iXML document.
+const IXML_SECTION: &str = "POING";
+
+/// ACID chunk flags.
+const ACID_ONE_SHOT: u32 = 0x01;
+const ACID_ROOT_NOTE_SET: u32 = 0x02;
+const ACID_STRETCH: u32 = 0x04;
+
+/// How a take was generated: everything needed to regenerate or vary it.
+/// `settings.seed` is the seed the take was generated with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TakeMetadata {
+    pub settings: GenerationSettings,
+    /// Prompt as sent to the model, including the tempo and key hints.
+    pub full_prompt: String,
+    pub model_dir: PathBuf,
+    pub beats_per_bar: u32,
+    pub duration_seconds: f32,
+    /// The audio was stretched to `settings.bpm` and `settings.bars`. Only
+    /// then is an ACID chunk written.
+    pub tempo_synced: bool,
+}
+
+impl TakeMetadata {
+    pub fn from_take(take: &Take) -> Self {
+        let mut settings = take.settings.clone().unwrap_or_else(|| GenerationSettings {
+            prompt: take.prompt.clone(),
+            guidance_scale: take.params.guidance_scale,
+            top_k: take.params.top_k,
+            ..GenerationSettings::default()
+        });
+        settings.seed = take.params.seed;
+        if let Some(grid) = take.grid {
+            settings.bpm = grid.bpm;
+        }
+        Self {
+            settings,
+            full_prompt: take.prompt.clone(),
+            model_dir: take.model_dir.clone(),
+            beats_per_bar: take.grid.map_or(4, |grid| grid.beats_per_bar),
+            duration_seconds: take.params.duration_seconds,
+            tempo_synced: take.settings.is_some(),
+        }
+    }
+
+    /// Field name and value pairs stored in the iXML chunk.
+    fn fields(&self) -> Vec<(&'static str, String)> {
+        let settings = &self.settings;
+        vec![
+            ("PROMPT", settings.prompt.clone()),
+            ("FULL_PROMPT", self.full_prompt.clone()),
+            ("TEMPLATE", settings.template.clone()),
+            ("GENRE", settings.genre.clone().unwrap_or_default()),
+            ("INSTRUMENTS", settings.instruments.join(", ")),
+            ("MODEL", self.model_dir.to_string_lossy().into_owned()),
+            (
+                "SEED",
+                settings
+                    .seed
+                    .map(|seed| seed.to_string())
+                    .unwrap_or_default(),
+            ),
+            ("GUIDANCE", settings.guidance_scale.to_string()),
+            ("TOP_K", settings.top_k.to_string()),
+            ("BPM", settings.bpm.to_string()),
+            ("BARS", settings.bars.to_string()),
+            ("BEATS_PER_BAR", self.beats_per_bar.to_string()),
+            (
+                "KEY",
+                settings.key.map(|key| key.to_string()).unwrap_or_default(),
+            ),
+            ("LOOP", (settings.loop_mode as u8).to_string()),
+            ("DURATION", self.duration_seconds.to_string()),
+            ("TEMPO_SYNCED", (self.tempo_synced as u8).to_string()),
+        ]
+    }
+}
+
+/// Append LIST/INFO, iXML and (for tempo-synced takes) ACID chunks describing `metadata` to an
+/// existing WAV file.
+///
+/// INFO carries the prompt and a readable summary for file browsers, iXML
+/// every setting for [`read_metadata`], and ACID the tempo and bar length so
+/// DAWs stretch the file to the project tempo on import.
+pub fn write_metadata(
+    path: &Path,
+    metadata: &TakeMetadata,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let settings = &metadata.settings;
+    let mut comment = format!(
+        "{} | model {} | guidance {} | top-k {}",
+        metadata.full_prompt,
+        model_name(&metadata.model_dir),
+        settings.guidance_scale,
+        settings.top_k
+    );
+    if let Some(seed) = settings.seed {
+        comment.push_str(&format!(" | seed {}", seed));
+    }
+    let mut info = b"INFO".to_vec();
+    for (id, text) in [
+        (b"INAM", settings.prompt.as_str()),
+        (b"ICMT", comment.as_str()),
+        (b"ISFT", SOFTWARE),
+    ] {
+        let mut data = text.as_bytes().to_vec();
+        data.push(0);
+        info.extend_from_slice(id);
+        info.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        info.extend_from_slice(&data);
+        if data.len() % 2 == 1 {
+            info.push(0);
+        }
+    }
+    wav::append_chunk(path, b"LIST", &info)?;
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<BWFXML>\n");
+    xml.push_str("  <IXML_VERSION>2.10</IXML_VERSION>\n");
+    xml.push_str(&format!("  <PROJECT>{}</PROJECT>\n", SOFTWARE));
+    xml.push_str(&format!(
+        "  <NOTE>{}</NOTE>\n",
+        escape_xml(&metadata.full_prompt)
+    ));
+    xml.push_str(&format!("  <{}>\n", IXML_SECTION));
+    for (tag, value) in metadata.fields() {
+        xml.push_str(&format!("    <{tag}>{}</{tag}>\n", escape_xml(&value)));
+    }
+    xml.push_str(&format!("  </{}>\n</BWFXML>\n", IXML_SECTION));
+    wav::append_chunk(path, b"iXML", xml.as_bytes())?;
+    if !metadata.tempo_synced {
+        return Ok(());
+    }
+
+    let mut flags = ACID_STRETCH;
+    if !settings.loop_mode {
+        flags |= ACID_ONE_SHOT;
+    }
+    // Root note in MIDI, tonic in the octave of middle C
+    let root_note = match settings.key {
+        Some(key) => {
+            flags |= ACID_ROOT_NOTE_SET;
+            60 + key.tonic as u16
+        }
+        None => 60,
+    };
+    let mut acid = Vec::with_capacity(24);
+    acid.extend_from_slice(&flags.to_le_bytes());
+    acid.extend_from_slice(&root_note.to_le_bytes());
+    acid.extend_from_slice(&0x8000u16.to_le_bytes());
+    acid.extend_from_slice(&0f32.to_le_bytes());
+    acid.extend_from_slice(&(settings.bars * metadata.beats_per_bar).to_le_bytes());
+    acid.extend_from_slice(&4u16.to_le_bytes());
+    acid.extend_from_slice(&(metadata.beats_per_bar as u16).to_le_bytes());
+    acid.extend_from_slice(&settings.bpm.to_le_bytes());
+    wav::append_chunk(path, b"acid", &acid)
+}
+
+/// Read the metadata written by [`write_metadata`], or `None` for WAV files
+/// not exported by Poing.
+pub fn read_metadata(path: &Path) -> Result<Option<TakeMetadata>, Box<dyn std::error::Error>> {
+    let Some((_, xml)) = wav::read_chunks(path)?
+        .into_iter()
+        .find(|(id, _)| id == b"iXML")
+    else {
+        return Ok(None);
+    };
+    let xml = String::from_utf8_lossy(&xml);
+    let Some(section) = xml_element(&xml, IXML_SECTION) else {
+        return Ok(None);
+    };
+    let field = |tag: &str| xml_element(section, tag).map(unescape_xml);
+
+    let defaults = GenerationSettings::default();
+    let settings = GenerationSettings {
+        prompt: field("PROMPT").unwrap_or_default(),
+        template: field("TEMPLATE").unwrap_or(defaults.template),
+        genre: field("GENRE").filter(|genre| !genre.is_empty()),
+        instruments: field("INSTRUMENTS")
+            .map(|list| {
+                list.split(',')
+                    .map(str::trim)
+                    .filter(|instrument| !instrument.is_empty())
+                    .map(String::from)
+                    .collect()
+            })
+            .unwrap_or_default(),
+        bpm: parse_field(section, "BPM").unwrap_or(defaults.bpm),
+        bars: parse_field(section, "BARS").unwrap_or(defaults.bars),
+        key: field("KEY").and_then(|key| key.parse::<Key>().ok()),
+        loop_mode: field("LOOP").as_deref() == Some("1"),
+        guidance_scale: parse_field(section, "GUIDANCE").unwrap_or(defaults.guidance_scale),
+        top_k: parse_field(section, "TOP_K").unwrap_or(defaults.top_k),
+        seed: parse_field(section, "SEED"),
+    };
+    Ok(Some(TakeMetadata {
+        full_prompt: field("FULL_PROMPT").unwrap_or_else(|| settings.full_prompt()),
+        settings,
+        model_dir: field("MODEL").map(PathBuf::from).unwrap_or_default(),
+        beats_per_bar: parse_field(section, "BEATS_PER_BAR").unwrap_or(4),
+        duration_seconds: parse_field(section, "DURATION").unwrap_or(0.0),
+        tempo_synced: field("TEMPO_SYNCED").as_deref() == Some("1"),
+    }))
+}
+
+fn parse_field<T: FromStr>(section: &str, tag: &str) -> Option<T> {
+    xml_element(section, tag)?.trim().parse().ok()
+}
+
+pub(crate) fn model_name(model_dir: &Path) -> String {
+    model_dir
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| model_dir.to_string_lossy().into_owned())
+}
+
+/// Text between the first `<tag>` and the following `</tag>`.
+fn xml_element<'a>(xml: &'a str, tag: &str) -> Option<&'a str> {
+    let open = format!("<{}>", tag);
+    let start = xml.find(&open)? + open.len();
+    let end = xml[start..].find(&format!("</{}>", tag))?;
+    Some(&xml[start..start + end])
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn unescape_xml(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metadata_round_trip() {
+        let path = std::env::temp_dir().join(format!("poing_metadata_{}.wav", std::process::id()));
+        wav::write_wav(&[0.0; 3200], 32000, &path).unwrap();
+        let metadata = TakeMetadata {
+            settings: GenerationSettings {
+                prompt: "acid <303> line & \"squelch\"".into(),
+                template: "[{genre}, ]{bpm} bpm. {prompt}".into(),
+                genre: Some("acid house".into()),
+                instruments: vec!["303".into(), "drum machine".into()],
+                bpm: 138.0,
+                bars: 8,
+                key: "F# minor".parse().ok(),
+                loop_mode: true,
+                guidance_scale: 4.5,
+                top_k: 120,
+                seed: Some(987654321),
+            },
+            full_prompt: "138 bpm, in F# minor. acid <303> line".into(),
+            model_dir: PathBuf::from("/models/musicgen-small"),
+            beats_per_bar: 4,
+            duration_seconds: 16.5,
+            tempo_synced: true,
+        };
+        write_metadata(&path, &metadata).unwrap();
+
+        assert_eq!(read_metadata(&path).unwrap(), Some(metadata));
+        let chunks = wav::read_chunks(&path).unwrap();
+        let acid = &chunks.iter().find(|(id, _)| id == b"acid").unwrap().1;
+        assert_eq!(u32::from_le_bytes(acid[12..16].try_into().unwrap()), 32);
+        assert_eq!(f32::from_le_bytes(acid[20..24].try_into().unwrap()), 138.0);
+        assert_eq!(wav::read_wav(&path).unwrap().0.len(), 3200);
+
+        wav::write_wav(&[0.0; 100], 32000, &path).unwrap();
+        assert_eq!(read_metadata(&path).unwrap(), None);
+        let _ = std::fs::remove_file(&path);
+    }
+}
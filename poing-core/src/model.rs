@@ -1,6 +1,16 @@
 use ort::session::Session;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek};
 use std::path::Path;
 
+/// Name and element type of one model input or output.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TensorInfo {
+    pub name: String,
+    pub dtype: String,
+}
+
 pub struct OnnxModel {
     pub session: Session,
 }
@@ -25,4 +35,365 @@ impl OnnxModel {
             .map(|o| o.name())
             .collect()
     }
+
+    /// Describe the model's inputs.
+    pub fn inputs(&self) -> Vec<TensorInfo> {
+        self.session
+            .inputs()
+            .iter()
+            .map(|i| TensorInfo {
+                name: i.name().to_string(),
+                dtype: format!("{:?}", i.dtype()),
+            })
+            .collect()
+    }
+
+    /// Describe the model's outputs.
+    pub fn outputs(&self) -> Vec<TensorInfo> {
+        self.session
+            .outputs()
+            .iter()
+            .map(|o| TensorInfo {
+                name: o.name().to_string(),
+                dtype: format!("{:?}", o.dtype()),
+            })
+            .collect()
+    }
+}
+
+/// Every `.onnx` file in a model directory, sorted by name.
+pub fn onnx_files(model_dir: &Path) -> std::io::Result<Vec<std::path::PathBuf>> {
+    let mut files: Vec<_> = std::fs::read_dir(model_dir)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "onnx"))
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+/// Precision of a model's weights.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Quantization {
+    Int8,
+    Fp16,
+    Fp32,
+}
+
+impl Quantization {
+    pub fn name(self) -> &'static str {
+        match self {
+            Quantization::Int8 => "int8",
+            Quantization::Fp16 => "fp16",
+            Quantization::Fp32 => "fp32",
+        }
+    }
+}
+
+/// Operators only found in quantized graphs.
+const QUANTIZED_OPS: &[&str] = &[
+    "ConvInteger",
+    "DequantizeLinear",
+    "DynamicQuantizeLinear",
+    "MatMulInteger",
+    "MatMulNBits",
+    "QLinearConv",
+    "QLinearMatMul",
+];
+
+// Field numbers in onnx.proto
+const MODEL_GRAPH: u32 = 7;
+const GRAPH_NODE: u32 = 1;
+const GRAPH_INITIALIZER: u32 = 5;
+const NODE_OP_TYPE: u32 = 4;
+const NODE_ATTRIBUTE: u32 = 5;
+const ATTRIBUTE_GRAPH: u32 = 6;
+const ATTRIBUTE_GRAPHS: u32 = 11;
+const TENSOR_DIMS: u32 = 1;
+const TENSOR_DATA_TYPE: u32 = 2;
+
+/// Tell the precision of an ONNX file from its graph: quantization
+/// operators or mostly 8-bit weights mean int8, otherwise whichever of
+/// fp16 and fp32 holds more weight bytes.
+///
+/// Only the graph structure is read; weight data is skipped, so this is
+/// quick even for large files.
+pub fn detect_quantization(path: &Path) -> io::Result<Quantization> {
+    let file = File::open(path)?;
+    let end = file.metadata()?.len();
+    let mut reader = ProtoReader {
+        reader: BufReader::new(file),
+        position: 0,
+    };
+    let mut stats = WeightStats::default();
+    while let Some((number, field)) = reader.field(end)? {
+        match (number, field) {
+            (MODEL_GRAPH, Field::Bytes(graph_end)) => {
+                read_graph(&mut reader, graph_end, &mut stats)?
+            }
+            (_, Field::Bytes(field_end)) => reader.skip_to(field_end)?,
+            _ => {}
+        }
+    }
+    stats
+        .quantization()
+        .ok_or_else(|| invalid_data("no weights found in the graph"))
+}
+
+/// Weight bytes by precision, and whether quantization operators occur.
+#[derive(Default)]
+struct WeightStats {
+    int8: u64,
+    fp16: u64,
+    fp32: u64,
+    quantized_ops: bool,
+}
+
+impl WeightStats {
+    fn quantization(&self) -> Option<Quantization> {
+        if self.quantized_ops || self.int8 > self.fp16 + self.fp32 {
+            Some(Quantization::Int8)
+        } else if self.fp16 > self.fp32 {
+            Some(Quantization::Fp16)
+        } else if self.fp32 > 0 {
+            Some(Quantization::Fp32)
+        } else {
+            None
+        }
+    }
+}
+
+fn read_graph<R: Read + Seek>(
+    reader: &mut ProtoReader<R>,
+    end: u64,
+    stats: &mut WeightStats,
+) -> io::Result<()> {
+    while let Some((number, field)) = reader.field(end)? {
+        match (number, field) {
+            (GRAPH_NODE, Field::Bytes(node_end)) => read_node(reader, node_end, stats)?,
+            (GRAPH_INITIALIZER, Field::Bytes(tensor_end)) => {
+                read_initializer(reader, tensor_end, stats)?
+            }
+            (_, Field::Bytes(field_end)) => reader.skip_to(field_end)?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Note quantization operators, including those in subgraphs such as the
+/// branches of the `If` in merged decoders.
+fn read_node<R: Read + Seek>(
+    reader: &mut ProtoReader<R>,
+    end: u64,
+    stats: &mut WeightStats,
+) -> io::Result<()> {
+    while let Some((number, field)) = reader.field(end)? {
+        match (number, field) {
+            (NODE_OP_TYPE, Field::Bytes(op_end)) => {
+                let op_type = reader.bytes(op_end)?;
+                stats.quantized_ops |= QUANTIZED_OPS
+                    .iter()
+                    .any(|op| op.as_bytes() == op_type.as_slice());
+            }
+            (NODE_ATTRIBUTE, Field::Bytes(attribute_end)) => {
+                while let Some((number, field)) = reader.field(attribute_end)? {
+                    match (number, field) {
+                        (ATTRIBUTE_GRAPH | ATTRIBUTE_GRAPHS, Field::Bytes(graph_end)) => {
+                            read_graph(reader, graph_end, stats)?
+                        }
+                        (_, Field::Bytes(field_end)) => reader.skip_to(field_end)?,
+                        _ => {}
+                    }
+                }
+            }
+            (_, Field::Bytes(field_end)) => reader.skip_to(field_end)?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn read_initializer<R: Read + Seek>(
+    reader: &mut ProtoReader<R>,
+    end: u64,
+    stats: &mut WeightStats,
+) -> io::Result<()> {
+    let mut elements = 1u64;
+    let mut data_type = 0;
+    while let Some((number, field)) = reader.field(end)? {
+        match (number, field) {
+            (TENSOR_DIMS, Field::Varint(dim)) => elements = elements.saturating_mul(dim),
+            (TENSOR_DIMS, Field::Bytes(dims_end)) => {
+                while reader.position < dims_end {
+                    elements = elements.saturating_mul(reader.varint()?);
+                }
+            }
+            (TENSOR_DATA_TYPE, Field::Varint(value)) => data_type = value,
+            (_, Field::Bytes(field_end)) => reader.skip_to(field_end)?,
+            _ => {}
+        }
+    }
+    // TensorProto.DataType: UINT8, INT8, FLOAT16, BFLOAT16 and FLOAT
+    match data_type {
+        2 | 3 => stats.int8 = stats.int8.saturating_add(elements),
+        10 | 16 => stats.fp16 = stats.fp16.saturating_add(elements.saturating_mul(2)),
+        1 => stats.fp32 = stats.fp32.saturating_add(elements.saturating_mul(4)),
+        _ => {}
+    }
+    Ok(())
+}
+
+/// A protobuf field value; length-delimited fields give the offset they end at.
+enum Field {
+    Varint(u64),
+    Bytes(u64),
+    Fixed,
+}
+
+/// Reads protobuf fields one at a time, skipping over the contents of
+/// those that are not needed.
+struct ProtoReader<R> {
+    reader: BufReader<R>,
+    position: u64,
+}
+
+impl<R: Read + Seek> ProtoReader<R> {
+    /// The next field of the message ending at `end`, or `None` at its end.
+    fn field(&mut self, end: u64) -> io::Result<Option<(u32, Field)>> {
+        if self.position >= end {
+            return Ok(None);
+        }
+        let key = self.varint()?;
+        let number = (key >> 3) as u32;
+        let field = match key & 7 {
+            0 => Field::Varint(self.varint()?),
+            1 => {
+                self.skip_to(self.position + 8)?;
+                Field::Fixed
+            }
+            2 => {
+                let length = self.varint()?;
+                let field_end = self.position.saturating_add(length);
+                if field_end > end {
+                    return Err(invalid_data("field runs past the end of its message"));
+                }
+                Field::Bytes(field_end)
+            }
+            5 => {
+                self.skip_to(self.position + 4)?;
+                Field::Fixed
+            }
+            _ => return Err(invalid_data("unsupported protobuf wire type")),
+        };
+        Ok(Some((number, field)))
+    }
+
+    fn varint(&mut self) -> io::Result<u64> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let mut byte = [0];
+            self.reader.read_exact(&mut byte)?;
+            self.position += 1;
+            value |= u64::from(byte[0] & 0x7f) << shift;
+            if byte[0] & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(invalid_data("varint too long"))
+    }
+
+    fn bytes(&mut self, end: u64) -> io::Result<Vec<u8>> {
+        let mut bytes = vec![0; (end - self.position) as usize];
+        self.reader.read_exact(&mut bytes)?;
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn skip_to(&mut self, end: u64) -> io::Result<()> {
+        self.reader.seek_relative((end - self.position) as i64)?;
+        self.position = end;
+        Ok(())
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn varint(mut value: u64) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                bytes.push(byte);
+                return bytes;
+            }
+            bytes.push(byte | 0x80);
+        }
+    }
+
+    fn message(number: u32, contents: &[u8]) -> Vec<u8> {
+        let mut bytes = varint(u64::from(number) << 3 | 2);
+        bytes.extend(varint(contents.len() as u64));
+        bytes.extend_from_slice(contents);
+        bytes
+    }
+
+    fn initializer(data_type: u64, dims: &[u64]) -> Vec<u8> {
+        let mut tensor = message(
+            TENSOR_DIMS,
+            &dims.iter().flat_map(|&d| varint(d)).collect::<Vec<_>>(),
+        );
+        tensor.extend(varint(u64::from(TENSOR_DATA_TYPE) << 3));
+        tensor.extend(varint(data_type));
+        tensor.extend(message(8, b"weight"));
+        tensor.extend(message(9, &[0; 64]));
+        message(GRAPH_INITIALIZER, &tensor)
+    }
+
+    fn node(op_type: &str, subgraph: Option<&[u8]>) -> Vec<u8> {
+        let mut node = message(NODE_OP_TYPE, op_type.as_bytes());
+        if let Some(graph) = subgraph {
+            node.extend(message(NODE_ATTRIBUTE, &message(ATTRIBUTE_GRAPH, graph)));
+        }
+        message(GRAPH_NODE, &node)
+    }
+
+    fn detect(graph: &[u8]) -> io::Result<Quantization> {
+        let path = std::env::temp_dir().join(format!("poing_quant_{}.onnx", std::process::id()));
+        let mut model = varint(1 << 3);
+        model.extend(varint(8));
+        model.extend(message(MODEL_GRAPH, graph));
+        std::fs::write(&path, model).unwrap();
+        let quantization = detect_quantization(&path);
+        let _ = std::fs::remove_file(&path);
+        quantization
+    }
+
+    #[test]
+    fn test_detect_quantization() {
+        let fp32 = [initializer(1, &[64, 64]), node("MatMul", None)].concat();
+        assert_eq!(detect(&fp32).unwrap(), Quantization::Fp32);
+
+        let fp16 = [
+            initializer(10, &[64, 64]),
+            initializer(1, &[64]),
+            node("MatMul", None),
+        ]
+        .concat();
+        assert_eq!(detect(&fp16).unwrap(), Quantization::Fp16);
+
+        // Quantized operators hidden in a branch of an If node
+        let branch = node("MatMulInteger", None);
+        let merged = [initializer(1, &[64, 64]), node("If", Some(&branch))].concat();
+        assert_eq!(detect(&merged).unwrap(), Quantization::Int8);
+
+        assert!(detect(&node("MatMul", None)).is_err());
+    }
 }
@@ -1,5 +1,7 @@
 use std::collections::HashMap;
+use std::fmt;
 use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 use ndarray::{s, Array1, Array2, Array3, ArrayD, Axis, IxDyn};
 use ort::session::builder::GraphOptimizationLevel;
@@ -7,6 +9,10 @@ use ort::session::Session;
 use ort::value::Tensor;
 use rand::distributions::WeightedIndex;
 use rand::prelude::*;
+use rand::rngs::StdRng;
+use serde::{Deserialize, Serialize};
+
+use crate::config;
 
 const NUM_CODEBOOKS: usize = 4;
 const NUM_HEADS: usize = 16;
@@ -18,9 +24,17 @@ const DEFAULT_GUIDANCE_SCALE: f32 = 3.0;
 const DEFAULT_MAX_LENGTH: usize = 1500;
 const DEFAULT_TOP_K: usize = 50;
 const CODEC_FRAME_RATE: f32 = 50.0;
+/// Number of decoder steps between partial EnCodec decodes (5 seconds of audio).
+const PARTIAL_INTERVAL_STEPS: usize = 250;
+
+/// Sample rate of the audio produced by MusicGen's EnCodec decoder.
+pub const SAMPLE_RATE: u32 = 32000;
+
+/// Longest clip the decoder generates in one pass.
+pub const MAX_DURATION_SECONDS: f32 = DEFAULT_MAX_LENGTH as f32 / CODEC_FRAME_RATE;
 
 /// Parameters controlling audio generation.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct GenerationParams {
     /// Target duration in seconds. Capped to model's max (default 30s).
     pub duration_seconds: f32,
@@ -28,6 +42,8 @@ pub struct GenerationParams {
     pub guidance_scale: f32,
     /// Top-K sampling. Higher = more diverse. Default 50.
     pub top_k: usize,
+    /// Seed for the sampling RNG. `None` draws a fresh seed from the OS.
+    pub seed: Option<u64>,
 }
 
 impl Default for GenerationParams {
@@ -36,19 +52,66 @@ impl Default for GenerationParams {
             duration_seconds: 30.0,
             guidance_scale: DEFAULT_GUIDANCE_SCALE,
             top_k: DEFAULT_TOP_K,
+            seed: None,
         }
     }
 }
 
-struct MusicGenPipeline {
+/// Error returned when a generation is interrupted through [`GenerationHooks::cancel`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("generation cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// Observers and cancellation for a running generation.
+pub struct GenerationHooks<'a> {
+    /// Called after every decoder step with the fraction completed (0.0..=1.0).
+    pub progress: &'a dyn Fn(f32),
+    /// Called periodically with the audio decoded so far.
+    pub partial: Option<&'a dyn Fn(Vec<f32>)>,
+    /// Checked between decoder steps; when set, generation stops with [`Cancelled`].
+    pub cancel: Option<&'a AtomicBool>,
+}
+
+impl<'a> GenerationHooks<'a> {
+    /// Hooks that only report progress.
+    pub fn progress(progress: &'a dyn Fn(f32)) -> Self {
+        Self {
+            progress,
+            partial: None,
+            cancel: None,
+        }
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancel.is_some_and(|c| c.load(Ordering::Relaxed))
+    }
+}
+
+/// A loaded MusicGen model. Keeping one around avoids reloading the ONNX
+/// sessions for every generation.
+pub struct MusicGenPipeline {
     text_encoder: Session,
     decoder: Session,
     encodec_decode: Session,
+    /// Optional EnCodec encoder, required for audio continuation.
+    encodec_encode: Option<Session>,
     tokenizer: tokenizers::Tokenizer,
 }
 
 impl MusicGenPipeline {
-    fn load(model_dir: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+    /// Load all ONNX sessions and the tokenizer from a model directory.
+    pub fn load(model_dir: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let missing = config::missing_model_files(model_dir);
+        if !missing.is_empty() {
+            return Err(format!("{} lacks {}", model_dir.display(), missing.join(", ")).into());
+        }
         let session = || {
             Session::builder()?
                 .with_optimization_level(GraphOptimizationLevel::Level1)
@@ -63,6 +126,13 @@ impl MusicGenPipeline {
         eprintln!("[poing] Loading encodec_decode.onnx...");
         let encodec_decode =
             session()?.commit_from_file(model_dir.join("encodec_decode.onnx"))?;
+        let encode_path = model_dir.join("encodec_encode.onnx");
+        let encodec_encode = if encode_path.exists() {
+            eprintln!("[poing] Loading encodec_encode.onnx...");
+            Some(session()?.commit_from_file(encode_path)?)
+        } else {
+            None
+        };
         eprintln!("[poing] Loading tokenizer...");
         let tokenizer = tokenizers::Tokenizer::from_file(model_dir.join("tokenizer.json"))
             .map_err(|e| e.to_string())?;
@@ -72,17 +142,87 @@ impl MusicGenPipeline {
             text_encoder,
             decoder,
             encodec_decode,
+            encodec_encode,
             tokenizer,
         })
     }
 
-    fn generate(
+    /// Whether this model can continue existing audio (has `encodec_encode.onnx`).
+    pub fn supports_continuation(&self) -> bool {
+        self.encodec_encode.is_some()
+    }
+
+    /// Generate audio from a text prompt. Returns mono f32 samples at [`SAMPLE_RATE`].
+    pub fn generate(
         &mut self,
         prompt: &str,
         params: &GenerationParams,
         progress_callback: impl Fn(f32),
     ) -> Result<Vec<f32>, Box<dyn std::error::Error>> {
-        let mut rng = rand::thread_rng();
+        self.generate_with_hooks(prompt, params, &GenerationHooks::progress(&progress_callback))
+    }
+
+    /// Generate audio from a text prompt, reporting partial audio and honouring cancellation.
+    pub fn generate_with_hooks(
+        &mut self,
+        prompt: &str,
+        params: &GenerationParams,
+        hooks: &GenerationHooks,
+    ) -> Result<Vec<f32>, Box<dyn std::error::Error>> {
+        self.run(prompt, None, params, hooks)
+    }
+
+    /// Continue `audio` (mono, [`SAMPLE_RATE`]) guided by a text prompt.
+    ///
+    /// The input is encoded with EnCodec and forced as the start of the token
+    /// sequence; `params.duration_seconds` worth of new material is generated
+    /// after it. The returned clip contains the input followed by the continuation.
+    pub fn continue_audio(
+        &mut self,
+        prompt: &str,
+        audio: &[f32],
+        params: &GenerationParams,
+        hooks: &GenerationHooks,
+    ) -> Result<Vec<f32>, Box<dyn std::error::Error>> {
+        let codes = self.encode_audio(audio)?;
+        self.run(prompt, Some(&codes), params, hooks)
+    }
+
+    /// Encode mono audio into EnCodec codes of shape `[NUM_CODEBOOKS, frames]`.
+    fn encode_audio(&mut self, audio: &[f32]) -> Result<Array2<i64>, Box<dyn std::error::Error>> {
+        let encoder = self
+            .encodec_encode
+            .as_mut()
+            .ok_or("model has no encodec_encode.onnx; audio continuation is unsupported")?;
+        if audio.is_empty() {
+            return Err("input audio is empty".into());
+        }
+        let input = Tensor::from_array(([1usize, 1, audio.len()], audio.to_vec()))?;
+        let outputs = encoder.run(ort::inputs! {
+            "input_values" => input,
+        })?;
+        let codes = outputs["audio_codes"].try_extract_array::<i64>()?;
+        // [1, 1, NUM_CODEBOOKS, frames] -> [NUM_CODEBOOKS, frames]
+        let shape = codes.shape().to_vec();
+        let frames = *shape.last().ok_or("audio_codes has no dimensions")?;
+        let flat: Vec<i64> = codes.iter().copied().collect();
+        if flat.len() != NUM_CODEBOOKS * frames {
+            return Err(format!("unexpected audio_codes shape {:?}", shape).into());
+        }
+        Ok(Array2::from_shape_vec((NUM_CODEBOOKS, frames), flat)?)
+    }
+
+    fn run(
+        &mut self,
+        prompt: &str,
+        audio_prompt: Option<&Array2<i64>>,
+        params: &GenerationParams,
+        hooks: &GenerationHooks,
+    ) -> Result<Vec<f32>, Box<dyn std::error::Error>> {
+        let mut rng = match params.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
 
         // Step 1: Tokenize prompt (add_special_tokens=true to append T5 EOS token)
         let encoding = self
@@ -141,8 +281,10 @@ impl MusicGenPipeline {
         // Total sequence length for the delayed representation:
         // Compute from duration: aligned_len = duration * CODEC_FRAME_RATE,
         // total_seq_len = aligned_len + 1 + (NUM_CODEBOOKS - 1)
+        // When continuing audio, its frames come first and are forced rather than sampled.
+        let prompt_frames = audio_prompt.map_or(0, |codes| codes.ncols());
         let aligned_target =
-            (params.duration_seconds * CODEC_FRAME_RATE).ceil() as usize;
+            prompt_frames + (params.duration_seconds * CODEC_FRAME_RATE).ceil() as usize;
         let total_seq_len =
             (aligned_target + NUM_CODEBOOKS).min(DEFAULT_MAX_LENGTH);
         let total_codebook_rows = 2 * NUM_CODEBOOKS; // 8 (CFG batch)
@@ -185,6 +327,9 @@ impl MusicGenPipeline {
         let num_gen_steps = total_seq_len - 1;
 
         for step in 0..num_gen_steps {
+            if hooks.is_cancelled() {
+                return Err(Box::new(Cancelled));
+            }
             let use_cache = step > 0;
 
             let mut inputs: Vec<(
@@ -273,6 +418,9 @@ impl MusicGenPipeline {
                 }
             }
 
+            // Release the session borrow so partial decodes can run below
+            drop(outputs);
+
             // Sample next tokens from logits
             let logits_3d = logits.into_dimensionality::<ndarray::Ix3>()?;
             let cond_logits = logits_3d.slice(s![..NUM_CODEBOOKS, .., ..]).to_owned();
@@ -284,18 +432,27 @@ impl MusicGenPipeline {
             let cfg_logits =
                 &uncond_logits + params.guidance_scale * (&cond_logits - &uncond_logits);
 
+            // Position in all_tokens is (step + 1) since step 0 produces position 1
+            let pos = step + 1;
+
             let mut sampled = vec![PAD_TOKEN; total_codebook_rows];
             for cb in 0..NUM_CODEBOOKS {
                 let logit_slice = cfg_logits.slice(s![cb, 0, ..]);
-                let token =
+                let mut token =
                     top_k_sample(logit_slice.as_slice().unwrap(), params.top_k, &mut rng);
+                // Force the audio prompt's codes while this codebook is still inside it
+                if let Some(codes) = audio_prompt {
+                    if let Some(t) = pos.checked_sub(1 + cb) {
+                        if t < prompt_frames {
+                            token = codes[[cb, t]];
+                        }
+                    }
+                }
                 sampled[cb] = token;
                 sampled[cb + NUM_CODEBOOKS] = token;
             }
 
             // Write sampled tokens into the delayed representation
-            // Position in all_tokens is (step + 1) since step 0 produces position 1
-            let pos = step + 1;
             if pos < total_seq_len {
                 for r in 0..total_codebook_rows {
                     let cb = r % NUM_CODEBOOKS;
@@ -314,21 +471,38 @@ impl MusicGenPipeline {
                 }
             }
 
-            progress_callback(step as f32 / num_gen_steps as f32);
+            (hooks.progress)(step as f32 / num_gen_steps as f32);
+
+            if let Some(partial) = hooks.partial {
+                let filled = pos + 1;
+                if pos % PARTIAL_INTERVAL_STEPS == 0 && filled > NUM_CODEBOOKS {
+                    partial(self.decode(&all_tokens, filled)?);
+                }
+            }
         }
 
-        progress_callback(1.0);
+        (hooks.progress)(1.0);
+
+        // Steps 6 + 7: undelay and EnCodec decode
+        self.decode(&all_tokens, total_seq_len)
+    }
 
-        // Step 6: Undelay -- align codebooks by removing delay offsets
+    /// Undelay the first `filled` positions of `all_tokens` and decode them to audio.
+    fn decode(
+        &mut self,
+        all_tokens: &Array2<i64>,
+        filled: usize,
+    ) -> Result<Vec<f32>, Box<dyn std::error::Error>> {
+        // Undelay -- align codebooks by removing delay offsets
         // CB k's first generated token is at position (1 + k) in all_tokens.
         // Aligned timestep t maps to all_tokens[cb, 1 + cb + t].
-        // Number of aligned timesteps: total_seq_len - 1 - (NUM_CODEBOOKS - 1)
-        let aligned_len = total_seq_len - 1 - (NUM_CODEBOOKS - 1);
+        // Number of aligned timesteps: filled - 1 - (NUM_CODEBOOKS - 1)
+        let aligned_len = filled - 1 - (NUM_CODEBOOKS - 1);
         let mut audio_codes_flat = vec![0i64; NUM_CODEBOOKS * aligned_len];
         for cb in 0..NUM_CODEBOOKS {
             for t in 0..aligned_len {
                 let src_col = 1 + cb + t;
-                if src_col < total_seq_len {
+                if src_col < filled {
                     let val = all_tokens[[cb, src_col]]; // Use conditional batch (rows 0..4)
                     audio_codes_flat[cb * aligned_len + t] =
                         if val == PAD_TOKEN { 0 } else { val };
@@ -336,7 +510,7 @@ impl MusicGenPipeline {
             }
         }
 
-        // Step 7: EnCodec decode
+        // EnCodec decode
         // Input shape: [1, batch_size, 4, chunk_length]
         let codes_shape = [1usize, 1, NUM_CODEBOOKS, aligned_len];
         let codes_tensor = Tensor::from_array((codes_shape, audio_codes_flat))?;
@@ -354,7 +528,7 @@ impl MusicGenPipeline {
 }
 
 fn top_k_sample(logits: &[f32], k: usize, rng: &mut impl Rng) -> i64 {
-    let k = k.min(logits.len());
+    let k = k.max(1).min(logits.len());
 
     let mut indexed: Vec<(usize, f32)> =
         logits.iter().enumerate().map(|(i, &v)| (i, v)).collect();
@@ -387,12 +561,20 @@ pub fn generate_from_text(
 
 /// Generate audio from a text prompt combined with input audio using a MusicGen ONNX model.
 ///
-/// Returns mono f32 samples at the model's native sample rate (typically 32kHz).
+/// `input_audio` must be mono at [`SAMPLE_RATE`]; the result starts with it and
+/// continues for `params.duration_seconds`. Requires `encodec_encode.onnx`.
 pub fn generate_from_audio(
-    _prompt: &str,
-    _input_audio: &[f32],
-    _model_dir: &Path,
-    _progress_callback: impl Fn(f32),
+    prompt: &str,
+    input_audio: &[f32],
+    model_dir: &Path,
+    params: &GenerationParams,
+    progress_callback: impl Fn(f32),
 ) -> Result<Vec<f32>, Box<dyn std::error::Error>> {
-    todo!("MusicGen audio-to-audio inference not yet implemented")
+    let mut pipeline = MusicGenPipeline::load(model_dir)?;
+    pipeline.continue_audio(
+        prompt,
+        input_audio,
+        params,
+        &GenerationHooks::progress(&progress_callback),
+    )
 }
@@ -0,0 +1,345 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::f32::consts::PI;
+
+/// Cutoff of the DC blocker.
+const DC_CUTOFF_HZ: f32 = 5.0;
+/// Limiter look-ahead, also the length of its attack ramp.
+const LIMITER_LOOKAHEAD_SECONDS: f32 = 0.005;
+const LIMITER_RELEASE_SECONDS: f32 = 0.08;
+/// Audio from the end of a loop used to settle the filters before its start.
+const LOOP_WARMUP_SECONDS: f32 = 0.5;
+/// Gating block length and hop for integrated loudness (ITU-R BS.1770-4).
+const LOUDNESS_BLOCK_SECONDS: f32 = 0.4;
+const LOUDNESS_HOP_SECONDS: f32 = 0.1;
+const ABSOLUTE_GATE_LUFS: f32 = -70.0;
+const RELATIVE_GATE_LU: f32 = -10.0;
+
+/// Which post-processing stages run on each take, and their settings.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PostProcessSettings {
+    pub dc_removal: bool,
+    pub high_pass: bool,
+    pub high_pass_hz: f32,
+    pub normalize: bool,
+    /// Integrated loudness to normalize to.
+    pub target_lufs: f32,
+    pub limiter: bool,
+    /// Peak ceiling of the limiter in dBFS.
+    pub ceiling_db: f32,
+    /// Short fades at the start and end. Skipped for seamless loops.
+    pub fades: bool,
+    pub fade_in_ms: f32,
+    pub fade_out_ms: f32,
+}
+
+impl Default for PostProcessSettings {
+    fn default() -> Self {
+        Self {
+            dc_removal: true,
+            high_pass: true,
+            high_pass_hz: 30.0,
+            normalize: true,
+            target_lufs: -14.0,
+            limiter: true,
+            ceiling_db: -1.0,
+            fades: true,
+            fade_in_ms: 2.0,
+            fade_out_ms: 20.0,
+        }
+    }
+}
+
+/// Run the enabled stages in order: DC removal, high-pass, loudness
+/// normalization, peak limiting and fades.
+///
+/// For `looped` clips the filters are first run over the end of the clip so
+/// they start in the state the loop wraps around with, and fades are skipped,
+/// keeping the seam click-free.
+pub fn process(
+    samples: &mut [f32],
+    sample_rate: u32,
+    settings: &PostProcessSettings,
+    looped: bool,
+) {
+    if samples.is_empty() {
+        return;
+    }
+    let sr = sample_rate as f32;
+
+    let mut filters = Vec::new();
+    if settings.dc_removal {
+        filters.push(Biquad::high_pass(sr, DC_CUTOFF_HZ, 0.5));
+    }
+    if settings.high_pass {
+        filters.push(Biquad::high_pass(
+            sr,
+            settings.high_pass_hz,
+            std::f32::consts::FRAC_1_SQRT_2,
+        ));
+    }
+    if !filters.is_empty() {
+        if looped {
+            let warmup = ((LOOP_WARMUP_SECONDS * sr) as usize).min(samples.len());
+            for &sample in &samples[samples.len() - warmup..] {
+                filters
+                    .iter_mut()
+                    .fold(sample, |x, filter| filter.process(x));
+            }
+        }
+        for sample in samples.iter_mut() {
+            *sample = filters
+                .iter_mut()
+                .fold(*sample, |x, filter| filter.process(x));
+        }
+    }
+
+    if settings.normalize {
+        if let Some(loudness) = integrated_loudness(samples, sample_rate) {
+            let gain = db_to_gain(settings.target_lufs - loudness);
+            samples.iter_mut().for_each(|s| *s *= gain);
+        }
+    }
+
+    if settings.limiter {
+        limit(samples, sample_rate, db_to_gain(settings.ceiling_db));
+    }
+
+    if settings.fades && !looped {
+        let fade_in = ((settings.fade_in_ms / 1000.0 * sr) as usize).min(samples.len());
+        for (i, sample) in samples[..fade_in].iter_mut().enumerate() {
+            *sample *= i as f32 / fade_in as f32;
+        }
+        let fade_out = ((settings.fade_out_ms / 1000.0 * sr) as usize).min(samples.len());
+        let len = samples.len();
+        for (i, sample) in samples[len - fade_out..].iter_mut().rev().enumerate() {
+            *sample *= i as f32 / fade_out as f32;
+        }
+    }
+}
+
+/// Integrated loudness of mono audio in LUFS per ITU-R BS.1770-4, or `None`
+/// when the audio is shorter than one gating block or entirely below the
+/// absolute gate.
+pub fn integrated_loudness(samples: &[f32], sample_rate: u32) -> Option<f32> {
+    let sr = sample_rate as f32;
+    let block = (LOUDNESS_BLOCK_SECONDS * sr) as usize;
+    let hop = (LOUDNESS_HOP_SECONDS * sr) as usize;
+    if samples.len() < block || hop == 0 {
+        return None;
+    }
+
+    // K-weighting: a high shelf modelling the head, then the RLB high-pass
+    let mut shelf = Biquad::k_weighting_shelf(sr);
+    let mut rlb = Biquad::high_pass(sr, 38.135_47, 0.500_327);
+    let squared: Vec<f64> = samples
+        .iter()
+        .map(|&s| {
+            let weighted = rlb.process(shelf.process(s)) as f64;
+            weighted * weighted
+        })
+        .collect();
+
+    let mut block_powers = Vec::new();
+    let mut sum: f64 = squared[..block].iter().sum();
+    let mut start = 0;
+    loop {
+        block_powers.push(sum / block as f64);
+        if start + hop + block > squared.len() {
+            break;
+        }
+        // Slide the running sum one hop forward
+        sum -= squared[start..start + hop].iter().sum::<f64>();
+        sum += squared[start + block..start + block + hop]
+            .iter()
+            .sum::<f64>();
+        start += hop;
+    }
+
+    let loudness = |power: f64| -0.691 + 10.0 * power.max(1e-20).log10() as f32;
+    let mean = |powers: &[f64]| powers.iter().sum::<f64>() / powers.len() as f64;
+    let above_absolute: Vec<f64> = block_powers
+        .into_iter()
+        .filter(|p| loudness(*p) > ABSOLUTE_GATE_LUFS)
+        .collect();
+    if above_absolute.is_empty() {
+        return None;
+    }
+    let relative_gate = loudness(mean(&above_absolute)) + RELATIVE_GATE_LU;
+    let gated: Vec<f64> = above_absolute
+        .into_iter()
+        .filter(|p| loudness(*p) > relative_gate)
+        .collect();
+    Some(loudness(mean(&gated)))
+}
+
+/// Look-ahead peak limiter keeping every sample within `ceiling`.
+///
+/// The gain needed for each sample is spread backwards over the look-ahead
+/// window by a sliding minimum and then smoothed with a moving average of the
+/// same length, so gain reduction ramps in before a peak rather than
+/// clipping it. Recovery follows a one-pole release.
+fn limit(samples: &mut [f32], sample_rate: u32, ceiling: f32) {
+    let sr = sample_rate as f32;
+    let lookahead = ((LIMITER_LOOKAHEAD_SECONDS * sr) as usize).max(1);
+    let release = 1.0 - (-1.0 / (LIMITER_RELEASE_SECONDS * sr)).exp();
+    let required: Vec<f32> = samples
+        .iter()
+        .map(|s| {
+            if s.abs() > ceiling {
+                ceiling / s.abs()
+            } else {
+                1.0
+            }
+        })
+        .collect();
+    if required.iter().all(|g| *g >= 1.0) {
+        return;
+    }
+
+    // Minimum of `required` over [i, i + lookahead], via a monotonic deque
+    let mut window_min = vec![1.0f32; samples.len()];
+    let mut deque: VecDeque<usize> = VecDeque::new();
+    for i in (0..samples.len()).rev() {
+        while deque.back().is_some_and(|&j| required[j] >= required[i]) {
+            deque.pop_back();
+        }
+        deque.push_back(i);
+        while deque.front().is_some_and(|&j| j > i + lookahead) {
+            deque.pop_front();
+        }
+        window_min[i] = required[deque[0]];
+    }
+
+    // Average over [i - lookahead, i]: every term is at most required[i]
+    let mut gain = 1.0f32;
+    let mut sum = 0.0f32;
+    for i in 0..samples.len() {
+        sum += window_min[i];
+        if i > lookahead {
+            sum -= window_min[i - lookahead - 1];
+        }
+        let count = (i + 1).min(lookahead + 1);
+        // Samples before the clip need no reduction
+        let average = (sum + (lookahead + 1 - count) as f32) / (lookahead + 1) as f32;
+        gain = (gain + (1.0 - gain) * release).min(average);
+        samples[i] = (samples[i] * gain).clamp(-ceiling, ceiling);
+    }
+}
+
+fn db_to_gain(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+/// Second-order IIR filter (transposed direct form II).
+struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+    z1: f32,
+    z2: f32,
+}
+
+impl Biquad {
+    fn new(b: [f32; 3], a: [f32; 3]) -> Self {
+        Self {
+            b0: b[0] / a[0],
+            b1: b[1] / a[0],
+            b2: b[2] / a[0],
+            a1: a[1] / a[0],
+            a2: a[2] / a[0],
+            z1: 0.0,
+            z2: 0.0,
+        }
+    }
+
+    /// RBJ cookbook high-pass.
+    fn high_pass(sample_rate: f32, cutoff: f32, q: f32) -> Self {
+        let w0 = 2.0 * PI * cutoff / sample_rate;
+        let alpha = w0.sin() / (2.0 * q);
+        let cos = w0.cos();
+        Self::new(
+            [(1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0],
+            [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+        )
+    }
+
+    /// The shelving stage of the K-weighting filter, derived for any sample
+    /// rate from the analog prototype of the 48 kHz coefficients in BS.1770.
+    fn k_weighting_shelf(sample_rate: f32) -> Self {
+        let cutoff = 1_681.974_5;
+        let q = 0.707_175_24;
+        let k = (PI * cutoff / sample_rate).tan();
+        let vh = 10f32.powf(3.999_843_9 / 20.0);
+        let vb = vh.powf(0.499_666_77);
+        Self::new(
+            [
+                vh + vb * k / q + k * k,
+                2.0 * (k * k - vh),
+                vh - vb * k / q + k * k,
+            ],
+            [
+                1.0 + k / q + k * k,
+                2.0 * (k * k - 1.0),
+                1.0 - k / q + k * k,
+            ],
+        )
+    }
+
+    fn process(&mut self, x: f32) -> f32 {
+        let y = self.b0 * x + self.z1;
+        self.z1 = self.b1 * x - self.a1 * y + self.z2;
+        self.z2 = self.b2 * x - self.a2 * y;
+        y
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(freq: f32, amplitude: f32, seconds: f32, sample_rate: u32) -> Vec<f32> {
+        (0..(seconds * sample_rate as f32) as usize)
+            .map(|i| amplitude * (2.0 * PI * freq * i as f32 / sample_rate as f32).sin())
+            .collect()
+    }
+
+    #[test]
+    fn test_loudness_of_full_scale_sine() {
+        // BS.1770 calibration: a 0 dBFS 1 kHz sine in one channel reads -3.01 LUFS
+        for sample_rate in [32000, 48000] {
+            let loudness =
+                integrated_loudness(&sine(1000.0, 1.0, 5.0, sample_rate), sample_rate).unwrap();
+            assert!(
+                (loudness + 3.01).abs() < 0.1,
+                "{} at {}",
+                loudness,
+                sample_rate
+            );
+        }
+        assert_eq!(integrated_loudness(&vec![0.0; 64000], 32000), None);
+    }
+
+    #[test]
+    fn test_chain_normalizes_and_limits() {
+        let mut audio: Vec<f32> = sine(220.0, 0.05, 4.0, 32000)
+            .iter()
+            .map(|s| s + 0.2)
+            .collect();
+        // A spike that would clip once the quiet sine is brought up to -14 LUFS
+        audio[40000] = 0.9;
+        process(&mut audio, 32000, &PostProcessSettings::default(), false);
+
+        let peak = audio.iter().fold(0.0f32, |m, s| m.max(s.abs()));
+        assert!(peak <= db_to_gain(-1.0) + 1e-6, "peak {}", peak);
+        let mean = audio.iter().sum::<f32>() / audio.len() as f32;
+        assert!(mean.abs() < 0.01, "DC {}", mean);
+        let loudness = integrated_loudness(&audio, 32000).unwrap();
+        assert!((loudness + 14.0).abs() < 1.0, "{}", loudness);
+        assert_eq!(audio[0], 0.0);
+        assert_eq!(*audio.last().unwrap(), 0.0);
+    }
+}
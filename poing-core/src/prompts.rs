@@ -0,0 +1,205 @@
+use serde::{Deserialize, Serialize};
+
+/// Template giving the tempo and key hint Poing has always sent.
+pub const DEFAULT_TEMPLATE: &str = "{bpm} bpm[, in {key}]. {prompt}";
+
+/// Genre chips offered when the config lists none.
+pub const DEFAULT_GENRES: &[&str] = &[
+    "gabber",
+    "hardcore",
+    "techno",
+    "house",
+    "drum and bass",
+    "hip hop",
+    "lo-fi",
+    "ambient",
+    "trap",
+    "dubstep",
+];
+
+/// Instrument chips offered when the config lists none.
+pub const DEFAULT_INSTRUMENTS: &[&str] = &[
+    "kick", "808", "bass", "synth", "piano", "drums", "guitar", "strings", "pads", "vocals",
+];
+
+/// Prompts kept in the history.
+pub const MAX_HISTORY: usize = 100;
+
+/// A named prompt layout.
+///
+/// `{prompt}`, `{bpm}`, `{key}`, `{genre}` and `{instruments}` are replaced
+/// by their values. Text in square brackets is left out when a placeholder
+/// inside it is empty, e.g. `[, in {key}]` without a key.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PromptTemplate {
+    pub name: String,
+    pub template: String,
+}
+
+impl PromptTemplate {
+    pub fn new(name: &str, template: &str) -> Self {
+        Self {
+            name: name.into(),
+            template: template.into(),
+        }
+    }
+}
+
+/// Templates offered when the config lists none.
+pub fn default_templates() -> Vec<PromptTemplate> {
+    vec![
+        PromptTemplate::new("Tempo and key", DEFAULT_TEMPLATE),
+        PromptTemplate::new(
+            "Genre first",
+            "[{genre}, ]{bpm} bpm[, in {key}][, {instruments}]. {prompt}",
+        ),
+        PromptTemplate::new("Prompt only", "{prompt}"),
+    ]
+}
+
+/// Fill the placeholders of `template` from `values`, dropping bracketed
+/// sections with an empty placeholder. Unknown placeholders are kept as
+/// written. Runs of spaces left by empty values collapse to one.
+pub fn render(template: &str, values: &[(&str, &str)]) -> String {
+    let mut output = String::new();
+    let mut rest = template;
+    while !rest.is_empty() {
+        let (section, optional, tail) = match rest.find('[') {
+            Some(0) => match rest.find(']') {
+                Some(end) => (&rest[1..end], true, &rest[end + 1..]),
+                None => (rest, false, ""),
+            },
+            Some(start) => (&rest[..start], false, &rest[start..]),
+            None => (rest, false, ""),
+        };
+        let (text, complete) = fill(section, values);
+        if complete || !optional {
+            output.push_str(&text);
+        }
+        rest = tail;
+    }
+    output.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Replace the placeholders in `text`, and tell whether all had a value.
+fn fill(text: &str, values: &[(&str, &str)]) -> (String, bool) {
+    let mut output = String::new();
+    let mut complete = true;
+    let mut rest = text;
+    while let Some(start) = rest.find('{') {
+        output.push_str(&rest[..start]);
+        let Some(length) = rest[start..].find('}') else {
+            output.push_str(&rest[start..]);
+            return (output, complete);
+        };
+        let placeholder = &rest[start..start + length + 1];
+        let name = &placeholder[1..placeholder.len() - 1];
+        match values.iter().find(|(key, _)| *key == name) {
+            Some((_, value)) => {
+                complete &= !value.trim().is_empty();
+                output.push_str(value.trim());
+            }
+            None => output.push_str(placeholder),
+        }
+        rest = &rest[start + length + 1..];
+    }
+    output.push_str(rest);
+    (output, complete)
+}
+
+/// Move `prompt` to the front of `history`, dropping older copies and the
+/// entries beyond [`MAX_HISTORY`].
+pub fn record_history(history: &mut Vec<String>, prompt: &str) {
+    let prompt = prompt.trim();
+    if prompt.is_empty() {
+        return;
+    }
+    history.retain(|entry| entry != prompt);
+    history.insert(0, prompt.to_string());
+    history.truncate(MAX_HISTORY);
+}
+
+/// Up to `limit` earlier prompts completing `typed`, ignoring case: those
+/// starting with it first, then those containing it, most recent first.
+pub fn suggestions(history: &[String], typed: &str, limit: usize) -> Vec<String> {
+    let typed = typed.trim().to_lowercase();
+    if typed.is_empty() {
+        return Vec::new();
+    }
+    let candidates = || {
+        history
+            .iter()
+            .map(|entry| (entry, entry.to_lowercase()))
+            .filter(|(_, lower)| *lower != typed)
+    };
+    let prefixed = candidates().filter(|(_, lower)| lower.starts_with(&typed));
+    let containing = candidates().filter(|(_, lower)| {
+        !lower.starts_with(&typed) && lower.contains(&typed)
+    });
+    prefixed
+        .chain(containing)
+        .map(|(entry, _)| entry.clone())
+        .take(limit)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_drops_empty_sections() {
+        let values = |key: &'static str, genre: &'static str| {
+            [
+                ("prompt", "rolling bassline"),
+                ("bpm", "174"),
+                ("key", key),
+                ("genre", genre),
+                ("instruments", ""),
+            ]
+        };
+        assert_eq!(
+            render(DEFAULT_TEMPLATE, &values("F minor", "")),
+            "174 bpm, in F minor. rolling bassline"
+        );
+        assert_eq!(
+            render(DEFAULT_TEMPLATE, &values("", "")),
+            "174 bpm. rolling bassline"
+        );
+        let genre_first = &default_templates()[1].template;
+        assert_eq!(
+            render(genre_first, &values("", "drum and bass")),
+            "drum and bass, 174 bpm. rolling bassline"
+        );
+        assert_eq!(
+            render("{prompt} {mood} [ with {nothing}", &values("", "")),
+            "rolling bassline {mood} [ with {nothing}"
+        );
+        assert_eq!(
+            render("{prompt}. extra {oops", &values("", "")),
+            "rolling bassline. extra {oops"
+        );
+    }
+
+    #[test]
+    fn test_history_recall_and_suggestions() {
+        let mut history = Vec::new();
+        for prompt in ["dark techno kick", "lofi piano", "techno rumble", " lofi piano "] {
+            record_history(&mut history, prompt);
+        }
+        assert_eq!(history, ["lofi piano", "techno rumble", "dark techno kick"]);
+
+        assert_eq!(
+            suggestions(&history, "Techno", 5),
+            ["techno rumble", "dark techno kick"]
+        );
+        assert_eq!(suggestions(&history, "techno", 1), ["techno rumble"]);
+        assert!(suggestions(&history, "lofi piano", 5).is_empty());
+        assert!(suggestions(&history, " ", 5).is_empty());
+
+        for i in 0..MAX_HISTORY + 5 {
+            record_history(&mut history, &format!("prompt {}", i));
+        }
+        assert_eq!(history.len(), MAX_HISTORY);
+    }
+}
@@ -0,0 +1,385 @@
+use std::ops::Range;
+
+/// Positions this close to a bar line, in quarter notes, count as on it.
+const BAR_EPSILON: f64 = 1e-6;
+
+/// Which input channels are captured into the recording.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InputChannel {
+    #[default]
+    Left,
+    Right,
+    /// Both channels averaged into mono, `0.5 * (left + right)`.
+    Mix,
+    /// Both channels, kept apart in the recording and only mixed down where
+    /// mono audio is needed.
+    Stereo,
+}
+
+impl InputChannel {
+    pub const ALL: [InputChannel; 4] = [
+        InputChannel::Left,
+        InputChannel::Right,
+        InputChannel::Mix,
+        InputChannel::Stereo,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            InputChannel::Left => "Left",
+            InputChannel::Right => "Right",
+            InputChannel::Mix => "Mix (L+R)",
+            InputChannel::Stereo => "Stereo",
+        }
+    }
+
+    /// Number of channels in the recording.
+    pub fn channels(self) -> usize {
+        match self {
+            InputChannel::Stereo => 2,
+            _ => 1,
+        }
+    }
+
+    /// Index into [`InputChannel::ALL`], for storing in an atomic.
+    pub fn index(self) -> u8 {
+        self as u8
+    }
+
+    pub fn from_index(index: u8) -> Self {
+        Self::ALL.get(index as usize).copied().unwrap_or_default()
+    }
+
+    /// The recorded samples for one frame, of which the first
+    /// [`channels`](Self::channels) are kept. Mono inputs pass their only
+    /// channel as both `left` and `right`.
+    pub fn capture(self, left: f32, right: f32) -> [f32; 2] {
+        match self {
+            InputChannel::Left => [left, left],
+            InputChannel::Right => [right, right],
+            InputChannel::Mix => [0.5 * (left + right); 2],
+            InputChannel::Stereo => [left, right],
+        }
+    }
+}
+
+/// Captured input audio, one buffer per recorded channel.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Recording {
+    pub channels: Vec<Vec<f32>>,
+}
+
+impl Recording {
+    pub fn mono(samples: Vec<f32>) -> Self {
+        Self {
+            channels: vec![samples],
+        }
+    }
+
+    /// Length in frames.
+    pub fn len(&self) -> usize {
+        self.channels.first().map_or(0, Vec::len)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&mut self) {
+        self.channels.clear();
+    }
+
+    /// The channels averaged into mono, as used for conditioning, analysis
+    /// and display.
+    pub fn mix_down(&self) -> Vec<f32> {
+        match self.channels.as_slice() {
+            [] => Vec::new(),
+            [mono] => mono.clone(),
+            channels => {
+                let gain = 1.0 / channels.len() as f32;
+                (0..self.len())
+                    .map(|i| channels.iter().map(|c| c[i]).sum::<f32>() * gain)
+                    .collect()
+            }
+        }
+    }
+}
+
+/// Which plugin input is recorded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RecordSource {
+    /// The track the plugin sits on.
+    #[default]
+    Main,
+    /// The sidechain input, fed from another track or bus by the host.
+    Sidechain,
+}
+
+impl RecordSource {
+    pub const ALL: [RecordSource; 2] = [RecordSource::Main, RecordSource::Sidechain];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            RecordSource::Main => "Main",
+            RecordSource::Sidechain => "Sidechain",
+        }
+    }
+
+    /// Index into [`RecordSource::ALL`], for storing in an atomic.
+    pub fn index(self) -> u8 {
+        self as u8
+    }
+
+    pub fn from_index(index: u8) -> Self {
+        Self::ALL.get(index as usize).copied().unwrap_or_default()
+    }
+}
+
+/// Host transport state at the start of a process block. Positions are in
+/// quarter notes.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct TransportPosition {
+    pub playing: bool,
+    pub tempo: Option<f64>,
+    pub pos_beats: Option<f64>,
+    pub pos_seconds: Option<f64>,
+    /// Start of the bar containing `pos_beats`, when the host reports it.
+    pub bar_start_beats: Option<f64>,
+    /// Length of a bar in quarter notes, e.g. 3.0 in 6/8.
+    pub bar_beats: f64,
+    /// Active loop range, when the host is looping.
+    pub loop_range_beats: Option<(f64, f64)>,
+}
+
+/// What to do with one block of input while recording is armed.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct BlockAction {
+    /// Recording begins in this block; previous audio should be discarded.
+    pub started: bool,
+    /// Samples of the block to record.
+    pub record: Range<usize>,
+    /// Recording ends with this block.
+    pub finished: bool,
+}
+
+/// Decides, block by block, when an armed recording starts and stops.
+///
+/// Recording starts on the first bar line once the transport is playing,
+/// and stops after a fixed number of bars, at the end of the host loop, when
+/// playback jumps back (the loop wrapping around), or when the transport
+/// stops. Start and end are sample accurate within a block.
+#[derive(Debug, Default)]
+pub struct ArmedRecorder {
+    recording: bool,
+    /// Samples left to record, when recording a fixed number of bars.
+    remaining: Option<usize>,
+    /// Expected transport position at the start of the next block.
+    next_pos_beats: Option<f64>,
+}
+
+impl ArmedRecorder {
+    pub fn is_recording(&self) -> bool {
+        self.recording
+    }
+
+    /// Forget any recording in progress, e.g. after being disarmed.
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Process one block of `block_len` samples. `bars` is the number of bars
+    /// to record, or 0 to record until the loop end or the transport stops.
+    pub fn process(
+        &mut self,
+        transport: &TransportPosition,
+        block_len: usize,
+        sample_rate: f32,
+        bars: u32,
+    ) -> BlockAction {
+        let mut action = BlockAction::default();
+        let timing = transport
+            .tempo
+            .filter(|tempo| *tempo > 0.0)
+            .zip(transport.pos_beats)
+            .map(|(tempo, pos)| (sample_rate as f64 * 60.0 / tempo, pos));
+        let expected = self.next_pos_beats.take();
+        if let Some((samples_per_beat, pos)) = timing {
+            self.next_pos_beats = Some(pos + block_len as f64 / samples_per_beat);
+        }
+
+        let mut start = 0;
+        if !self.recording {
+            let Some((samples_per_beat, pos)) = timing else {
+                return action;
+            };
+            if !transport.playing || transport.bar_beats <= 0.0 {
+                return action;
+            }
+            let bar_start = transport
+                .bar_start_beats
+                .unwrap_or_else(|| (pos / transport.bar_beats).floor() * transport.bar_beats);
+            let since_bar = pos - bar_start;
+            let to_next_bar = if since_bar <= BAR_EPSILON {
+                0.0
+            } else {
+                transport.bar_beats - since_bar
+            };
+            start = (to_next_bar * samples_per_beat).round() as usize;
+            if start >= block_len {
+                return action;
+            }
+            self.recording = true;
+            self.remaining = (bars > 0)
+                .then(|| (bars as f64 * transport.bar_beats * samples_per_beat).round() as usize);
+            action.started = true;
+        } else if !transport.playing {
+            self.reset();
+            action.finished = true;
+            return action;
+        } else if let (Some(expected), Some((_, pos))) = (expected, timing) {
+            // Playback jumped back: the loop wrapped between blocks
+            if pos + BAR_EPSILON < expected - 0.5 {
+                self.reset();
+                action.finished = true;
+                return action;
+            }
+        }
+
+        let mut end = block_len;
+        if let Some(remaining) = self.remaining {
+            end = end.min(start + remaining);
+            self.remaining = Some(remaining - (end - start));
+            action.finished |= remaining == end - start;
+        }
+        if let (Some((samples_per_beat, pos)), Some((_, loop_end))) =
+            (timing, transport.loop_range_beats)
+        {
+            if pos < loop_end {
+                let loop_end_offset = ((loop_end - pos) * samples_per_beat).round() as usize;
+                if loop_end_offset <= end {
+                    end = loop_end_offset.max(start);
+                    action.finished = true;
+                }
+            }
+        }
+
+        action.record = start..end;
+        if action.finished {
+            self.reset();
+        }
+        action
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f32 = 48000.0;
+    const BLOCK: usize = 512;
+
+    /// Play from `pos` at 120 BPM in 4/4 (24000 samples per beat).
+    fn transport(pos: f64, loop_range_beats: Option<(f64, f64)>) -> TransportPosition {
+        TransportPosition {
+            playing: true,
+            tempo: Some(120.0),
+            pos_beats: Some(pos),
+            pos_seconds: Some(pos / 2.0),
+            bar_start_beats: Some((pos / 4.0).floor() * 4.0),
+            bar_beats: 4.0,
+            loop_range_beats,
+        }
+    }
+
+    /// Run blocks from `pos` until recording finishes, returning the absolute
+    /// sample range recorded relative to `pos`.
+    fn run(
+        recorder: &mut ArmedRecorder,
+        mut pos: f64,
+        bars: u32,
+        loop_range: Option<(f64, f64)>,
+    ) -> Range<usize> {
+        let mut first = None;
+        let mut last = 0;
+        for block in 0..1000 {
+            if let Some((loop_start, loop_end)) = loop_range {
+                if pos >= loop_end {
+                    pos = loop_start + (pos - loop_end);
+                }
+            }
+            let action = recorder.process(&transport(pos, loop_range), BLOCK, SAMPLE_RATE, bars);
+            if action.started {
+                first = Some(block * BLOCK + action.record.start);
+            }
+            if !action.record.is_empty() {
+                last = block * BLOCK + action.record.end;
+            }
+            if action.finished {
+                return first.unwrap()..last;
+            }
+            pos += BLOCK as f64 / 24000.0;
+        }
+        panic!("recording never finished");
+    }
+
+    #[test]
+    fn test_records_whole_bars_from_next_bar_line() {
+        // Armed half a beat into bar 1: recording starts at beat 4
+        let mut recorder = ArmedRecorder::default();
+        let recorded = run(&mut recorder, 3.5, 2, None);
+        assert_eq!(recorded, 12_000..12_000 + 2 * 4 * 24_000);
+        assert!(!recorder.is_recording());
+    }
+
+    #[test]
+    fn test_stops_at_loop_end() {
+        let mut recorder = ArmedRecorder::default();
+        // Loop over bars 2-3, armed at the loop start
+        let recorded = run(&mut recorder, 4.0, 0, Some((4.0, 12.0)));
+        assert_eq!(recorded, 0..8 * 24_000);
+    }
+
+    #[test]
+    fn test_waits_for_playback() {
+        let mut recorder = ArmedRecorder::default();
+        let stopped = TransportPosition {
+            playing: false,
+            ..transport(0.0, None)
+        };
+        assert_eq!(
+            recorder.process(&stopped, BLOCK, SAMPLE_RATE, 1),
+            BlockAction::default()
+        );
+        assert!(!recorder.is_recording());
+    }
+
+    #[test]
+    fn test_record_source_index() {
+        for source in RecordSource::ALL {
+            assert_eq!(RecordSource::from_index(source.index()), source);
+        }
+        assert_eq!(RecordSource::from_index(7), RecordSource::Main);
+    }
+
+    #[test]
+    fn test_input_channels() {
+        assert_eq!(InputChannel::Left.capture(1.0, 0.0)[0], 1.0);
+        assert_eq!(InputChannel::Mix.capture(1.0, 0.0)[0], 0.5);
+        assert_eq!(InputChannel::Stereo.capture(1.0, 0.0), [1.0, 0.0]);
+        assert_eq!(InputChannel::Stereo.channels(), 2);
+        assert_eq!(InputChannel::Mix.channels(), 1);
+        assert_eq!(InputChannel::from_index(1), InputChannel::Right);
+        assert_eq!(InputChannel::from_index(7), InputChannel::Left);
+    }
+
+    #[test]
+    fn test_stereo_recording_mixes_down() {
+        let recording = Recording {
+            channels: vec![vec![1.0, 0.5], vec![0.0, 0.5]],
+        };
+        assert_eq!(recording.len(), 2);
+        assert_eq!(recording.mix_down(), vec![0.5, 0.5]);
+        assert_eq!(Recording::mono(vec![0.25]).mix_down(), vec![0.25]);
+        assert!(Recording::default().is_empty());
+    }
+}
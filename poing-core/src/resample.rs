@@ -0,0 +1,61 @@
+use std::f64::consts::PI;
+
+/// Half-width of the windowed-sinc kernel in input samples (at unity ratio).
+const KERNEL_HALF_WIDTH: usize = 16;
+
+/// Resample mono audio from `from_rate` to `to_rate` with a windowed-sinc filter.
+///
+/// When downsampling the kernel is widened so content above the new Nyquist
+/// frequency is filtered out instead of aliasing.
+pub fn resample(samples: &[f32], from_rate: u32, to_rate: u32) -> Vec<f32> {
+    if from_rate == to_rate || samples.is_empty() {
+        return samples.to_vec();
+    }
+
+    let ratio = to_rate as f64 / from_rate as f64;
+    let out_len = (samples.len() as f64 * ratio).round() as usize;
+    // Cutoff relative to the input Nyquist frequency
+    let cutoff = ratio.min(1.0);
+    let half_width = (KERNEL_HALF_WIDTH as f64 / cutoff).ceil() as isize;
+
+    (0..out_len)
+        .map(|i| {
+            let center = i as f64 / ratio;
+            let first = center.floor() as isize - half_width + 1;
+            let mut acc = 0.0f64;
+            let mut weight_sum = 0.0f64;
+            for j in first..first + 2 * half_width {
+                if j < 0 || j as usize >= samples.len() {
+                    continue;
+                }
+                let x = j as f64 - center;
+                let w = cutoff * sinc(cutoff * x) * blackman(x / half_width as f64);
+                acc += samples[j as usize] as f64 * w;
+                weight_sum += w;
+            }
+            // Normalizing by the weight sum keeps DC gain at unity near the edges
+            if weight_sum.abs() > 1e-9 {
+                (acc / weight_sum) as f32
+            } else {
+                0.0
+            }
+        })
+        .collect()
+}
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-9 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+/// Blackman window over `x` in -1..=1.
+fn blackman(x: f64) -> f64 {
+    if x.abs() >= 1.0 {
+        return 0.0;
+    }
+    let t = (x + 1.0) * 0.5;
+    0.42 - 0.5 * (2.0 * PI * t).cos() + 0.08 * (4.0 * PI * t).cos()
+}
@@ -0,0 +1,426 @@
+//! Local HTTP/JSON API exposing generation to other tools.
+//!
+//! | Method   | Path                      | Description                                  |
+//! |----------|---------------------------|----------------------------------------------|
+//! | `GET`    | `/models`                 | Configured models and whether they are valid |
+//! | `POST`   | `/jobs`                   | Submit a job, returns `{"id": ...}`          |
+//! | `GET`    | `/jobs/{id}`              | Job status and progress                      |
+//! | `GET`    | `/jobs/{id}/audio?take=N` | Finished take as a WAV file                  |
+//! | `DELETE` | `/jobs/{id}`              | Cancel a queued or running job               |
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+use crate::config;
+use crate::musicgen::{GenerationParams, SAMPLE_RATE};
+use crate::wav;
+use crate::worker::{InferenceWorker, Job, JobId, JobKind, WorkerEvent, MAX_VARIATIONS};
+
+/// Finished jobs kept in memory before the oldest are forgotten.
+const MAX_RETAINED_JOBS: usize = 100;
+
+/// A model the server can generate with.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ModelInfo {
+    pub name: String,
+    pub path: PathBuf,
+    pub valid: bool,
+}
+
+impl ModelInfo {
+    fn from_path(path: PathBuf) -> Self {
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_else(|| path.to_string_lossy().into_owned());
+        let valid = config::validate_model_dir(&path);
+        Self { name, path, valid }
+    }
+}
+
+/// Body of `POST /jobs`. Unset parameters use [`GenerationParams::default`].
+#[derive(Debug, Deserialize)]
+struct JobRequest {
+    prompt: String,
+    /// Model name or path; defaults to the first configured model.
+    #[serde(default)]
+    model: Option<String>,
+    #[serde(default, alias = "duration")]
+    duration_seconds: Option<f32>,
+    #[serde(default, alias = "guidance")]
+    guidance_scale: Option<f32>,
+    /// At least 1.
+    #[serde(default)]
+    top_k: Option<usize>,
+    #[serde(default)]
+    seed: Option<u64>,
+    /// Takes to generate with consecutive seeds, 1 to [`MAX_VARIATIONS`].
+    #[serde(default)]
+    variations: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum JobState {
+    Queued,
+    Running,
+    Done,
+    Error,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct JobStatus {
+    id: JobId,
+    state: JobState,
+    progress: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    /// Seeds of the finished takes, one per take.
+    seeds: Vec<u64>,
+    #[serde(skip)]
+    takes: Vec<Arc<Vec<f32>>>,
+}
+
+impl JobStatus {
+    fn new(id: JobId) -> Self {
+        Self {
+            id,
+            state: JobState::Queued,
+            progress: 0.0,
+            error: None,
+            seeds: Vec::new(),
+            takes: Vec::new(),
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        matches!(
+            self.state,
+            JobState::Done | JobState::Error | JobState::Cancelled
+        )
+    }
+}
+
+type JobTable = Arc<Mutex<BTreeMap<JobId, JobStatus>>>;
+
+/// A running HTTP server. Dropping the handle does not stop it; call [`ServerHandle::stop`].
+pub struct ServerHandle {
+    addr: std::net::SocketAddr,
+    server: Arc<tiny_http::Server>,
+    thread: JoinHandle<()>,
+}
+
+impl ServerHandle {
+    /// The address the server is listening on.
+    pub fn local_addr(&self) -> std::net::SocketAddr {
+        self.addr
+    }
+
+    /// Block until the server stops.
+    pub fn join(self) {
+        let _ = self.thread.join();
+    }
+
+    /// Stop accepting requests and wait for the server thread to exit.
+    pub fn stop(self) {
+        self.server.unblock();
+        let _ = self.thread.join();
+    }
+}
+
+/// Start serving on `addr` (e.g. `127.0.0.1:7878`, or port 0 for any free port)
+/// with the given model directories.
+pub fn start(
+    addr: &str,
+    model_paths: Vec<PathBuf>,
+) -> Result<ServerHandle, Box<dyn std::error::Error + Send + Sync>> {
+    let server = Arc::new(tiny_http::Server::http(addr)?);
+    let addr = server
+        .server_addr()
+        .to_ip()
+        .ok_or("server is not listening on an IP address")?;
+    let models: Vec<ModelInfo> = model_paths.into_iter().map(ModelInfo::from_path).collect();
+
+    let jobs: JobTable = Arc::new(Mutex::new(BTreeMap::new()));
+    let worker = Arc::new(InferenceWorker::new(|_| {}));
+    let events = worker.subscribe();
+    let event_jobs = jobs.clone();
+    std::thread::spawn(move || {
+        for event in events {
+            apply_event(&event_jobs, event);
+        }
+    });
+
+    let request_server = server.clone();
+    let thread = std::thread::Builder::new()
+        .name("poing-server".into())
+        .spawn(move || {
+            for request in request_server.incoming_requests() {
+                handle_request(request, &models, &worker, &jobs);
+            }
+        })?;
+
+    Ok(ServerHandle {
+        addr,
+        server,
+        thread,
+    })
+}
+
+fn apply_event(jobs: &JobTable, event: WorkerEvent) {
+    let mut jobs = jobs.lock().unwrap();
+    let status = jobs
+        .entry(event.job_id())
+        .or_insert_with(|| JobStatus::new(event.job_id()));
+    match event {
+        WorkerEvent::Queued { .. } | WorkerEvent::Partial { .. } => {}
+        WorkerEvent::Started { .. } => status.state = JobState::Running,
+        WorkerEvent::Progress { progress, .. } => status.progress = progress,
+        WorkerEvent::Done { takes, .. } => {
+            status.state = JobState::Done;
+            status.progress = 1.0;
+            status.seeds = takes.iter().filter_map(|t| t.params.seed).collect();
+            status.takes = takes.into_iter().map(|t| t.audio).collect();
+        }
+        WorkerEvent::Error { message, .. } => {
+            status.state = JobState::Error;
+            status.error = Some(message);
+        }
+        WorkerEvent::Cancelled { .. } => status.state = JobState::Cancelled,
+    }
+
+    // Forget the oldest finished jobs once the table grows too large
+    while jobs.len() > MAX_RETAINED_JOBS {
+        let Some(oldest) = jobs.values().find(|s| s.is_finished()).map(|s| s.id) else {
+            break;
+        };
+        jobs.remove(&oldest);
+    }
+}
+
+fn handle_request(
+    mut request: tiny_http::Request,
+    models: &[ModelInfo],
+    worker: &InferenceWorker,
+    jobs: &JobTable,
+) {
+    let url = request.url().to_string();
+    let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    let method = request.method().clone();
+
+    let response = match (&method, segments.as_slice()) {
+        (tiny_http::Method::Get, ["models"]) => json_response(200, &models),
+        (tiny_http::Method::Post, ["jobs"]) => {
+            let mut body = String::new();
+            match request.as_reader().read_to_string(&mut body) {
+                Ok(_) => submit_job(&body, models, worker, jobs),
+                Err(e) => error_response(400, &e.to_string()),
+            }
+        }
+        (tiny_http::Method::Get, ["jobs", id]) => match find_job(jobs, id) {
+            Some(status) => json_response(200, &status),
+            None => error_response(404, "no such job"),
+        },
+        (tiny_http::Method::Delete, ["jobs", id]) => match find_job(jobs, id) {
+            Some(status) => {
+                worker.cancel(status.id);
+                json_response(202, &status)
+            }
+            None => error_response(404, "no such job"),
+        },
+        (tiny_http::Method::Get, ["jobs", id, "audio"]) => job_audio(jobs, id, query),
+        _ => error_response(404, "not found"),
+    };
+    let _ = request.respond(response);
+}
+
+fn submit_job(
+    body: &str,
+    models: &[ModelInfo],
+    worker: &InferenceWorker,
+    jobs: &JobTable,
+) -> tiny_http::Response<std::io::Cursor<Vec<u8>>> {
+    let job_request: JobRequest = match serde_json::from_str(body) {
+        Ok(r) => r,
+        Err(e) => return error_response(400, &format!("invalid job: {}", e)),
+    };
+    if job_request.prompt.trim().is_empty() {
+        return error_response(400, "prompt is empty");
+    }
+    let model = match &job_request.model {
+        Some(wanted) => models
+            .iter()
+            .find(|m| m.name == *wanted || m.path.to_string_lossy() == *wanted),
+        None => models.first(),
+    };
+    let Some(model) = model else {
+        return error_response(400, "unknown model");
+    };
+
+    if job_request.top_k == Some(0) {
+        return error_response(400, "top_k must be at least 1");
+    }
+    let defaults = GenerationParams::default();
+    let params = GenerationParams {
+        duration_seconds: job_request
+            .duration_seconds
+            .unwrap_or(defaults.duration_seconds),
+        guidance_scale: job_request
+            .guidance_scale
+            .unwrap_or(defaults.guidance_scale),
+        top_k: job_request.top_k.unwrap_or(defaults.top_k),
+        seed: job_request.seed,
+    };
+    let kind = match job_request.variations {
+        Some(count) if !(1..=MAX_VARIATIONS).contains(&count) => {
+            return error_response(
+                400,
+                &format!("variations must be between 1 and {}", MAX_VARIATIONS),
+            );
+        }
+        Some(count) if count > 1 => JobKind::Variations { count },
+        _ => JobKind::Generate,
+    };
+
+    let id = worker.submit(Job {
+        prompt: job_request.prompt,
+        model_dir: model.path.clone(),
+        params,
+        kind,
+        tempo: None,
+        post_process: None,
+        settings: None,
+    });
+    jobs.lock()
+        .unwrap()
+        .entry(id)
+        .or_insert_with(|| JobStatus::new(id));
+    json_response(202, &serde_json::json!({ "id": id }))
+}
+
+fn find_job(jobs: &JobTable, id: &str) -> Option<JobStatus> {
+    let id: JobId = id.parse().ok()?;
+    jobs.lock().unwrap().get(&id).cloned()
+}
+
+fn job_audio(
+    jobs: &JobTable,
+    id: &str,
+    query: &str,
+) -> tiny_http::Response<std::io::Cursor<Vec<u8>>> {
+    let Some(status) = find_job(jobs, id) else {
+        return error_response(404, "no such job");
+    };
+    if status.state != JobState::Done {
+        return error_response(409, "job has not finished");
+    }
+    let take: usize = query
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("take="))
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0);
+    let Some(audio) = status.takes.get(take) else {
+        return error_response(404, "no such take");
+    };
+    match wav::encode_wav(audio, SAMPLE_RATE) {
+        Ok(bytes) => tiny_http::Response::from_data(bytes)
+            .with_header(header("Content-Type", "audio/wav")),
+        Err(e) => error_response(500, &e.to_string()),
+    }
+}
+
+fn header(name: &str, value: &str) -> tiny_http::Header {
+    tiny_http::Header::from_bytes(name.as_bytes(), value.as_bytes()).unwrap()
+}
+
+fn json_response(
+    code: u16,
+    body: &impl Serialize,
+) -> tiny_http::Response<std::io::Cursor<Vec<u8>>> {
+    let json = serde_json::to_vec(body).unwrap_or_default();
+    tiny_http::Response::from_data(json)
+        .with_status_code(code)
+        .with_header(header("Content-Type", "application/json"))
+}
+
+fn error_response(code: u16, message: &str) -> tiny_http::Response<std::io::Cursor<Vec<u8>>> {
+    json_response(code, &serde_json::json!({ "error": message }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::{SocketAddr, TcpStream};
+    use std::time::{Duration, Instant};
+
+    fn request(addr: SocketAddr, method: &str, path: &str, body: &str) -> (u16, String) {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(
+            stream,
+            "{} {} HTTP/1.0\r\nContent-Length: {}\r\n\r\n{}",
+            method,
+            path,
+            body.len(),
+            body
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let code = response[9..12].parse().unwrap();
+        let body = response
+            .split_once("\r\n\r\n")
+            .map(|(_, b)| b.to_string())
+            .unwrap_or_default();
+        (code, body)
+    }
+
+    #[test]
+    fn test_models_and_job_lifecycle() {
+        let server = start("127.0.0.1:0", vec![PathBuf::from("/nonexistent/musicgen")]).unwrap();
+        let addr = server.local_addr();
+
+        let (code, body) = request(addr, "GET", "/models", "");
+        assert_eq!(code, 200);
+        assert!(body.contains("\"name\":\"musicgen\""));
+        assert!(body.contains("\"valid\":false"));
+
+        let (code, _) = request(addr, "POST", "/jobs", "not json");
+        assert_eq!(code, 400);
+        let too_many = r#"{"prompt": "gabber", "variations": 100}"#;
+        let (code, body) = request(addr, "POST", "/jobs", too_many);
+        assert_eq!(code, 400);
+        assert!(body.contains("variations"));
+        let no_tokens = r#"{"prompt": "gabber", "top_k": 0}"#;
+        let (code, body) = request(addr, "POST", "/jobs", no_tokens);
+        assert_eq!(code, 400);
+        assert!(body.contains("top_k"));
+        let (code, _) = request(addr, "GET", "/jobs/999", "");
+        assert_eq!(code, 404);
+
+        // The model doesn't exist, so the job fails once the worker picks it up
+        let (code, body) = request(addr, "POST", "/jobs", r#"{"prompt": "gabber"}"#);
+        assert_eq!(code, 202);
+        let id: serde_json::Value = serde_json::from_str(&body).unwrap();
+        let path = format!("/jobs/{}", id["id"]);
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            let (code, body) = request(addr, "GET", &path, "");
+            assert_eq!(code, 200);
+            if body.contains("\"state\":\"error\"") {
+                break;
+            }
+            assert!(Instant::now() < deadline, "job never failed: {}", body);
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        let (code, _) = request(addr, "GET", &format!("{}/audio", path), "");
+        assert_eq!(code, 409);
+
+        server.stop();
+    }
+}
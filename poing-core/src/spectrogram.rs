@@ -0,0 +1,181 @@
+use rustfft::num_complex::Complex;
+use rustfft::FftPlanner;
+
+/// STFT frame length: 64 ms at the model rate, enough to separate bass notes.
+const FRAME_SIZE: usize = 2048;
+const HOP_SIZE: usize = FRAME_SIZE / 4;
+/// Lowest frequency shown.
+pub const MIN_FREQ: f32 = 30.0;
+/// Levels at or below this many dB under full scale show as silence.
+pub const FLOOR_DB: f32 = -90.0;
+
+/// Colour stops from silence to full scale, dark purple through orange to
+/// pale yellow.
+const COLOR_STOPS: [(u8, u8, u8); 5] = [
+    (15, 15, 23),
+    (60, 15, 100),
+    (160, 40, 100),
+    (240, 120, 40),
+    (252, 250, 170),
+];
+
+/// Short-time spectrum of mono audio on a logarithmic frequency scale.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Spectrogram {
+    pub sample_rate: u32,
+    pub bands: usize,
+    /// Upper edge of the highest band, the Nyquist frequency.
+    pub max_freq: f32,
+    /// Level in dB relative to a full-scale sine of every band, frame after
+    /// frame. Frame `i` is centered on sample `i * HOP_SIZE`.
+    levels: Vec<f32>,
+}
+
+impl Spectrogram {
+    /// Run the STFT over `samples` with Hann windowed frames, folding the
+    /// bins into `bands` log-spaced bands. Each band takes its loudest bin,
+    /// so tones stand out rather than being averaged away; bands narrower
+    /// than a bin at the bottom interpolate between the nearest two.
+    pub fn compute(samples: &[f32], sample_rate: u32, bands: usize) -> Self {
+        let max_freq = sample_rate as f32 / 2.0;
+        let mut spectrogram = Self {
+            sample_rate,
+            bands,
+            max_freq,
+            levels: Vec::new(),
+        };
+        if samples.is_empty() || bands == 0 || max_freq <= MIN_FREQ {
+            return spectrogram;
+        }
+
+        let fft = FftPlanner::<f32>::new().plan_fft_forward(FRAME_SIZE);
+        let window: Vec<f32> = (0..FRAME_SIZE)
+            .map(|i| 0.5 - 0.5 * (2.0 * std::f32::consts::PI * i as f32 / FRAME_SIZE as f32).cos())
+            .collect();
+        // A full-scale sine peaks at a quarter of the frame length after the window
+        let reference = (FRAME_SIZE as f32 / 4.0).powi(2);
+        let bin_hz = sample_rate as f32 / FRAME_SIZE as f32;
+        let edges: Vec<f32> = (0..=bands)
+            .map(|band| spectrogram.band_frequency(band as f32) / bin_hz)
+            .collect();
+
+        let frames = samples.len() / HOP_SIZE + 1;
+        let mut buffer = vec![Complex::new(0.0, 0.0); FRAME_SIZE];
+        let mut power = vec![0.0f32; FRAME_SIZE / 2 + 1];
+        spectrogram.levels.reserve(frames * bands);
+        for frame in 0..frames {
+            let start = (frame * HOP_SIZE) as isize - FRAME_SIZE as isize / 2;
+            for (i, slot) in buffer.iter_mut().enumerate() {
+                let sample = usize::try_from(start + i as isize)
+                    .ok()
+                    .and_then(|index| samples.get(index))
+                    .copied()
+                    .unwrap_or(0.0);
+                *slot = Complex::new(sample * window[i], 0.0);
+            }
+            fft.process(&mut buffer);
+            for (bin, p) in power.iter_mut().enumerate() {
+                *p = buffer[bin].norm_sqr();
+            }
+
+            for edge in edges.windows(2) {
+                let (low, high) = (edge[0], edge[1]);
+                let first = low.ceil() as usize;
+                let last = (high.ceil() as usize).min(power.len());
+                let band_power = if first < last {
+                    power[first..last].iter().fold(0.0f32, |a, &b| a.max(b))
+                } else {
+                    let center = (low + high) / 2.0;
+                    let below = (center.floor() as usize).min(power.len() - 1);
+                    let above = (below + 1).min(power.len() - 1);
+                    let t = center - below as f32;
+                    power[below] * (1.0 - t) + power[above] * t
+                };
+                let db = 10.0 * (band_power / reference).max(1e-12).log10();
+                spectrogram.levels.push(db.max(FLOOR_DB));
+            }
+        }
+        spectrogram
+    }
+
+    pub fn frames(&self) -> usize {
+        self.levels.len().checked_div(self.bands).unwrap_or(0)
+    }
+
+    /// Band levels in dB of frame `index`, lowest band first.
+    pub fn frame(&self, index: usize) -> &[f32] {
+        &self.levels[index * self.bands..(index + 1) * self.bands]
+    }
+
+    /// The frame centered closest to `time` seconds.
+    pub fn frame_at(&self, time: f32) -> Option<&[f32]> {
+        let frames = self.frames();
+        if frames == 0 || time < 0.0 {
+            return None;
+        }
+        let index = (time * self.sample_rate as f32 / HOP_SIZE as f32).round() as usize;
+        (index < frames).then(|| self.frame(index))
+    }
+
+    /// Frequency at fractional band edge `band`, from [`MIN_FREQ`] at 0 to
+    /// `max_freq` at `bands`.
+    pub fn band_frequency(&self, band: f32) -> f32 {
+        MIN_FREQ * (self.max_freq / MIN_FREQ).powf(band / self.bands as f32)
+    }
+
+    /// Height of `freq` on the log scale, 0 at the bottom and 1 at the top.
+    pub fn frequency_position(&self, freq: f32) -> f32 {
+        ((freq / MIN_FREQ).ln() / (self.max_freq / MIN_FREQ).ln()).clamp(0.0, 1.0)
+    }
+}
+
+/// Map a level in dB to 0..=1 between [`FLOOR_DB`] and full scale.
+pub fn db_to_level(db: f32) -> f32 {
+    (1.0 - db / FLOOR_DB).clamp(0.0, 1.0)
+}
+
+/// Colour of a level from [`db_to_level`], interpolated between the stops.
+pub fn colormap(level: f32) -> (u8, u8, u8) {
+    let position = level.clamp(0.0, 1.0) * (COLOR_STOPS.len() - 1) as f32;
+    let index = (position as usize).min(COLOR_STOPS.len() - 2);
+    let t = position - index as f32;
+    let (a, b) = (COLOR_STOPS[index], COLOR_STOPS[index + 1]);
+    let mix = |x: u8, y: u8| (x as f32 + (y as f32 - x as f32) * t).round() as u8;
+    (mix(a.0, b.0), mix(a.1, b.1), mix(a.2, b.2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sine_shows_in_its_band() {
+        let sample_rate = 32000;
+        let sine: Vec<f32> = (0..sample_rate)
+            .map(|i| {
+                0.5 * (2.0 * std::f32::consts::PI * 1000.0 * i as f32 / sample_rate as f32).sin()
+            })
+            .collect();
+        let spectrogram = Spectrogram::compute(&sine, sample_rate, 96);
+        assert_eq!(spectrogram.frames(), sine.len() / HOP_SIZE + 1);
+
+        let band = (spectrogram.frequency_position(1000.0) * 96.0) as usize;
+        let frame = spectrogram.frame_at(0.5).unwrap();
+        // Half scale is 6 dB down
+        assert!((frame[band] + 6.0).abs() < 1.5, "{}", frame[band]);
+        let low = (spectrogram.frequency_position(100.0) * 96.0) as usize;
+        assert!(frame[low] < -60.0, "{}", frame[low]);
+
+        let silence = Spectrogram::compute(&[0.0; 4096], sample_rate, 32);
+        assert!(silence.frame(3).iter().all(|&db| db == FLOOR_DB));
+        assert!(spectrogram.frame_at(10.0).is_none());
+    }
+
+    #[test]
+    fn test_colormap_spans_stops() {
+        assert_eq!(colormap(db_to_level(FLOOR_DB)), COLOR_STOPS[0]);
+        assert_eq!(colormap(db_to_level(0.0)), COLOR_STOPS[4]);
+        assert_eq!(colormap(0.5), COLOR_STOPS[2]);
+        assert_eq!(db_to_level(-45.0), 0.5);
+    }
+}
@@ -0,0 +1,139 @@
+/// Analysis/synthesis frame length in seconds.
+const FRAME_SECONDS: f32 = 0.046;
+/// Decimation used for the coarse alignment search.
+const COARSE_STEP: usize = 4;
+
+/// Time-stretch mono audio to exactly `target_len` samples without changing pitch.
+///
+/// Uses WSOLA: Hann-windowed frames are overlap-added at a fixed synthesis hop
+/// while the analysis position advances at the stretched rate. Each frame is
+/// shifted within a small tolerance to the position that best continues the
+/// previous one, which avoids the phasing of plain overlap-add.
+pub fn time_stretch(samples: &[f32], sample_rate: u32, target_len: usize) -> Vec<f32> {
+    if samples.is_empty() || target_len == 0 {
+        return vec![0.0; target_len];
+    }
+    if samples.len() == target_len {
+        return samples.to_vec();
+    }
+
+    let frame = ((sample_rate as f32 * FRAME_SECONDS) as usize / 2 * 2).max(64);
+    let synthesis_hop = frame / 2;
+    let tolerance = frame / 4;
+    let analysis_hop = synthesis_hop as f64 * samples.len() as f64 / target_len as f64;
+    let window: Vec<f32> = (0..frame)
+        .map(|i| {
+            let phase = 2.0 * std::f32::consts::PI * i as f32 / frame as f32;
+            0.5 - 0.5 * phase.cos()
+        })
+        .collect();
+
+    let sample_at = |i: isize| -> f32 {
+        if i < 0 {
+            0.0
+        } else {
+            samples.get(i as usize).copied().unwrap_or(0.0)
+        }
+    };
+
+    let mut output = vec![0.0f32; target_len + frame];
+    let mut weights = vec![0.0f32; target_len + frame];
+    // Frames are centred on their positions, so the first one starts half a frame early
+    let offset = (frame / 2) as isize;
+    let mut previous: Option<isize> = None;
+
+    let frames = target_len / synthesis_hop + 2;
+    for k in 0..frames {
+        let nominal = (k as f64 * analysis_hop).round() as isize - offset;
+        let position = match previous {
+            None => nominal,
+            Some(prev) => {
+                let natural = prev + synthesis_hop as isize;
+                nominal + best_shift(&sample_at, natural, nominal, tolerance as isize, frame)
+            }
+        };
+        previous = Some(position);
+
+        let out_start = (k * synthesis_hop) as isize - offset;
+        for (i, w) in window.iter().enumerate() {
+            let out_index = out_start + i as isize;
+            if out_index < 0 || out_index as usize >= output.len() {
+                continue;
+            }
+            output[out_index as usize] += sample_at(position + i as isize) * w;
+            weights[out_index as usize] += w;
+        }
+    }
+
+    output.truncate(target_len);
+    for (sample, weight) in output.iter_mut().zip(&weights) {
+        if *weight > 1e-3 {
+            *sample /= weight;
+        }
+    }
+    output
+}
+
+/// Shift in `-tolerance..=tolerance` that makes the frame at `nominal + shift`
+/// best match the natural continuation starting at `natural`.
+fn best_shift(
+    sample_at: &impl Fn(isize) -> f32,
+    natural: isize,
+    nominal: isize,
+    tolerance: isize,
+    frame: usize,
+) -> isize {
+    // Only the overlapping half of the frame matters for the transition
+    let overlap = (frame / 2) as isize;
+    let correlation = |shift: isize, step: usize| -> f32 {
+        (0..overlap)
+            .step_by(step)
+            .map(|i| sample_at(natural + i) * sample_at(nominal + shift + i))
+            .sum()
+    };
+
+    let coarse = (-tolerance..=tolerance)
+        .step_by(COARSE_STEP)
+        .max_by(|a, b| correlation(*a, COARSE_STEP).total_cmp(&correlation(*b, COARSE_STEP)))
+        .unwrap_or(0);
+    let radius = COARSE_STEP as isize;
+    (coarse - radius..=coarse + radius)
+        .filter(|shift| shift.abs() <= tolerance)
+        .max_by(|a, b| correlation(*a, 1).total_cmp(&correlation(*b, 1)))
+        .unwrap_or(coarse)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(freq: f32, sample_rate: u32, len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|i| (2.0 * std::f32::consts::PI * freq * i as f32 / sample_rate as f32).sin())
+            .collect()
+    }
+
+    /// Frequency estimated from the zero-crossing rate.
+    fn frequency(samples: &[f32], sample_rate: u32) -> f32 {
+        let crossings = samples
+            .windows(2)
+            .filter(|w| (w[0] < 0.0) != (w[1] < 0.0))
+            .count();
+        crossings as f32 / 2.0 * sample_rate as f32 / samples.len() as f32
+    }
+
+    #[test]
+    fn test_stretch_preserves_length_and_pitch() {
+        let sample_rate = 32000;
+        let input = sine(440.0, sample_rate, sample_rate as usize);
+        for target_len in [24000, 41000] {
+            let output = time_stretch(&input, sample_rate, target_len);
+            assert_eq!(output.len(), target_len);
+            // Ignore the edges where frames are only partially covered
+            let middle = &output[2000..target_len - 2000];
+            assert!((frequency(middle, sample_rate) - 440.0).abs() < 5.0);
+            let peak = middle.iter().fold(0.0f32, |m, s| m.max(s.abs()));
+            assert!(peak > 0.8 && peak < 1.2, "peak {}", peak);
+        }
+    }
+}
@@ -0,0 +1,271 @@
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Subdirectory of the system temp dir holding Poing's files.
+const TEMP_SUBDIR: &str = "poing";
+/// Files left behind by earlier sessions are removed once they are this old,
+/// giving hosts that reference dropped files in place a day to copy them.
+pub const STALE_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+/// Longest prompt slug in a file name.
+const MAX_SLUG_LEN: usize = 40;
+
+/// Outstanding [`TempFile`] handles of every file created by a manager.
+type References = Arc<Mutex<HashMap<PathBuf, usize>>>;
+
+/// Creates uniquely named temp files, such as the WAVs handed to the host
+/// by drag and drop, and removes them again.
+///
+/// Files are deleted when the manager is dropped unless a [`TempFile`]
+/// handle to them is still alive, e.g. held by a drag in progress. Those,
+/// and files of sessions that ended without cleaning up, are removed by the
+/// next manager once older than [`STALE_AGE`].
+pub struct TempFiles {
+    dir: PathBuf,
+    references: References,
+}
+
+impl TempFiles {
+    /// Manage the `poing` subdirectory of the system temp dir, removing
+    /// stale files from earlier sessions.
+    pub fn new() -> Self {
+        let manager = Self::with_dir(std::env::temp_dir().join(TEMP_SUBDIR));
+        manager.remove_stale(STALE_AGE);
+        manager
+    }
+
+    pub fn with_dir(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            references: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Create an empty file named after the prompt, take number and current
+    /// time, e.g. `warm-lofi-piano_take3_20261018-142301.wav`. A counter is
+    /// appended when the name is taken, so concurrent drags and plugin
+    /// instances never share a file.
+    pub fn create(&self, prompt: &str, take: usize, extension: &str) -> io::Result<TempFile> {
+        fs::create_dir_all(&self.dir)?;
+        let stem = format!(
+            "{}_take{}_{}",
+            slug(prompt),
+            take,
+            timestamp(SystemTime::now())
+        );
+        for attempt in 1.. {
+            let name = match attempt {
+                1 => format!("{}.{}", stem, extension),
+                n => format!("{}-{}.{}", stem, n, extension),
+            };
+            let path = self.dir.join(name);
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(_) => {
+                    self.references.lock().unwrap().insert(path.clone(), 1);
+                    return Ok(TempFile {
+                        path,
+                        references: self.references.clone(),
+                    });
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        unreachable!()
+    }
+
+    /// Delete files this manager created that no handle refers to anymore.
+    pub fn cleanup(&self) {
+        self.references.lock().unwrap().retain(|path, count| {
+            if *count > 0 {
+                return true;
+            }
+            let _ = fs::remove_file(path);
+            false
+        });
+    }
+
+    /// Delete files in the directory last modified more than `max_age` ago,
+    /// except those this manager still tracks.
+    pub fn remove_stale(&self, max_age: Duration) {
+        let Ok(entries) = fs::read_dir(&self.dir) else {
+            return;
+        };
+        let references = self.references.lock().unwrap();
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let is_stale = entry
+                .metadata()
+                .and_then(|meta| meta.modified())
+                .ok()
+                .and_then(|modified| modified.elapsed().ok())
+                .is_some_and(|age| age > max_age);
+            if is_stale && !references.contains_key(&path) {
+                let _ = fs::remove_file(&path);
+            }
+        }
+    }
+}
+
+impl Default for TempFiles {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for TempFiles {
+    fn drop(&mut self) {
+        self.cleanup();
+    }
+}
+
+/// Handle keeping a file from [`TempFiles::create`] alive; clones count as
+/// further references.
+#[derive(Debug)]
+pub struct TempFile {
+    path: PathBuf,
+    references: References,
+}
+
+impl TempFile {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Clone for TempFile {
+    fn clone(&self) -> Self {
+        if let Some(count) = self.references.lock().unwrap().get_mut(&self.path) {
+            *count += 1;
+        }
+        Self {
+            path: self.path.clone(),
+            references: self.references.clone(),
+        }
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if let Some(count) = self.references.lock().unwrap().get_mut(&self.path) {
+            *count = count.saturating_sub(1);
+        }
+    }
+}
+
+/// Lowercase words of `prompt` joined by dashes, for file names.
+pub(crate) fn slug(prompt: &str) -> String {
+    let mut slug = String::new();
+    for word in prompt
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+    {
+        if slug.len() + word.len() + 1 > MAX_SLUG_LEN && !slug.is_empty() {
+            break;
+        }
+        if !slug.is_empty() {
+            slug.push('-');
+        }
+        slug.push_str(&word.to_ascii_lowercase());
+    }
+    slug.truncate(MAX_SLUG_LEN);
+    if slug.is_empty() {
+        slug.push_str("untitled");
+    }
+    slug
+}
+
+/// `time` in UTC as `YYYYMMDD-HHMMSS`.
+pub(crate) fn timestamp(time: SystemTime) -> String {
+    let seconds = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (days, seconds) = (seconds / 86400, seconds % 86400);
+    // Civil date from days since 1970-01-01, after Howard Hinnant
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_names_are_unique_and_descriptive() {
+        assert_eq!(slug("Warm lo-fi piano, 80 BPM!"), "warm-lo-fi-piano-80-bpm");
+        assert_eq!(slug("  ..."), "untitled");
+        assert!(slug(&"drum ".repeat(20)).len() <= MAX_SLUG_LEN);
+        assert_eq!(
+            timestamp(UNIX_EPOCH + Duration::from_secs(1_792_333_381)),
+            "20261018-142301"
+        );
+
+        let dir = std::env::temp_dir().join(format!("poing_temp_test_{}", std::process::id()));
+        let manager = TempFiles::with_dir(dir.clone());
+        let first = manager.create("Dusty breakbeat", 3, "wav").unwrap();
+        let second = manager.create("Dusty breakbeat", 3, "wav").unwrap();
+        assert_ne!(first.path(), second.path());
+        let name = first.path().file_name().unwrap().to_string_lossy();
+        assert!(name.starts_with("dusty-breakbeat_take3_"), "{}", name);
+        assert!(name.ends_with(".wav"));
+        drop(manager);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_cleanup_keeps_referenced_files() {
+        let dir = std::env::temp_dir().join(format!("poing_cleanup_test_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let manager = TempFiles::with_dir(dir.clone());
+        let dragged = manager.create("bass", 1, "wav").unwrap();
+        let released = manager.create("bass", 2, "wav").unwrap();
+        let (dragged_path, released_path) = (dragged.path().to_owned(), released.path().to_owned());
+        let drag_thread = dragged.clone();
+        drop(dragged);
+        drop(released);
+
+        manager.cleanup();
+        assert!(dragged_path.exists());
+        assert!(!released_path.exists());
+
+        // Left behind when the manager goes while the drag still runs
+        drop(manager);
+        assert!(dragged_path.exists());
+        drop(drag_thread);
+
+        // Only old files from other sessions are removed at startup
+        let next = TempFiles::with_dir(dir.clone());
+        next.remove_stale(STALE_AGE);
+        assert!(dragged_path.exists());
+        std::thread::sleep(Duration::from_millis(10));
+        next.remove_stale(Duration::ZERO);
+        assert!(!dragged_path.exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
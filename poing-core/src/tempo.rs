@@ -0,0 +1,391 @@
+use rustfft::num_complex::Complex;
+use rustfft::FftPlanner;
+
+use crate::beats::{self, BeatGrid};
+use crate::{looping, stretch};
+
+/// STFT frame length for onset detection.
+const FRAME_SIZE: usize = 1024;
+/// Hop between onset envelope frames.
+const HOP_SIZE: usize = 256;
+/// Upper edge of the band used for downbeat detection.
+const BASS_CUTOFF_HZ: f32 = 200.0;
+/// Tempo search range.
+const MIN_BPM: f32 = 50.0;
+const MAX_BPM: f32 = 220.0;
+/// Width in octaves of the tempo prior around the expected BPM. Narrow enough
+/// to rule out half/double tempo, wide enough for MusicGen's drift.
+const HINTED_PRIOR_OCTAVES: f32 = 0.3;
+const UNHINTED_PRIOR_OCTAVES: f32 = 1.0;
+
+/// Onset strength over time, one value per STFT hop.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OnsetEnvelope {
+    pub values: Vec<f32>,
+    /// Envelope frames per second.
+    pub frame_rate: f32,
+}
+
+impl OnsetEnvelope {
+    /// Time in seconds of the attack detected at (fractional) `frame`, which
+    /// is where the attack reaches the centre of the analysis window.
+    pub fn frame_time(&self, frame: f32) -> f32 {
+        (frame + (FRAME_SIZE / HOP_SIZE) as f32 / 2.0) / self.frame_rate
+    }
+
+    /// Linearly interpolated value at a fractional frame, zero outside the envelope.
+    pub fn value_at(&self, frame: f32) -> f32 {
+        if frame < 0.0 {
+            return 0.0;
+        }
+        let index = frame as usize;
+        let frac = frame - index as f32;
+        let a = self.values.get(index).copied().unwrap_or(0.0);
+        let b = self.values.get(index + 1).copied().unwrap_or(0.0);
+        a + (b - a) * frac
+    }
+}
+
+/// Spectral flux of mono audio: the summed increase in log magnitude per bin,
+/// with the local mean removed so only sharp attacks remain.
+pub fn onset_envelope(samples: &[f32], sample_rate: u32) -> OnsetEnvelope {
+    spectral_flux(samples, sample_rate, FRAME_SIZE / 2 + 1)
+}
+
+/// Spectral flux below [`BASS_CUTOFF_HZ`], which follows kicks and bass notes
+/// and so tends to peak on downbeats.
+pub fn bass_onset_envelope(samples: &[f32], sample_rate: u32) -> OnsetEnvelope {
+    let bins = (BASS_CUTOFF_HZ * FRAME_SIZE as f32 / sample_rate as f32).ceil() as usize + 1;
+    spectral_flux(samples, sample_rate, bins)
+}
+
+fn spectral_flux(samples: &[f32], sample_rate: u32, bins: usize) -> OnsetEnvelope {
+    let frame_rate = sample_rate as f32 / HOP_SIZE as f32;
+    if samples.len() < FRAME_SIZE {
+        return OnsetEnvelope {
+            values: Vec::new(),
+            frame_rate,
+        };
+    }
+
+    let fft = FftPlanner::<f32>::new().plan_fft_forward(FRAME_SIZE);
+    let window: Vec<f32> = (0..FRAME_SIZE)
+        .map(|i| 0.5 - 0.5 * (2.0 * std::f32::consts::PI * i as f32 / FRAME_SIZE as f32).cos())
+        .collect();
+    let mut previous = vec![0.0f32; bins];
+    let mut buffer = vec![Complex::new(0.0, 0.0); FRAME_SIZE];
+
+    let frames = (samples.len() - FRAME_SIZE) / HOP_SIZE + 1;
+    let mut flux = Vec::with_capacity(frames);
+    for frame in 0..frames {
+        let start = frame * HOP_SIZE;
+        for (i, slot) in buffer.iter_mut().enumerate() {
+            *slot = Complex::new(samples[start + i] * window[i], 0.0);
+        }
+        fft.process(&mut buffer);
+
+        let mut sum = 0.0;
+        for (bin, prev) in previous.iter_mut().enumerate() {
+            let magnitude = (1.0 + 100.0 * buffer[bin].norm()).ln();
+            if frame > 0 {
+                sum += (magnitude - *prev).max(0.0);
+            }
+            *prev = magnitude;
+        }
+        flux.push(sum);
+    }
+
+    // Subtract a moving average of about a quarter second
+    let half = (frame_rate * 0.125) as usize;
+    let values = (0..flux.len())
+        .map(|i| {
+            let lo = i.saturating_sub(half);
+            let hi = (i + half + 1).min(flux.len());
+            let mean = flux[lo..hi].iter().sum::<f32>() / (hi - lo) as f32;
+            (flux[i] - mean).max(0.0)
+        })
+        .collect();
+    OnsetEnvelope { values, frame_rate }
+}
+
+/// Estimate the tempo of mono audio in BPM, or `None` if no pulse is found.
+///
+/// Candidates are scored by the autocorrelation of the onset envelope at the
+/// beat period and its multiples, weighted by a log-normal prior around
+/// `expected_bpm` (120 if unknown) to settle half/double tempo ambiguity.
+pub fn estimate_tempo(samples: &[f32], sample_rate: u32, expected_bpm: Option<f32>) -> Option<f32> {
+    tempo_from_envelope(&onset_envelope(samples, sample_rate), expected_bpm)
+}
+
+pub(crate) fn tempo_from_envelope(
+    envelope: &OnsetEnvelope,
+    expected_bpm: Option<f32>,
+) -> Option<f32> {
+    let values = &envelope.values;
+    let n = values.len();
+    let energy: f32 = values.iter().map(|v| v * v).sum();
+    if energy <= f32::EPSILON {
+        return None;
+    }
+
+    let autocorrelation = |lag: usize| -> f32 {
+        if lag == 0 || lag >= n {
+            return 0.0;
+        }
+        values[..n - lag]
+            .iter()
+            .zip(&values[lag..])
+            .map(|(a, b)| a * b)
+            .sum::<f32>()
+            / (n - lag) as f32
+    };
+
+    let (center, width) = match expected_bpm {
+        Some(bpm) if bpm > 0.0 => (bpm, HINTED_PRIOR_OCTAVES),
+        _ => (120.0, UNHINTED_PRIOR_OCTAVES),
+    };
+    let lag_for = |bpm: f32| 60.0 * envelope.frame_rate / bpm;
+    let min_lag = lag_for(MAX_BPM).floor().max(1.0) as usize;
+    let max_lag = (lag_for(MIN_BPM).ceil() as usize).min(n / 2);
+    if min_lag >= max_lag {
+        return None;
+    }
+
+    let score = |lag: usize| -> f32 {
+        let pulse = autocorrelation(lag) + 0.5 * autocorrelation(2 * lag);
+        let octaves = (lag_for(center) / lag as f32).log2();
+        pulse * (-0.5 * (octaves / width).powi(2)).exp()
+    };
+    let (best, best_score) = (min_lag..=max_lag)
+        .map(|lag| (lag, score(lag)))
+        .max_by(|a, b| a.1.total_cmp(&b.1))?;
+    if best_score <= 0.0 {
+        return None;
+    }
+
+    // Refine at the largest multiple of the period that still fits, which
+    // divides the lag quantization error by that multiple.
+    let multiple = [4, 2, 1]
+        .into_iter()
+        .find(|k| best * k + 1 < n / 2)
+        .unwrap_or(1);
+    let coarse = best * multiple;
+    let peak = (coarse.saturating_sub(multiple)..=coarse + multiple)
+        .filter(|lag| *lag > 1)
+        .max_by(|a, b| autocorrelation(*a).total_cmp(&autocorrelation(*b)))?;
+    let (left, mid, right) = (
+        autocorrelation(peak - 1),
+        autocorrelation(peak),
+        autocorrelation(peak + 1),
+    );
+    let denominator = left - 2.0 * mid + right;
+    let offset = if denominator.abs() > f32::EPSILON {
+        (0.5 * (left - right) / denominator).clamp(-0.5, 0.5)
+    } else {
+        0.0
+    };
+    let period = (peak as f32 + offset) / multiple as f32;
+    Some(60.0 * envelope.frame_rate / period)
+}
+
+/// The grid a clip should be conformed to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TempoTarget {
+    pub bpm: f32,
+    pub bars: u32,
+    pub beats_per_bar: u32,
+    /// Crossfade the beat after the last bar into the head so the clip loops seamlessly.
+    pub looped: bool,
+}
+
+impl TempoTarget {
+    pub fn beats(&self) -> f32 {
+        (self.bars * self.beats_per_bar) as f32
+    }
+
+    pub fn duration_seconds(&self) -> f32 {
+        self.beats() * 60.0 / self.bpm
+    }
+}
+
+/// Align a clip to its first downbeat and time-stretch it so that
+/// `target.bars` bars at its detected tempo last exactly
+/// `target.duration_seconds()`.
+///
+/// Returns the conformed audio and, if a beat grid was found, the grid of the
+/// result, which starts on a downbeat at sample 0. When no grid is found the
+/// clip is taken to follow the BPM hint and the target length is cut from its
+/// start unstretched.
+///
+/// A clip that holds fewer bars than requested keeps the target tempo and is
+/// cut to the whole bars it does hold, so the result may be shorter than the
+/// target but never carries a false tempo.
+///
+/// Looped targets keep one extra beat, stretched along with the bars, as the
+/// crossfade source for [`looping::make_seamless`].
+pub fn conform_to_tempo(
+    samples: &[f32],
+    sample_rate: u32,
+    target: &TempoTarget,
+) -> (Vec<f32>, Option<BeatGrid>) {
+    let sr = sample_rate as f32;
+    let target_len = (target.duration_seconds() * sr).round() as usize;
+    let beat_len = (60.0 / target.bpm * sr).round() as usize;
+    let tail_len = if target.looped { beat_len } else { 0 };
+
+    let grid = beats::detect_beats(samples, sample_rate, Some(target.bpm), target.beats_per_bar);
+    let (aligned, source_bpm) = match &grid {
+        Some(grid) => (
+            beats::align_to_downbeat(samples, sample_rate, grid),
+            grid.bpm,
+        ),
+        None => (samples.to_vec(), target.bpm),
+    };
+
+    let tail_beats = if target.looped { 1.0 } else { 0.0 };
+    let needed = ((target.beats() + tail_beats) * 60.0 / source_bpm * sr).round() as usize;
+    let source_len = needed.min(aligned.len());
+    let stretched_len = if source_len == needed {
+        target_len + tail_len
+    } else {
+        (source_len as f32 * source_bpm / target.bpm).round() as usize
+    };
+    let mut stretched = stretch::time_stretch(&aligned[..source_len], sample_rate, stretched_len);
+
+    let bar_len = beat_len * target.beats_per_bar.max(1) as usize;
+    let whole_bars = stretched_len / bar_len * bar_len;
+    let loop_len = if stretched_len >= target_len {
+        target_len
+    } else if whole_bars > 0 {
+        whole_bars
+    } else {
+        stretched_len
+    };
+    let conformed = if target.looped {
+        looping::make_seamless(&stretched, loop_len, beat_len, sample_rate)
+    } else {
+        stretched.truncate(loop_len);
+        stretched
+    };
+
+    let grid = grid.map(|_| BeatGrid {
+        bpm: target.bpm,
+        beats_per_bar: target.beats_per_bar,
+        first_downbeat: 0.0,
+    });
+    (conformed, grid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Decaying noise bursts on every beat, louder on the downbeat.
+    fn click_track(bpm: f32, seconds: f32, sample_rate: u32) -> Vec<f32> {
+        let len = (seconds * sample_rate as f32) as usize;
+        let beat = 60.0 / bpm * sample_rate as f32;
+        let mut seed = 1u32;
+        (0..len)
+            .map(|i| {
+                seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+                let noise = (seed >> 8) as f32 / (1u32 << 24) as f32 * 2.0 - 1.0;
+                let beat_index = (i as f32 / beat) as usize;
+                let since = i as f32 - beat_index as f32 * beat;
+                let gain = if beat_index.is_multiple_of(4) {
+                    1.0
+                } else {
+                    0.6
+                };
+                noise * gain * (-since / (0.01 * sample_rate as f32)).exp()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_estimates_click_track_tempo() {
+        for bpm in [92.0, 128.0, 174.0] {
+            let audio = click_track(bpm, 8.0, 32000);
+            let estimate = estimate_tempo(&audio, 32000, Some(bpm * 1.1)).unwrap();
+            assert!(
+                (estimate - bpm).abs() < bpm * 0.01,
+                "{} vs {}",
+                estimate,
+                bpm
+            );
+        }
+        assert_eq!(estimate_tempo(&vec![0.0; 32000], 32000, None), None);
+    }
+
+    #[test]
+    fn test_conform_to_tempo() {
+        // Generated at 125 BPM but requested at 120: 4 bars must last 8 seconds
+        let audio = click_track(125.0, 10.0, 32000);
+        let target = TempoTarget {
+            bpm: 120.0,
+            bars: 4,
+            beats_per_bar: 4,
+            looped: false,
+        };
+        let (conformed, grid) = conform_to_tempo(&audio, 32000, &target);
+        assert_eq!(grid.map(|g| g.first_downbeat), Some(0.0));
+        assert_eq!(conformed.len(), 8 * 32000);
+        let estimate = estimate_tempo(&conformed, 32000, Some(120.0)).unwrap();
+        assert!((estimate - 120.0).abs() < 1.5, "{}", estimate);
+
+        let looped = TempoTarget {
+            looped: true,
+            ..target
+        };
+        let (conformed, _) = conform_to_tempo(&audio, 32000, &looped);
+        assert_eq!(conformed.len(), 8 * 32000);
+    }
+
+    #[test]
+    fn test_conform_without_grid_keeps_pitch_and_length() {
+        // A steady pad has no beats; generated with headroom it is 20% too long
+        let cycle: Vec<f32> = (0..128)
+            .map(|i| (2.0 * std::f32::consts::PI * i as f32 / 128.0).sin())
+            .collect();
+        let pad: Vec<f32> = (0..(9.6 * 32000.0) as usize)
+            .map(|i| cycle[i % 128])
+            .collect();
+        let target = TempoTarget {
+            bpm: 120.0,
+            bars: 4,
+            beats_per_bar: 4,
+            looped: false,
+        };
+        let (conformed, grid) = conform_to_tempo(&pad, 32000, &target);
+        assert_eq!(grid, None);
+        assert_eq!(conformed.len(), 8 * 32000);
+        // Cut, not squeezed: the pitch stays at 250 Hz
+        let crossings = conformed
+            .windows(2)
+            .filter(|w| w[0] < 0.0 && w[1] >= 0.0)
+            .count();
+        assert!(
+            (crossings as f32 / 8.0 - 250.0).abs() < 2.0,
+            "{}",
+            crossings
+        );
+    }
+
+    #[test]
+    fn test_short_clip_keeps_target_tempo() {
+        // Only 6 seconds at 120 BPM: fewer whole bars than the 4 requested
+        let audio = click_track(120.0, 6.0, 32000);
+        let target = TempoTarget {
+            bpm: 120.0,
+            bars: 4,
+            beats_per_bar: 4,
+            looped: false,
+        };
+        let (conformed, grid) = conform_to_tempo(&audio, 32000, &target);
+        assert_eq!(grid.map(|g| g.bpm), Some(120.0));
+        let bar_len = 2 * 32000;
+        assert!(conformed.len() < 4 * bar_len && conformed.len() >= bar_len);
+        assert_eq!(conformed.len() % bar_len, 0);
+        let estimate = estimate_tempo(&conformed, 32000, Some(120.0)).unwrap();
+        assert!((estimate - 120.0).abs() < 1.5, "{}", estimate);
+    }
+}
@@ -0,0 +1,12 @@
+//! Fixtures shared by the unit tests.
+
+use std::path::PathBuf;
+
+/// An empty folder `poing_<name>_<pid>` in the system temp directory. Tests
+/// remove it when they are done.
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("poing_{}_{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
@@ -1,3 +1,5 @@
+use std::fs::OpenOptions;
+use std::io::{Read, Seek, SeekFrom, Write};
 use std::path::Path;
 
 /// Write mono f32 samples to a WAV file at the given path.
@@ -20,12 +22,168 @@ pub fn write_wav(
     Ok(())
 }
 
-/// Write mono f32 samples to a WAV file in a temp directory, returning the path.
-pub fn write_wav_temp(
+/// Encode mono f32 samples as an in-memory 32-bit float WAV file.
+pub fn encode_wav(
     samples: &[f32],
     sample_rate: u32,
-) -> Result<std::path::PathBuf, Box<dyn std::error::Error>> {
-    let path = std::env::temp_dir().join("poing_generated.wav");
-    write_wav(samples, sample_rate, &path)?;
-    Ok(path)
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let spec = hound::WavSpec {
+        channels: 1,
+        sample_rate,
+        bits_per_sample: 32,
+        sample_format: hound::SampleFormat::Float,
+    };
+    let mut cursor = std::io::Cursor::new(Vec::new());
+    let mut writer = hound::WavWriter::new(&mut cursor, spec)?;
+    for &sample in samples {
+        writer.write_sample(sample)?;
+    }
+    writer.finalize()?;
+    Ok(cursor.into_inner())
+}
+
+/// Read a WAV file and mix it down to mono f32 samples, returning them with the file's sample rate.
+pub fn read_wav(path: &Path) -> Result<(Vec<f32>, u32), Box<dyn std::error::Error>> {
+    let mut reader = hound::WavReader::open(path)?;
+    let spec = reader.spec();
+    let interleaved: Vec<f32> = match spec.sample_format {
+        hound::SampleFormat::Float => reader.samples::<f32>().collect::<Result<_, _>>()?,
+        hound::SampleFormat::Int => {
+            let scale = 1.0 / (1u64 << (spec.bits_per_sample - 1)) as f32;
+            reader
+                .samples::<i32>()
+                .map(|s| s.map(|v| v as f32 * scale))
+                .collect::<Result<_, _>>()?
+        }
+    };
+    let channels = spec.channels.max(1) as usize;
+    let mono = interleaved
+        .chunks(channels)
+        .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+        .collect();
+    Ok((mono, spec.sample_rate))
+}
+
+/// Read a WAV or FLAC file, chosen by extension, mixed down to mono f32
+/// samples. Returns them with the file's sample rate.
+pub fn read_audio_file(path: &Path) -> Result<(Vec<f32>, u32), Box<dyn std::error::Error>> {
+    let extension = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "wav" | "wave" => read_wav(path),
+        "flac" => crate::flac::read_flac(path),
+        _ => Err(format!("unsupported audio file {}", path.display()).into()),
+    }
+}
+
+/// Append a `smpl` chunk with one forward loop from `start` to `end` (sample
+/// frames, inclusive) to an existing WAV file, so samplers and DAWs loop it.
+pub fn write_loop_points(
+    path: &Path,
+    sample_rate: u32,
+    start: u32,
+    end: u32,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut chunk = Vec::with_capacity(60);
+    let sample_period_ns = 1_000_000_000 / sample_rate.max(1);
+    // Manufacturer, product, sample period, MIDI unity note (middle C),
+    // pitch fraction, SMPTE format, SMPTE offset, loop count, sampler data
+    for value in [0, 0, sample_period_ns, 60, 0, 0, 0, 1, 0] {
+        chunk.extend_from_slice(&u32::to_le_bytes(value));
+    }
+    // Cue point ID, type (0 = forward), start, end, fraction, play count (0 = infinite)
+    for value in [0, 0, start, end, 0, 0] {
+        chunk.extend_from_slice(&u32::to_le_bytes(value));
+    }
+    append_chunk(path, b"smpl", &chunk)
+}
+
+/// The first loop in a WAV file's `smpl` chunk as inclusive `(start, end)` sample frames.
+pub fn read_loop_points(path: &Path) -> Result<Option<(u32, u32)>, Box<dyn std::error::Error>> {
+    let u32_at = |data: &[u8], offset: usize| -> Option<u32> {
+        Some(u32::from_le_bytes(
+            data.get(offset..offset + 4)?.try_into().ok()?,
+        ))
+    };
+    for (id, data) in read_chunks(path)? {
+        if &id == b"smpl" && u32_at(&data, 28).unwrap_or(0) > 0 {
+            // Loop records follow the 36-byte header
+            return Ok(u32_at(&data, 36 + 8).zip(u32_at(&data, 36 + 12)));
+        }
+    }
+    Ok(None)
+}
+
+/// Append a chunk to the end of an existing WAV file and update the RIFF size.
+pub fn append_chunk(
+    path: &Path,
+    id: &[u8; 4],
+    data: &[u8],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    let end_of_file = file.seek(SeekFrom::End(0))?;
+    // Chunks start on even offsets
+    if end_of_file % 2 == 1 {
+        file.write_all(&[0])?;
+    }
+    file.write_all(id)?;
+    file.write_all(&(data.len() as u32).to_le_bytes())?;
+    file.write_all(data)?;
+    if data.len() % 2 == 1 {
+        file.write_all(&[0])?;
+    }
+
+    let riff_size = file.seek(SeekFrom::End(0))? - 8;
+    file.seek(SeekFrom::Start(4))?;
+    file.write_all(&(riff_size as u32).to_le_bytes())?;
+    Ok(())
+}
+
+/// A RIFF chunk ID and its data.
+pub type Chunk = ([u8; 4], Vec<u8>);
+
+/// Every top-level chunk of a WAV file, in file order.
+pub fn read_chunks(path: &Path) -> Result<Vec<Chunk>, Box<dyn std::error::Error>> {
+    let mut bytes = Vec::new();
+    std::fs::File::open(path)?.read_to_end(&mut bytes)?;
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err("not a RIFF/WAVE file".into());
+    }
+
+    let mut chunks = Vec::new();
+    let mut offset = 12;
+    while let Some(header) = bytes.get(offset..offset + 8) {
+        let id: [u8; 4] = header[0..4].try_into()?;
+        let size = u32::from_le_bytes(header[4..8].try_into()?) as usize;
+        let data = offset + 8;
+        let end = (data + size).min(bytes.len());
+        chunks.push((id, bytes[data..end].to_vec()));
+        offset = data + size + size % 2;
+    }
+    Ok(chunks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_loop_points_round_trip() {
+        let path = std::env::temp_dir().join(format!("poing_loop_{}.wav", std::process::id()));
+        let samples: Vec<f32> = (0..1000).map(|i| (i as f32 * 0.01).sin()).collect();
+        write_wav(&samples, 32000, &path).unwrap();
+        assert_eq!(read_loop_points(&path).unwrap(), None);
+
+        write_loop_points(&path, 32000, 0, 999).unwrap();
+        assert_eq!(read_loop_points(&path).unwrap(), Some((0, 999)));
+        // The audio is still readable and the RIFF size covers the new chunk
+        let (read, rate) = read_wav(&path).unwrap();
+        assert_eq!((read.len(), rate), (1000, 32000));
+        let bytes = std::fs::read(&path).unwrap();
+        let riff_size = u32::from_le_bytes(bytes[4..8].try_into().unwrap());
+        assert_eq!(riff_size as usize, bytes.len() - 8);
+        let _ = std::fs::remove_file(&path);
+    }
 }
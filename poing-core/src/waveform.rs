@@ -0,0 +1,292 @@
+use crate::beats::BeatGrid;
+use crate::recording::TransportPosition;
+
+/// Samples summarized by each precomputed min/max block.
+const BLOCK_SIZE: usize = 256;
+/// Narrowest view, as a fraction of the whole clip.
+const MIN_VIEW_WIDTH: f64 = 1e-4;
+
+/// Mono audio with a min/max summary, so columns for any visible range can
+/// be computed without scanning every sample when zoomed out.
+#[derive(Debug, Clone, Default)]
+pub struct Peaks {
+    samples: Vec<f32>,
+    /// Min and max of each `BLOCK_SIZE` samples.
+    blocks: Vec<(f32, f32)>,
+}
+
+impl Peaks {
+    pub fn new(samples: Vec<f32>) -> Self {
+        let blocks = samples.chunks(BLOCK_SIZE).map(min_max).collect();
+        Self { samples, blocks }
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    pub fn samples(&self) -> &[f32] {
+        &self.samples
+    }
+
+    /// Min and max of each of `columns` equal slices of the samples from
+    /// `start` to `end`. Columns narrower than a sample show the sample they
+    /// fall on.
+    pub fn columns(&self, start: f64, end: f64, columns: usize) -> Vec<(f32, f32)> {
+        let len = self.samples.len();
+        if len == 0 || columns == 0 || end <= start {
+            return vec![(0.0, 0.0); columns];
+        }
+        let width = (end - start) / columns as f64;
+        (0..columns)
+            .map(|col| {
+                let from = (start + col as f64 * width).floor().max(0.0) as usize;
+                let to = ((start + (col + 1) as f64 * width).ceil() as usize).min(len);
+                if from >= len {
+                    return (0.0, 0.0);
+                }
+                self.range_min_max(from, to.max(from + 1))
+            })
+            .collect()
+    }
+
+    fn range_min_max(&self, from: usize, to: usize) -> (f32, f32) {
+        let first_block = from.div_ceil(BLOCK_SIZE);
+        let last_block = to / BLOCK_SIZE;
+        if first_block >= last_block {
+            return min_max(&self.samples[from..to]);
+        }
+        let head = min_max(&self.samples[from..first_block * BLOCK_SIZE]);
+        let tail = min_max(&self.samples[last_block * BLOCK_SIZE..to]);
+        self.blocks[first_block..last_block]
+            .iter()
+            .chain([&head, &tail])
+            .fold((0.0, 0.0), |(lo, hi), &(min, max)| {
+                (lo.min(min), hi.max(max))
+            })
+    }
+}
+
+/// Min and max of `samples`, including zero so silence draws as a flat line.
+fn min_max(samples: &[f32]) -> (f32, f32) {
+    samples
+        .iter()
+        .fold((0.0f32, 0.0f32), |(lo, hi), &s| (lo.min(s), hi.max(s)))
+}
+
+/// The visible part of a clip, as fractions of its length.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Viewport {
+    pub start: f64,
+    pub end: f64,
+}
+
+impl Default for Viewport {
+    fn default() -> Self {
+        Self {
+            start: 0.0,
+            end: 1.0,
+        }
+    }
+}
+
+impl Viewport {
+    pub fn width(&self) -> f64 {
+        self.end - self.start
+    }
+
+    pub fn is_zoomed(&self) -> bool {
+        self.width() < 1.0
+    }
+
+    /// Zoom in by `factor` (below 1 zooms out), keeping the clip position
+    /// under `anchor` in place. `anchor` is a fraction of the view width.
+    pub fn zoom(&mut self, factor: f64, anchor: f64) {
+        let width = (self.width() / factor).clamp(MIN_VIEW_WIDTH, 1.0);
+        let pivot = self.start + anchor * self.width();
+        self.start = pivot - anchor * width;
+        self.end = self.start + width;
+        self.clamp();
+    }
+
+    /// Scroll by `delta` view widths, positive to the right.
+    pub fn scroll(&mut self, delta: f64) {
+        let offset = delta * self.width();
+        self.start += offset;
+        self.end += offset;
+        self.clamp();
+    }
+
+    /// Position of the clip fraction `position` within the view, 0 at the
+    /// left edge and 1 at the right.
+    pub fn to_view(&self, position: f64) -> f64 {
+        (position - self.start) / self.width()
+    }
+
+    /// Clip fraction at the view fraction `x`.
+    pub fn to_clip(&self, x: f64) -> f64 {
+        self.start + x * self.width()
+    }
+
+    fn clamp(&mut self) {
+        let width = self.width();
+        if self.start < 0.0 {
+            self.start = 0.0;
+        } else if self.end > 1.0 {
+            self.start = 1.0 - width;
+        }
+        self.end = self.start + width;
+    }
+}
+
+/// A labelled mark on the bar ruler.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RulerTick {
+    pub time: f32,
+    /// Bar number, or bar and beat ("5.3") between bar lines.
+    pub label: String,
+    pub is_downbeat: bool,
+}
+
+/// Ticks for the bars and beats of `grid` between `start` and `end` seconds,
+/// thinned to whole bars and then every 2nd, 4th, ... bar so there are at
+/// most `max_ticks`. Bar 1 starts at the grid's first downbeat.
+pub fn ruler_ticks(grid: &BeatGrid, start: f32, end: f32, max_ticks: usize) -> Vec<RulerTick> {
+    let beat = grid.beat_seconds();
+    if beat <= 0.0 || !beat.is_finite() || end <= start || max_ticks == 0 {
+        return Vec::new();
+    }
+    let beats_per_bar = grid.beats_per_bar.max(1) as i64;
+    let first = ((start - grid.first_downbeat) / beat).ceil() as i64;
+    let last = ((end - grid.first_downbeat) / beat).floor() as i64;
+    let visible = (last - first + 1).max(0) as usize;
+
+    let mut stride = 1;
+    while visible.div_ceil(stride as usize) > max_ticks {
+        stride = if stride == 1 {
+            beats_per_bar
+        } else {
+            stride * 2
+        };
+    }
+    (first..=last)
+        .filter(|index| index.rem_euclid(stride) == 0)
+        .map(|index| {
+            let bar = index.div_euclid(beats_per_bar) + 1;
+            let beat_in_bar = index.rem_euclid(beats_per_bar);
+            RulerTick {
+                time: grid.first_downbeat + index as f32 * beat,
+                label: if beat_in_bar == 0 {
+                    bar.to_string()
+                } else {
+                    format!("{}.{}", bar, beat_in_bar + 1)
+                },
+                is_downbeat: beat_in_bar == 0,
+            }
+        })
+        .collect()
+}
+
+/// Where in a clip of `duration` seconds playback is, assuming the clip
+/// starts at the beginning of the host timeline and repeats. Tempo-synced
+/// clips follow the host's beat position, others its time in seconds.
+/// `None` while the host is stopped.
+pub fn playhead_seconds(
+    transport: &TransportPosition,
+    duration: f32,
+    grid: Option<&BeatGrid>,
+) -> Option<f32> {
+    if !transport.playing || duration <= 0.0 {
+        return None;
+    }
+    let seconds = match (grid, transport.pos_beats) {
+        (Some(grid), Some(beats)) if grid.bpm > 0.0 => beats * 60.0 / grid.bpm as f64,
+        _ => transport.pos_seconds?,
+    };
+    Some(seconds.rem_euclid(duration as f64) as f32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_columns_match_direct_scan_at_any_zoom() {
+        let samples: Vec<f32> = (0..10_000)
+            .map(|i| (i as f32 * 0.013).sin() * (i as f32 / 10_000.0))
+            .collect();
+        let peaks = Peaks::new(samples.clone());
+        for (start, end, columns) in [
+            (0.0, 10_000.0, 100),
+            (1234.5, 7000.25, 333),
+            (5000.0, 5010.0, 40),
+        ] {
+            let width = (end - start) / columns as f64;
+            for (col, &(min, max)) in peaks.columns(start, end, columns).iter().enumerate() {
+                let from = (start + col as f64 * width).floor() as usize;
+                let to = ((start + (col + 1) as f64 * width).ceil() as usize).max(from + 1);
+                assert_eq!(
+                    (min, max),
+                    min_max(&samples[from..to]),
+                    "{start}..{end} column {col}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_viewport_zoom_and_scroll() {
+        let mut view = Viewport::default();
+        view.zoom(4.0, 0.5);
+        assert_eq!(
+            view,
+            Viewport {
+                start: 0.375,
+                end: 0.625
+            }
+        );
+        // The position under the anchor stays put
+        let pivot = view.to_clip(0.25);
+        view.zoom(2.0, 0.25);
+        assert!((view.to_clip(0.25) - pivot).abs() < 1e-12);
+
+        view.scroll(100.0);
+        assert!((view.end - 1.0).abs() < 1e-12);
+        assert!((view.width() - 0.125).abs() < 1e-12);
+        view.zoom(0.001, 0.0);
+        assert_eq!(view, Viewport::default());
+    }
+
+    #[test]
+    fn test_ruler_ticks_thin_out_to_bars() {
+        let grid = BeatGrid {
+            bpm: 120.0,
+            beats_per_bar: 4,
+            first_downbeat: 0.0,
+        };
+        let beats = ruler_ticks(&grid, 0.0, 4.0, 16);
+        assert_eq!(beats.len(), 9);
+        assert_eq!(beats[1].label, "1.2");
+        assert_eq!(beats[4].label, "2");
+        assert!(beats[4].is_downbeat);
+
+        let bars = ruler_ticks(&grid, 0.0, 64.0, 16);
+        assert!(bars.len() <= 16);
+        assert!(bars.iter().all(|tick| tick.is_downbeat));
+        assert_eq!(bars[1].label, "5");
+
+        let transport = TransportPosition {
+            playing: true,
+            pos_beats: Some(18.0),
+            pos_seconds: Some(9.0),
+            ..TransportPosition::default()
+        };
+        // Eight beats at 120 BPM: beat 18 is 1 second into the third repeat
+        assert_eq!(playhead_seconds(&transport, 4.0, Some(&grid)), Some(1.0));
+        assert_eq!(playhead_seconds(&transport, 4.0, None), Some(1.0));
+    }
+}
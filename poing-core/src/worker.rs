@@ -0,0 +1,619 @@
+use std::collections::{HashSet, VecDeque};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+
+use crate::beats::BeatGrid;
+use crate::export::{self, ExportFormat, ExportOptions};
+use crate::metadata::{self, TakeMetadata};
+use crate::musicgen::{
+    Cancelled, GenerationHooks, GenerationParams, MusicGenPipeline, SAMPLE_RATE,
+};
+use crate::postprocess::{self, PostProcessSettings};
+use crate::tempo::{self, TempoTarget};
+use crate::{wav, GenerationSettings};
+
+/// Identifier assigned to every submitted job, unique per worker.
+pub type JobId = u64;
+
+/// What a job should produce.
+#[derive(Debug, Clone, PartialEq)]
+pub enum JobKind {
+    /// Generate a single clip from the prompt.
+    Generate,
+    /// Continue the given mono audio (at [`crate::musicgen::SAMPLE_RATE`]),
+    /// `count` times with consecutive seeds.
+    Continue {
+        input_audio: Arc<Vec<f32>>,
+        count: usize,
+    },
+    /// Generate `count` clips from the same prompt with consecutive seeds.
+    Variations { count: usize },
+}
+
+/// Fraction of a bar a region may be off from whole bars and still count
+/// as tempo synced.
+const BAR_TOLERANCE: f32 = 0.01;
+
+/// Most clips a [`JobKind::Variations`] or [`JobKind::Continue`] job may ask for.
+pub const MAX_VARIATIONS: usize = 16;
+
+/// A unit of work for the inference worker.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Job {
+    pub prompt: String,
+    pub model_dir: PathBuf,
+    pub params: GenerationParams,
+    pub kind: JobKind,
+    /// Align every take to its first downbeat and time-stretch it to this grid.
+    pub tempo: Option<TempoTarget>,
+    /// Post-processing run on every take, after tempo conforming.
+    pub post_process: Option<PostProcessSettings>,
+    /// Settings the job was built from, kept with its takes for export metadata.
+    pub settings: Option<GenerationSettings>,
+}
+
+impl Job {
+    /// Number of takes the job produces.
+    pub fn take_count(&self) -> usize {
+        match self.kind {
+            JobKind::Generate => 1,
+            JobKind::Continue { count, .. } | JobKind::Variations { count } => {
+                count.clamp(1, MAX_VARIATIONS)
+            }
+        }
+    }
+}
+
+/// One finished clip. `params.seed` is always set so the take can be reproduced.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Take {
+    pub job_id: JobId,
+    pub prompt: String,
+    pub model_dir: PathBuf,
+    pub params: GenerationParams,
+    pub audio: Arc<Vec<f32>>,
+    /// Beat grid of `audio`, when the job asked for a tempo and one was found.
+    pub grid: Option<BeatGrid>,
+    /// `audio` was made to loop seamlessly from its last sample to its first.
+    pub looped: bool,
+    /// Beats per bar of the time signature the take was generated in.
+    pub beats_per_bar: u32,
+    /// Settings of the job that produced the take.
+    pub settings: Option<GenerationSettings>,
+}
+
+impl Take {
+    /// Write the take as a 32-bit float WAV file, with loop points if it is a
+    /// seamless loop.
+    pub fn write_wav(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let options = ExportOptions {
+            format: ExportFormat::WavFloat,
+            sample_rate: None,
+        };
+        self.export(path, &options)
+    }
+
+    /// The part of the take from sample `range.start` to `range.end`, e.g. a
+    /// few bars selected for export. The grid moves with the audio, and
+    /// the settings count the bars the region spans, on the grid or at the
+    /// requested tempo. A region that isn't whole bars loses its settings,
+    /// so it isn't exported as tempo synced.
+    pub fn region(&self, range: Range<usize>) -> Take {
+        let end = range.end.min(self.audio.len());
+        let start = range.start.min(end);
+        if start == 0 && end == self.audio.len() {
+            return self.clone();
+        }
+        let audio = self.audio[start..end].to_vec();
+        let offset = start as f32 / SAMPLE_RATE as f32;
+        let duration = audio.len() as f32 / SAMPLE_RATE as f32;
+        // Bar 1 of the region is the bar line nearest its start
+        let grid = self.grid.map(|grid| {
+            let bar = grid.beat_seconds() * grid.beats_per_bar.max(1) as f32;
+            let shifted = grid.first_downbeat - offset;
+            BeatGrid {
+                first_downbeat: shifted - (shifted / bar).round() * bar,
+                ..grid
+            }
+        });
+        let settings = self.settings.clone().and_then(|mut settings| {
+            let bar = match grid {
+                Some(grid) => grid.beat_seconds() * grid.beats_per_bar.max(1) as f32,
+                // Conformed without a grid when beat detection failed
+                None if settings.bpm > 0.0 => {
+                    self.beats_per_bar.max(1) as f32 * 60.0 / settings.bpm
+                }
+                None => return None,
+            };
+            let bars = duration / bar;
+            if bars.round() < 1.0 || (bars - bars.round()).abs() > BAR_TOLERANCE {
+                return None;
+            }
+            settings.bars = bars.round() as u32;
+            Some(settings)
+        });
+        Take {
+            params: GenerationParams {
+                duration_seconds: duration,
+                ..self.params.clone()
+            },
+            audio: Arc::new(audio),
+            grid,
+            // Only the whole clip loops seamlessly
+            looped: false,
+            settings,
+            ..self.clone()
+        }
+    }
+
+    /// Write the take in any export format. WAV files also get generation
+    /// metadata, and loop points for seamless loops.
+    pub fn export(
+        &self,
+        path: &Path,
+        options: &ExportOptions,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        export::export(&self.audio, SAMPLE_RATE, path, options)?;
+        if self.looped && !self.audio.is_empty() && options.format.extension() == "wav" {
+            let rate = options.output_rate(SAMPLE_RATE);
+            let len = (self.audio.len() as f64 * rate as f64 / SAMPLE_RATE as f64).round();
+            wav::write_loop_points(path, rate, 0, len as u32 - 1)?;
+        }
+        if options.format.extension() == "wav" {
+            metadata::write_metadata(path, &TakeMetadata::from_take(self))?;
+        }
+        Ok(())
+    }
+}
+
+/// Events published by the worker, in the order they happen for each job.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WorkerEvent {
+    /// The job was accepted; `position` jobs are ahead of it.
+    Queued { id: JobId, position: usize },
+    /// The job left the queue and models are being loaded or run.
+    Started { id: JobId },
+    /// Fraction (0.0..=1.0) of the job completed.
+    Progress { id: JobId, progress: f32 },
+    /// Audio decoded so far for the clip currently being generated.
+    Partial { id: JobId, audio: Arc<Vec<f32>> },
+    /// The job finished; variations produce several takes.
+    Done { id: JobId, takes: Vec<Take> },
+    /// The job failed.
+    Error { id: JobId, message: String },
+    /// The job was cancelled while queued or running.
+    Cancelled { id: JobId },
+}
+
+impl WorkerEvent {
+    /// The job this event belongs to.
+    pub fn job_id(&self) -> JobId {
+        match self {
+            WorkerEvent::Queued { id, .. }
+            | WorkerEvent::Started { id }
+            | WorkerEvent::Progress { id, .. }
+            | WorkerEvent::Partial { id, .. }
+            | WorkerEvent::Done { id, .. }
+            | WorkerEvent::Error { id, .. }
+            | WorkerEvent::Cancelled { id } => *id,
+        }
+    }
+}
+
+enum Command {
+    Submit(JobId, Box<Job>),
+    Cancel(JobId),
+}
+
+type Hook = Box<dyn Fn(&WorkerEvent) + Send + Sync>;
+
+/// Fan-out of worker events to the hook and all live subscribers.
+struct Publisher {
+    hook: Hook,
+    subscribers: Mutex<Vec<Sender<WorkerEvent>>>,
+}
+
+impl Publisher {
+    fn publish(&self, event: WorkerEvent) {
+        (self.hook)(&event);
+        let mut subscribers = self.subscribers.lock().unwrap();
+        subscribers.retain(|tx| tx.send(event.clone()).is_ok());
+    }
+}
+
+/// State shared between the handle and the worker thread.
+struct Shared {
+    publisher: Publisher,
+    /// Jobs submitted but not yet started.
+    pending: AtomicUsize,
+    /// Jobs submitted and neither started nor cancelled.
+    queued: Mutex<HashSet<JobId>>,
+    /// Every job with an id below this was cancelled by [`InferenceWorker::cancel_all`].
+    cancelled_below: AtomicU64,
+    /// Cancellation flag of the job currently running.
+    running: Mutex<Option<(JobId, Arc<AtomicBool>)>>,
+}
+
+/// A persistent background thread that owns the MusicGen pipeline and runs
+/// jobs one at a time in submission order.
+///
+/// The thread is started lazily on the first submission and keeps the last
+/// loaded model in memory until a job asks for a different one. Dropping the
+/// worker cancels everything and lets the thread exit on its own.
+pub struct InferenceWorker {
+    shared: Arc<Shared>,
+    commands: Mutex<Option<Sender<Command>>>,
+    next_id: AtomicU64,
+}
+
+impl InferenceWorker {
+    /// Create a worker. `hook` sees every event on the thread that produced it,
+    /// before subscribers do.
+    pub fn new(hook: impl Fn(&WorkerEvent) + Send + Sync + 'static) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                publisher: Publisher {
+                    hook: Box::new(hook),
+                    subscribers: Mutex::new(Vec::new()),
+                },
+                pending: AtomicUsize::new(0),
+                queued: Mutex::new(HashSet::new()),
+                cancelled_below: AtomicU64::new(0),
+                running: Mutex::new(None),
+            }),
+            commands: Mutex::new(None),
+            next_id: AtomicU64::new(1),
+        }
+    }
+
+    /// Receive all events published from now on. Dropping the receiver unsubscribes.
+    pub fn subscribe(&self) -> Receiver<WorkerEvent> {
+        let (tx, rx) = mpsc::channel();
+        self.shared.publisher.subscribers.lock().unwrap().push(tx);
+        rx
+    }
+
+    /// Queue a job and return its id. A [`WorkerEvent::Queued`] is published immediately.
+    pub fn submit(&self, job: Job) -> JobId {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.shared.queued.lock().unwrap().insert(id);
+        let position = self.shared.pending.fetch_add(1, Ordering::SeqCst)
+            + usize::from(self.shared.running.lock().unwrap().is_some());
+        self.shared
+            .publisher
+            .publish(WorkerEvent::Queued { id, position });
+        self.send(Command::Submit(id, Box::new(job)));
+        id
+    }
+
+    /// Cancel a queued or running job. Unknown or finished ids are ignored.
+    pub fn cancel(&self, id: JobId) {
+        // The worker moves a job from `queued` to `running` under this lock
+        let running = self.shared.running.lock().unwrap();
+        if let Some((running_id, flag)) = running.as_ref() {
+            if *running_id == id {
+                flag.store(true, Ordering::Relaxed);
+                return;
+            }
+        }
+        let queued = self.shared.queued.lock().unwrap().remove(&id);
+        drop(running);
+        if queued {
+            self.send(Command::Cancel(id));
+        }
+    }
+
+    /// Cancel every queued job and the running one.
+    pub fn cancel_all(&self) {
+        self.shared
+            .cancelled_below
+            .store(self.next_id.load(Ordering::Relaxed), Ordering::SeqCst);
+        if let Some((_, flag)) = self.shared.running.lock().unwrap().as_ref() {
+            flag.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Number of jobs waiting to start.
+    pub fn pending_jobs(&self) -> usize {
+        self.shared.pending.load(Ordering::SeqCst)
+    }
+
+    fn send(&self, command: Command) {
+        let mut commands = self.commands.lock().unwrap();
+        let tx = commands.get_or_insert_with(|| {
+            let (tx, rx) = mpsc::channel();
+            let shared = self.shared.clone();
+            std::thread::Builder::new()
+                .name("poing-inference".into())
+                .spawn(move || worker_loop(rx, shared))
+                .expect("failed to spawn inference thread");
+            tx
+        });
+        let _ = tx.send(command);
+    }
+}
+
+impl Drop for InferenceWorker {
+    fn drop(&mut self) {
+        self.cancel_all();
+        // Closing the channel makes the worker thread exit once the queue drains
+        self.commands.lock().unwrap().take();
+    }
+}
+
+fn worker_loop(rx: Receiver<Command>, shared: Arc<Shared>) {
+    let mut queue: VecDeque<(JobId, Job)> = VecDeque::new();
+    let mut pipeline: Option<(PathBuf, MusicGenPipeline)> = None;
+
+    loop {
+        if queue.is_empty() {
+            match rx.recv() {
+                Ok(command) => apply_command(command, &mut queue, &shared),
+                Err(_) => return,
+            }
+        }
+        while let Ok(command) = rx.try_recv() {
+            apply_command(command, &mut queue, &shared);
+        }
+
+        let Some((id, job)) = queue.pop_front() else {
+            continue;
+        };
+        shared.pending.fetch_sub(1, Ordering::SeqCst);
+
+        // Publish the job as running first, so a cancel from now on sets its flag
+        let cancel = Arc::new(AtomicBool::new(false));
+        let queued = {
+            let mut running = shared.running.lock().unwrap();
+            *running = Some((id, cancel.clone()));
+            shared.queued.lock().unwrap().remove(&id)
+        };
+        if !queued || id < shared.cancelled_below.load(Ordering::SeqCst) {
+            *shared.running.lock().unwrap() = None;
+            shared.publisher.publish(WorkerEvent::Cancelled { id });
+            continue;
+        }
+        shared.publisher.publish(WorkerEvent::Started { id });
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            run_job(id, &job, &mut pipeline, &cancel, &shared.publisher)
+        }));
+        *shared.running.lock().unwrap() = None;
+
+        let event = match result {
+            Ok(Ok(takes)) => WorkerEvent::Done { id, takes },
+            Ok(Err(e)) if e.is::<Cancelled>() => WorkerEvent::Cancelled { id },
+            Ok(Err(e)) => WorkerEvent::Error {
+                id,
+                message: e.to_string(),
+            },
+            Err(panic) => {
+                let msg = if let Some(s) = panic.downcast_ref::<String>() {
+                    s.clone()
+                } else if let Some(s) = panic.downcast_ref::<&str>() {
+                    s.to_string()
+                } else {
+                    "Unknown panic".to_string()
+                };
+                eprintln!("[poing] Inference worker panicked: {}", msg);
+                // The sessions may be in an inconsistent state; reload next time
+                pipeline = None;
+                WorkerEvent::Error {
+                    id,
+                    message: format!("Internal error: {}", msg),
+                }
+            }
+        };
+        shared.publisher.publish(event);
+    }
+}
+
+fn apply_command(command: Command, queue: &mut VecDeque<(JobId, Job)>, shared: &Shared) {
+    match command {
+        Command::Submit(id, job) => queue.push_back((id, *job)),
+        Command::Cancel(id) => {
+            if let Some(index) = queue.iter().position(|(queued, _)| *queued == id) {
+                queue.remove(index);
+                shared.pending.fetch_sub(1, Ordering::SeqCst);
+                shared.publisher.publish(WorkerEvent::Cancelled { id });
+            }
+        }
+    }
+}
+
+fn run_job(
+    id: JobId,
+    job: &Job,
+    pipeline: &mut Option<(PathBuf, MusicGenPipeline)>,
+    cancel: &AtomicBool,
+    publisher: &Publisher,
+) -> Result<Vec<Take>, Box<dyn std::error::Error>> {
+    if pipeline
+        .as_ref()
+        .is_none_or(|(dir, _)| *dir != job.model_dir)
+    {
+        // Free the old model before loading the next one
+        *pipeline = None;
+        *pipeline = Some((
+            job.model_dir.clone(),
+            MusicGenPipeline::load(&job.model_dir)?,
+        ));
+    }
+    let (_, pipeline) = pipeline.as_mut().unwrap();
+
+    let progress = |progress: f32| publisher.publish(WorkerEvent::Progress { id, progress });
+    let partial = |audio: Vec<f32>| {
+        publisher.publish(WorkerEvent::Partial {
+            id,
+            audio: Arc::new(audio),
+        });
+    };
+    let hooks = GenerationHooks {
+        progress: &progress,
+        partial: Some(&partial),
+        cancel: Some(cancel),
+    };
+    render_job(id, job, pipeline, &hooks, |_, _| Ok(()))
+}
+
+/// Render the takes of `job` with a loaded pipeline, one after another with
+/// consecutive seeds, conforming and post-processing each as the job asks.
+///
+/// `hooks.progress` sees the fraction of the whole job completed, and
+/// `on_take` every take with its index as soon as it is finished, e.g. to
+/// write it out before the next one starts.
+pub fn render_job(
+    id: JobId,
+    job: &Job,
+    pipeline: &mut MusicGenPipeline,
+    hooks: &GenerationHooks,
+    mut on_take: impl FnMut(usize, &Take) -> Result<(), Box<dyn std::error::Error>>,
+) -> Result<Vec<Take>, Box<dyn std::error::Error>> {
+    let count = job.take_count();
+    let base_seed = job.params.seed.unwrap_or_else(rand::random);
+
+    let mut takes = Vec::with_capacity(count);
+    for index in 0..count {
+        let params = GenerationParams {
+            seed: Some(base_seed.wrapping_add(index as u64)),
+            ..job.params.clone()
+        };
+        let progress = |p: f32| (hooks.progress)((index as f32 + p) / count as f32);
+        let take_hooks = GenerationHooks {
+            progress: &progress,
+            ..*hooks
+        };
+
+        let mut audio = match &job.kind {
+            JobKind::Continue { input_audio, .. } => {
+                pipeline.continue_audio(&job.prompt, input_audio, &params, &take_hooks)?
+            }
+            JobKind::Generate | JobKind::Variations { .. } => {
+                pipeline.generate_with_hooks(&job.prompt, &params, &take_hooks)?
+            }
+        };
+        let mut grid = None;
+        if let Some(target) = &job.tempo {
+            (audio, grid) = tempo::conform_to_tempo(&audio, SAMPLE_RATE, target);
+        }
+        let looped = job.tempo.is_some_and(|target| target.looped);
+        if let Some(settings) = &job.post_process {
+            postprocess::process(&mut audio, SAMPLE_RATE, settings, looped);
+        }
+        let take = Take {
+            job_id: id,
+            prompt: job.prompt.clone(),
+            model_dir: job.model_dir.clone(),
+            params,
+            audio: Arc::new(audio),
+            grid,
+            looped,
+            beats_per_bar: job.tempo.map_or(4, |target| target.beats_per_bar),
+            settings: job.settings.clone(),
+        };
+        on_take(index, &take)?;
+        takes.push(take);
+    }
+    Ok(takes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn job(model_dir: &str) -> Job {
+        Job {
+            prompt: "test".into(),
+            model_dir: PathBuf::from(model_dir),
+            params: GenerationParams::default(),
+            kind: JobKind::Generate,
+            tempo: None,
+            post_process: None,
+            settings: None,
+        }
+    }
+
+    fn next(rx: &Receiver<WorkerEvent>) -> WorkerEvent {
+        rx.recv_timeout(Duration::from_secs(10))
+            .expect("worker event")
+    }
+
+    #[test]
+    fn test_missing_model_reports_error() {
+        let worker = InferenceWorker::new(|_| {});
+        let rx = worker.subscribe();
+        let id = worker.submit(job("/nonexistent/poing-model"));
+
+        assert_eq!(next(&rx), WorkerEvent::Queued { id, position: 0 });
+        assert_eq!(next(&rx), WorkerEvent::Started { id });
+        assert!(matches!(next(&rx), WorkerEvent::Error { id: e, .. } if e == id));
+        assert_eq!(worker.pending_jobs(), 0);
+    }
+
+    #[test]
+    fn test_region_keeps_grid_aligned() {
+        // Four bars at 120 BPM, the first downbeat 0.1s in
+        let take = Take {
+            job_id: 1,
+            prompt: "test".into(),
+            model_dir: PathBuf::from("/models/test"),
+            params: GenerationParams::default(),
+            audio: Arc::new(vec![0.0; 8 * SAMPLE_RATE as usize + 3200]),
+            grid: Some(BeatGrid {
+                bpm: 120.0,
+                beats_per_bar: 4,
+                first_downbeat: 0.1,
+            }),
+            looped: true,
+            beats_per_bar: 4,
+            settings: Some(GenerationSettings::default()),
+        };
+        // Bars 3-4
+        let start = (4.1 * SAMPLE_RATE as f32) as usize;
+        let region = take.region(start..start + 4 * SAMPLE_RATE as usize);
+        assert_eq!(region.audio.len(), 4 * SAMPLE_RATE as usize);
+        assert!(region.grid.unwrap().first_downbeat.abs() < 1e-4);
+        assert_eq!(region.settings.unwrap().bars, 2);
+        assert_eq!(region.params.duration_seconds, 4.0);
+        assert!(!region.looped);
+        assert_eq!(take.region(0..usize::MAX), take);
+
+        // Without a grid bars are counted at the requested 120 BPM
+        let take = Take { grid: None, ..take };
+        let region = take.region(0..4 * SAMPLE_RATE as usize);
+        assert_eq!(region.settings.unwrap().bars, 2);
+        let slice = take.region(0..3 * SAMPLE_RATE as usize / 2);
+        assert!(slice.settings.is_none());
+
+        // or in 3/4, at 1.5 seconds a bar
+        let take = Take {
+            beats_per_bar: 3,
+            ..take
+        };
+        let region = take.region(0..3 * SAMPLE_RATE as usize);
+        assert_eq!(region.settings.unwrap().bars, 2);
+    }
+
+    #[test]
+    fn test_cancelled_job_never_starts() {
+        let worker = InferenceWorker::new(|_| {});
+        let rx = worker.subscribe();
+        let first = worker.submit(job("/nonexistent/a"));
+        let second = worker.submit(job("/nonexistent/b"));
+        worker.cancel(second);
+
+        let events: Vec<WorkerEvent> = (0..5).map(|_| next(&rx)).collect();
+        assert!(events.contains(&WorkerEvent::Cancelled { id: second }));
+        assert!(events.contains(&WorkerEvent::Started { id: first }));
+        assert!(!events.contains(&WorkerEvent::Started { id: second }));
+
+        // Finished and unknown ids leave nothing behind
+        worker.cancel(first);
+        worker.cancel(second + 100);
+        assert!(worker.shared.queued.lock().unwrap().is_empty());
+    }
+}
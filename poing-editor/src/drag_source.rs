@@ -1,18 +1,19 @@
-use std::path::Path;
+use poing_core::tempfiles::TempFile;
 
 /// Initiate an OS-level file drag from the plugin window.
 ///
-/// This writes the generated audio to a temp WAV file and asks the OS
-/// to start a drag operation so the user can drop it onto a DAW timeline.
-pub fn start_file_drag(file_path: &Path) {
+/// The caller writes the generated audio to a temp WAV file and this asks
+/// the OS to start a drag operation so the user can drop it onto a DAW
+/// timeline.
+pub fn start_file_drag(file: &TempFile) {
     #[cfg(target_os = "macos")]
-    macos::start_file_drag_macos(file_path);
+    macos::start_file_drag_macos(file.path());
 
     #[cfg(target_os = "windows")]
-    windows::start_file_drag_windows(file_path);
+    windows::start_file_drag_windows(file.path());
 
     #[cfg(target_os = "linux")]
-    linux::start_file_drag_linux(file_path);
+    linux::start_file_drag_linux(file);
 }
 
 #[cfg(target_os = "macos")]
@@ -108,16 +109,647 @@ mod windows {
 
 #[cfg(target_os = "linux")]
 mod linux {
+    //! Source side of the XDND protocol, see
+    //! <https://www.freedesktop.org/wiki/Specifications/XDND/>.
+    //!
+    //! The drag runs on its own thread and X connection. The plugin window's
+    //! connection holds the implicit grab of the pressed mouse button, so
+    //! instead of grabbing the pointer the drag polls it until the button is
+    //! released, and talks to whichever XDND aware window is underneath.
+
+    use std::ffi::CString;
+    use std::os::raw::{c_int, c_long, c_uchar, c_uint, c_ulong};
+    use poing_core::tempfiles::TempFile;
     use std::path::Path;
+    use std::sync::{Mutex, Once};
+    use std::time::{Duration, Instant};
+    use x11_dl::xlib;
 
-    pub fn start_file_drag_linux(file_path: &Path) {
-        // XDND protocol requires setting atoms on the X11 window and handling
-        // a sequence of client messages. For now, log the path.
-        // TODO: Implement XDND protocol for full drag-drop support.
-        let _ = file_path;
-        eprintln!(
-            "poing: drag-drop not yet implemented on Linux (file: {})",
-            file_path.display()
-        );
+    /// Protocol version we speak; targets may answer with an older one.
+    const XDND_VERSION: c_long = 5;
+    const POLL_INTERVAL: Duration = Duration::from_millis(10);
+    /// How long the target gets to answer once the button is released.
+    const DROP_TIMEOUT: Duration = Duration::from_secs(5);
+
+    type ErrorHandler =
+        Option<unsafe extern "C" fn(*mut xlib::Display, *mut xlib::XErrorEvent) -> c_int>;
+
+    /// Displays opened for drags, whose errors are ignored, and the handler
+    /// that was installed before ours, which gets everything else.
+    static DRAG_DISPLAYS: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+    static PREVIOUS_ERROR_HANDLER: Mutex<ErrorHandler> = Mutex::new(None);
+    static INSTALL_ERROR_HANDLER: Once = Once::new();
+
+    /// Windows vanishing mid-drag raise BadWindow, which Xlib's default
+    /// handler answers by exiting the host.
+    unsafe extern "C" fn ignore_drag_errors(
+        display: *mut xlib::Display,
+        event: *mut xlib::XErrorEvent,
+    ) -> c_int {
+        if DRAG_DISPLAYS.lock().unwrap().contains(&(display as usize)) {
+            return 0;
+        }
+        match *PREVIOUS_ERROR_HANDLER.lock().unwrap() {
+            Some(previous) => previous(display, event),
+            None => 0,
+        }
+    }
+
+    pub fn start_file_drag_linux(file: &TempFile) {
+        let uri_list = format!("{}\r\n", file_uri(file.path()));
+        // Keeps the file until the target is done reading it
+        let file = file.clone();
+        std::thread::spawn(move || {
+            let _file = file;
+            let result = XConnection::open()
+                .and_then(|conn| drag(&conn, uri_list.as_bytes(), || conn.query_pointer()));
+            if let Err(e) = result {
+                eprintln!("poing: drag failed: {}", e);
+            }
+        });
+    }
+
+    /// `file://` URI of an absolute path, percent-encoding everything but
+    /// unreserved characters and slashes.
+    pub fn file_uri(path: &Path) -> String {
+        use std::os::unix::ffi::OsStrExt;
+        let mut uri = String::from("file://");
+        for &byte in path.as_os_str().as_bytes() {
+            match byte {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                    uri.push(byte as char)
+                }
+                _ => uri.push_str(&format!("%{:02X}", byte)),
+            }
+        }
+        uri
+    }
+
+    struct Atoms {
+        aware: xlib::Atom,
+        proxy: xlib::Atom,
+        selection: xlib::Atom,
+        enter: xlib::Atom,
+        position: xlib::Atom,
+        status: xlib::Atom,
+        leave: xlib::Atom,
+        drop: xlib::Atom,
+        finished: xlib::Atom,
+        action_copy: xlib::Atom,
+        uri_list: xlib::Atom,
+        targets: xlib::Atom,
+    }
+
+    struct XConnection {
+        xlib: xlib::Xlib,
+        display: *mut xlib::Display,
+        root: xlib::Window,
+        atoms: Atoms,
+    }
+
+    impl XConnection {
+        fn open() -> Result<Self, String> {
+            let xlib = xlib::Xlib::open().map_err(|e| e.to_string())?;
+            let display = unsafe { (xlib.XOpenDisplay)(std::ptr::null()) };
+            if display.is_null() {
+                return Err("cannot open the X display".into());
+            }
+            DRAG_DISPLAYS.lock().unwrap().push(display as usize);
+            INSTALL_ERROR_HANDLER.call_once(|| unsafe {
+                let previous = (xlib.XSetErrorHandler)(Some(ignore_drag_errors));
+                *PREVIOUS_ERROR_HANDLER.lock().unwrap() = previous;
+            });
+            let intern = |name: &str| {
+                let name = CString::new(name).unwrap();
+                unsafe { (xlib.XInternAtom)(display, name.as_ptr(), xlib::False) }
+            };
+            let atoms = Atoms {
+                aware: intern("XdndAware"),
+                proxy: intern("XdndProxy"),
+                selection: intern("XdndSelection"),
+                enter: intern("XdndEnter"),
+                position: intern("XdndPosition"),
+                status: intern("XdndStatus"),
+                leave: intern("XdndLeave"),
+                drop: intern("XdndDrop"),
+                finished: intern("XdndFinished"),
+                action_copy: intern("XdndActionCopy"),
+                uri_list: intern("text/uri-list"),
+                targets: intern("TARGETS"),
+            };
+            let root = unsafe { (xlib.XDefaultRootWindow)(display) };
+            Ok(Self {
+                xlib,
+                display,
+                root,
+                atoms,
+            })
+        }
+
+        /// Pointer position on the root window while the left button is held.
+        fn query_pointer(&self) -> Option<(i32, i32)> {
+            let (mut root, mut child) = (0, 0);
+            let (mut root_x, mut root_y, mut win_x, mut win_y) = (0, 0, 0, 0);
+            let mut mask: c_uint = 0;
+            let on_screen = unsafe {
+                (self.xlib.XQueryPointer)(
+                    self.display,
+                    self.root,
+                    &mut root,
+                    &mut child,
+                    &mut root_x,
+                    &mut root_y,
+                    &mut win_x,
+                    &mut win_y,
+                    &mut mask,
+                )
+            };
+            (on_screen != 0 && mask & xlib::Button1Mask != 0).then_some((root_x, root_y))
+        }
+
+        /// The first item of a 32-bit property such as an ATOM or WINDOW.
+        fn property_long(&self, window: xlib::Window, property: xlib::Atom) -> Option<c_ulong> {
+            let (mut actual_type, mut format) = (0, 0);
+            let (mut items, mut remaining) = (0, 0);
+            let mut data: *mut c_uchar = std::ptr::null_mut();
+            let status = unsafe {
+                (self.xlib.XGetWindowProperty)(
+                    self.display,
+                    window,
+                    property,
+                    0,
+                    1,
+                    xlib::False,
+                    xlib::AnyPropertyType as c_ulong,
+                    &mut actual_type,
+                    &mut format,
+                    &mut items,
+                    &mut remaining,
+                    &mut data,
+                )
+            };
+            if data.is_null() {
+                return None;
+            }
+            // 32-bit items are returned as C longs
+            let value = (status == xlib::Success as c_int && format == 32 && items > 0)
+                .then(|| unsafe { *(data as *const c_ulong) });
+            unsafe { (self.xlib.XFree)(data as *mut _) };
+            value
+        }
+
+        /// The innermost XDND aware window under the root position, with
+        /// the protocol version to use.
+        fn find_target(&self, x: i32, y: i32) -> Option<Target> {
+            let mut parent = self.root;
+            loop {
+                let (mut child_x, mut child_y, mut child) = (0, 0, 0);
+                let on_screen = unsafe {
+                    (self.xlib.XTranslateCoordinates)(
+                        self.display,
+                        self.root,
+                        parent,
+                        x,
+                        y,
+                        &mut child_x,
+                        &mut child_y,
+                        &mut child,
+                    )
+                };
+                if on_screen == 0 || child == 0 {
+                    return None;
+                }
+                if let Some(version) = self.property_long(child, self.atoms.aware) {
+                    // Messages go to the proxy window when there is one
+                    let proxy = self.property_long(child, self.atoms.proxy);
+                    return Some(Target {
+                        window: child,
+                        destination: proxy.unwrap_or(child),
+                        version: (version as c_long).min(XDND_VERSION),
+                        awaiting_status: false,
+                        accepted: false,
+                    });
+                }
+                parent = child;
+            }
+        }
+
+        fn send_message(
+            &self,
+            destination: xlib::Window,
+            window: xlib::Window,
+            message_type: xlib::Atom,
+            data: [c_long; 5],
+        ) {
+            let mut event = xlib::XEvent::from(xlib::XClientMessageEvent {
+                type_: xlib::ClientMessage,
+                serial: 0,
+                send_event: xlib::True,
+                display: self.display,
+                window,
+                message_type,
+                format: 32,
+                data: xlib::ClientMessageData::from(data),
+            });
+            unsafe {
+                (self.xlib.XSendEvent)(
+                    self.display,
+                    destination,
+                    xlib::False,
+                    xlib::NoEventMask,
+                    &mut event,
+                );
+                (self.xlib.XFlush)(self.display);
+            }
+        }
+    }
+
+    impl Drop for XConnection {
+        fn drop(&mut self) {
+            unsafe { (self.xlib.XCloseDisplay)(self.display) };
+            DRAG_DISPLAYS
+                .lock()
+                .unwrap()
+                .retain(|display| *display != self.display as usize);
+        }
+    }
+
+    /// The window the pointer is over.
+    struct Target {
+        window: xlib::Window,
+        /// Where messages for `window` are sent, its XdndProxy if it has one.
+        destination: xlib::Window,
+        version: c_long,
+        /// An XdndPosition was sent and its XdndStatus hasn't arrived yet.
+        awaiting_status: bool,
+        /// The last XdndStatus accepted the drop.
+        accepted: bool,
+    }
+
+    struct DragSource<'a> {
+        conn: &'a XConnection,
+        window: xlib::Window,
+        uri_list: &'a [u8],
+        target: Option<Target>,
+        /// Set by XdndFinished to whether the target took the file.
+        finished: Option<bool>,
+    }
+
+    impl DragSource<'_> {
+        fn send(&self, target: &Target, message_type: xlib::Atom, data: [c_long; 5]) {
+            self.conn
+                .send_message(target.destination, target.window, message_type, data);
+        }
+
+        fn enter(&mut self, target: Target) {
+            // Three types or fewer fit in the message, so no XdndTypeList
+            let data = [
+                self.window as c_long,
+                target.version << 24,
+                self.conn.atoms.uri_list as c_long,
+                0,
+                0,
+            ];
+            self.send(&target, self.conn.atoms.enter, data);
+            self.target = Some(target);
+        }
+
+        fn leave(&mut self) {
+            if let Some(target) = self.target.take() {
+                let data = [self.window as c_long, 0, 0, 0, 0];
+                self.send(&target, self.conn.atoms.leave, data);
+            }
+        }
+
+        fn position(&mut self, x: i32, y: i32) {
+            let data = [
+                self.window as c_long,
+                0,
+                ((x as c_long) << 16) | (y as c_long & 0xFFFF),
+                xlib::CurrentTime as c_long,
+                self.conn.atoms.action_copy as c_long,
+            ];
+            if let Some(target) = &self.target {
+                self.send(target, self.conn.atoms.position, data);
+            }
+            if let Some(target) = &mut self.target {
+                target.awaiting_status = true;
+            }
+        }
+
+        /// Handle status and finish messages and serve the file URI to
+        /// selection requests.
+        fn process_events(&mut self) {
+            let conn = self.conn;
+            while unsafe { (conn.xlib.XPending)(conn.display) } > 0 {
+                let mut event: xlib::XEvent = unsafe { std::mem::zeroed() };
+                unsafe { (conn.xlib.XNextEvent)(conn.display, &mut event) };
+                match event.get_type() {
+                    xlib::ClientMessage => {
+                        let message: &xlib::XClientMessageEvent = event.as_ref();
+                        let from = message.data.get_long(0) as xlib::Window;
+                        let Some(target) = self.target.as_mut().filter(|t| t.window == from) else {
+                            continue;
+                        };
+                        if message.message_type == conn.atoms.status {
+                            target.awaiting_status = false;
+                            target.accepted = message.data.get_long(1) & 1 != 0;
+                        } else if message.message_type == conn.atoms.finished {
+                            // Before version 5 finishing means success
+                            let accepted = target.version < 5 || message.data.get_long(1) & 1 != 0;
+                            self.finished = Some(accepted);
+                        }
+                    }
+                    xlib::SelectionRequest => {
+                        let request: xlib::XSelectionRequestEvent = *event.as_ref();
+                        self.answer_selection_request(&request);
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        fn answer_selection_request(&self, request: &xlib::XSelectionRequestEvent) {
+            let conn = self.conn;
+            // Obsolete clients leave the property unset
+            let mut property = if request.property == 0 {
+                request.target
+            } else {
+                request.property
+            };
+            unsafe {
+                if request.selection != conn.atoms.selection {
+                    property = 0;
+                } else if request.target == conn.atoms.uri_list {
+                    (conn.xlib.XChangeProperty)(
+                        conn.display,
+                        request.requestor,
+                        property,
+                        conn.atoms.uri_list,
+                        8,
+                        xlib::PropModeReplace,
+                        self.uri_list.as_ptr(),
+                        self.uri_list.len() as c_int,
+                    );
+                } else if request.target == conn.atoms.targets {
+                    let targets = [conn.atoms.targets, conn.atoms.uri_list];
+                    (conn.xlib.XChangeProperty)(
+                        conn.display,
+                        request.requestor,
+                        property,
+                        xlib::XA_ATOM,
+                        32,
+                        xlib::PropModeReplace,
+                        targets.as_ptr() as *const c_uchar,
+                        targets.len() as c_int,
+                    );
+                } else {
+                    property = 0;
+                }
+                let mut notify = xlib::XEvent::from(xlib::XSelectionEvent {
+                    type_: xlib::SelectionNotify,
+                    serial: 0,
+                    send_event: xlib::True,
+                    display: conn.display,
+                    requestor: request.requestor,
+                    selection: request.selection,
+                    target: request.target,
+                    property,
+                    time: request.time,
+                });
+                (conn.xlib.XSendEvent)(
+                    conn.display,
+                    request.requestor,
+                    xlib::False,
+                    xlib::NoEventMask,
+                    &mut notify,
+                );
+                (conn.xlib.XFlush)(conn.display);
+            }
+        }
+
+        /// Process events until `done` holds or the drop timeout passes.
+        fn wait_until(&mut self, done: impl Fn(&Self) -> bool) {
+            let deadline = Instant::now() + DROP_TIMEOUT;
+            while !done(self) && Instant::now() < deadline {
+                std::thread::sleep(POLL_INTERVAL);
+                self.process_events();
+            }
+        }
+    }
+
+    /// Drag `uri_list` until `pointer` reports the button released, then
+    /// drop it on the window underneath. Returns whether the target took it.
+    fn drag(
+        conn: &XConnection,
+        uri_list: &[u8],
+        mut pointer: impl FnMut() -> Option<(i32, i32)>,
+    ) -> Result<bool, String> {
+        let window = unsafe {
+            (conn.xlib.XCreateSimpleWindow)(conn.display, conn.root, -10, -10, 1, 1, 0, 0, 0)
+        };
+        if window == 0 {
+            return Err("cannot create the drag source window".into());
+        }
+        unsafe {
+            (conn.xlib.XSetSelectionOwner)(
+                conn.display,
+                conn.atoms.selection,
+                window,
+                xlib::CurrentTime,
+            );
+        }
+        let mut source = DragSource {
+            conn,
+            window,
+            uri_list,
+            target: None,
+            finished: None,
+        };
+
+        let mut last_position = None;
+        while let Some((x, y)) = pointer() {
+            source.process_events();
+            let found = conn.find_target(x, y);
+            let current = source.target.as_ref().map(|target| target.window);
+            if found.as_ref().map(|target| target.window) != current {
+                source.leave();
+                if let Some(target) = found {
+                    source.enter(target);
+                }
+                last_position = None;
+            }
+            // One position at a time, as the protocol asks
+            let ready = source.target.as_ref().is_some_and(|t| !t.awaiting_status);
+            if ready && last_position != Some((x, y)) {
+                source.position(x, y);
+                last_position = Some((x, y));
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+
+        source.wait_until(|source| {
+            source
+                .target
+                .as_ref()
+                .is_none_or(|target| !target.awaiting_status)
+        });
+        let accepted = source.target.as_ref().is_some_and(|target| target.accepted);
+        if accepted {
+            if let Some(target) = &source.target {
+                let data = [window as c_long, 0, xlib::CurrentTime as c_long, 0, 0];
+                source.send(target, conn.atoms.drop, data);
+            }
+            source.wait_until(|source| source.finished.is_some());
+        } else {
+            source.leave();
+        }
+        unsafe { (conn.xlib.XDestroyWindow)(conn.display, window) };
+        Ok(source.finished.unwrap_or(false))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use std::sync::mpsc::{self, Sender};
+
+        #[test]
+        fn test_file_uri_escapes_path() {
+            assert_eq!(
+                file_uri(Path::new("/tmp/poing take #1.wav")),
+                "file:///tmp/poing%20take%20%231.wav"
+            );
+        }
+
+        /// Drag onto a drop target run by a second connection. Needs an X
+        /// server, e.g. `xvfb-run cargo test -p poing-editor`, and passes
+        /// trivially without one.
+        #[test]
+        fn test_drop_onto_target_window() {
+            if std::env::var_os("DISPLAY").is_none() {
+                return;
+            }
+            let (ready_tx, ready_rx) = mpsc::channel();
+            let (received_tx, received_rx) = mpsc::channel();
+            let harness = std::thread::spawn(move || drop_target(ready_tx, received_tx));
+            ready_rx.recv().unwrap();
+
+            let conn = XConnection::open().unwrap();
+            // Hover over the target, moving a little, then release
+            let mut polls = 0;
+            let pointer = || {
+                polls += 1;
+                (polls < 30).then_some((40 + polls, 60))
+            };
+            let uri_list = b"file:///tmp/poing%20take.wav\r\n";
+            assert_eq!(drag(&conn, uri_list, pointer), Ok(true));
+            assert_eq!(
+                received_rx.recv_timeout(DROP_TIMEOUT).unwrap(),
+                String::from_utf8_lossy(uri_list)
+            );
+            harness.join().unwrap();
+        }
+
+        /// A window covering the top left corner of the screen that accepts
+        /// copies of `text/uri-list` and reports what was dropped.
+        fn drop_target(ready: Sender<()>, received: Sender<String>) {
+            let conn = XConnection::open().unwrap();
+            let xlib = &conn.xlib;
+            let atoms = &conn.atoms;
+            let property = {
+                let name = CString::new("POING_DROP").unwrap();
+                unsafe { (xlib.XInternAtom)(conn.display, name.as_ptr(), xlib::False) }
+            };
+            let window = unsafe {
+                let window =
+                    (xlib.XCreateSimpleWindow)(conn.display, conn.root, 0, 0, 200, 200, 0, 0, 0);
+                let version = XDND_VERSION as c_ulong;
+                (xlib.XChangeProperty)(
+                    conn.display,
+                    window,
+                    atoms.aware,
+                    xlib::XA_ATOM,
+                    32,
+                    xlib::PropModeReplace,
+                    &version as *const c_ulong as *const c_uchar,
+                    1,
+                );
+                (xlib.XMapWindow)(conn.display, window);
+                (xlib.XSync)(conn.display, xlib::False);
+                window
+            };
+            ready.send(()).unwrap();
+
+            let mut source = 0;
+            let deadline = Instant::now() + DROP_TIMEOUT;
+            while Instant::now() < deadline {
+                if unsafe { (xlib.XPending)(conn.display) } == 0 {
+                    std::thread::sleep(POLL_INTERVAL);
+                    continue;
+                }
+                let mut event: xlib::XEvent = unsafe { std::mem::zeroed() };
+                unsafe { (xlib.XNextEvent)(conn.display, &mut event) };
+                match event.get_type() {
+                    xlib::ClientMessage => {
+                        let message: &xlib::XClientMessageEvent = event.as_ref();
+                        source = message.data.get_long(0) as xlib::Window;
+                        if message.message_type == atoms.enter {
+                            assert_eq!(message.data.get_long(2) as xlib::Atom, atoms.uri_list);
+                        } else if message.message_type == atoms.position {
+                            let data = [window as c_long, 1, 0, 0, atoms.action_copy as c_long];
+                            conn.send_message(source, source, atoms.status, data);
+                        } else if message.message_type == atoms.drop {
+                            unsafe {
+                                (xlib.XConvertSelection)(
+                                    conn.display,
+                                    atoms.selection,
+                                    atoms.uri_list,
+                                    property,
+                                    window,
+                                    message.data.get_long(2) as xlib::Time,
+                                );
+                                (xlib.XFlush)(conn.display);
+                            }
+                        }
+                    }
+                    xlib::SelectionNotify => {
+                        let notify: &xlib::XSelectionEvent = event.as_ref();
+                        assert_eq!(notify.property, property);
+                        received.send(read_text(&conn, window, property)).unwrap();
+                        let data = [window as c_long, 1, atoms.action_copy as c_long, 0, 0];
+                        conn.send_message(source, source, atoms.finished, data);
+                        return;
+                    }
+                    _ => {}
+                }
+            }
+            panic!("nothing was dropped");
+        }
+
+        fn read_text(conn: &XConnection, window: xlib::Window, property: xlib::Atom) -> String {
+            let (mut actual_type, mut format) = (0, 0);
+            let (mut items, mut remaining) = (0, 0);
+            let mut data: *mut c_uchar = std::ptr::null_mut();
+            unsafe {
+                (conn.xlib.XGetWindowProperty)(
+                    conn.display,
+                    window,
+                    property,
+                    0,
+                    1024,
+                    xlib::True,
+                    xlib::AnyPropertyType as c_ulong,
+                    &mut actual_type,
+                    &mut format,
+                    &mut items,
+                    &mut remaining,
+                    &mut data,
+                );
+                let bytes = std::slice::from_raw_parts(data, items as usize);
+                let text = String::from_utf8_lossy(bytes).into_owned();
+                (conn.xlib.XFree)(data as *mut _);
+                text
+            }
+        }
     }
 }
@@ -1,11 +1,14 @@
 mod drag_source;
+mod library;
 mod model;
 mod waveform;
 
-use model::{PoingEvent, PoingModel};
+use model::{PoingEvent, PoingModel, PostStage};
 use nih_plug::prelude::Editor;
 use nih_plug_vizia::vizia::prelude::*;
 use nih_plug_vizia::{assets, create_vizia_editor, ViziaState, ViziaTheming};
+use poing_core::export::ExportFormat;
+use poing_core::recording::{InputChannel, RecordSource};
 use poing_core::SharedState;
 use std::sync::Arc;
 use waveform::WaveformView;
@@ -73,13 +76,24 @@ pub fn create(
                             let names = names_lens.get(cx);
                             for (i, name) in names.iter().enumerate() {
                                 let name = name.clone();
-                                Label::new(cx, &name)
-                                    .class("dropdown-item")
-                                    .width(Stretch(1.0))
-                                    .on_press(move |cx| {
-                                        cx.emit(PoingEvent::SelectModel(i));
-                                        cx.emit(PopupEvent::Close);
+                                let description =
+                                    PoingModel::model_descriptions.map(move |descriptions| {
+                                        descriptions.get(i).cloned().unwrap_or_default()
                                     });
+                                VStack::new(cx, |cx| {
+                                    Label::new(cx, &name).width(Stretch(1.0));
+                                    Label::new(cx, description.clone())
+                                        .class("status-label")
+                                        .width(Stretch(1.0))
+                                        .display(description.map(|text| !text.is_empty()));
+                                })
+                                .class("dropdown-item")
+                                .height(Auto)
+                                .width(Stretch(1.0))
+                                .on_press(move |cx| {
+                                    cx.emit(PoingEvent::SelectModel(i));
+                                    cx.emit(PopupEvent::Close);
+                                });
                             }
                         });
                     },
@@ -92,6 +106,12 @@ pub fn create(
                     |cx| Label::new(cx, "Browse"),
                 );
 
+                Button::new(
+                    cx,
+                    |cx| cx.emit(PoingEvent::AddModelRoot),
+                    |cx| Label::new(cx, "Add Folder"),
+                );
+
                 Button::new(
                     cx,
                     |cx| cx.emit(PoingEvent::RemoveModel),
@@ -103,6 +123,25 @@ pub fn create(
             .child_top(Stretch(1.0))
             .child_bottom(Stretch(1.0));
 
+            // Name and description of the selected model
+            HStack::new(cx, |cx| {
+                Label::new(cx, "Name:").class("field-label");
+                Textbox::new(cx, PoingModel::model_display_name)
+                    .on_edit(|cx, text| cx.emit(PoingEvent::SetModelName(text)))
+                    .on_submit(|cx, _, _| cx.emit(PoingEvent::SaveModelDetails))
+                    .placeholder("folder name")
+                    .width(Pixels(160.0));
+                Textbox::new(cx, PoingModel::model_description)
+                    .on_edit(|cx, text| cx.emit(PoingEvent::SetModelDescription(text)))
+                    .on_submit(|cx, _, _| cx.emit(PoingEvent::SaveModelDetails))
+                    .placeholder("Description")
+                    .width(Stretch(1.0));
+            })
+            .height(Auto)
+            .col_between(Pixels(8.0))
+            .child_top(Stretch(1.0))
+            .child_bottom(Stretch(1.0));
+
             // Prompt input
             HStack::new(cx, |cx| {
                 Label::new(cx, "Prompt:").class("field-label");
@@ -112,12 +151,140 @@ pub fn create(
                     })
                     .placeholder("Describe the music to generate...")
                     .width(Stretch(1.0));
+
+                Dropdown::new(
+                    cx,
+                    |cx| Label::new(cx, "History \u{25BE}"),
+                    |cx| {
+                        Binding::new(cx, PoingModel::prompt_history, |cx, history_lens| {
+                            for prompt in history_lens.get(cx) {
+                                Label::new(cx, &prompt)
+                                    .class("dropdown-item")
+                                    .width(Stretch(1.0))
+                                    .on_press(move |cx| {
+                                        cx.emit(PoingEvent::UsePrompt(prompt.clone()));
+                                        cx.emit(PopupEvent::Close);
+                                    });
+                            }
+                        });
+                    },
+                )
+                .width(Pixels(90.0));
             })
             .height(Auto)
             .col_between(Pixels(8.0))
             .child_top(Stretch(1.0))
             .child_bottom(Stretch(1.0));
 
+            // Earlier prompts completing the one being typed
+            VStack::new(cx, |cx| {
+                Binding::new(cx, PoingModel::prompt_suggestions, |cx, suggestions_lens| {
+                    for prompt in suggestions_lens.get(cx) {
+                        Label::new(cx, &prompt)
+                            .class("suggestion")
+                            .width(Stretch(1.0))
+                            .on_press(move |cx| cx.emit(PoingEvent::UsePrompt(prompt.clone())));
+                    }
+                });
+            })
+            .display(PoingModel::has_suggestions)
+            .height(Auto)
+            .class("suggestions");
+
+            // Prompt template and the prompt it produces
+            HStack::new(cx, |cx| {
+                Label::new(cx, "Template:").class("field-label");
+                Dropdown::new(
+                    cx,
+                    |cx| Label::new(cx, PoingModel::template_name),
+                    |cx| {
+                        Binding::new(cx, PoingModel::template_names, |cx, names_lens| {
+                            let names = names_lens.get(cx);
+                            for (i, name) in names.iter().enumerate() {
+                                Label::new(cx, name)
+                                    .class("dropdown-item")
+                                    .width(Stretch(1.0))
+                                    .on_press(move |cx| {
+                                        cx.emit(PoingEvent::SelectTemplate(i));
+                                        cx.emit(PopupEvent::Close);
+                                    });
+                            }
+                        });
+                    },
+                )
+                .width(Pixels(120.0));
+
+                Textbox::new(cx, PoingModel::template_text)
+                    .on_edit(|cx, text| cx.emit(PoingEvent::SetTemplateText(text)))
+                    .on_submit(|cx, _, _| cx.emit(PoingEvent::SaveTemplates))
+                    .placeholder("{bpm} bpm[, in {key}]. {prompt}")
+                    .width(Stretch(1.0));
+
+                Button::new(
+                    cx,
+                    |cx| cx.emit(PoingEvent::AddTemplate),
+                    |cx| Label::new(cx, "+"),
+                );
+                Button::new(
+                    cx,
+                    |cx| cx.emit(PoingEvent::RemoveTemplate),
+                    |cx| Label::new(cx, "\u{2212}"),
+                );
+            })
+            .height(Auto)
+            .col_between(Pixels(8.0))
+            .child_top(Stretch(1.0))
+            .child_bottom(Stretch(1.0));
+            Label::new(cx, PoingModel::prompt_preview).class("status-label");
+
+            // Genre and instrument chips filling the template
+            HStack::new(cx, |cx| {
+                Label::new(cx, "Genre:").class("field-label");
+                Binding::new(cx, PoingModel::genre_tags, |cx, tags_lens| {
+                    for tag in tags_lens.get(cx) {
+                        let selected = tag.clone();
+                        Button::new(
+                            cx,
+                            move |cx| cx.emit(PoingEvent::SelectGenre(tag.clone())),
+                            |cx| Label::new(cx, &selected),
+                        )
+                        .class("chip")
+                        .toggle_class(
+                            "selected",
+                            PoingModel::genre.map(move |genre| genre.as_ref() == Some(&selected)),
+                        );
+                    }
+                });
+            })
+            .height(Auto)
+            .col_between(Pixels(4.0))
+            .child_top(Stretch(1.0))
+            .child_bottom(Stretch(1.0));
+
+            HStack::new(cx, |cx| {
+                Label::new(cx, "Instruments:").class("field-label");
+                Binding::new(cx, PoingModel::instrument_tags, |cx, tags_lens| {
+                    for tag in tags_lens.get(cx) {
+                        let selected = tag.clone();
+                        Button::new(
+                            cx,
+                            move |cx| cx.emit(PoingEvent::ToggleInstrument(tag.clone())),
+                            |cx| Label::new(cx, &selected),
+                        )
+                        .class("chip")
+                        .toggle_class(
+                            "selected",
+                            PoingModel::instruments
+                                .map(move |instruments| instruments.contains(&selected)),
+                        );
+                    }
+                });
+            })
+            .height(Auto)
+            .col_between(Pixels(4.0))
+            .child_top(Stretch(1.0))
+            .child_bottom(Stretch(1.0));
+
             // Generation settings row
             HStack::new(cx, |cx| {
                 Label::new(cx, "BPM:").class("field-label");
@@ -141,7 +308,32 @@ pub fn create(
                     |cx| cx.emit(PoingEvent::SyncDurationToRecording),
                     |cx| Label::new(cx, "Match Recording"),
                 );
+
+                Label::new(cx, "Key:").class("field-label");
+                Textbox::new(cx, PoingModel::key)
+                    .on_edit(|cx, text| cx.emit(PoingEvent::SetKey(text)))
+                    .placeholder("e.g. D minor")
+                    .width(Pixels(70.0));
+
+                Checkbox::new(cx, PoingModel::loop_mode)
+                    .on_toggle(|cx| cx.emit(PoingEvent::ToggleLoopMode));
+                Label::new(cx, "Loop").class("field-label");
+            })
+            .height(Auto)
+            .col_between(Pixels(8.0))
+            .child_top(Stretch(1.0))
+            .child_bottom(Stretch(1.0));
+
+            // Tempo and key detected in the recording, shown until applied
+            HStack::new(cx, |cx| {
+                Label::new(cx, PoingModel::detected_text).class("status-label");
+                Button::new(
+                    cx,
+                    |cx| cx.emit(PoingEvent::ApplyDetected),
+                    |cx| Label::new(cx, "Use"),
+                );
             })
+            .display(PoingModel::has_detection)
             .height(Auto)
             .col_between(Pixels(8.0))
             .child_top(Stretch(1.0))
@@ -158,6 +350,127 @@ pub fn create(
                 Textbox::new(cx, PoingModel::top_k)
                     .on_edit(|cx, text| cx.emit(PoingEvent::SetTopK(text)))
                     .width(Pixels(45.0));
+
+                Label::new(cx, "Seed:").class("field-label");
+                Textbox::new(cx, PoingModel::seed)
+                    .on_edit(|cx, text| cx.emit(PoingEvent::SetSeed(text)))
+                    .placeholder("random")
+                    .width(Pixels(120.0));
+            })
+            .height(Auto)
+            .col_between(Pixels(8.0))
+            .child_top(Stretch(1.0))
+            .child_bottom(Stretch(1.0));
+
+            // Recording row
+            HStack::new(cx, |cx| {
+                Checkbox::new(cx, PoingModel::record_synced)
+                    .on_toggle(|cx| cx.emit(PoingEvent::ToggleRecordSync));
+                Label::new(cx, "Record on bar with transport").class("field-label");
+
+                Label::new(cx, "Bars:").class("field-label");
+                Textbox::new(cx, PoingModel::record_bars)
+                    .on_edit(|cx, text| cx.emit(PoingEvent::SetRecordBars(text)))
+                    .placeholder("loop")
+                    .width(Pixels(40.0));
+
+                Label::new(cx, "Source:").class("field-label");
+                Dropdown::new(
+                    cx,
+                    |cx| Label::new(cx, PoingModel::record_source_name),
+                    |cx| {
+                        for source in RecordSource::ALL {
+                            Label::new(cx, source.name())
+                                .class("dropdown-item")
+                                .width(Stretch(1.0))
+                                .on_press(move |cx| {
+                                    cx.emit(PoingEvent::SelectRecordSource(source));
+                                    cx.emit(PopupEvent::Close);
+                                });
+                        }
+                    },
+                )
+                .width(Pixels(110.0));
+
+                Label::new(cx, "Input:").class("field-label");
+                Dropdown::new(
+                    cx,
+                    |cx| Label::new(cx, PoingModel::input_channel_name),
+                    |cx| {
+                        for channel in InputChannel::ALL {
+                            Label::new(cx, channel.name())
+                                .class("dropdown-item")
+                                .width(Stretch(1.0))
+                                .on_press(move |cx| {
+                                    cx.emit(PoingEvent::SelectInputChannel(channel));
+                                    cx.emit(PopupEvent::Close);
+                                });
+                        }
+                    },
+                )
+                .width(Pixels(80.0));
+            })
+            .height(Auto)
+            .col_between(Pixels(8.0))
+            .child_top(Stretch(1.0))
+            .child_bottom(Stretch(1.0));
+
+            // Post-processing and export format row
+            HStack::new(cx, |cx| {
+                Checkbox::new(cx, PoingModel::dc_removal)
+                    .on_toggle(|cx| cx.emit(PoingEvent::TogglePostStage(PostStage::DcRemoval)));
+                Label::new(cx, "DC").class("field-label");
+                Checkbox::new(cx, PoingModel::high_pass)
+                    .on_toggle(|cx| cx.emit(PoingEvent::TogglePostStage(PostStage::HighPass)));
+                Label::new(cx, "High-pass").class("field-label");
+                Checkbox::new(cx, PoingModel::limiter)
+                    .on_toggle(|cx| cx.emit(PoingEvent::TogglePostStage(PostStage::Limiter)));
+                Label::new(cx, "Limiter").class("field-label");
+                Checkbox::new(cx, PoingModel::fades)
+                    .on_toggle(|cx| cx.emit(PoingEvent::TogglePostStage(PostStage::Fades)));
+                Label::new(cx, "Fades").class("field-label");
+                Checkbox::new(cx, PoingModel::normalize)
+                    .on_toggle(|cx| cx.emit(PoingEvent::TogglePostStage(PostStage::Normalize)));
+                Label::new(cx, "Normalize").class("field-label");
+
+                Textbox::new(cx, PoingModel::target_lufs)
+                    .on_edit(|cx, text| cx.emit(PoingEvent::SetTargetLufs(text)))
+                    .width(Pixels(40.0));
+                Label::new(cx, "LUFS").class("field-label");
+
+                Label::new(cx, "Export:").class("field-label");
+                Dropdown::new(
+                    cx,
+                    |cx| Label::new(cx, PoingModel::export_format_name),
+                    |cx| {
+                        for format in ExportFormat::ALL {
+                            Label::new(cx, &format.to_string())
+                                .class("dropdown-item")
+                                .width(Stretch(1.0))
+                                .on_press(move |cx| {
+                                    cx.emit(PoingEvent::SelectExportFormat(format));
+                                    cx.emit(PopupEvent::Close);
+                                });
+                        }
+                    },
+                )
+                .width(Pixels(130.0));
+                Dropdown::new(
+                    cx,
+                    |cx| Label::new(cx, PoingModel::export_rate_name),
+                    |cx| {
+                        for rate in model::EXPORT_RATES {
+                            Label::new(cx, &model::export_rate_name(rate))
+                                .class("dropdown-item")
+                                .width(Stretch(1.0))
+                                .on_press(move |cx| {
+                                    cx.emit(PoingEvent::SelectExportRate(rate));
+                                    cx.emit(PopupEvent::Close);
+                                });
+                        }
+                    },
+                )
+                .width(Pixels(80.0));
             })
             .height(Auto)
             .col_between(Pixels(8.0))
@@ -173,6 +486,12 @@ pub fn create(
                 )
                 .class("generate");
 
+                Button::new(
+                    cx,
+                    |cx| cx.emit(PoingEvent::Cancel),
+                    |cx| Label::new(cx, "Cancel"),
+                );
+
                 Button::new(
                     cx,
                     |cx| cx.emit(PoingEvent::ToggleRecording),
@@ -184,6 +503,44 @@ pub fn create(
                     |cx| cx.emit(PoingEvent::Export),
                     |cx| Label::new(cx, "Export"),
                 );
+
+                Button::new(
+                    cx,
+                    |cx| cx.emit(PoingEvent::Import),
+                    |cx| Label::new(cx, "Import Settings"),
+                );
+
+                Button::new(
+                    cx,
+                    |cx| cx.emit(PoingEvent::LoadAudio),
+                    |cx| Label::new(cx, "Load Audio"),
+                );
+
+                Button::new(
+                    cx,
+                    |cx| cx.emit(PoingEvent::UseAsConditioning),
+                    |cx| Label::new(cx, "Use as Conditioning"),
+                );
+
+                Button::new(
+                    cx,
+                    |cx| cx.emit(PoingEvent::PreviousTake),
+                    |cx| Label::new(cx, "\u{25C2}"),
+                );
+                Label::new(cx, PoingModel::take_label).class("field-label");
+                Button::new(
+                    cx,
+                    |cx| cx.emit(PoingEvent::NextTake),
+                    |cx| Label::new(cx, "\u{25B8}"),
+                );
+
+                Checkbox::new(cx, PoingModel::show_spectrogram)
+                    .on_toggle(|cx| cx.emit(PoingEvent::ToggleSpectrogram));
+                Label::new(cx, "Spectrogram").class("field-label");
+
+                Checkbox::new(cx, PoingModel::show_library)
+                    .on_toggle(|cx| cx.emit(PoingEvent::ToggleLibrary));
+                Label::new(cx, "Library").class("field-label");
             })
             .height(Auto)
             .col_between(Pixels(12.0));
@@ -196,11 +553,17 @@ pub fn create(
                 .height(Pixels(6.0))
                 .width(Stretch(1.0));
 
-            // Waveform display
-            WaveformView::new(cx)
-                .width(Stretch(1.0))
-                .height(Stretch(1.0))
-                .class("waveform-container");
+            // Library browser beside the waveform display
+            HStack::new(cx, |cx| {
+                library::library_panel(cx);
+
+                WaveformView::new(cx)
+                    .width(Stretch(1.0))
+                    .height(Stretch(1.0))
+                    .class("waveform-container");
+            })
+            .col_between(Pixels(8.0))
+            .height(Stretch(1.0));
         })
         .width(Stretch(1.0))
         .height(Stretch(1.0));
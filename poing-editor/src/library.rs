@@ -0,0 +1,218 @@
+use nih_plug_vizia::vizia::prelude::*;
+use nih_plug_vizia::vizia::vg::{Color as VgColor, Paint, Path};
+use poing_core::library::{LibraryEntry, Thumbnail};
+
+use crate::model::{PoingEvent, PoingModel};
+
+/// Pointer travel in pixels that turns a press on a thumbnail into a drag.
+const DRAG_DISTANCE: f32 = 10.0;
+
+/// Search and filter fields above the list of saved takes.
+pub fn library_panel(cx: &mut Context) {
+    VStack::new(cx, |cx| {
+        Textbox::new(cx, PoingModel::library_search)
+            .on_edit(|cx, text| cx.emit(PoingEvent::SetLibrarySearch(text)))
+            .placeholder("Search prompts and #tags")
+            .width(Stretch(1.0));
+
+        HStack::new(cx, |cx| {
+            Label::new(cx, "BPM:").class("field-label");
+            Textbox::new(cx, PoingModel::library_bpm)
+                .on_edit(|cx, text| cx.emit(PoingEvent::SetLibraryBpm(text)))
+                .placeholder("any")
+                .width(Pixels(60.0));
+
+            Dropdown::new(
+                cx,
+                |cx| Label::new(cx, PoingModel::library_model_name),
+                |cx| {
+                    Label::new(cx, "All models")
+                        .class("dropdown-item")
+                        .width(Stretch(1.0))
+                        .on_press(|cx| {
+                            cx.emit(PoingEvent::SelectLibraryModel(None));
+                            cx.emit(PopupEvent::Close);
+                        });
+                    Binding::new(cx, PoingModel::library_models, |cx, models_lens| {
+                        for model in models_lens.get(cx) {
+                            Label::new(cx, &model)
+                                .class("dropdown-item")
+                                .width(Stretch(1.0))
+                                .on_press(move |cx| {
+                                    cx.emit(PoingEvent::SelectLibraryModel(Some(model.clone())));
+                                    cx.emit(PopupEvent::Close);
+                                });
+                        }
+                    });
+                },
+            )
+            .width(Stretch(1.0));
+
+            Checkbox::new(cx, PoingModel::library_starred_only)
+                .on_toggle(|cx| cx.emit(PoingEvent::ToggleLibraryStarred));
+            Label::new(cx, "\u{2605}").class("field-label");
+        })
+        .height(Auto)
+        .col_between(Pixels(6.0))
+        .child_top(Stretch(1.0))
+        .child_bottom(Stretch(1.0));
+
+        ScrollView::new(cx, 0.0, 0.0, false, true, |cx| {
+            Binding::new(cx, PoingModel::library_entries, |cx, entries_lens| {
+                let entries = entries_lens.get(cx);
+                if entries.is_empty() {
+                    Label::new(cx, "No saved takes").class("status-label");
+                }
+                for entry in entries.iter() {
+                    library_row(cx, entry);
+                }
+            });
+        })
+        .height(Stretch(1.0));
+    })
+    .display(PoingModel::show_library)
+    .width(Pixels(300.0))
+    .row_between(Pixels(6.0))
+    .class("library-panel");
+}
+
+/// Thumbnail, prompt and details of one take, with its tags and star.
+/// Pressing the thumbnail or prompt opens the take; dragging the thumbnail
+/// drags it out like a fresh one.
+fn library_row(cx: &mut Context, entry: &LibraryEntry) {
+    let id = entry.id.clone();
+    let info = &entry.info;
+    let mut details = Vec::new();
+    if let Some(bpm) = info.bpm {
+        details.push(format!("{:.0} BPM", bpm));
+    }
+    if let Some(key) = &info.key {
+        details.push(key.clone());
+    }
+    details.push(info.model.clone());
+    details.push(format!("{:.1}s", info.params.duration_seconds));
+
+    VStack::new(cx, |cx| {
+        HStack::new(cx, |cx| {
+            LibraryThumbnail::new(cx, id.clone(), entry.thumbnail.clone())
+                .width(Pixels(64.0))
+                .height(Pixels(24.0));
+            let open_id = id.clone();
+            Label::new(cx, &info.prompt)
+                .width(Stretch(1.0))
+                .on_press(move |cx| cx.emit(PoingEvent::OpenLibraryEntry(open_id.clone())));
+            let star_id = id.clone();
+            let star = if info.starred { "\u{2605}" } else { "\u{2606}" };
+            Button::new(
+                cx,
+                move |cx| cx.emit(PoingEvent::ToggleEntryStar(star_id.clone())),
+                move |cx| Label::new(cx, star),
+            );
+        })
+        .height(Auto)
+        .col_between(Pixels(6.0))
+        .child_top(Stretch(1.0))
+        .child_bottom(Stretch(1.0));
+
+        HStack::new(cx, |cx| {
+            Label::new(cx, &details.join(" \u{b7} ")).class("status-label");
+            let tags_id = id.clone();
+            let tags = PoingModel::library_entries.map(move |entries| {
+                entries
+                    .iter()
+                    .find(|entry| entry.id == tags_id)
+                    .map(|entry| entry.info.tags.join(", "))
+                    .unwrap_or_default()
+            });
+            Textbox::new(cx, tags)
+                .on_submit(move |cx, text, _| cx.emit(PoingEvent::SetEntryTags(id.clone(), text)))
+                .placeholder("tags")
+                .width(Stretch(1.0));
+        })
+        .height(Auto)
+        .col_between(Pixels(6.0))
+        .child_top(Stretch(1.0))
+        .child_bottom(Stretch(1.0));
+    })
+    .height(Auto)
+    .row_between(Pixels(2.0))
+    .class("library-row");
+}
+
+/// Waveform thumbnail of a library entry, pressed to open the take and
+/// dragged to drag it out.
+pub struct LibraryThumbnail {
+    id: String,
+    thumbnail: Option<Thumbnail>,
+    /// Where the left button went down, while it is held.
+    pressed_at: Option<(f32, f32)>,
+}
+
+impl LibraryThumbnail {
+    pub fn new(cx: &mut Context, id: String, thumbnail: Option<Thumbnail>) -> Handle<Self> {
+        Self {
+            id,
+            thumbnail,
+            pressed_at: None,
+        }
+        .build(cx, |_| {})
+    }
+}
+
+impl View for LibraryThumbnail {
+    fn element(&self) -> Option<&'static str> {
+        Some("library-thumbnail")
+    }
+
+    fn event(&mut self, cx: &mut EventContext, event: &mut Event) {
+        event.map(|window_event, _| match window_event {
+            WindowEvent::MouseDown(button) if *button == MouseButton::Left => {
+                self.pressed_at = Some(cx.mouse().left.pos_down);
+                cx.capture();
+            }
+            WindowEvent::MouseUp(button) if *button == MouseButton::Left => {
+                if self.pressed_at.take().is_some() {
+                    cx.emit(PoingEvent::OpenLibraryEntry(self.id.clone()));
+                }
+                cx.release();
+            }
+            WindowEvent::MouseMove(x, y) => {
+                if let Some((start_x, start_y)) = self.pressed_at {
+                    let (dx, dy) = (*x - start_x, *y - start_y);
+                    if (dx * dx + dy * dy).sqrt() >= DRAG_DISTANCE {
+                        self.pressed_at = None;
+                        cx.release();
+                        cx.emit(PoingEvent::DragLibraryEntry(self.id.clone()));
+                    }
+                }
+            }
+            _ => {}
+        });
+    }
+
+    fn draw(&self, cx: &mut DrawContext, canvas: &mut Canvas) {
+        let bounds = cx.bounds();
+        let bg_color = VgColor::rgb(15, 15, 23); // #0f0f17
+        let output_color = VgColor::rgb(61, 122, 209); // #3d7ad1
+
+        let mut path = Path::new();
+        path.rect(bounds.x, bounds.y, bounds.w, bounds.h);
+        canvas.fill_path(&path, &Paint::color(bg_color));
+
+        let Some(thumbnail) = &self.thumbnail else {
+            return;
+        };
+        if thumbnail.columns.is_empty() || bounds.w < 1.0 {
+            return;
+        }
+        let center_y = bounds.y + bounds.h / 2.0;
+        let columns = bounds.w as usize;
+        let mut path = Path::new();
+        for x in 0..columns {
+            let index = x * thumbnail.columns.len() / columns;
+            let half = (thumbnail.columns[index] * bounds.h / 2.0).max(0.5);
+            path.rect(bounds.x + x as f32, center_y - half, 1.0, half * 2.0);
+        }
+        canvas.fill_path(&path, &Paint::color(output_color));
+    }
+}
@@ -1,30 +1,138 @@
 use nih_plug_vizia::vizia::prelude::*;
-use poing_core::config;
-use poing_core::musicgen::GenerationParams;
-use poing_core::{GenerationState, SharedState};
-use std::path::PathBuf;
+use poing_core::beats::BeatGrid;
+use poing_core::catalog::{self, ModelEntry};
+use poing_core::config::{self, ConfigWatcher, PoingConfig};
+use poing_core::export::{ExportFormat, ExportOptions};
+use poing_core::key::Key;
+use poing_core::library::{self, LibraryEntry, LibraryFilter};
+use poing_core::metadata;
+use poing_core::musicgen::SAMPLE_RATE;
+use poing_core::prompts::{self, PromptTemplate};
+use poing_core::recording::{InputChannel, RecordSource};
+use poing_core::spectrogram::Spectrogram;
+use poing_core::tempfiles::TempFile;
+use poing_core::waveform::{self, Peaks};
+use poing_core::worker::{JobId, Take, WorkerEvent};
+use poing_core::{RecordingAnalysis, SharedState};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::Ordering;
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::waveform::{WaveformData, WaveformSource};
+
+/// Log-spaced frequency bands of the spectrogram view.
+const SPECTROGRAM_BANDS: usize = 96;
+/// Earlier prompts offered below the prompt while typing.
+const MAX_SUGGESTIONS: usize = 5;
+/// How often the playhead follows the host transport.
+const PLAYHEAD_INTERVAL: Duration = Duration::from_millis(33);
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum PoingEvent {
     Generate,
     ToggleRecording,
+    ToggleRecordSync,
+    SetRecordBars(String),
+    SelectInputChannel(InputChannel),
+    SelectRecordSource(RecordSource),
     Export,
     ExportStatus(String),
+    Import,
+    ImportStatus(String),
+    LoadAudio,
+    LoadAudioFile(PathBuf),
+    AudioLoaded(Result<usize, String>),
+    /// Select a region of the shown audio, in seconds, or clear the selection.
+    SelectRegion(Option<(f32, f32)>),
+    UseAsConditioning,
+    ToggleSpectrogram,
+    /// A spectrogram finished computing for request number `.0`.
+    SpectrogramReady(u64, Arc<Spectrogram>),
+    SelectExportFormat(ExportFormat),
+    SelectExportRate(Option<u32>),
     BrowseModel,
     BrowseModelResult(PathBuf),
+    /// Pick a folder to search for models.
+    AddModelRoot,
+    /// A folder was picked, holding this many models.
+    AddModelRootResult(PathBuf, usize),
     RemoveModel,
+    SetModelName(String),
+    SetModelDescription(String),
+    /// Store the edited name and description, once editing is done.
+    SaveModelDetails,
+    /// Models of the config were listed for request number `.0`.
+    ModelsListed(u64, Vec<ModelEntry>),
     SelectModel(usize),
     SetPrompt(String),
+    /// Replace the prompt with one from the history or a suggestion.
+    UsePrompt(String),
+    SelectTemplate(usize),
+    SetTemplateText(String),
+    /// Store the edited template, once editing is done.
+    SaveTemplates,
+    AddTemplate,
+    RemoveTemplate,
+    /// Select a genre chip, or deselect it when it is selected.
+    SelectGenre(String),
+    ToggleInstrument(String),
     SetBpm(String),
     SetNumBars(String),
+    SetKey(String),
+    ToggleLoopMode,
+    TogglePostStage(PostStage),
+    SetTargetLufs(String),
     SetGuidanceScale(String),
     SetTopK(String),
+    SetSeed(String),
     SyncBpm,
     SyncDurationToRecording,
     StartDrag,
-    TimerTick,
+    Cancel,
+    PreviousTake,
+    NextTake,
+    Worker(WorkerEvent),
+    RemoteChange,
+    /// Periodic update of the playhead.
+    Tick,
+    RecordingAnalyzed(RecordingAnalysis),
+    ApplyDetected,
+    ToggleLibrary,
+    /// Library entries were read from disk, newest first.
+    LibraryLoaded(Arc<Vec<LibraryEntry>>),
+    SetLibrarySearch(String),
+    SetLibraryBpm(String),
+    ToggleLibraryStarred,
+    /// List the takes of one model, or of every model.
+    SelectLibraryModel(Option<String>),
+    ToggleEntryStar(String),
+    SetEntryTags(String, String),
+    OpenLibraryEntry(String),
+    DragLibraryEntry(String),
+    /// The config file was changed, possibly by another instance.
+    ConfigChanged(Result<PoingConfig, String>),
+}
+
+/// Sample rates offered for export; `None` keeps the model's rate.
+pub const EXPORT_RATES: [Option<u32>; 4] = [None, Some(44_100), Some(48_000), Some(96_000)];
+
+pub fn export_rate_name(rate: Option<u32>) -> String {
+    match rate {
+        Some(rate) => format!("{} kHz", rate as f32 / 1000.0),
+        None => format!("{} kHz", SAMPLE_RATE / 1000),
+    }
+}
+
+/// A stage of the post-processing chain that can be switched off.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PostStage {
+    DcRemoval,
+    HighPass,
+    Normalize,
+    Limiter,
+    Fades,
 }
 
 #[derive(Lens)]
@@ -33,140 +141,652 @@ pub struct PoingModel {
     pub shared_state: SharedState,
     #[lens(ignore)]
     proxy: ContextProxy,
+    /// Stops watching the config when the editor closes.
+    #[lens(ignore)]
+    _config_watcher: ConfigWatcher,
+    /// Job currently running on the inference worker.
+    #[lens(ignore)]
+    active_job: Option<JobId>,
+    /// Jobs submitted from this editor that haven't started yet.
+    #[lens(ignore)]
+    queued_jobs: Vec<JobId>,
+    /// Tempo and key found in the last recording, offered until applied.
+    #[lens(ignore)]
+    detected: Option<RecordingAnalysis>,
+    #[lens(ignore)]
+    export_options: ExportOptions,
+    /// Number of the latest spectrogram computation, so results for audio
+    /// that is no longer shown are dropped.
     #[lens(ignore)]
-    was_generating: bool,
+    spectrogram_request: u64,
+    /// File of the last drag, kept until the next one so the host can still
+    /// read it after the drop.
+    #[lens(ignore)]
+    dragged_file: Option<TempFile>,
+    /// Whether the GUI currently shows a recording in progress.
+    #[lens(ignore)]
+    showing_recording: bool,
+    /// Every take in the library, newest first.
+    #[lens(ignore)]
+    library_all: Vec<LibraryEntry>,
+    #[lens(ignore)]
+    library_model: Option<String>,
+    /// Prompt templates, including edits not saved yet.
+    #[lens(ignore)]
+    templates: Vec<PromptTemplate>,
+    /// Prompt templates as last read from or written to the config.
+    #[lens(ignore)]
+    saved_templates: Vec<PromptTemplate>,
+    #[lens(ignore)]
+    template_index: usize,
+    /// Library entry id and take index of the last take opened from the
+    /// library, so dragging it again doesn't add it twice.
+    #[lens(ignore)]
+    opened_entry: Option<(String, usize)>,
+    /// Models in the dropdown, in the order of `model_names`.
+    #[lens(ignore)]
+    model_entries: Vec<ModelEntry>,
+    /// Number of the latest model listing, so older results are dropped.
+    #[lens(ignore)]
+    models_request: u64,
 
     pub status_text: String,
     pub progress: f32,
     pub prompt: String,
+    /// Prompt as it will be sent, with the template filled in.
+    pub prompt_preview: String,
+    /// Earlier prompts, most recent first.
+    pub prompt_history: Vec<String>,
+    /// Earlier prompts completing the one being typed.
+    pub prompt_suggestions: Vec<String>,
+    pub has_suggestions: bool,
+    pub template_names: Vec<String>,
+    pub template_name: String,
+    pub template_text: String,
+    pub genre_tags: Vec<String>,
+    pub instrument_tags: Vec<String>,
+    pub genre: Option<String>,
+    pub instruments: Vec<String>,
     pub model_names: Vec<String>,
+    /// Description of each model in `model_names`, or empty.
+    pub model_descriptions: Vec<String>,
     pub selected_model_index: usize,
     pub is_generating: bool,
     pub record_button_text: String,
+    /// Record arms and waits for the host transport instead of starting at once.
+    pub record_synced: bool,
+    /// Bars to record when synced, empty or 0 for until loop end or stop.
+    pub record_bars: String,
+    pub input_channel_name: String,
+    /// Whether recording captures the main input or the sidechain.
+    pub record_source_name: String,
     pub selected_model_name: String,
-    pub waveform_data: Arc<Vec<(f32, f32)>>,
+    /// Name and description of the selected model, as being edited.
+    pub model_display_name: String,
+    pub model_description: String,
+    pub waveform: Arc<WaveformData>,
+    /// Playback position within the shown take in seconds, while the host plays.
+    pub playhead: Option<f32>,
+    /// Selected region of the shown audio in seconds. Drag, export and
+    /// "Use as Conditioning" act on it instead of the whole clip.
+    pub selection: Option<(f32, f32)>,
+    pub show_spectrogram: bool,
+    /// Spectrogram of the shown audio, once computed.
+    pub spectrogram: Option<Arc<Spectrogram>>,
+    pub take_label: String,
+
+    // Library browser
+    pub show_library: bool,
+    /// Library takes passing the search and filters.
+    pub library_entries: Arc<Vec<LibraryEntry>>,
+    pub library_search: String,
+    /// Tempo filter such as `120` or `90-110`.
+    pub library_bpm: String,
+    pub library_starred_only: bool,
+    pub library_model_name: String,
+    /// Models the library has takes of.
+    pub library_models: Vec<String>,
 
     // Generation parameters
     pub bpm: String,
     pub num_bars: String,
+    pub key: String,
+    pub loop_mode: bool,
     pub guidance_scale: String,
     pub top_k: String,
+    /// Fixed seed, empty for a random one per generation.
+    pub seed: String,
     pub host_bpm_label: String,
+    pub detected_text: String,
+    pub has_detection: bool,
+
+    // Post-processing stages
+    pub dc_removal: bool,
+    pub high_pass: bool,
+    pub normalize: bool,
+    pub target_lufs: String,
+    pub limiter: bool,
+    pub fades: bool,
+
+    pub export_format_name: String,
+    pub export_rate_name: String,
 }
 
 impl PoingModel {
     pub fn new(shared_state: SharedState, proxy: ContextProxy) -> Self {
-        let model_paths = shared_state.model_paths.lock().unwrap().clone();
-        let model_names = Self::paths_to_names(&model_paths);
-        let selected_model_name = model_names.first().cloned().unwrap_or_else(|| "No models loaded".into());
+        let (cfg, config_error) = match config::try_load_config() {
+            Ok(cfg) => (cfg, None),
+            Err(e) => (PoingConfig::default(), Some(e.to_string())),
+        };
+        let templates = cfg.templates();
+
+        // Forward worker events into the GUI event loop. The thread ends once the
+        // editor is closed and the proxy stops accepting events.
+        let events = shared_state.worker.subscribe();
+        let mut event_proxy = proxy.clone();
+        std::thread::spawn(move || {
+            for event in events {
+                if event_proxy.emit(PoingEvent::Worker(event)).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let changes = shared_state.watch_changes();
+        let mut change_proxy = proxy.clone();
+        std::thread::spawn(move || {
+            for () in changes {
+                if change_proxy.emit(PoingEvent::RemoteChange).is_err() {
+                    break;
+                }
+            }
+        });
+
+        // Pick up config changes saved by other instances until the editor
+        // is closed and the watcher dropped with the model
+        let mut config_proxy = proxy.clone();
+        let config_watcher = config::watch_config(move |result| {
+            config_proxy.emit(PoingEvent::ConfigChanged(result)).is_ok()
+        });
 
-        Self {
+        let mut tick_proxy = proxy.clone();
+        std::thread::spawn(move || loop {
+            std::thread::sleep(PLAYHEAD_INTERVAL);
+            if tick_proxy.emit(PoingEvent::Tick).is_err() {
+                break;
+            }
+        });
+
+        let mut model = Self {
             shared_state,
             proxy,
-            was_generating: false,
-            status_text: "Ready".into(),
+            _config_watcher: config_watcher,
+            active_job: None,
+            queued_jobs: Vec::new(),
+            detected: None,
+            export_options: ExportOptions::default(),
+            spectrogram_request: 0,
+            dragged_file: None,
+            showing_recording: false,
+            library_all: Vec::new(),
+            library_model: None,
+            template_names: templates.iter().map(|t| t.name.clone()).collect(),
+            template_name: templates[0].name.clone(),
+            template_text: templates[0].template.clone(),
+            saved_templates: templates.clone(),
+            templates,
+            template_index: 0,
+            opened_entry: None,
+            model_entries: Vec::new(),
+            models_request: 0,
+            status_text: config_error
+                .map(|e| format!("Config error: {}", e))
+                .unwrap_or_else(|| "Ready".into()),
             progress: 0.0,
             prompt: String::new(),
-            model_names,
+            prompt_preview: String::new(),
+            prompt_history: cfg.prompt_history.clone(),
+            prompt_suggestions: Vec::new(),
+            has_suggestions: false,
+            genre_tags: cfg.genres(),
+            instrument_tags: cfg.instruments(),
+            genre: None,
+            instruments: Vec::new(),
+            model_names: Vec::new(),
+            model_descriptions: Vec::new(),
             selected_model_index: 0,
             is_generating: false,
             record_button_text: "Record".into(),
-            selected_model_name,
-            waveform_data: Arc::new(Vec::new()),
+            record_synced: false,
+            record_bars: String::new(),
+            input_channel_name: InputChannel::default().name().into(),
+            record_source_name: RecordSource::default().name().into(),
+            selected_model_name: String::new(),
+            model_display_name: String::new(),
+            model_description: String::new(),
+            waveform: Arc::new(WaveformData::default()),
+            playhead: None,
+            selection: None,
+            show_spectrogram: false,
+            spectrogram: None,
+            take_label: String::new(),
+            show_library: false,
+            library_entries: Arc::new(Vec::new()),
+            library_search: String::new(),
+            library_bpm: String::new(),
+            library_starred_only: false,
+            library_model_name: "All models".into(),
+            library_models: Vec::new(),
             bpm: "120".into(),
             num_bars: "4".into(),
+            key: String::new(),
+            loop_mode: false,
             guidance_scale: "3.0".into(),
             top_k: "50".into(),
+            seed: String::new(),
             host_bpm_label: "Sync BPM".into(),
+            detected_text: String::new(),
+            has_detection: false,
+            dc_removal: true,
+            high_pass: true,
+            normalize: true,
+            target_lufs: "-14".into(),
+            limiter: true,
+            fades: true,
+            export_format_name: ExportFormat::default().to_string(),
+            export_rate_name: export_rate_name(None),
+        };
+        model.load_settings();
+        model.load_post_process();
+        // Restore the current take when the editor is reopened
+        model.show_current_take();
+        if model.shared_state.is_recording.load(Ordering::Relaxed) {
+            model.update_recording_status(true);
+        } else if model.shared_state.record_armed.load(Ordering::Relaxed) {
+            model.record_button_text = "Disarm".into();
+        }
+        model.record_synced = model.shared_state.record_synced.load(Ordering::Relaxed);
+        let bars = model.shared_state.record_bars.load(Ordering::Relaxed);
+        if bars > 0 {
+            model.record_bars = bars.to_string();
         }
+        model.input_channel_name = model.shared_state.input_channel().name().into();
+        model.update_record_source_name();
+        model.update_take_label();
+        model.update_host_bpm_label();
+        // Show the models known so far until the roots have been searched
+        let known = model.shared_state.model_paths.lock().unwrap().clone();
+        model.set_models(catalog::with_details(&cfg, known));
+        model.list_models(&cfg);
+        model.refresh_models();
+        model
     }
 
-    fn paths_to_names(paths: &[PathBuf]) -> Vec<String> {
-        if paths.is_empty() {
-            vec!["No models loaded".into()]
-        } else {
-            paths
-                .iter()
-                .map(|p| {
-                    p.file_name()
-                        .map(|n| n.to_string_lossy().into_owned())
-                        .unwrap_or_else(|| p.to_string_lossy().into_owned())
-                })
-                .collect()
-        }
+    /// List the models of `cfg` in the dropdown. Searching the model roots
+    /// can take a while, so it runs in the background.
+    fn list_models(&mut self, cfg: &PoingConfig) {
+        self.models_request += 1;
+        let request = self.models_request;
+        let cfg = cfg.clone();
+        let mut proxy = self.proxy.clone();
+        std::thread::spawn(move || {
+            let _ = proxy.emit(PoingEvent::ModelsListed(request, catalog::entries(&cfg)));
+        });
     }
 
-    fn poll_shared_state(&mut self, cx: &mut EventContext) {
-        let gen_state = self.shared_state.generation_state.lock().unwrap().clone();
-        let progress = *self.shared_state.progress.lock().unwrap();
-        let is_recording = self.shared_state.is_recording.load(Ordering::Relaxed);
+    /// Show `entries` in the dropdown, falling back to the first model when
+    /// the selected one is gone.
+    fn set_models(&mut self, entries: Vec<ModelEntry>) {
+        let shown = self.model_entries.get(self.selected_model_index).cloned();
+        self.model_entries = entries;
+        self.model_names = if self.model_entries.is_empty() {
+            vec!["No models loaded".into()]
+        } else {
+            self.model_entries.iter().map(ModelEntry::label).collect()
+        };
+        self.model_descriptions = self
+            .model_entries
+            .iter()
+            .map(|entry| entry.details.description.clone().unwrap_or_default())
+            .collect();
+        let model_paths: Vec<PathBuf> = self
+            .model_entries
+            .iter()
+            .map(|entry| entry.path.clone())
+            .collect();
+        let index = {
+            let mut model_path = self.shared_state.model_path.lock().unwrap();
+            let index = model_path
+                .as_ref()
+                .and_then(|path| model_paths.iter().position(|p| p == path));
+            if index.is_none() {
+                *model_path = model_paths.first().cloned();
+            }
+            index.unwrap_or(0)
+        };
+        *self.shared_state.model_paths.lock().unwrap() = model_paths;
 
-        // Update waveform when generation transitions from Generating to Complete
-        if self.was_generating && matches!(gen_state, GenerationState::Complete) {
-            let audio = self.shared_state.generated_audio.lock().unwrap().clone();
-            if let Some(audio) = audio {
-                self.waveform_data = Arc::new(compute_waveform_columns(&audio, 1024));
+        // Keep text being typed unless the name or description changed elsewhere
+        let keep_text = match (shown, self.model_entries.get(index)) {
+            (Some(shown), Some(entry)) => {
+                shown.path == entry.path
+                    && shown.details.name == entry.details.name
+                    && shown.details.description == entry.details.description
             }
+            _ => false,
+        };
+        if keep_text {
+            self.selected_model_index = index;
+            self.selected_model_name = self.model_names.get(index).cloned().unwrap_or_default();
+        } else {
+            self.show_model(index);
         }
-        self.was_generating = matches!(gen_state, GenerationState::Generating);
-        self.is_generating = matches!(gen_state, GenerationState::Generating);
+    }
 
-        // Update progress
-        self.progress = progress;
+    /// Measure new or changed models and detect their precision in the
+    /// background. The results arrive through the config watcher.
+    fn refresh_models(&self) {
+        std::thread::spawn(|| {
+            if let Err(e) = catalog::refresh_details() {
+                eprintln!("[poing] Failed to scan models: {}", e);
+            }
+        });
+    }
 
-        // Update status label -- recording takes priority
-        self.status_text = if is_recording {
-            "Recording...".into()
-        } else {
-            match &gen_state {
-                GenerationState::Idle => "Ready".into(),
-                GenerationState::Generating => {
-                    format!("Generating... {:.0}%", progress * 100.0)
+    fn handle_worker_event(&mut self, event: &WorkerEvent) {
+        match event {
+            WorkerEvent::Queued { id, .. } => {
+                self.queued_jobs.push(*id);
+            }
+            WorkerEvent::Started { id } => {
+                self.queued_jobs.retain(|queued| queued != id);
+                self.active_job = Some(*id);
+                self.progress = 0.0;
+            }
+            WorkerEvent::Progress { progress, .. } => {
+                self.progress = *progress;
+            }
+            WorkerEvent::Partial { audio, .. } => {
+                self.show_audio(audio, SAMPLE_RATE as f32, WaveformSource::Output, None);
+            }
+            WorkerEvent::Done { id, takes } => {
+                self.finish_job(*id);
+                if let Some(take) = takes.last() {
+                    self.show_audio(
+                        &take.audio,
+                        SAMPLE_RATE as f32,
+                        WaveformSource::Output,
+                        take.grid,
+                    );
                 }
-                GenerationState::Complete => {
-                    let samples = self
-                        .shared_state
-                        .generated_audio
-                        .lock()
-                        .unwrap()
-                        .as_ref()
-                        .map_or(0, |a| a.len());
-                    format!("Complete \u{2014} {} samples generated", samples)
+                self.update_take_label();
+                // The new takes were saved to the library
+                if self.show_library {
+                    self.reload_library();
                 }
-                GenerationState::Error(e) => format!("Error: {}", e),
             }
+            WorkerEvent::Error { id, .. } | WorkerEvent::Cancelled { id } => {
+                self.finish_job(*id);
+            }
+        }
+        self.is_generating = self.active_job.is_some();
+
+        // Recording status takes priority over generation status
+        if !self.shared_state.is_recording.load(Ordering::Relaxed) {
+            self.status_text = self.generation_status(event);
+        }
+        self.update_host_bpm_label();
+    }
+
+    fn finish_job(&mut self, id: JobId) {
+        self.queued_jobs.retain(|queued| *queued != id);
+        if self.active_job == Some(id) {
+            self.active_job = None;
+            self.progress = 0.0;
+        }
+    }
+
+    fn generation_status(&self, event: &WorkerEvent) -> String {
+        let queued = match self.queued_jobs.len() {
+            0 => String::new(),
+            n => format!(" ({} queued)", n),
         };
+        match event {
+            WorkerEvent::Queued { .. } if self.active_job.is_none() => {
+                format!("Loading models...{}", queued)
+            }
+            WorkerEvent::Done { takes, .. } => {
+                let samples = takes.last().map_or(0, |t| t.audio.len());
+                format!("Complete \u{2014} {} samples generated{}", samples, queued)
+            }
+            WorkerEvent::Error { message, .. } => format!("Error: {}{}", message, queued),
+            WorkerEvent::Cancelled { .. } if self.active_job.is_none() => {
+                format!("Cancelled{}", queued)
+            }
+            _ => format!("Generating... {:.0}%{}", self.progress * 100.0, queued),
+        }
+    }
 
-        // Update host BPM label
+    fn update_host_bpm_label(&mut self) {
         if let Ok(host_tempo) = self.shared_state.host_tempo.try_lock() {
             if let Some(tempo) = *host_tempo {
                 self.host_bpm_label = format!("Sync BPM ({:.0})", tempo);
             }
         }
+    }
 
-        // Update record button text
-        self.record_button_text = if is_recording {
-            "Stop Recording".into()
-        } else {
-            "Record".into()
+    fn update_take_label(&mut self) {
+        let count = self.shared_state.takes.lock().unwrap().len();
+        let selected = *self.shared_state.selected_take.lock().unwrap();
+        self.take_label = match selected {
+            Some(index) if count > 0 => format!("Take {}/{}", index + 1, count),
+            _ => "No takes".into(),
         };
+    }
 
-        cx.needs_redraw();
+    fn step_take(&mut self, delta: isize) {
+        let count = self.shared_state.takes.lock().unwrap().len();
+        let Some(current) = *self.shared_state.selected_take.lock().unwrap() else {
+            return;
+        };
+        let index = current.saturating_add_signed(delta).min(count.saturating_sub(1));
+        if index != current && self.shared_state.select_take(index) {
+            self.show_current_take();
+            self.update_take_label();
+        }
     }
 
-    /// Compute the target generation duration in seconds from BPM and bars.
-    fn compute_duration_seconds(&self) -> f32 {
-        let bpm: f32 = self.bpm.parse().unwrap_or(120.0);
-        let bars: f32 = self.num_bars.parse().unwrap_or(4.0);
-        let beats_per_bar = self
-            .shared_state
-            .host_time_sig
-            .try_lock()
-            .ok()
-            .and_then(|ts| ts.map(|(num, _)| num as f32))
-            .unwrap_or(4.0);
+    /// Show the selected take and its beat grid in the waveform view.
+    fn show_current_take(&mut self) {
+        let audio = self.shared_state.generated_audio.lock().unwrap().clone();
+        let Some(audio) = audio else {
+            return;
+        };
+        let selected = *self.shared_state.selected_take.lock().unwrap();
+        let grid = selected
+            .and_then(|index| self.shared_state.takes.lock().unwrap().get(index)?.grid);
+        self.show_audio(&audio, SAMPLE_RATE as f32, WaveformSource::Output, grid);
+    }
 
-        (bars * beats_per_bar * 60.0 / bpm).min(30.0)
+    /// Show `audio` in the waveform view. Without a detected grid, the ruler
+    /// counts bars at the BPM setting from the start of the audio.
+    fn show_audio(
+        &mut self,
+        audio: &[f32],
+        sample_rate: f32,
+        source: WaveformSource,
+        grid: Option<BeatGrid>,
+    ) {
+        let bpm = self.bpm.parse::<f32>().ok().filter(|bpm| *bpm > 0.0);
+        // Keep the selection and spectrogram while the same clip is shown again
+        let same_audio =
+            source == self.waveform.source && audio == self.waveform.peaks.samples();
+        if !same_audio {
+            self.selection = None;
+            self.spectrogram = None;
+        }
+        self.waveform = Arc::new(WaveformData {
+            peaks: Peaks::new(audio.to_vec()),
+            sample_rate,
+            source,
+            grid: grid.unwrap_or(BeatGrid {
+                bpm: bpm.unwrap_or(120.0),
+                beats_per_bar: self.shared_state.beats_per_bar() as u32,
+                first_downbeat: 0.0,
+            }),
+            has_grid: grid.is_some(),
+        });
+        if self.show_spectrogram && self.spectrogram.is_none() {
+            self.request_spectrogram();
+        }
+        self.update_playhead();
+    }
+
+    /// Compute the spectrogram of the shown audio in the background.
+    fn request_spectrogram(&mut self) {
+        self.spectrogram_request += 1;
+        let request = self.spectrogram_request;
+        let waveform = self.waveform.clone();
+        let mut proxy = self.proxy.clone();
+        std::thread::spawn(move || {
+            let spectrogram = Spectrogram::compute(
+                waveform.peaks.samples(),
+                waveform.sample_rate as u32,
+                SPECTROGRAM_BANDS,
+            );
+            let _ = proxy.emit(PoingEvent::SpectrogramReady(request, Arc::new(spectrogram)));
+        });
+    }
+
+    /// Follow host playback through the shown take. Recordings have no playhead.
+    fn update_playhead(&mut self) -> bool {
+        let playhead = match self.waveform.source {
+            WaveformSource::Output => {
+                let transport = *self.shared_state.host_transport.lock().unwrap();
+                let grid = self.waveform.has_grid.then_some(&self.waveform.grid);
+                waveform::playhead_seconds(&transport, self.waveform.duration(), grid)
+            }
+            WaveformSource::Input => None,
+        };
+        let changed = playhead != self.playhead;
+        self.playhead = playhead;
+        changed
+    }
+
+    /// Parse the text fields into the shared generation settings. Fields that
+    /// don't parse keep their previous value.
+    fn store_settings(&mut self) {
+        let mut settings = self.shared_state.settings.lock().unwrap();
+        settings.prompt = self.prompt.clone();
+        settings.template = self.template_text.clone();
+        settings.genre = self.genre.clone();
+        settings.instruments = self.instruments.clone();
+        if let Ok(bpm) = self.bpm.parse::<f32>() {
+            if bpm > 0.0 {
+                settings.bpm = bpm;
+            }
+        }
+        if let Ok(bars) = self.num_bars.parse::<u32>() {
+            settings.bars = bars.max(1);
+        }
+        settings.loop_mode = self.loop_mode;
+        if self.key.trim().is_empty() {
+            settings.key = None;
+        } else if let Ok(key) = self.key.parse::<Key>() {
+            settings.key = Some(key);
+        }
+        if let Ok(guidance) = self.guidance_scale.parse() {
+            settings.guidance_scale = guidance;
+        }
+        if let Ok(top_k) = self.top_k.parse() {
+            settings.top_k = top_k;
+        }
+        if self.seed.trim().is_empty() {
+            settings.seed = None;
+        } else if let Ok(seed) = self.seed.trim().parse() {
+            settings.seed = Some(seed);
+        }
+        let preview = settings.full_prompt();
+        drop(settings);
+        self.prompt_preview = preview;
+    }
+
+    /// Copy the post-processing toggles into the shared settings, which the
+    /// plugin persists with the project.
+    fn store_post_process(&self) {
+        let mut settings = self.shared_state.post_process.lock().unwrap();
+        settings.dc_removal = self.dc_removal;
+        settings.high_pass = self.high_pass;
+        settings.normalize = self.normalize;
+        settings.limiter = self.limiter;
+        settings.fades = self.fades;
+        if let Ok(lufs) = self.target_lufs.parse::<f32>() {
+            settings.target_lufs = lufs.min(0.0);
+        }
+    }
+
+    fn load_post_process(&mut self) {
+        let settings = self.shared_state.post_process.lock().unwrap().clone();
+        self.dc_removal = settings.dc_removal;
+        self.high_pass = settings.high_pass;
+        self.normalize = settings.normalize;
+        self.limiter = settings.limiter;
+        self.fades = settings.fades;
+        if self.target_lufs.parse::<f32>().ok() != Some(settings.target_lufs) {
+            self.target_lufs = format!("{:.0}", settings.target_lufs);
+        }
+    }
+
+    /// Refresh the text fields from the shared settings, leaving fields that
+    /// already parse to the same value untouched.
+    fn load_settings(&mut self) {
+        let settings = self.shared_state.settings.lock().unwrap().clone();
+        self.prompt_preview = settings.full_prompt();
+        self.prompt = settings.prompt;
+        self.genre = settings.genre;
+        self.instruments = settings.instruments;
+        if self.template_text != settings.template {
+            match self.templates.iter().position(|t| t.template == settings.template) {
+                Some(index) => self.select_template(index),
+                None => self.template_text = settings.template,
+            }
+        }
+        if self.bpm.parse::<f32>().ok() != Some(settings.bpm) {
+            self.bpm = format!("{:.0}", settings.bpm);
+        }
+        if self.num_bars.parse::<u32>().ok() != Some(settings.bars) {
+            self.num_bars = settings.bars.to_string();
+        }
+        self.loop_mode = settings.loop_mode;
+        if self.key.parse::<Key>().ok() != settings.key {
+            self.key = settings.key.map(|key| key.to_string()).unwrap_or_default();
+        }
+        if self.guidance_scale.parse::<f32>().ok() != Some(settings.guidance_scale) {
+            self.guidance_scale = format!("{:.1}", settings.guidance_scale);
+        }
+        if self.top_k.parse::<usize>().ok() != Some(settings.top_k) {
+            self.top_k = settings.top_k.to_string();
+        }
+        if self.seed.trim().parse::<u64>().ok() != settings.seed {
+            self.seed = settings.seed.map(|seed| seed.to_string()).unwrap_or_default();
+        }
+    }
+
+    /// Pick up settings, recording state and take selection changed by remote control.
+    fn handle_remote_change(&mut self) {
+        self.load_settings();
+        self.sync_selected_model();
+        self.update_record_source_name();
+        let is_recording = self.shared_state.is_recording.load(Ordering::Relaxed);
+        if is_recording != self.showing_recording {
+            self.update_recording_status(is_recording);
+        } else if !is_recording {
+            // Armed or disarmed over OSC
+            let armed = self.shared_state.record_armed.load(Ordering::Relaxed);
+            self.record_button_text = if armed { "Disarm" } else { "Record" }.into();
+        }
+        self.show_current_take();
+        self.update_take_label();
     }
 
     fn sync_bpm(&mut self) {
@@ -175,6 +795,7 @@ impl PoingModel {
                 self.bpm = format!("{:.0}", tempo);
             }
         }
+        self.store_settings();
     }
 
     fn sync_duration_to_recording(&mut self) {
@@ -190,157 +811,338 @@ impl PoingModel {
         let sample_rate = *self.shared_state.sample_rate.lock().unwrap();
         let duration_secs = sample_count as f32 / sample_rate;
         let bpm: f32 = self.bpm.parse().unwrap_or(120.0);
-        let beats_per_bar = self
-            .shared_state
-            .host_time_sig
-            .try_lock()
-            .ok()
-            .and_then(|ts| ts.map(|(num, _)| num as f32))
-            .unwrap_or(4.0);
+        let beats_per_bar = self.shared_state.beats_per_bar();
         let bars = (duration_secs * bpm / (60.0 * beats_per_bar)).round().max(1.0);
         self.num_bars = format!("{}", bars as u32);
+        self.store_settings();
     }
 
     fn start_generation(&mut self, cx: &mut EventContext) {
-        // Prevent multiple simultaneous generations (set immediately, don't wait for timer)
-        if self.is_generating {
-            return;
-        }
-        self.is_generating = true;
-        self.status_text = "Loading models...".into();
-        self.progress = 0.0;
-        cx.needs_redraw();
-
-        let prompt = self.prompt.clone();
-        if prompt.trim().is_empty() {
-            self.is_generating = false;
-            self.status_text = "Error: Please enter a prompt".into();
-            *self.shared_state.generation_state.lock().unwrap() =
-                GenerationState::Error("Please enter a prompt".into());
+        self.store_settings();
+        // On success the Queued event arrives through the worker subscription
+        // and updates the status
+        if let Err(e) = self.shared_state.submit_generation() {
+            self.status_text = format!("Error: {}", e);
             cx.needs_redraw();
             return;
         }
+        // The prompt went into the history
+        self.prompt_history = config::load_config().prompt_history;
+        self.update_suggestions(false);
+    }
 
-        if self.shared_state.model_path.lock().unwrap().is_none() {
-            self.is_generating = false;
-            self.status_text = "Error: No model path configured".into();
-            *self.shared_state.generation_state.lock().unwrap() =
-                GenerationState::Error("No model path configured".into());
-            cx.needs_redraw();
+    /// Offer earlier prompts completing the current one while it is typed.
+    fn update_suggestions(&mut self, typing: bool) {
+        self.prompt_suggestions = if typing {
+            prompts::suggestions(&self.prompt_history, &self.prompt, MAX_SUGGESTIONS)
+        } else {
+            Vec::new()
+        };
+        self.has_suggestions = !self.prompt_suggestions.is_empty();
+    }
+
+    fn select_template(&mut self, index: usize) {
+        let Some(template) = self.templates.get(index) else {
+            return;
+        };
+        self.template_index = index;
+        self.template_name = template.name.clone();
+        self.template_text = template.template.clone();
+    }
+
+    /// Write the templates back to the config, if they changed, and refresh
+    /// their names.
+    fn save_templates(&mut self) {
+        self.template_names = self.templates.iter().map(|t| t.name.clone()).collect();
+        if self.templates == self.saved_templates {
             return;
         }
+        let templates = self.templates.clone();
+        match config::update_config(|cfg| cfg.prompt_templates = templates) {
+            Ok(_) => self.saved_templates = self.templates.clone(),
+            Err(e) => self.status_text = format!("Error: templates not saved: {}", e),
+        }
+    }
 
-        // Build the full prompt with BPM hint
-        let bpm: f32 = self.bpm.parse().unwrap_or(120.0);
-        let full_prompt = format!("{:.0} bpm. {}", bpm, prompt);
+    /// Take over the model folders, prompt history, templates and chips from
+    /// a config saved elsewhere.
+    fn apply_config(&mut self, cfg: &PoingConfig) {
+        self.list_models(cfg);
+        self.prompt_history = cfg.prompt_history.clone();
+        // Edits not saved yet are kept unless the templates changed elsewhere
+        let templates = cfg.templates();
+        if templates != self.saved_templates {
+            self.saved_templates = templates.clone();
+            self.templates = templates;
+            self.template_names = self.templates.iter().map(|t| t.name.clone()).collect();
+            self.select_template(self.template_index.min(self.templates.len() - 1));
+        }
+        self.genre_tags = cfg.genres();
+        self.instrument_tags = cfg.instruments();
+    }
+
+    fn cancel_generation(&mut self) {
+        self.shared_state.worker.cancel_all();
+    }
 
-        // Build generation params
-        let gen_params = GenerationParams {
-            duration_seconds: self.compute_duration_seconds(),
-            guidance_scale: self.guidance_scale.parse().unwrap_or(3.0),
-            top_k: self.top_k.parse().unwrap_or(50),
+    fn toggle_recording(&mut self, _cx: &mut EventContext) {
+        let was_recording = self.shared_state.is_recording.load(Ordering::Relaxed);
+        let was_armed = self.shared_state.record_armed.load(Ordering::Relaxed);
+        self.shared_state.request_recording(!was_armed && !was_recording);
+        if was_armed {
+            if was_recording {
+                self.update_recording_status(false);
+            } else {
+                self.record_button_text = "Record".into();
+                self.status_text = "Disarmed".into();
+            }
+        } else if self.shared_state.record_armed.load(Ordering::Relaxed) {
+            self.record_button_text = "Disarm".into();
+            self.status_text = "Armed: recording starts at the next bar once playing".into();
+        } else {
+            self.update_recording_status(!was_recording);
+        }
+    }
+
+    /// Name the selected record source, or say there is no sidechain when the
+    /// main input is recorded in its place.
+    fn update_record_source_name(&mut self) {
+        let selected = self.shared_state.record_source();
+        self.record_source_name = if self.shared_state.recorded_source() == selected {
+            selected.name().into()
+        } else {
+            "No sidechain".into()
         };
+    }
 
-        *self.shared_state.prompt.lock().unwrap() = full_prompt.clone();
-        *self.shared_state.generation_state.lock().unwrap() = GenerationState::Generating;
-        *self.shared_state.progress.lock().unwrap() = 0.0;
-        *self.shared_state.generated_audio.lock().unwrap() = None;
+    fn update_recording_status(&mut self, is_recording: bool) {
+        self.showing_recording = is_recording;
+        if is_recording {
+            self.status_text = "Recording...".into();
+            self.record_button_text = "Stop Recording".into();
+        } else {
+            self.record_button_text = "Record".into();
+            let recorded = self.shared_state.recorded_audio.lock().unwrap().mix_down();
+            if !recorded.is_empty() {
+                let sample_rate = *self.shared_state.sample_rate.lock().unwrap();
+                self.show_audio(&recorded, sample_rate, WaveformSource::Input, None);
+                self.status_text = format!("Recorded {} samples", recorded.len());
+                self.analyze_recording();
+            }
+        }
+    }
 
-        let state = self.shared_state.clone();
+    /// Detect tempo and key of the recording in the background.
+    fn analyze_recording(&mut self) {
+        self.detected = None;
+        self.has_detection = false;
+        let shared_state = self.shared_state.clone();
         let mut proxy = self.proxy.clone();
         std::thread::spawn(move || {
-            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
-                let model_dir = state.model_path.lock().unwrap().clone();
-                if let Some(model_dir) = model_dir {
-                    let progress_state = state.progress.clone();
-                    let progress_proxy = Mutex::new(proxy.clone());
-                    match poing_core::musicgen::generate_from_text(
-                        &full_prompt,
-                        &model_dir,
-                        &gen_params,
-                        move |p| {
-                            *progress_state.lock().unwrap() = p;
-                            let _ = progress_proxy.lock().unwrap().emit(PoingEvent::TimerTick);
-                        },
-                    ) {
-                        Ok(audio) => {
-                            *state.generated_audio.lock().unwrap() = Some(audio);
-                            *state.generation_state.lock().unwrap() = GenerationState::Complete;
-                        }
-                        Err(e) => {
-                            *state.generation_state.lock().unwrap() =
-                                GenerationState::Error(e.to_string());
-                        }
-                    }
-                } else {
-                    *state.generation_state.lock().unwrap() =
-                        GenerationState::Error("No model path configured".into());
-                }
-            }));
-            if let Err(panic) = result {
-                let msg = if let Some(s) = panic.downcast_ref::<String>() {
-                    s.clone()
-                } else if let Some(s) = panic.downcast_ref::<&str>() {
-                    s.to_string()
-                } else {
-                    "Unknown panic".to_string()
-                };
-                eprintln!("[poing] Generation thread panicked: {}", msg);
-                if let Ok(mut gen_state) = state.generation_state.try_lock() {
-                    *gen_state = GenerationState::Error(format!("Internal error: {}", msg));
-                }
-            }
-            // Final UI update after generation completes (or errors)
-            let _ = proxy.emit(PoingEvent::TimerTick);
+            let analysis = shared_state.analyze_recording();
+            let _ = proxy.emit(PoingEvent::RecordingAnalyzed(analysis));
         });
     }
 
-    fn toggle_recording(&mut self, _cx: &mut EventContext) {
-        let was_recording = self.shared_state.is_recording.load(Ordering::Relaxed);
-        self.shared_state
-            .is_recording
-            .store(!was_recording, Ordering::Relaxed);
+    fn handle_recording_analysis(&mut self, analysis: RecordingAnalysis) {
+        let mut found = Vec::new();
+        if let Some(bpm) = analysis.bpm {
+            found.push(format!("{:.0} BPM", bpm));
+        }
+        if let Some(key) = analysis.key {
+            found.push(key.to_string());
+        }
+        self.has_detection = !found.is_empty();
+        self.detected_text = format!("Detected in recording: {}", found.join(", "));
+        self.detected = Some(analysis);
+    }
 
-        if was_recording {
-            // Just stopped recording
-            let recorded = self.shared_state.recorded_audio.lock().unwrap().clone();
-            if !recorded.is_empty() {
-                self.waveform_data = Arc::new(compute_waveform_columns(&recorded, 1024));
-                self.status_text = format!("Recorded {} samples", recorded.len());
+    /// Fill the BPM and key fields with what was detected in the recording.
+    fn apply_detected(&mut self) {
+        let Some(analysis) = self.detected.take() else {
+            return;
+        };
+        if let Some(bpm) = analysis.bpm {
+            self.bpm = format!("{:.0}", bpm);
+        }
+        if let Some(key) = analysis.key {
+            self.key = key.to_string();
+        }
+        self.has_detection = false;
+        self.store_settings();
+    }
+
+    /// Sample range of the selection in audio at `sample_rate`.
+    fn selected_range(&self, sample_rate: f32) -> Option<Range<usize>> {
+        let (start, end) = self.selection?;
+        Some((start * sample_rate).round() as usize..(end * sample_rate).round() as usize)
+    }
+
+    /// The current take, trimmed to the selection when the take is shown.
+    fn selected_take(&self) -> Option<Take> {
+        let take = self.shared_state.current_take()?;
+        match self.selected_range(SAMPLE_RATE as f32) {
+            Some(range) if self.waveform.source == WaveformSource::Output => {
+                Some(take.region(range))
             }
-        } else {
-            // Just started recording
-            self.shared_state.recorded_audio.lock().unwrap().clear();
-            self.status_text = "Recording...".into();
+            _ => Some(take),
         }
     }
 
+    /// Replace the conditioning audio with the selected region of the shown
+    /// audio, or all of it without a selection.
+    fn use_as_conditioning(&mut self) {
+        let waveform = self.waveform.clone();
+        if waveform.peaks.is_empty() {
+            self.status_text = "No audio to use as conditioning".into();
+            return;
+        }
+        let samples = waveform.peaks.samples();
+        let range = self
+            .selected_range(waveform.sample_rate)
+            .unwrap_or(0..samples.len());
+        let end = range.end.min(samples.len());
+        let region = &samples[range.start.min(end)..end];
+        let len = self
+            .shared_state
+            .set_conditioning(region, waveform.sample_rate as u32);
+        self.update_recording_status(false);
+        let seconds = len as f32 / *self.shared_state.sample_rate.lock().unwrap();
+        self.status_text = format!("Using {:.1}s as conditioning audio", seconds);
+    }
+
     fn export_audio(&mut self, _cx: &mut EventContext) {
-        let audio = self.shared_state.generated_audio.lock().unwrap().clone();
-        let Some(samples) = audio else {
+        let Some(take) = self.selected_take() else {
             self.status_text = "No audio to export".into();
             return;
         };
 
         // Spawn dialog on background thread to avoid RefCell re-entrancy from
         // macOS modal event loop. rfd dispatches to the main thread internally.
+        let options = self.export_options;
+        let mut proxy = self.proxy.clone();
+        std::thread::spawn(move || {
+            // The chosen format's filter comes first so it is the default. The
+            // dialog doesn't report which filter was picked, so the extension
+            // decides between WAV and FLAC.
+            let mut dialog = rfd::FileDialog::new().set_file_name(format!(
+                "poing_generated.{}",
+                options.format.extension()
+            ));
+            let others = ExportFormat::ALL.into_iter().filter(|f| *f != options.format);
+            for format in std::iter::once(options.format).chain(others) {
+                dialog = dialog.add_filter(format.to_string(), &[format.extension()]);
+            }
+
+            if let Some(path) = dialog.save_file() {
+                let status = match ExportFormat::for_path(&path, options.format) {
+                    Some(format) => {
+                        let options = ExportOptions { format, ..options };
+                        match take.export(&path, &options) {
+                            Ok(()) => format!("Exported {} to {}", format, path.display()),
+                            Err(e) => format!("Export failed: {}", e),
+                        }
+                    }
+                    None => "Export failed: use a .wav or .flac file name".to_string(),
+                };
+                let _ = proxy.emit(PoingEvent::ExportStatus(status));
+            }
+        });
+    }
+
+    /// Pick a WAV exported by Poing and restore the settings it was made with.
+    fn import_settings(&mut self, _cx: &mut EventContext) {
+        // Spawn dialog on background thread to avoid RefCell re-entrancy from
+        // macOS modal event loop. rfd dispatches to the main thread internally.
+        let shared_state = self.shared_state.clone();
         let mut proxy = self.proxy.clone();
         std::thread::spawn(move || {
             let result = rfd::FileDialog::new()
-                .set_file_name("poing_generated.wav")
+                .set_title("Import Settings From Poing WAV")
                 .add_filter("WAV", &["wav"])
-                .save_file();
+                .pick_file();
 
             if let Some(path) = result {
-                let status = match poing_core::wav::write_wav(&samples, 32000, &path) {
-                    Ok(()) => format!("Exported to {}", path.display()),
-                    Err(e) => format!("Export failed: {}", e),
+                let status = match metadata::read_metadata(&path) {
+                    Ok(Some(metadata)) => {
+                        // Notifies the editor, which reloads its fields
+                        shared_state.restore_settings(&metadata);
+                        format!("Restored settings from {}", path.display())
+                    }
+                    Ok(None) => "No Poing settings found in that file".to_string(),
+                    Err(e) => format!("Import failed: {}", e),
                 };
-                let _ = proxy.emit(PoingEvent::ExportStatus(status));
+                let _ = proxy.emit(PoingEvent::ImportStatus(status));
+            }
+        });
+    }
+
+    fn browse_audio_file(&mut self, _cx: &mut EventContext) {
+        // Spawn dialog on background thread to avoid RefCell re-entrancy from
+        // macOS modal event loop. rfd dispatches to the main thread internally.
+        let mut proxy = self.proxy.clone();
+        std::thread::spawn(move || {
+            let result = rfd::FileDialog::new()
+                .set_title("Load Conditioning Audio")
+                .add_filter("Audio", &["wav", "flac"])
+                .pick_file();
+
+            if let Some(path) = result {
+                let _ = proxy.emit(PoingEvent::LoadAudioFile(path));
             }
         });
     }
 
+    /// Decode and resample a file into the recording buffer in the background.
+    fn load_audio_file(&mut self, path: &Path) {
+        self.status_text = format!("Loading {}...", path.display());
+        let shared_state = self.shared_state.clone();
+        let path = path.to_path_buf();
+        let mut proxy = self.proxy.clone();
+        std::thread::spawn(move || {
+            let result = shared_state
+                .import_recording(&path)
+                .map_err(|e| format!("Could not load {}: {}", path.display(), e));
+            let _ = proxy.emit(PoingEvent::AudioLoaded(result));
+        });
+    }
+
+    fn handle_audio_loaded(&mut self, result: &Result<usize, String>) {
+        match result {
+            Ok(len) => {
+                // Loading stops any recording in progress
+                self.update_recording_status(false);
+                let seconds = *len as f32 / *self.shared_state.sample_rate.lock().unwrap();
+                self.status_text = format!("Loaded {:.1}s of conditioning audio", seconds);
+            }
+            Err(e) => self.status_text = e.clone(),
+        }
+    }
+
+    /// Point the model dropdown at the shared model path after it changed elsewhere.
+    fn sync_selected_model(&mut self) {
+        let model_path = self.shared_state.model_path.lock().unwrap().clone();
+        let model_paths = self.shared_state.model_paths.lock().unwrap().clone();
+        if let Some(index) = model_paths.iter().position(|p| Some(p) == model_path.as_ref()) {
+            self.selected_model_index = index;
+            self.selected_model_name = self.model_names.get(index).cloned().unwrap_or_default();
+        }
+    }
+
+    /// Select entry `index` of the dropdown and load its name and
+    /// description for editing.
+    fn show_model(&mut self, index: usize) {
+        self.selected_model_index = index;
+        self.selected_model_name = self.model_names.get(index).cloned().unwrap_or_default();
+        let details = self
+            .model_entries
+            .get(index)
+            .map(|entry| entry.details.clone())
+            .unwrap_or_default();
+        self.model_display_name = details.name.unwrap_or_default();
+        self.model_description = details.description.unwrap_or_default();
+    }
+
     fn browse_model(&mut self, _cx: &mut EventContext) {
         // Spawn dialog on background thread to avoid RefCell re-entrancy from
         // macOS modal event loop. rfd dispatches to the main thread internally.
@@ -358,63 +1160,112 @@ impl PoingModel {
 
     fn handle_browse_result(&mut self, path: &PathBuf) {
         if !config::validate_model_dir(path) {
-            *self.shared_state.generation_state.lock().unwrap() = GenerationState::Error(
-                "Invalid model directory: missing required files (text_encoder.onnx, decoder_model_merged.onnx, encodec_decode.onnx, tokenizer.json)".into(),
+            self.status_text = format!(
+                "Invalid model directory: missing required files ({})",
+                config::REQUIRED_MODEL_FILES.join(", ")
             );
             return;
         }
 
-        let index = {
-            let mut model_paths = self.shared_state.model_paths.lock().unwrap();
-            if !model_paths.contains(path) {
-                model_paths.push(path.clone());
-                let cfg = config::PoingConfig {
-                    model_paths: model_paths.clone(),
-                };
-                config::save_config(&cfg);
+        match catalog::add(path) {
+            Ok(cfg) => {
+                // Selected once listed
+                *self.shared_state.model_path.lock().unwrap() = Some(path.clone());
+                self.list_models(&cfg);
+                self.refresh_models();
             }
-            model_paths
-                .iter()
-                .position(|p| p == path)
-                .unwrap_or(0)
-        };
+            Err(e) => self.status_text = format!("Error: model not saved: {}", e),
+        }
+    }
 
-        *self.shared_state.model_path.lock().unwrap() = Some(path.clone());
-        let model_paths = self.shared_state.model_paths.lock().unwrap().clone();
-        self.model_names = Self::paths_to_names(&model_paths);
-        self.selected_model_index = index;
-        self.selected_model_name = self.model_names.get(index).cloned().unwrap_or_default();
+    fn browse_model_root(&mut self) {
+        let mut proxy = self.proxy.clone();
+        std::thread::spawn(move || {
+            let result = rfd::FileDialog::new()
+                .set_title("Select Folder of Models")
+                .pick_folder();
+
+            if let Some(path) = result {
+                let found = catalog::discover(&path).len();
+                let _ = proxy.emit(PoingEvent::AddModelRootResult(path, found));
+            }
+        });
+    }
 
-        *self.shared_state.generation_state.lock().unwrap() = GenerationState::Idle;
+    /// Offer every model below `root`, now and whenever Poing starts.
+    fn add_model_root(&mut self, root: &Path, found: usize) {
+        if found == 0 {
+            self.status_text = format!("No models found in {}", root.display());
+            return;
+        }
+        let added = root.to_path_buf();
+        match config::update_config(|cfg| {
+            if !cfg.model_roots.contains(&added) {
+                cfg.model_roots.push(added);
+            }
+        }) {
+            Ok(cfg) => {
+                self.list_models(&cfg);
+                self.status_text = format!("Found {} model(s) in {}", found, root.display());
+                self.refresh_models();
+            }
+            Err(e) => self.status_text = format!("Error: folder not saved: {}", e),
+        }
     }
 
+    /// Drop the selected model from the list. Models found below a model
+    /// root are hidden instead, until added again with Browse.
     fn remove_selected_model(&mut self, _cx: &mut EventContext) {
-        let mut model_paths = self.shared_state.model_paths.lock().unwrap();
-        let selected = self.selected_model_index;
-
-        if selected < model_paths.len() {
-            model_paths.remove(selected);
-            let cfg = config::PoingConfig {
-                model_paths: model_paths.clone(),
-            };
-            config::save_config(&cfg);
-
-            if model_paths.is_empty() {
-                drop(model_paths);
-                *self.shared_state.model_path.lock().unwrap() = None;
-                self.model_names = Self::paths_to_names(&[]);
-                self.selected_model_index = 0;
-                self.selected_model_name = "No models loaded".into();
-            } else {
-                let idx = selected.min(model_paths.len() - 1);
-                let path = model_paths[idx].clone();
-                let names = Self::paths_to_names(&model_paths);
-                drop(model_paths);
-                *self.shared_state.model_path.lock().unwrap() = Some(path);
-                self.model_names = names;
-                self.selected_model_index = idx;
-                self.selected_model_name = self.model_names.get(idx).cloned().unwrap_or_default();
+        let index = self.selected_model_index;
+        let Some(removed) = self.model_entries.get(index).cloned() else {
+            return;
+        };
+        match catalog::remove(&removed.path) {
+            Ok(cfg) => {
+                // Select the next model once listed
+                let next = self
+                    .model_entries
+                    .get(index + 1)
+                    .or_else(|| self.model_entries.get(index.wrapping_sub(1)))
+                    .map(|entry| entry.path.clone());
+                *self.shared_state.model_path.lock().unwrap() = next;
+                self.list_models(&cfg);
+                let hidden = cfg
+                    .models
+                    .get(&removed.path)
+                    .is_some_and(|details| details.hidden);
+                if hidden {
+                    self.status_text = format!(
+                        "Hid {}; Browse to its folder to show it again",
+                        removed.name()
+                    );
+                }
             }
+            Err(e) => self.status_text = format!("Error: model not removed: {}", e),
+        }
+    }
+
+    /// Store the edited name and description of the selected model.
+    fn save_model_details(&mut self) {
+        let Some(entry) = self.model_entries.get(self.selected_model_index) else {
+            return;
+        };
+        let unchanged = |text: &str, stored: &Option<String>| {
+            text.trim() == stored.as_deref().unwrap_or_default()
+        };
+        if unchanged(&self.model_display_name, &entry.details.name)
+            && unchanged(&self.model_description, &entry.details.description)
+        {
+            return;
+        }
+        let saved = catalog::describe(
+            &entry.path,
+            &self.model_display_name,
+            &self.model_description,
+        );
+        match saved {
+            Ok(cfg) => self.list_models(&cfg),
+            Err(e) => self.status_text = format!("Error: model details not saved: {}", e),
         }
     }
 
@@ -422,19 +1273,106 @@ impl PoingModel {
         let model_paths = self.shared_state.model_paths.lock().unwrap().clone();
         if let Some(path) = model_paths.get(index) {
             *self.shared_state.model_path.lock().unwrap() = Some(path.clone());
-            self.selected_model_index = index;
-            self.selected_model_name = self.model_names.get(index).cloned().unwrap_or_default();
+            self.show_model(index);
         }
     }
 
-    fn start_drag(&self) {
-        let audio = self.shared_state.generated_audio.lock().unwrap().clone();
-        if let Some(samples) = audio {
-            if let Ok(path) = poing_core::wav::write_wav_temp(&samples, 32000) {
-                crate::drag_source::start_file_drag(&path);
+    /// Read the library on a background thread; [`PoingEvent::LibraryLoaded`]
+    /// delivers the entries.
+    fn reload_library(&self) {
+        let library = self.shared_state.library.clone();
+        let mut proxy = self.proxy.clone();
+        std::thread::spawn(move || {
+            let entries = library.entries();
+            let _ = proxy.emit(PoingEvent::LibraryLoaded(Arc::new(entries)));
+        });
+    }
+
+    fn set_library(&mut self, entries: &[LibraryEntry]) {
+        self.library_all = entries.to_vec();
+        let mut models: Vec<String> = entries.iter().map(|e| e.info.model.clone()).collect();
+        models.sort();
+        models.dedup();
+        self.library_models = models;
+        self.filter_library();
+    }
+
+    fn filter_library(&mut self) {
+        let filter = LibraryFilter {
+            text: self.library_search.clone(),
+            starred_only: self.library_starred_only,
+            bpm: LibraryFilter::parse_bpm(&self.library_bpm),
+            model: self.library_model.clone(),
+        };
+        let entries = self
+            .library_all
+            .iter()
+            .filter(|entry| filter.matches(&entry.info))
+            .cloned()
+            .collect();
+        self.library_entries = Arc::new(entries);
+    }
+
+    /// Change the sidecar of the library entry `id`.
+    fn edit_library_entry(&mut self, id: &str, edit: impl FnOnce(&mut LibraryEntry)) {
+        let Some(entry) = self.library_all.iter_mut().find(|entry| entry.id == id) else {
+            return;
+        };
+        edit(entry);
+        if let Err(e) = entry.save_info() {
+            self.status_text = format!("Saving library entry failed: {}", e);
+        }
+    }
+
+    /// Add the library entry `id` to the takes and show it, so it can be
+    /// dragged, exported and trimmed like a fresh take.
+    fn open_library_entry(&mut self, id: &str) -> bool {
+        let selected = *self.shared_state.selected_take.lock().unwrap();
+        if let Some((opened, index)) = &self.opened_entry {
+            if opened == id && selected == Some(*index) {
+                return true;
+            }
+        }
+        let Some(entry) = self.library_all.iter().find(|entry| entry.id == id) else {
+            return false;
+        };
+        match entry.load_take() {
+            Ok(take) => {
+                self.status_text = format!("Opened \"{}\" from the library", entry.info.prompt);
+                self.shared_state.add_take(take);
+                let index = self.shared_state.takes.lock().unwrap().len() - 1;
+                self.opened_entry = Some((id.to_string(), index));
+                self.show_current_take();
+                self.update_take_label();
+                true
+            }
+            Err(e) => {
+                self.status_text = format!("Opening library take failed: {}", e);
+                false
             }
         }
     }
+
+    fn start_drag(&mut self) {
+        let Some(take) = self.selected_take() else {
+            return;
+        };
+        let number = self.shared_state.selected_take.lock().unwrap().map_or(0, |i| i + 1);
+        let file = match self.shared_state.temp_files.create(&take.prompt, number, "wav") {
+            Ok(file) => file,
+            Err(e) => {
+                self.status_text = format!("Drag failed: {}", e);
+                return;
+            }
+        };
+        match take.write_wav(file.path()) {
+            Ok(()) => {
+                crate::drag_source::start_file_drag(&file);
+                self.dragged_file = Some(file);
+            }
+            Err(e) => self.status_text = format!("Drag failed: {}", e),
+        }
+    }
 }
 
 impl Model for PoingModel {
@@ -442,23 +1380,198 @@ impl Model for PoingModel {
         event.map(|e, _| match e {
             PoingEvent::Generate => self.start_generation(cx),
             PoingEvent::ToggleRecording => self.toggle_recording(cx),
+            PoingEvent::ToggleRecordSync => {
+                self.record_synced = !self.record_synced;
+                self.shared_state
+                    .record_synced
+                    .store(self.record_synced, Ordering::Relaxed);
+            }
+            PoingEvent::SetRecordBars(text) => {
+                self.record_bars = text.clone();
+                let bars = text.trim().parse::<u32>().unwrap_or(0);
+                self.shared_state.record_bars.store(bars, Ordering::Relaxed);
+            }
+            PoingEvent::SelectInputChannel(channel) => {
+                self.shared_state
+                    .input_channel
+                    .store(channel.index(), Ordering::Relaxed);
+                self.input_channel_name = channel.name().into();
+            }
+            PoingEvent::SelectRecordSource(source) => {
+                self.shared_state
+                    .record_source
+                    .store(source.index(), Ordering::Relaxed);
+                self.update_record_source_name();
+                if self.shared_state.recorded_source() != *source {
+                    self.status_text =
+                        "The host provides no sidechain; recording the main input".into();
+                }
+            }
             PoingEvent::Export => self.export_audio(cx),
             PoingEvent::ExportStatus(status) => {
                 self.status_text = status.clone();
                 cx.needs_redraw();
             }
+            PoingEvent::Import => self.import_settings(cx),
+            PoingEvent::ImportStatus(status) => {
+                self.status_text = status.clone();
+                cx.needs_redraw();
+            }
+            PoingEvent::LoadAudio => self.browse_audio_file(cx),
+            PoingEvent::LoadAudioFile(path) => {
+                self.load_audio_file(path);
+                cx.needs_redraw();
+            }
+            PoingEvent::AudioLoaded(result) => {
+                self.handle_audio_loaded(result);
+                cx.needs_redraw();
+            }
+            PoingEvent::SelectRegion(region) => {
+                self.selection = *region;
+                cx.needs_redraw();
+            }
+            PoingEvent::UseAsConditioning => {
+                self.use_as_conditioning();
+                cx.needs_redraw();
+            }
+            PoingEvent::SelectExportFormat(format) => {
+                self.export_options.format = *format;
+                self.export_format_name = format.to_string();
+            }
+            PoingEvent::SelectExportRate(rate) => {
+                self.export_options.sample_rate = *rate;
+                self.export_rate_name = export_rate_name(*rate);
+            }
             PoingEvent::BrowseModel => self.browse_model(cx),
             PoingEvent::BrowseModelResult(path) => {
                 self.handle_browse_result(path);
                 cx.needs_redraw();
             }
+            PoingEvent::AddModelRoot => self.browse_model_root(),
+            PoingEvent::AddModelRootResult(path, found) => {
+                self.add_model_root(path, *found);
+                cx.needs_redraw();
+            }
             PoingEvent::RemoveModel => self.remove_selected_model(cx),
+            PoingEvent::SetModelName(text) => self.model_display_name = text.clone(),
+            PoingEvent::SetModelDescription(text) => self.model_description = text.clone(),
+            PoingEvent::SaveModelDetails => self.save_model_details(),
+            PoingEvent::ModelsListed(request, entries) => {
+                if *request == self.models_request {
+                    self.set_models(entries.clone());
+                    cx.needs_redraw();
+                }
+            }
             PoingEvent::SelectModel(index) => self.select_model(*index),
-            PoingEvent::SetPrompt(text) => self.prompt = text.clone(),
-            PoingEvent::SetBpm(text) => self.bpm = text.clone(),
-            PoingEvent::SetNumBars(text) => self.num_bars = text.clone(),
-            PoingEvent::SetGuidanceScale(text) => self.guidance_scale = text.clone(),
-            PoingEvent::SetTopK(text) => self.top_k = text.clone(),
+            PoingEvent::SetPrompt(text) => {
+                self.prompt = text.clone();
+                self.store_settings();
+                self.update_suggestions(true);
+            }
+            PoingEvent::UsePrompt(prompt) => {
+                self.prompt = prompt.clone();
+                self.store_settings();
+                self.update_suggestions(false);
+            }
+            PoingEvent::SelectTemplate(index) => {
+                self.select_template(*index);
+                self.store_settings();
+            }
+            PoingEvent::SetTemplateText(text) => {
+                self.template_text = text.clone();
+                if let Some(template) = self.templates.get_mut(self.template_index) {
+                    template.template = text.clone();
+                }
+                self.store_settings();
+            }
+            PoingEvent::SaveTemplates => self.save_templates(),
+            PoingEvent::AddTemplate => {
+                let name = format!("Template {}", self.templates.len() + 1);
+                self.templates.push(PromptTemplate::new(&name, &self.template_text));
+                self.save_templates();
+                self.select_template(self.templates.len() - 1);
+            }
+            PoingEvent::RemoveTemplate => {
+                if self.templates.len() > 1 {
+                    self.templates.remove(self.template_index);
+                    self.save_templates();
+                    self.select_template(self.template_index.saturating_sub(1));
+                    self.store_settings();
+                }
+            }
+            PoingEvent::SelectGenre(genre) => {
+                self.genre = if self.genre.as_deref() == Some(genre.as_str()) {
+                    None
+                } else {
+                    Some(genre.clone())
+                };
+                self.store_settings();
+            }
+            PoingEvent::ToggleInstrument(instrument) => {
+                if let Some(index) = self.instruments.iter().position(|i| i == instrument) {
+                    self.instruments.remove(index);
+                } else {
+                    self.instruments.push(instrument.clone());
+                }
+                self.store_settings();
+            }
+            PoingEvent::SetBpm(text) => {
+                self.bpm = text.clone();
+                self.store_settings();
+            }
+            PoingEvent::SetNumBars(text) => {
+                self.num_bars = text.clone();
+                self.store_settings();
+            }
+            PoingEvent::SetKey(text) => {
+                self.key = text.clone();
+                self.store_settings();
+            }
+            PoingEvent::ToggleSpectrogram => {
+                self.show_spectrogram = !self.show_spectrogram;
+                let missing = self.spectrogram.is_none() && !self.waveform.peaks.is_empty();
+                if self.show_spectrogram && missing {
+                    self.request_spectrogram();
+                }
+                cx.needs_redraw();
+            }
+            PoingEvent::SpectrogramReady(request, spectrogram) => {
+                if *request == self.spectrogram_request {
+                    self.spectrogram = Some(spectrogram.clone());
+                    cx.needs_redraw();
+                }
+            }
+            PoingEvent::ToggleLoopMode => {
+                self.loop_mode = !self.loop_mode;
+                self.store_settings();
+            }
+            PoingEvent::TogglePostStage(stage) => {
+                let enabled = match stage {
+                    PostStage::DcRemoval => &mut self.dc_removal,
+                    PostStage::HighPass => &mut self.high_pass,
+                    PostStage::Normalize => &mut self.normalize,
+                    PostStage::Limiter => &mut self.limiter,
+                    PostStage::Fades => &mut self.fades,
+                };
+                *enabled = !*enabled;
+                self.store_post_process();
+            }
+            PoingEvent::SetTargetLufs(text) => {
+                self.target_lufs = text.clone();
+                self.store_post_process();
+            }
+            PoingEvent::SetGuidanceScale(text) => {
+                self.guidance_scale = text.clone();
+                self.store_settings();
+            }
+            PoingEvent::SetTopK(text) => {
+                self.top_k = text.clone();
+                self.store_settings();
+            }
+            PoingEvent::SetSeed(text) => {
+                self.seed = text.clone();
+                self.store_settings();
+            }
             PoingEvent::SyncBpm => {
                 self.sync_bpm();
                 cx.needs_redraw();
@@ -468,29 +1581,93 @@ impl Model for PoingModel {
                 cx.needs_redraw();
             }
             PoingEvent::StartDrag => self.start_drag(),
-            PoingEvent::TimerTick => self.poll_shared_state(cx),
+            PoingEvent::Cancel => self.cancel_generation(),
+            PoingEvent::PreviousTake => {
+                self.step_take(-1);
+                cx.needs_redraw();
+            }
+            PoingEvent::NextTake => {
+                self.step_take(1);
+                cx.needs_redraw();
+            }
+            PoingEvent::Worker(worker_event) => {
+                self.handle_worker_event(worker_event);
+                cx.needs_redraw();
+            }
+            PoingEvent::Tick => {
+                if self.update_playhead() {
+                    cx.needs_redraw();
+                }
+            }
+            PoingEvent::ConfigChanged(result) => {
+                match result {
+                    Ok(cfg) => self.apply_config(cfg),
+                    Err(e) => self.status_text = format!("Config error: {}", e),
+                }
+                cx.needs_redraw();
+            }
+            PoingEvent::RemoteChange => {
+                self.handle_remote_change();
+                cx.needs_redraw();
+            }
+            PoingEvent::RecordingAnalyzed(analysis) => {
+                self.handle_recording_analysis(*analysis);
+                cx.needs_redraw();
+            }
+            PoingEvent::ApplyDetected => {
+                self.apply_detected();
+                cx.needs_redraw();
+            }
+            PoingEvent::ToggleLibrary => {
+                self.show_library = !self.show_library;
+                if self.show_library {
+                    self.reload_library();
+                }
+            }
+            PoingEvent::LibraryLoaded(entries) => self.set_library(entries),
+            PoingEvent::SetLibrarySearch(text) => {
+                self.library_search = text.clone();
+                self.filter_library();
+            }
+            PoingEvent::SetLibraryBpm(text) => {
+                self.library_bpm = text.clone();
+                self.filter_library();
+            }
+            PoingEvent::ToggleLibraryStarred => {
+                self.library_starred_only = !self.library_starred_only;
+                self.filter_library();
+            }
+            PoingEvent::SelectLibraryModel(model) => {
+                self.library_model_name = model.clone().unwrap_or_else(|| "All models".into());
+                self.library_model = model.clone();
+                self.filter_library();
+            }
+            PoingEvent::ToggleEntryStar(id) => {
+                self.edit_library_entry(id, |entry| entry.info.starred = !entry.info.starred);
+                self.filter_library();
+            }
+            // Sent when editing ends. The list isn't rebuilt, which would
+            // take the focus from the next box being edited
+            PoingEvent::SetEntryTags(id, text) => {
+                let tags = library::parse_tags(text);
+                let unchanged = self
+                    .library_all
+                    .iter()
+                    .any(|entry| entry.id == *id && entry.info.tags == tags);
+                if !unchanged {
+                    self.edit_library_entry(id, |entry| entry.info.tags = tags);
+                }
+            }
+            PoingEvent::OpenLibraryEntry(id) => {
+                self.open_library_entry(id);
+                cx.needs_redraw();
+            }
+            PoingEvent::DragLibraryEntry(id) => {
+                if self.open_library_entry(id) {
+                    self.start_drag();
+                }
+                cx.needs_redraw();
+            }
         });
     }
 }
-
-/// Compute min/max pairs per column from audio samples for waveform rendering.
-pub fn compute_waveform_columns(samples: &[f32], num_cols: usize) -> Vec<(f32, f32)> {
-    if samples.is_empty() {
-        return vec![(0.0, 0.0); num_cols];
-    }
-
-    let samples_per_col = samples.len() as f32 / num_cols as f32;
-    (0..num_cols)
-        .map(|col| {
-            let start = (col as f32 * samples_per_col) as usize;
-            let end = (((col + 1) as f32 * samples_per_col) as usize).min(samples.len());
-            let mut min_val: f32 = 0.0;
-            let mut max_val: f32 = 0.0;
-            for i in start..end {
-                min_val = min_val.min(samples[i]);
-                max_val = max_val.max(samples[i]);
-            }
-            (min_val, max_val)
-        })
-        .collect()
-}
@@ -1,13 +1,100 @@
 use nih_plug_vizia::vizia::prelude::*;
-use nih_plug_vizia::vizia::vg::{Paint, Path, Color as VgColor};
+use nih_plug_vizia::vizia::vg::{Align, Color as VgColor, Paint, Path};
+use poing_core::beats::BeatGrid;
+use poing_core::musicgen::SAMPLE_RATE;
+use poing_core::spectrogram::{self, Spectrogram};
+use poing_core::waveform::{self, Peaks, Viewport};
 use std::sync::Arc;
 
 use crate::model::{PoingEvent, PoingModel};
 
+/// Height of the bar ruler above the waveform.
+const RULER_HEIGHT: f32 = 16.0;
+/// Minimum spacing of labelled ruler ticks in pixels.
+const TICK_SPACING: f32 = 48.0;
+/// Share of the area below the ruler the waveform keeps when the
+/// spectrogram is shown beneath it.
+const SPECTROGRAM_WAVE_SHARE: f32 = 0.4;
+/// Colour steps of the spectrogram; cells of equal colour are drawn as one path.
+const SPECTROGRAM_COLORS: usize = 24;
+/// Frequencies labelled on the spectrogram.
+const FREQUENCY_LABELS: [(f32, &str); 4] = [
+    (100.0, "100"),
+    (1000.0, "1k"),
+    (5000.0, "5k"),
+    (10_000.0, "10k"),
+];
+/// Zoom factor per mouse wheel step.
+const WHEEL_ZOOM: f64 = 1.25;
+/// View widths scrolled per horizontal wheel step.
+const WHEEL_SCROLL: f64 = 0.1;
+
+/// Whether the view shows recorded input or generated output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WaveformSource {
+    Input,
+    #[default]
+    Output,
+}
+
+/// Audio shown in the waveform view.
+#[derive(Debug, Clone)]
+pub struct WaveformData {
+    pub peaks: Peaks,
+    pub sample_rate: f32,
+    pub source: WaveformSource,
+    /// Beat grid the ruler counts bars on.
+    pub grid: BeatGrid,
+    /// `grid` was found in the audio rather than assumed from the BPM setting.
+    pub has_grid: bool,
+}
+
+impl Default for WaveformData {
+    fn default() -> Self {
+        Self {
+            peaks: Peaks::default(),
+            sample_rate: SAMPLE_RATE as f32,
+            source: WaveformSource::default(),
+            grid: BeatGrid {
+                bpm: 120.0,
+                beats_per_bar: 4,
+                first_downbeat: 0.0,
+            },
+            has_grid: false,
+        }
+    }
+}
+
+impl WaveformData {
+    pub fn duration(&self) -> f32 {
+        self.peaks.len() as f32 / self.sample_rate
+    }
+
+    /// The region between two times in either order, widened to the nearest
+    /// bar lines when `snap` is set, and kept within the clip.
+    pub fn region(&self, a: f32, b: f32, snap: bool) -> (f32, f32) {
+        let (mut start, mut end) = (a.min(b), a.max(b));
+        if snap {
+            let bar = self.grid.beat_seconds() * self.grid.beats_per_bar.max(1) as f32;
+            start = self.grid.nearest_downbeat(start);
+            end = self.grid.nearest_downbeat(end);
+            if end <= start {
+                end = start + bar;
+            }
+        }
+        let duration = self.duration();
+        (start.clamp(0.0, duration), end.clamp(0.0, duration))
+    }
+}
+
 pub struct WaveformView {
     is_dragging: bool,
     drag_start_x: f32,
     drag_start_y: f32,
+    /// Where a region selection began, in seconds, while the mouse is down.
+    selecting_from: Option<f32>,
+    /// Zoomed and scrolled part of the clip on screen.
+    viewport: Viewport,
 }
 
 impl WaveformView {
@@ -16,9 +103,18 @@ impl WaveformView {
             is_dragging: false,
             drag_start_x: 0.0,
             drag_start_y: 0.0,
+            selecting_from: None,
+            viewport: Viewport::default(),
         }
         .build(cx, |_| {})
     }
+
+    /// Time in seconds of the clip under the window x coordinate `x`.
+    fn time_at(&self, cx: &EventContext, waveform: &WaveformData, x: f32) -> f32 {
+        let bounds = cx.bounds();
+        let position = self.viewport.to_clip(((x - bounds.x) / bounds.w) as f64);
+        position as f32 * waveform.duration()
+    }
 }
 
 impl View for WaveformView {
@@ -27,19 +123,64 @@ impl View for WaveformView {
     }
 
     fn event(&mut self, cx: &mut EventContext, event: &mut Event) {
-        event.map(|window_event, _| match window_event {
+        event.map(|window_event, meta| match window_event {
+            // Pressing inside the selection drags it out as a file, anywhere
+            // else starts selecting a new region
             WindowEvent::MouseDown(button) if *button == MouseButton::Left => {
-                self.is_dragging = true;
-                self.drag_start_x = cx.mouse().left.pos_down.0;
-                self.drag_start_y = cx.mouse().left.pos_down.1;
+                let (x, y) = cx.mouse().left.pos_down;
+                let waveform = PoingModel::waveform.get(cx);
+                let time = self.time_at(cx, &waveform, x);
+                let in_selection = PoingModel::selection
+                    .get(cx)
+                    .is_some_and(|(start, end)| (start..end).contains(&time));
+                if in_selection || waveform.peaks.is_empty() {
+                    self.is_dragging = true;
+                    self.drag_start_x = x;
+                    self.drag_start_y = y;
+                } else {
+                    self.selecting_from = Some(time);
+                    cx.emit(PoingEvent::SelectRegion(None));
+                }
                 cx.capture();
             }
             WindowEvent::MouseUp(button) if *button == MouseButton::Left => {
                 self.is_dragging = false;
+                self.selecting_from = None;
                 cx.release();
             }
+            WindowEvent::MouseDoubleClick(button) if *button == MouseButton::Left => {
+                self.viewport = Viewport::default();
+                cx.needs_redraw();
+            }
+            // The wheel zooms around the pointer; shift or a horizontal wheel scrolls
+            WindowEvent::MouseScroll(x, y) => {
+                if cx.modifiers().contains(Modifiers::SHIFT) || *x != 0.0 {
+                    let steps = if *x != 0.0 { -*x } else { -*y };
+                    self.viewport.scroll(steps as f64 * WHEEL_SCROLL);
+                } else {
+                    let bounds = cx.bounds();
+                    let anchor = ((cx.mouse().cursorx - bounds.x) / bounds.w).clamp(0.0, 1.0);
+                    self.viewport
+                        .zoom(WHEEL_ZOOM.powf(*y as f64), anchor as f64);
+                }
+                cx.needs_redraw();
+                meta.consume();
+            }
+            // Audio files dropped onto the view become the conditioning input
+            WindowEvent::Drop(DropData::File(path)) => {
+                cx.emit(PoingEvent::LoadAudioFile(path.clone()));
+            }
             WindowEvent::MouseMove(x, y) => {
-                if self.is_dragging {
+                if let Some(from) = self.selecting_from {
+                    // Regions snap to bars unless Alt is held
+                    let waveform = PoingModel::waveform.get(cx);
+                    let time = self.time_at(cx, &waveform, *x);
+                    let snap = !cx.modifiers().contains(Modifiers::ALT);
+                    let region = waveform.region(from, time, snap);
+                    if region.1 > region.0 {
+                        cx.emit(PoingEvent::SelectRegion(Some(region)));
+                    }
+                } else if self.is_dragging {
                     let dx = *x - self.drag_start_x;
                     let dy = *y - self.drag_start_y;
                     let distance = (dx * dx + dy * dy).sqrt();
@@ -56,21 +197,38 @@ impl View for WaveformView {
 
     fn draw(&self, cx: &mut DrawContext, canvas: &mut Canvas) {
         let bounds = cx.bounds();
-        if bounds.w == 0.0 || bounds.h == 0.0 {
+        if bounds.w == 0.0 || bounds.h <= RULER_HEIGHT {
             return;
         }
 
         let bg_color = VgColor::rgb(15, 15, 23); // #0f0f17
+        let ruler_color = VgColor::rgb(22, 22, 33); // #161621
+        let label_color = VgColor::rgb(140, 140, 170); // #8c8caa
         let center_color = VgColor::rgb(45, 45, 64); // #2d2d40
-        let wave_color = VgColor::rgb(61, 122, 209); // #3d7ad1
+        let output_color = VgColor::rgb(61, 122, 209); // #3d7ad1
+        let input_color = VgColor::rgb(209, 122, 61); // #d17a3d
+        let beat_color = VgColor::rgb(30, 30, 44); // #1e1e2c
+        let downbeat_color = VgColor::rgb(70, 70, 100); // #464664
+        let playhead_color = VgColor::rgb(230, 230, 240); // #e6e6f0
+        let selection_color = VgColor::rgba(120, 160, 255, 40);
 
-        // Draw background
+        // Draw background and ruler strip
         let mut path = Path::new();
         path.rect(bounds.x, bounds.y, bounds.w, bounds.h);
         canvas.fill_path(&path, &Paint::color(bg_color));
+        let mut path = Path::new();
+        path.rect(bounds.x, bounds.y, bounds.w, RULER_HEIGHT);
+        canvas.fill_path(&path, &Paint::color(ruler_color));
 
-        // Draw center line
-        let center_y = bounds.y + bounds.h * 0.5;
+        let show_spectrogram = PoingModel::show_spectrogram.get(cx);
+        let wave_top = bounds.y + RULER_HEIGHT;
+        let area_height = bounds.h - RULER_HEIGHT;
+        let wave_height = if show_spectrogram {
+            area_height * SPECTROGRAM_WAVE_SHARE
+        } else {
+            area_height
+        };
+        let center_y = wave_top + wave_height * 0.5;
         let mut path = Path::new();
         path.move_to(bounds.x, center_y);
         path.line_to(bounds.x + bounds.w, center_y);
@@ -78,32 +236,161 @@ impl View for WaveformView {
         paint.set_line_width(1.0);
         canvas.stroke_path(&path, &paint);
 
-        // Get waveform data from model
-        let waveform_data: Arc<Vec<(f32, f32)>> =
-            PoingModel::waveform_data.get(cx);
-
-        if waveform_data.is_empty() {
+        let waveform: Arc<_> = PoingModel::waveform.get(cx);
+        if waveform.peaks.is_empty() {
             return;
         }
+        let view = self.viewport;
+        let duration = waveform.duration();
+        let x_of = |time: f32| bounds.x + view.to_view((time / duration) as f64) as f32 * bounds.w;
+
+        // Bar ruler and grid lines behind the waveform, downbeats brighter
+        let max_ticks = (bounds.w / TICK_SPACING) as usize;
+        let start = view.start as f32 * duration;
+        let end = view.end as f32 * duration;
+        let mut label_paint = Paint::color(label_color);
+        label_paint.set_font_size(10.0);
+        for tick in waveform::ruler_ticks(&waveform.grid, start, end, max_ticks.max(1)) {
+            let x = x_of(tick.time).round() + 0.5;
+            let mut path = Path::new();
+            path.move_to(x, if tick.is_downbeat { bounds.y } else { wave_top });
+            path.line_to(x, bounds.y + bounds.h);
+            let color = if tick.is_downbeat {
+                downbeat_color
+            } else {
+                beat_color
+            };
+            let mut paint = Paint::color(color);
+            paint.set_line_width(1.0);
+            canvas.stroke_path(&path, &paint);
+            let _ = canvas.fill_text(
+                x + 3.0,
+                bounds.y + RULER_HEIGHT - 4.0,
+                &tick.label,
+                &label_paint,
+            );
+        }
+        if view.is_zoomed() {
+            let range = format!("{} - {}", format_time(start), format_time(end));
+            let mut paint = label_paint.clone();
+            paint.set_text_align(Align::Right);
+            let _ = canvas.fill_text(
+                bounds.x + bounds.w - 4.0,
+                bounds.y + RULER_HEIGHT - 4.0,
+                &range,
+                &paint,
+            );
+        }
+
+        if show_spectrogram {
+            let top = wave_top + wave_height;
+            let height = area_height - wave_height;
+            if let Some(spectrogram) = PoingModel::spectrogram.get(cx) {
+                let area = BoundingBox {
+                    x: bounds.x,
+                    y: top,
+                    w: bounds.w,
+                    h: height,
+                };
+                draw_spectrogram(canvas, &spectrogram, view, duration, area);
+                for (freq, label) in FREQUENCY_LABELS {
+                    if freq < spectrogram.max_freq {
+                        let y = top + height * (1.0 - spectrogram.frequency_position(freq));
+                        let _ = canvas.fill_text(bounds.x + 3.0, y, label, &label_paint);
+                    }
+                }
+            }
+        }
 
-        let num_cols = waveform_data.len();
-        let col_width = bounds.w / num_cols as f32;
+        if let Some((start, end)) = PoingModel::selection.get(cx) {
+            let left = x_of(start).max(bounds.x);
+            let right = x_of(end).min(bounds.x + bounds.w);
+            if right > left {
+                let mut path = Path::new();
+                path.rect(left, bounds.y, right - left, bounds.h);
+                canvas.fill_path(&path, &Paint::color(selection_color));
+            }
+        }
 
-        // Draw waveform bars
-        for (i, (min_val, max_val)) in waveform_data.iter().enumerate() {
+        // One min/max column per pixel of the visible range
+        let len = waveform.peaks.len() as f64;
+        let num_cols = bounds.w.ceil() as usize;
+        let columns = waveform
+            .peaks
+            .columns(view.start * len, view.end * len, num_cols);
+        let wave_color = match waveform.source {
+            WaveformSource::Input => input_color,
+            WaveformSource::Output => output_color,
+        };
+        let mut path = Path::new();
+        for (i, (min_val, max_val)) in columns.iter().enumerate() {
             if *min_val == 0.0 && *max_val == 0.0 {
                 continue;
             }
-
-            let x = bounds.x + i as f32 * col_width;
             // Map sample values (-1..1) to pixel coordinates
-            let y_top = center_y - max_val * bounds.h * 0.45;
-            let y_bottom = center_y - min_val * bounds.h * 0.45;
-            let bar_height = (y_bottom - y_top).max(1.0);
+            let y_top = center_y - max_val * wave_height * 0.45;
+            let y_bottom = center_y - min_val * wave_height * 0.45;
+            path.rect(bounds.x + i as f32, y_top, 1.0, (y_bottom - y_top).max(1.0));
+        }
+        canvas.fill_path(&path, &Paint::color(wave_color));
 
-            let mut path = Path::new();
-            path.rect(x, y_top, col_width.max(1.0), bar_height);
-            canvas.fill_path(&path, &Paint::color(wave_color));
+        if let Some(playhead) = PoingModel::playhead.get(cx) {
+            let x = x_of(playhead).round() + 0.5;
+            if x >= bounds.x && x <= bounds.x + bounds.w {
+                let mut path = Path::new();
+                path.move_to(x, bounds.y);
+                path.line_to(x, bounds.y + bounds.h);
+                let mut paint = Paint::color(playhead_color);
+                paint.set_line_width(1.0);
+                canvas.stroke_path(&path, &paint);
+            }
         }
     }
 }
+
+/// Draw the visible part of `spectrogram` into `area`, one column per pixel
+/// with frequency rising upwards.
+fn draw_spectrogram(
+    canvas: &mut Canvas,
+    spectrogram: &Spectrogram,
+    view: Viewport,
+    duration: f32,
+    area: BoundingBox,
+) {
+    let band_height = area.h / spectrogram.bands as f32;
+    let bottom = area.y + area.h;
+    let mut paths: Vec<Path> = (0..SPECTROGRAM_COLORS).map(|_| Path::new()).collect();
+    let step = (SPECTROGRAM_COLORS - 1) as f32;
+    for col in 0..area.w.ceil() as usize {
+        let position = view.to_clip((col as f64 + 0.5) / area.w as f64);
+        let Some(frame) = spectrogram.frame_at(position as f32 * duration) else {
+            continue;
+        };
+        // Merge runs of bands with the same colour into one rect
+        let mut run_start = 0;
+        for band in 1..=frame.len() {
+            let color =
+                |band: usize| (spectrogram::db_to_level(frame[band]) * step).round() as usize;
+            if band < frame.len() && color(band) == color(run_start) {
+                continue;
+            }
+            let color = color(run_start);
+            if color > 0 {
+                let y = bottom - band as f32 * band_height;
+                let height = (band - run_start) as f32 * band_height;
+                paths[color].rect(area.x + col as f32, y, 1.0, height);
+            }
+            run_start = band;
+        }
+    }
+    for (color, path) in paths.iter().enumerate().skip(1) {
+        let (r, g, b) = spectrogram::colormap(color as f32 / step);
+        canvas.fill_path(path, &Paint::color(VgColor::rgb(r, g, b)));
+    }
+}
+
+/// Seconds as "m:ss.s".
+fn format_time(seconds: f32) -> String {
+    let seconds = seconds.max(0.0);
+    format!("{}:{:04.1}", (seconds / 60.0) as u32, seconds % 60.0)
+}
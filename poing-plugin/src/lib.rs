@@ -1,15 +1,26 @@
 use nih_plug::prelude::*;
 use nih_plug_vizia::ViziaState;
 use poing_core::audio_buffer::RingBuffer;
-use poing_core::SharedState;
+use poing_core::postprocess::PostProcessSettings;
+use poing_core::recording::{ArmedRecorder, RecordSource, TransportPosition};
+use poing_core::{config, SharedState};
 use std::path::PathBuf;
 use std::sync::atomic::Ordering;
 use std::sync::{Arc, Mutex};
 
+mod osc;
+
 pub struct Poing {
     params: Arc<PoingParams>,
     shared_state: SharedState,
     ring_buffer: RingBuffer,
+    /// Right channel of stereo recordings.
+    ring_buffer_right: RingBuffer,
+    was_recording: bool,
+    armed_recorder: ArmedRecorder,
+    sample_rate: f32,
+    /// Running while `osc_port` is set in the config; stops when dropped.
+    osc_listener: Option<osc::OscListener>,
 }
 
 #[derive(Params)]
@@ -17,6 +28,10 @@ struct PoingParams {
     #[persist = "model-path"]
     pub selected_model_path: Arc<Mutex<Option<String>>>,
 
+    /// Shared with SharedState, so restoring a project updates it in place
+    #[persist = "post-process"]
+    pub post_process: Arc<Mutex<PostProcessSettings>>,
+
     #[persist = "editor-state"]
     pub editor_state: Arc<ViziaState>,
 }
@@ -38,10 +53,16 @@ impl Default for Poing {
         Self {
             params: Arc::new(PoingParams {
                 selected_model_path: Arc::new(Mutex::new(initial_path)),
+                post_process: shared_state.post_process.clone(),
                 editor_state: poing_editor::default_state(),
             }),
             shared_state,
             ring_buffer: RingBuffer::new(max_recording_samples),
+            ring_buffer_right: RingBuffer::new(max_recording_samples),
+            was_recording: false,
+            armed_recorder: ArmedRecorder::default(),
+            sample_rate: 48_000.0,
+            osc_listener: None,
         }
     }
 }
@@ -53,11 +74,24 @@ impl Plugin for Poing {
     const EMAIL: &'static str = "";
     const VERSION: &'static str = env!("CARGO_PKG_VERSION");
 
-    const AUDIO_IO_LAYOUTS: &'static [AudioIOLayout] = &[AudioIOLayout {
-        main_input_channels: NonZeroU32::new(2),
-        main_output_channels: NonZeroU32::new(2),
-        ..AudioIOLayout::const_default()
-    }];
+    // Hosts that can't route a sidechain fall back to the plain stereo layout
+    const AUDIO_IO_LAYOUTS: &'static [AudioIOLayout] = &[
+        AudioIOLayout {
+            main_input_channels: NonZeroU32::new(2),
+            main_output_channels: NonZeroU32::new(2),
+            aux_input_ports: &[new_nonzero_u32(2)],
+            names: PortNames {
+                aux_inputs: &["Sidechain"],
+                ..PortNames::const_default()
+            },
+            ..AudioIOLayout::const_default()
+        },
+        AudioIOLayout {
+            main_input_channels: NonZeroU32::new(2),
+            main_output_channels: NonZeroU32::new(2),
+            ..AudioIOLayout::const_default()
+        },
+    ];
 
     const MIDI_INPUT: MidiConfig = MidiConfig::None;
     const MIDI_OUTPUT: MidiConfig = MidiConfig::None;
@@ -71,10 +105,15 @@ impl Plugin for Poing {
 
     fn initialize(
         &mut self,
-        _audio_io_layout: &AudioIOLayout,
+        audio_io_layout: &AudioIOLayout,
         buffer_config: &BufferConfig,
         _context: &mut impl InitContext<Self>,
     ) -> bool {
+        self.sample_rate = buffer_config.sample_rate;
+        self.shared_state.has_sidechain.store(
+            !audio_io_layout.aux_input_ports.is_empty(),
+            Ordering::Relaxed,
+        );
         if let Ok(mut sr) = self.shared_state.sample_rate.lock() {
             *sr = buffer_config.sample_rate;
         }
@@ -87,13 +126,22 @@ impl Plugin for Poing {
             }
         }
 
+        if self.osc_listener.is_none() {
+            if let Some(port) = config::load_config().osc_port {
+                match osc::OscListener::start(port, self.shared_state.clone()) {
+                    Ok(listener) => self.osc_listener = Some(listener),
+                    Err(e) => nih_log!("OSC listener on port {} unavailable: {}", port, e),
+                }
+            }
+        }
+
         true
     }
 
     fn process(
         &mut self,
         buffer: &mut Buffer,
-        _aux: &mut AuxiliaryBuffers,
+        aux: &mut AuxiliaryBuffers,
         context: &mut impl ProcessContext<Self>,
     ) -> ProcessStatus {
         // Sync host transport info to shared state for the GUI
@@ -111,20 +159,90 @@ impl Plugin for Poing {
             }
         }
 
-        // Copy input to ring buffer when recording is armed
-        if self.shared_state.is_recording.load(Ordering::Relaxed) {
-            for sample_frame in buffer.iter_samples() {
-                // Record the first (left) channel as mono
-                if let Some(sample) = sample_frame.into_iter().next() {
-                    self.ring_buffer.write(&[*sample]);
+        let position = TransportPosition {
+            playing: transport.playing,
+            tempo: transport.tempo,
+            pos_beats: transport.pos_beats(),
+            pos_seconds: transport.pos_seconds(),
+            bar_start_beats: transport.bar_start_pos_beats(),
+            bar_beats: match (transport.time_sig_numerator, transport.time_sig_denominator) {
+                (Some(num), Some(den)) if den > 0 => num as f64 * 4.0 / den as f64,
+                _ => 4.0,
+            },
+            loop_range_beats: transport.loop_range_beats(),
+        };
+        if let Ok(mut host_transport) = self.shared_state.host_transport.try_lock() {
+            *host_transport = position;
+        }
+
+        let input_channel = self.shared_state.input_channel();
+        let frames = buffer.samples();
+        let mut record_range = 0..0;
+        if self.shared_state.record_armed.load(Ordering::Relaxed) {
+            // Start and stop on bar lines of the host transport
+            let bars = self.shared_state.record_bars.load(Ordering::Relaxed);
+            let action = self
+                .armed_recorder
+                .process(&position, frames, self.sample_rate, bars);
+            if action.started {
+                self.shared_state.is_recording.store(true, Ordering::Relaxed);
+                self.shared_state.try_notify_changed();
+            }
+            record_range = action.record;
+            if action.finished {
+                self.shared_state.record_armed.store(false, Ordering::Relaxed);
+                self.shared_state.is_recording.store(false, Ordering::Relaxed);
+            }
+        } else {
+            self.armed_recorder.reset();
+            if self.shared_state.is_recording.load(Ordering::Relaxed) {
+                record_range = 0..frames;
+            }
+        }
+
+        // Copy input to ring buffer while recording
+        let is_recording = !record_range.is_empty();
+        if is_recording && !self.was_recording {
+            self.ring_buffer.clear();
+            self.ring_buffer_right.clear();
+        }
+        if is_recording {
+            // Without a sidechain port in the active layout, record the main
+            // input; the editor says so next to the source
+            let channels = match self.shared_state.recorded_source() {
+                RecordSource::Sidechain => {
+                    aux.inputs.first().map(|input| input.as_slice_immutable())
+                }
+                RecordSource::Main => None,
+            }
+            .unwrap_or_else(|| buffer.as_slice_immutable());
+            let stereo = input_channel.channels() == 2;
+            if let Some(left) = channels.first() {
+                let right = channels.get(1).unwrap_or(left);
+                for index in record_range {
+                    let [first, second] = input_channel.capture(left[index], right[index]);
+                    self.ring_buffer.write(&[first]);
+                    if stereo {
+                        self.ring_buffer_right.write(&[second]);
+                    }
                 }
             }
 
             // Snapshot recorded audio into shared state (for GUI waveform display)
             if let Ok(mut recorded) = self.shared_state.recorded_audio.try_lock() {
-                *recorded = self.ring_buffer.read();
+                recorded.channels = if stereo {
+                    vec![self.ring_buffer.read(), self.ring_buffer_right.read()]
+                } else {
+                    vec![self.ring_buffer.read()]
+                };
             }
         }
+        // Armed recordings stop by themselves; tell the GUI once the audio is in place
+        let still_recording = self.shared_state.is_recording.load(Ordering::Relaxed);
+        if self.was_recording && !still_recording {
+            self.shared_state.try_notify_changed();
+        }
+        self.was_recording = still_recording;
 
         // Sync SharedState model_path -> persist field for DAW project save
         if let Ok(current) = self.shared_state.model_path.try_lock() {
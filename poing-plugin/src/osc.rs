@@ -0,0 +1,589 @@
+//! OSC remote control over local UDP.
+//!
+//! Incoming address space (arguments in brackets are optional):
+//!
+//! | Address               | Arguments      | Effect                                 |
+//! |-----------------------|----------------|----------------------------------------|
+//! | `/poing/prompt`       | `s`            | Set the prompt                         |
+//! | `/poing/bpm`          | `f`            | Set the BPM                            |
+//! | `/poing/bars`         | `i`            | Set the number of bars                 |
+//! | `/poing/key`          | `s`            | Set the key hint, e.g. "D minor"; empty clears |
+//! | `/poing/loop`         | `0/1`          | Turn seamless loop mode off or on      |
+//! | `/poing/guidance`     | `f`            | Set the guidance scale                 |
+//! | `/poing/top_k`        | `i`            | Set top-K                              |
+//! | `/poing/generate`     | `[trigger]`    | Queue a generation                     |
+//! | `/poing/cancel`       | `[trigger]`    | Cancel all queued and running jobs     |
+//! | `/poing/record`       | `[0/1]`        | Start (or arm, when synced to the transport) or stop recording; toggles without args |
+//! | `/poing/take`         | `i`            | Select take `i` (1-based)              |
+//! | `/poing/take/next`    | `[trigger]`    | Select the next take                   |
+//! | `/poing/take/previous`| `[trigger]`    | Select the previous take               |
+//!
+//! Triggers with a zero argument are ignored, so buttons that send 1 on press
+//! and 0 on release fire once. Replies go to every client that has sent a
+//! message: `/poing/queued i i`, `/poing/started i`, `/poing/progress f`,
+//! `/poing/done i i` (take number, take count), `/poing/error s`,
+//! `/poing/cancelled i`, `/poing/record i` and `/poing/take i i`.
+
+use poing_core::worker::WorkerEvent;
+use poing_core::SharedState;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// Clients remembered for replies.
+const MAX_CLIENTS: usize = 8;
+/// Largest datagram we accept.
+const MAX_PACKET_SIZE: usize = 8192;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum OscArg {
+    Int(i32),
+    Float(f32),
+    Str(String),
+    Bool(bool),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OscMessage {
+    pub addr: String,
+    pub args: Vec<OscArg>,
+}
+
+impl OscMessage {
+    pub fn new(addr: &str, args: Vec<OscArg>) -> Self {
+        Self {
+            addr: addr.to_string(),
+            args,
+        }
+    }
+
+    fn float(&self, index: usize) -> Option<f32> {
+        match self.args.get(index)? {
+            OscArg::Float(v) => Some(*v),
+            OscArg::Int(v) => Some(*v as f32),
+            OscArg::Bool(v) => Some(if *v { 1.0 } else { 0.0 }),
+            OscArg::Str(s) => s.trim().parse().ok(),
+        }
+    }
+
+    fn string(&self, index: usize) -> Option<&str> {
+        match self.args.get(index)? {
+            OscArg::Str(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// True for argument-less messages or a non-zero first argument.
+    fn is_trigger(&self) -> bool {
+        self.float(0).is_none_or(|v| v != 0.0)
+    }
+}
+
+/// Decode an OSC packet (a message or a bundle of them).
+pub fn decode_packet(buf: &[u8]) -> Result<Vec<OscMessage>, String> {
+    if buf.starts_with(b"#bundle\0") {
+        // "#bundle", 8-byte time tag, then size-prefixed elements
+        let mut pos = 16;
+        let mut messages = Vec::new();
+        while pos + 4 <= buf.len() {
+            let size = usize::try_from(read_i32(buf, &mut pos)?)
+                .map_err(|_| "negative bundle element size")?;
+            let end = pos
+                .checked_add(size)
+                .filter(|end| *end <= buf.len())
+                .ok_or("truncated bundle element")?;
+            messages.extend(decode_packet(&buf[pos..end])?);
+            pos = end;
+        }
+        return Ok(messages);
+    }
+
+    let mut pos = 0;
+    let addr = read_string(buf, &mut pos)?;
+    if !addr.starts_with('/') {
+        return Err(format!("invalid OSC address {:?}", addr));
+    }
+    // Some senders omit the type tag string when there are no arguments
+    let tags = if pos < buf.len() {
+        read_string(buf, &mut pos)?
+    } else {
+        ",".to_string()
+    };
+    let tags = tags.strip_prefix(',').ok_or("missing type tag string")?;
+
+    let mut args = Vec::with_capacity(tags.len());
+    for tag in tags.chars() {
+        let arg = match tag {
+            'i' => OscArg::Int(read_i32(buf, &mut pos)?),
+            'f' => OscArg::Float(f32::from_bits(read_i32(buf, &mut pos)? as u32)),
+            's' => OscArg::Str(read_string(buf, &mut pos)?),
+            'T' => OscArg::Bool(true),
+            'F' => OscArg::Bool(false),
+            other => return Err(format!("unsupported OSC type tag '{}'", other)),
+        };
+        args.push(arg);
+    }
+    Ok(vec![OscMessage { addr, args }])
+}
+
+/// Encode a single OSC message.
+pub fn encode_message(message: &OscMessage) -> Vec<u8> {
+    let mut out = Vec::new();
+    write_string(&mut out, &message.addr);
+    let mut tags = String::from(",");
+    for arg in &message.args {
+        tags.push(match arg {
+            OscArg::Int(_) => 'i',
+            OscArg::Float(_) => 'f',
+            OscArg::Str(_) => 's',
+            OscArg::Bool(true) => 'T',
+            OscArg::Bool(false) => 'F',
+        });
+    }
+    write_string(&mut out, &tags);
+    for arg in &message.args {
+        match arg {
+            OscArg::Int(v) => out.extend_from_slice(&v.to_be_bytes()),
+            OscArg::Float(v) => out.extend_from_slice(&v.to_bits().to_be_bytes()),
+            OscArg::Str(s) => write_string(&mut out, s),
+            OscArg::Bool(_) => {}
+        }
+    }
+    out
+}
+
+fn read_i32(buf: &[u8], pos: &mut usize) -> Result<i32, String> {
+    let bytes = buf.get(*pos..*pos + 4).ok_or("truncated OSC argument")?;
+    *pos += 4;
+    Ok(i32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// Read a null-terminated string padded to a multiple of 4 bytes.
+fn read_string(buf: &[u8], pos: &mut usize) -> Result<String, String> {
+    let rest = buf.get(*pos..).ok_or("truncated OSC string")?;
+    let len = rest
+        .iter()
+        .position(|&b| b == 0)
+        .ok_or("unterminated OSC string")?;
+    let s = std::str::from_utf8(&rest[..len])
+        .map_err(|e| e.to_string())?
+        .to_string();
+    *pos += (len + 4) & !3;
+    Ok(s)
+}
+
+fn write_string(out: &mut Vec<u8>, s: &str) {
+    out.extend_from_slice(s.as_bytes());
+    let padding = 4 - s.len() % 4;
+    out.extend(std::iter::repeat_n(0, padding));
+}
+
+/// Background thread receiving OSC messages on `127.0.0.1:<port>`.
+pub struct OscListener {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl OscListener {
+    /// Bind the port and start listening.
+    pub fn start(port: u16, shared_state: SharedState) -> std::io::Result<Self> {
+        let socket = UdpSocket::bind(("127.0.0.1", port))?;
+        socket.set_read_timeout(Some(Duration::from_millis(50)))?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let thread = std::thread::Builder::new()
+            .name("poing-osc".into())
+            .spawn(move || run(socket, shared_state, thread_stop))?;
+        Ok(Self {
+            stop,
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for OscListener {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn run(socket: UdpSocket, state: SharedState, stop: Arc<AtomicBool>) {
+    let events = state.worker.subscribe();
+    let mut clients: Vec<SocketAddr> = Vec::new();
+    let mut last_progress = -1.0f32;
+    let mut buf = vec![0u8; MAX_PACKET_SIZE];
+
+    while !stop.load(Ordering::Relaxed) {
+        match socket.recv_from(&mut buf) {
+            Ok((len, from)) => {
+                if !clients.contains(&from) {
+                    if clients.len() == MAX_CLIENTS {
+                        clients.remove(0);
+                    }
+                    clients.push(from);
+                }
+                match decode_packet(&buf[..len]) {
+                    Ok(messages) => {
+                        for message in messages {
+                            let replies = handle_message(&message, &state);
+                            send_all(&socket, &clients, &replies);
+                        }
+                    }
+                    Err(e) => send_all(&socket, &[from], &[error_reply(&e)]),
+                }
+            }
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                ) => {}
+            Err(e) => eprintln!("[poing] OSC receive failed: {}", e),
+        }
+
+        for event in events.try_iter() {
+            // Throttle progress replies to whole percents
+            if let WorkerEvent::Progress { progress, .. } = event {
+                if (progress - last_progress).abs() < 0.01 && progress < 1.0 {
+                    continue;
+                }
+                last_progress = progress;
+            }
+            if let Some(reply) = event_reply(&event, &state) {
+                send_all(&socket, &clients, &[reply]);
+            }
+        }
+    }
+}
+
+fn send_all(socket: &UdpSocket, clients: &[SocketAddr], replies: &[OscMessage]) {
+    for reply in replies {
+        let packet = encode_message(reply);
+        for client in clients {
+            let _ = socket.send_to(&packet, client);
+        }
+    }
+}
+
+fn error_reply(message: &str) -> OscMessage {
+    OscMessage::new("/poing/error", vec![OscArg::Str(message.to_string())])
+}
+
+fn take_reply(state: &SharedState) -> OscMessage {
+    let count = state.takes.lock().unwrap().len();
+    let selected = state.selected_take.lock().unwrap().map_or(0, |i| i + 1);
+    OscMessage::new(
+        "/poing/take",
+        vec![OscArg::Int(selected as i32), OscArg::Int(count as i32)],
+    )
+}
+
+/// Apply one message to the shared state, returning replies to broadcast.
+fn handle_message(message: &OscMessage, state: &SharedState) -> Vec<OscMessage> {
+    let setting_changed = {
+        let mut settings = state.settings.lock().unwrap();
+        match message.addr.as_str() {
+            "/poing/prompt" => message.string(0).map(|s| settings.prompt = s.to_string()),
+            "/poing/bpm" => message
+                .float(0)
+                .filter(|bpm| *bpm > 0.0)
+                .map(|bpm| settings.bpm = bpm),
+            "/poing/bars" => message
+                .float(0)
+                .map(|bars| settings.bars = (bars.round() as u32).max(1)),
+            "/poing/guidance" => message.float(0).map(|g| settings.guidance_scale = g),
+            "/poing/top_k" => message
+                .float(0)
+                .map(|k| settings.top_k = (k.round() as usize).max(1)),
+            "/poing/loop" => message.float(0).map(|v| settings.loop_mode = v != 0.0),
+            // An empty string clears the key
+            "/poing/key" => match message.string(0).map(str::trim) {
+                Some("") => {
+                    settings.key = None;
+                    Some(())
+                }
+                Some(key) => key.parse().ok().map(|key| settings.key = Some(key)),
+                None => None,
+            },
+            _ => None,
+        }
+    };
+    if setting_changed.is_some() {
+        state.notify_changed();
+        return Vec::new();
+    }
+
+    match message.addr.as_str() {
+        "/poing/prompt" | "/poing/bpm" | "/poing/bars" | "/poing/key" | "/poing/loop"
+        | "/poing/guidance" | "/poing/top_k" => {
+            vec![error_reply(&format!(
+                "{}: missing or invalid argument",
+                message.addr
+            ))]
+        }
+        "/poing/generate" if message.is_trigger() => match state.submit_generation() {
+            Ok(_) => Vec::new(),
+            Err(e) => vec![error_reply(&e)],
+        },
+        "/poing/cancel" if message.is_trigger() => {
+            state.worker.cancel_all();
+            Vec::new()
+        }
+        "/poing/record" => {
+            let active = state.record_armed.load(Ordering::Relaxed)
+                || state.is_recording.load(Ordering::Relaxed);
+            let start = match message.float(0) {
+                Some(v) => v != 0.0,
+                None => !active,
+            };
+            state.request_recording(start);
+            state.notify_changed();
+            vec![OscMessage::new(
+                "/poing/record",
+                vec![OscArg::Int(start as i32)],
+            )]
+        }
+        "/poing/take" => {
+            let selected = message
+                .float(0)
+                .map(|n| n.round() as usize)
+                .filter(|n| *n >= 1 && state.select_take(n - 1));
+            match selected {
+                Some(_) => {
+                    state.notify_changed();
+                    vec![take_reply(state)]
+                }
+                None => vec![error_reply("no such take")],
+            }
+        }
+        "/poing/take/next" | "/poing/take/previous" if message.is_trigger() => {
+            let current = *state.selected_take.lock().unwrap();
+            if let Some(current) = current {
+                let next = if message.addr.ends_with("next") {
+                    current + 1
+                } else {
+                    current.saturating_sub(1)
+                };
+                if state.select_take(next) {
+                    state.notify_changed();
+                }
+            }
+            vec![take_reply(state)]
+        }
+        "/poing/generate" | "/poing/cancel" | "/poing/take/next" | "/poing/take/previous" => {
+            Vec::new()
+        }
+        other => vec![error_reply(&format!("unknown address {}", other))],
+    }
+}
+
+fn event_reply(event: &WorkerEvent, state: &SharedState) -> Option<OscMessage> {
+    let int = |v: u64| OscArg::Int(v as i32);
+    Some(match event {
+        WorkerEvent::Queued { id, position } => {
+            OscMessage::new("/poing/queued", vec![int(*id), int(*position as u64)])
+        }
+        WorkerEvent::Started { id } => OscMessage::new("/poing/started", vec![int(*id)]),
+        WorkerEvent::Progress { progress, .. } => {
+            OscMessage::new("/poing/progress", vec![OscArg::Float(*progress)])
+        }
+        WorkerEvent::Partial { .. } => return None,
+        WorkerEvent::Done { .. } => {
+            let count = state.takes.lock().unwrap().len();
+            let selected = state.selected_take.lock().unwrap().map_or(0, |i| i + 1);
+            OscMessage::new("/poing/done", vec![int(selected as u64), int(count as u64)])
+        }
+        WorkerEvent::Error { message, .. } => error_reply(message),
+        WorkerEvent::Cancelled { id } => OscMessage::new("/poing/cancelled", vec![int(*id)]),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use poing_core::musicgen::GenerationParams;
+    use poing_core::worker::Take;
+    use std::path::PathBuf;
+
+    fn take() -> Take {
+        Take {
+            job_id: 1,
+            prompt: "gabber kick".into(),
+            model_dir: PathBuf::from("/models/test"),
+            params: GenerationParams::default(),
+            audio: Arc::new(vec![0.0; 16]),
+            grid: None,
+            looped: false,
+            beats_per_bar: 4,
+            settings: None,
+        }
+    }
+
+    #[test]
+    fn test_message_round_trip() {
+        let message = OscMessage::new(
+            "/poing/prompt",
+            vec![
+                OscArg::Str("gabber kick".into()),
+                OscArg::Int(-3),
+                OscArg::Float(0.5),
+                OscArg::Bool(true),
+            ],
+        );
+        let encoded = encode_message(&message);
+        assert_eq!(encoded.len() % 4, 0);
+        assert_eq!(decode_packet(&encoded).unwrap(), vec![message]);
+    }
+
+    #[test]
+    fn test_decode_bundle() {
+        let first = encode_message(&OscMessage::new("/poing/bpm", vec![OscArg::Float(180.0)]));
+        let second = encode_message(&OscMessage::new("/poing/generate", vec![]));
+        let mut bundle = b"#bundle\0".to_vec();
+        bundle.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 1]);
+        for element in [&first, &second] {
+            bundle.extend_from_slice(&(element.len() as i32).to_be_bytes());
+            bundle.extend_from_slice(element);
+        }
+
+        let messages = decode_packet(&bundle).unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].float(0), Some(180.0));
+        assert!(messages[1].is_trigger());
+    }
+
+    #[test]
+    fn test_rejects_truncated_packet() {
+        let mut encoded = encode_message(&OscMessage::new("/poing/bars", vec![OscArg::Int(8)]));
+        encoded.truncate(encoded.len() - 2);
+        assert!(decode_packet(&encoded).is_err());
+
+        let mut bundle = b"#bundle\0".to_vec();
+        bundle.extend_from_slice(&[0; 8]);
+        bundle.extend_from_slice(&(-4i32).to_be_bytes());
+        bundle.extend_from_slice(&[0; 8]);
+        assert!(decode_packet(&bundle).is_err());
+    }
+
+    #[test]
+    fn test_settings_and_unknown_addresses() {
+        let state = SharedState::default();
+        let replies = handle_message(
+            &OscMessage::new("/poing/bpm", vec![OscArg::Float(174.0)]),
+            &state,
+        );
+        assert!(replies.is_empty());
+        assert_eq!(state.settings.lock().unwrap().bpm, 174.0);
+
+        let replies = handle_message(&OscMessage::new("/poing/bpm", vec![]), &state);
+        assert_eq!(
+            replies,
+            [error_reply("/poing/bpm: missing or invalid argument")]
+        );
+        let replies = handle_message(&OscMessage::new("/poing/nope", vec![]), &state);
+        assert_eq!(replies, [error_reply("unknown address /poing/nope")]);
+    }
+
+    #[test]
+    fn test_record_toggles_and_arms() {
+        let state = SharedState::default();
+        let record = |args| handle_message(&OscMessage::new("/poing/record", args), &state);
+        let reply = |on: i32| vec![OscMessage::new("/poing/record", vec![OscArg::Int(on)])];
+
+        assert_eq!(record(vec![]), reply(1));
+        assert!(state.is_recording.load(Ordering::Relaxed));
+        assert_eq!(record(vec![]), reply(0));
+        assert!(!state.is_recording.load(Ordering::Relaxed));
+
+        // Synced to the transport, starting arms instead
+        state.record_synced.store(true, Ordering::Relaxed);
+        assert_eq!(record(vec![OscArg::Int(1)]), reply(1));
+        assert!(state.record_armed.load(Ordering::Relaxed));
+        assert!(!state.is_recording.load(Ordering::Relaxed));
+        assert_eq!(record(vec![OscArg::Int(0)]), reply(0));
+        assert!(!state.record_armed.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn test_take_selection() {
+        let state = SharedState::default();
+        state.add_take(take());
+        state.add_take(take());
+        let take_reply = |selected: i32| {
+            vec![OscMessage::new(
+                "/poing/take",
+                vec![OscArg::Int(selected), OscArg::Int(2)],
+            )]
+        };
+
+        let replies = handle_message(
+            &OscMessage::new("/poing/take", vec![OscArg::Int(1)]),
+            &state,
+        );
+        assert_eq!(replies, take_reply(1));
+        assert_eq!(*state.selected_take.lock().unwrap(), Some(0));
+        let replies = handle_message(
+            &OscMessage::new("/poing/take", vec![OscArg::Int(5)]),
+            &state,
+        );
+        assert_eq!(replies, [error_reply("no such take")]);
+
+        let replies = handle_message(&OscMessage::new("/poing/take/next", vec![]), &state);
+        assert_eq!(replies, take_reply(2));
+        // A button release sends 0 and does nothing
+        let release = OscMessage::new("/poing/take/previous", vec![OscArg::Int(0)]);
+        assert!(handle_message(&release, &state).is_empty());
+        assert_eq!(*state.selected_take.lock().unwrap(), Some(1));
+    }
+
+    #[test]
+    fn test_generate_trigger_and_replies() {
+        let state = SharedState::default();
+        let release = OscMessage::new("/poing/generate", vec![OscArg::Int(0)]);
+        assert!(handle_message(&release, &state).is_empty());
+        let press = OscMessage::new("/poing/generate", vec![OscArg::Int(1)]);
+        assert_eq!(
+            handle_message(&press, &state),
+            [error_reply("Please enter a prompt")]
+        );
+        assert_eq!(state.worker.pending_jobs(), 0);
+
+        let queued = WorkerEvent::Queued { id: 3, position: 1 };
+        assert_eq!(
+            event_reply(&queued, &state),
+            Some(OscMessage::new(
+                "/poing/queued",
+                vec![OscArg::Int(3), OscArg::Int(1)]
+            ))
+        );
+        let partial = WorkerEvent::Partial {
+            id: 3,
+            audio: Arc::new(Vec::new()),
+        };
+        assert_eq!(event_reply(&partial, &state), None);
+        state.add_take(take());
+        let done = WorkerEvent::Done {
+            id: 3,
+            takes: vec![take()],
+        };
+        assert_eq!(
+            event_reply(&done, &state),
+            Some(OscMessage::new(
+                "/poing/done",
+                vec![OscArg::Int(1), OscArg::Int(1)]
+            ))
+        );
+        let error = WorkerEvent::Error {
+            id: 3,
+            message: "model failed".into(),
+        };
+        assert_eq!(
+            event_reply(&error, &state),
+            Some(error_reply("model failed"))
+        );
+    }
+}